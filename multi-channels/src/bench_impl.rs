@@ -0,0 +1,579 @@
+//! 把 impl01 / impl02 / impl51 / ch_hub 四种 multi-channel 实现放在一起比较：
+//! - `MultiChOp` 统一它们的 Channel / Puber / Suber 接口
+//! - `Scenario` 是 fan-out / fan-in 的场景矩阵，每个场景所有实现轮流跑一遍
+//!
+//! 每个 channel 一个发送 task 发 msg_num 条，每个 suber 一个接收 task，
+//! 收到某个 channel 的最后一条或 Closed 就退订这个 channel，全部退订完结束。
+//! Lagged 之后继续收，记录从 Lagged 到这个 channel 再收到消息的恢复时间。
+//! 报告从开始发送到最后一个 suber 结束的耗时、收到/落后的数量、消息延迟和恢复时间
+
+use std::{future::{Future, Ready, ready}, sync::Arc, time::{Duration, Instant}};
+
+use anyhow::{Result, bail};
+use console::Term;
+use futures::{StreamExt, future::BoxFuture};
+use parking_lot::Mutex;
+use tokio::sync::{Semaphore, broadcast::error::RecvError};
+
+use crate::{
+    ch_common::{ChIdOp, RecvOutput, SeqVal, VecMap, uid::ChId},
+    ch_hub::{self, hub::{Hub, HubConfig}, store::NoStore},
+    cli_graph::{bars::{self, BarRow}, histogram::Histogram, hist_plot::Unit},
+    impl01, impl02, impl51,
+    mpsc_ch::mpsc_crossbeam_que::Mpsc,
+};
+
+/// (第几条, 发送时刻)，发送时刻是开始发送之后的纳秒数
+pub type Payload = (u64, u64);
+
+/// 四种实现的公共接口。建 channel、订阅不计时，用 BoxFuture；push / recv 在热路径上
+pub trait MultiChOp: Sized + Send + Sync + 'static {
+    type Puber: Send + 'static;
+    type Suber: Send + 'static;
+    type PushFut<'a>: Future<Output = Result<()>> + Send + 'a where Self: 'a;
+    type RecvFut<'a>: Future<Output = RecvOutput<ChId, Payload>> + Send + 'a where Self: 'a;
+
+    fn name() -> &'static str;
+
+    /// ch_cap 是每个 channel 缓存的消息数，inbox_cap 是每个 suber 的 inbox 大小（有的话）
+    fn with_capacity(ch_cap: usize, inbox_cap: usize) -> Self;
+
+    /// channel 不存在时创建
+    fn puber(&self, ch_id: ChId) -> BoxFuture<'_, Result<Self::Puber>>;
+
+    fn suber(&self) -> Self::Suber;
+
+    /// 从第一条消息开始订阅，channel 不存在时创建
+    fn subscribe<'a>(&'a self, suber: &'a mut Self::Suber, ch_id: ChId) -> BoxFuture<'a, Result<()>>;
+
+    fn unsubscribe(suber: &mut Self::Suber, ch_id: &ChId);
+
+    fn push(puber: &mut Self::Puber, v: Payload) -> Self::PushFut<'_>;
+
+    /// 没有订阅任何 channel 时返回 `RecvOutput::None`
+    fn recv(suber: &mut Self::Suber) -> Self::RecvFut<'_>;
+}
+
+/// 非 hub 的实现没有 channel 表，这里按 ch_id 建一个
+struct Channels<C> {
+    cap: usize,
+    channels: Mutex<VecMap<ChId, C>>,
+}
+
+impl<C: Clone> Channels<C> {
+    fn new(cap: usize) -> Self {
+        Self { cap, channels: Mutex::new(VecMap::new()) }
+    }
+
+    fn get_or_add<F>(&self, ch_id: ChId, make: F) -> C
+    where
+        F: FnOnce(ChId, usize) -> C,
+    {
+        self.channels.lock().entry(ch_id).or_insert_with(|| make(ch_id, self.cap)).clone()
+    }
+}
+
+pub struct Impl01 {
+    channels: Channels<impl01::SChannel<ChId, Payload>>,
+}
+
+impl MultiChOp for Impl01 {
+    type Puber = impl01::Puber<ChId, SeqVal<Payload>>;
+    type Suber = impl01::Suber<ChId, SeqVal<Payload>>;
+    type PushFut<'a> = Ready<Result<()>>;
+    type RecvFut<'a> = impl Future<Output = RecvOutput<ChId, Payload>> + Send + 'a;
+
+    fn name() -> &'static str {
+        impl01::impl_name()
+    }
+
+    fn with_capacity(ch_cap: usize, _inbox_cap: usize) -> Self {
+        Self { channels: Channels::new(ch_cap) }
+    }
+
+    fn puber(&self, ch_id: ChId) -> BoxFuture<'_, Result<Self::Puber>> {
+        let ch = self.channels.get_or_add(ch_id, impl01::SChannel::with_capacity);
+        Box::pin(ready(Ok(ch.puber())))
+    }
+
+    fn suber(&self) -> Self::Suber {
+        impl01::Suber::new()
+    }
+
+    fn subscribe<'a>(&'a self, suber: &'a mut Self::Suber, ch_id: ChId) -> BoxFuture<'a, Result<()>> {
+        let ch = self.channels.get_or_add(ch_id, impl01::SChannel::with_capacity);
+        Box::pin(ready(suber.subscribe(&ch, 1)))
+    }
+
+    fn unsubscribe(suber: &mut Self::Suber, ch_id: &ChId) {
+        suber.unsubscribe(ch_id);
+    }
+
+    fn push(puber: &mut Self::Puber, v: Payload) -> Self::PushFut<'_> {
+        ready(puber.push(v))
+    }
+
+    fn recv(suber: &mut Self::Suber) -> Self::RecvFut<'_> {
+        async move {
+            if suber.channels() == 0 {
+                return RecvOutput::None;
+            }
+            unseq(suber.recv_next().await)
+        }
+    }
+}
+
+pub struct Impl02 {
+    channels: Channels<impl02::SChannel<ChId, Payload, Mpsc>>,
+    inbox_cap: usize,
+}
+
+impl MultiChOp for Impl02 {
+    type Puber = impl02::Puber<ChId, SeqVal<Payload>, Mpsc>;
+    type Suber = impl02::Suber<ChId, SeqVal<Payload>, Mpsc>;
+    type PushFut<'a> = Ready<Result<()>>;
+    type RecvFut<'a> = impl Future<Output = RecvOutput<ChId, Payload>> + Send + 'a;
+
+    fn name() -> &'static str {
+        impl02::impl_name()
+    }
+
+    fn with_capacity(ch_cap: usize, inbox_cap: usize) -> Self {
+        Self { channels: Channels::new(ch_cap), inbox_cap }
+    }
+
+    fn puber(&self, ch_id: ChId) -> BoxFuture<'_, Result<Self::Puber>> {
+        let ch = self.channels.get_or_add(ch_id, impl02::SChannel::with_capacity);
+        Box::pin(ready(Ok(ch.puber())))
+    }
+
+    fn suber(&self) -> Self::Suber {
+        impl02::Suber::with_inbox_cap(self.inbox_cap)
+    }
+
+    fn subscribe<'a>(&'a self, suber: &'a mut Self::Suber, ch_id: ChId) -> BoxFuture<'a, Result<()>> {
+        let ch = self.channels.get_or_add(ch_id, impl02::SChannel::with_capacity);
+        Box::pin(ready(suber.subscribe(&ch, 1)))
+    }
+
+    fn unsubscribe(suber: &mut Self::Suber, ch_id: &ChId) {
+        suber.unsubscribe(ch_id);
+    }
+
+    fn push(puber: &mut Self::Puber, v: Payload) -> Self::PushFut<'_> {
+        ready(puber.push(v))
+    }
+
+    fn recv(suber: &mut Self::Suber) -> Self::RecvFut<'_> {
+        async move {
+            if suber.channels() == 0 {
+                return RecvOutput::None;
+            }
+            unseq(suber.recv_next().await)
+        }
+    }
+}
+
+/// impl51 的消息不带 ch_id，放在消息里
+pub struct Impl51 {
+    channels: Channels<impl51::Channel<(ChId, Payload)>>,
+}
+
+impl MultiChOp for Impl51 {
+    type Puber = (ChId, impl51::Puber<(ChId, Payload)>);
+    type Suber = (usize, impl51::Suber<(ChId, Payload)>);
+    type PushFut<'a> = Ready<Result<()>>;
+    type RecvFut<'a> = impl Future<Output = RecvOutput<ChId, Payload>> + Send + 'a;
+
+    fn name() -> &'static str {
+        "impl51-tokio-broadcast"
+    }
+
+    fn with_capacity(ch_cap: usize, _inbox_cap: usize) -> Self {
+        Self { channels: Channels::new(ch_cap) }
+    }
+
+    fn puber(&self, ch_id: ChId) -> BoxFuture<'_, Result<Self::Puber>> {
+        let ch = self.channels.get_or_add(ch_id, impl51::Channel::with_capacity);
+        Box::pin(ready(Ok((ch_id, ch.puber()))))
+    }
+
+    fn suber(&self) -> Self::Suber {
+        (0, impl51::Suber::new())
+    }
+
+    fn subscribe<'a>(&'a self, suber: &'a mut Self::Suber, ch_id: ChId) -> BoxFuture<'a, Result<()>> {
+        let ch = self.channels.get_or_add(ch_id, impl51::Channel::with_capacity);
+        let r = suber.1.subscribe(&ch);
+        if r.is_ok() {
+            suber.0 += 1;
+        }
+        Box::pin(ready(r))
+    }
+
+    fn unsubscribe(suber: &mut Self::Suber, ch_id: &ChId) {
+        if suber.1.unsubscribe(*ch_id) {
+            suber.0 -= 1;
+        }
+    }
+
+    fn push(puber: &mut Self::Puber, v: Payload) -> Self::PushFut<'_> {
+        puber.1.push((puber.0, v));
+        ready(Ok(()))
+    }
+
+    fn recv(suber: &mut Self::Suber) -> Self::RecvFut<'_> {
+        async move {
+            if suber.0 == 0 {
+                return RecvOutput::None;
+            }
+            // tokio broadcast 的 recv 可以取消，没轮到的 recver 下次重新等
+            match suber.1.recvers().next().await {
+                Some(Ok((ch_id, v))) => RecvOutput::Value(ch_id, v),
+                Some(Err(e)) => match e.error() {
+                    RecvError::Lagged(_n) => RecvOutput::Lagged(e.ch_id()),
+                    RecvError::Closed => RecvOutput::Closed(e.ch_id()),
+                },
+                None => RecvOutput::None,
+            }
+        }
+    }
+}
+
+type HubT = Hub<ChId, SeqVal<Payload>, Mpsc>;
+
+pub struct ChHub {
+    hub: HubT,
+    inbox_cap: usize,
+}
+
+impl MultiChOp for ChHub {
+    type Puber = ch_hub::hub::Puber<ChId, SeqVal<Payload>, Mpsc, NoStore>;
+    type Suber = ch_hub::Suber<ChId, SeqVal<Payload>, Mpsc>;
+    type PushFut<'a> = impl Future<Output = Result<()>> + Send + 'a;
+    type RecvFut<'a> = impl Future<Output = RecvOutput<ChId, Payload>> + Send + 'a;
+
+    fn name() -> &'static str {
+        "ch_hub-Hub"
+    }
+
+    fn with_capacity(ch_cap: usize, inbox_cap: usize) -> Self {
+        let config = HubConfig {
+            channel: ch_hub::ChannelOptions { capacity: ch_cap, ..Default::default() },
+            ..Default::default()
+        };
+        Self { hub: HubT::with_config(NoStore, config), inbox_cap }
+    }
+
+    fn puber(&self, ch_id: ChId) -> BoxFuture<'_, Result<Self::Puber>> {
+        Box::pin(async move { self.hub.puber(&ch_id).await })
+    }
+
+    fn suber(&self) -> Self::Suber {
+        ch_hub::Suber::with_inbox_cap(self.inbox_cap)
+    }
+
+    fn subscribe<'a>(&'a self, suber: &'a mut Self::Suber, ch_id: ChId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { self.hub.subscribe(&ch_id, suber, 1).await })
+    }
+
+    fn unsubscribe(suber: &mut Self::Suber, ch_id: &ChId) {
+        suber.unsubscribe(ch_id);
+    }
+
+    fn push(puber: &mut Self::Puber, v: Payload) -> Self::PushFut<'_> {
+        async move {
+            puber.push(v).await?;
+            Ok(())
+        }
+    }
+
+    fn recv(suber: &mut Self::Suber) -> Self::RecvFut<'_> {
+        async move {
+            if suber.channels() == 0 {
+                return RecvOutput::None;
+            }
+            unseq(suber.recv_next().await)
+        }
+    }
+}
+
+fn unseq<K: ChIdOp, V>(r: RecvOutput<K, SeqVal<V>>) -> RecvOutput<K, V> {
+    match r {
+        RecvOutput::Value(ch_id, v) => RecvOutput::Value(ch_id, v.1),
+        RecvOutput::Lagged(ch_id) => RecvOutput::Lagged(ch_id),
+        RecvOutput::Closed(ch_id) => RecvOutput::Closed(ch_id),
+        RecvOutput::Reset(ch_id) => RecvOutput::Reset(ch_id),
+        RecvOutput::None => RecvOutput::None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// 1 个 channel，n 个 suber
+    OneToN(usize),
+    /// n 个 channel，1 个 suber 订阅全部
+    NToOne(usize),
+    /// m 个 channel，k 个 suber 都订阅全部
+    MxK(usize, usize),
+    /// 和 MxK 一样，但 suber 等全部发完才开始收，msg_num 超过 ch_cap 时会落后
+    Lag(usize, usize),
+}
+
+impl Scenario {
+    pub fn name(&self) -> String {
+        match self {
+            Scenario::OneToN(n) => format!("1_to_{}", n),
+            Scenario::NToOne(n) => format!("{}_to_1", n),
+            Scenario::MxK(m, k) => format!("{}_x_{}", m, k),
+            Scenario::Lag(m, k) => format!("lag_{}_x_{}", m, k),
+        }
+    }
+
+    /// (channel 数, suber 数)
+    pub fn shape(&self) -> (usize, usize) {
+        match *self {
+            Scenario::OneToN(n) => (1, n),
+            Scenario::NToOne(n) => (n, 1),
+            Scenario::MxK(m, k) | Scenario::Lag(m, k) => (m, k),
+        }
+    }
+
+    fn is_lag(&self) -> bool {
+        matches!(self, Scenario::Lag(..))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImplArgs {
+    pub msg_num: usize,
+    pub ch_cap: usize,
+    pub inbox_cap: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ScenarioOutput {
+    pub elapsed: Duration,
+    pub recved: u64,
+    pub lagged: u64,
+    pub latency: Histogram,
+    /// Lagged 之后到这个 channel 再收到消息的耗时
+    pub recovery: Histogram,
+}
+
+pub async fn run_scenario<I: MultiChOp>(scenario: Scenario, args: &ImplArgs) -> Result<ScenarioOutput> {
+    if args.msg_num == 0 {
+        bail!("msg_num must > 0")
+    }
+    let (ch_num, suber_num) = scenario.shape();
+    let ch_ids: Vec<ChId> = (1..=ch_num as u64).map(ChId::new).collect();
+    let imp = Arc::new(I::with_capacity(args.ch_cap, args.inbox_cap));
+
+    let mut pubers = Vec::with_capacity(ch_num);
+    for ch_id in ch_ids.iter() {
+        pubers.push(imp.puber(*ch_id).await?);
+    }
+
+    let mut subers = Vec::with_capacity(suber_num);
+    for _ in 0..suber_num {
+        let mut suber = imp.suber();
+        for ch_id in ch_ids.iter() {
+            imp.subscribe(&mut suber, *ch_id).await?;
+        }
+        subers.push(suber);
+    }
+
+    // lag 场景下 suber 等发完再开始收
+    let gate = Arc::new(Semaphore::new(0));
+    let base = Instant::now();
+    let last = args.msg_num as u64 - 1;
+
+    let mut recv_tasks = Vec::with_capacity(suber_num);
+    for mut suber in subers {
+        let gate = if scenario.is_lag() { Some(gate.clone()) } else { None };
+        let mut remaining = ch_num;
+        recv_tasks.push(tokio::spawn(async move {
+            if let Some(gate) = gate {
+                gate.acquire().await?.forget();
+            }
+            let mut output = ScenarioOutput::default();
+            // 每个 channel 收到 Lagged 的时间，收到下一条时算恢复时间
+            let mut lagged_at: VecMap<ChId, u64> = VecMap::new();
+            while remaining > 0 {
+                let ch_id = match I::recv(&mut suber).await {
+                    RecvOutput::Value(ch_id, (n, sent)) => {
+                        let now = nanos_since(base);
+                        output.recved += 1;
+                        output.latency.record(now.saturating_sub(sent));
+                        if let Some(at) = lagged_at.swap_remove(&ch_id) {
+                            output.recovery.record(now.saturating_sub(at));
+                        }
+                        if n < last {
+                            continue;
+                        }
+                        ch_id
+                    },
+                    RecvOutput::Lagged(ch_id) => {
+                        output.lagged += 1;
+                        lagged_at.entry(ch_id).or_insert_with(|| nanos_since(base));
+                        continue;
+                    },
+                    RecvOutput::Closed(ch_id) | RecvOutput::Reset(ch_id) => ch_id,
+                    RecvOutput::None => break,
+                };
+                I::unsubscribe(&mut suber, &ch_id);
+                remaining -= 1;
+            }
+            output.elapsed = base.elapsed();
+            Result::<ScenarioOutput>::Ok(output)
+        }));
+    }
+
+    let mut push_tasks = Vec::with_capacity(ch_num);
+    for mut puber in pubers {
+        let msg_num = args.msg_num as u64;
+        push_tasks.push(tokio::spawn(async move {
+            for n in 0..msg_num {
+                I::push(&mut puber, (n, nanos_since(base))).await?;
+            }
+            Result::<()>::Ok(())
+        }));
+    }
+    for h in push_tasks {
+        h.await??;
+    }
+    gate.add_permits(suber_num);
+
+    let mut output = ScenarioOutput::default();
+    for h in recv_tasks {
+        let r = h.await??;
+        output.elapsed = output.elapsed.max(r.elapsed);
+        output.recved += r.recved;
+        output.lagged += r.lagged;
+        output.latency.merge(&r.latency);
+        output.recovery.merge(&r.recovery);
+    }
+    Ok(output)
+}
+
+fn nanos_since(base: Instant) -> u64 {
+    base.elapsed().as_nanos() as u64
+}
+
+pub async fn run() -> Result<()> {
+    let mut bench = Bench {
+        args: ImplArgs { msg_num: 100, ch_cap: 1024, inbox_cap: 256 },
+        term: Term::stderr(),
+        bars: Vec::with_capacity(4),
+        is_plot: false,
+    };
+
+    // warm up
+    bench.term.write_line("warming up...")?;
+    bench_round(&mut bench, Scenario::MxK(10, 10)).await?;
+    bench.term.write_line("warming up done")?;
+    bench.term.write_line("")?;
+
+    bench.is_plot = true;
+
+    for n in [10, 100, 1000] {
+        bench_round(&mut bench, Scenario::OneToN(n)).await?;
+    }
+    for n in [10, 100, 1000] {
+        bench_round(&mut bench, Scenario::NToOne(n)).await?;
+    }
+    for (m, k) in [(10, 10), (100, 10), (10, 100), (100, 100)] {
+        bench_round(&mut bench, Scenario::MxK(m, k)).await?;
+    }
+
+    // 每个 channel 发的比缓存的多
+    let args = bench.args.clone();
+    bench.args = ImplArgs { msg_num: 4 * args.ch_cap, ..args.clone() };
+    bench_round(&mut bench, Scenario::Lag(10, 10)).await?;
+    bench.args = args;
+
+    Ok(())
+}
+
+async fn bench_round(bench: &mut Bench, scenario: Scenario) -> Result<()> {
+    bench.term.write_line(&format!("{} round: {:?}", scenario.name(), bench.args))?;
+    bench_impl::<Impl01>(bench, scenario).await?;
+    bench_impl::<Impl02>(bench, scenario).await?;
+    bench_impl::<Impl51>(bench, scenario).await?;
+    bench_impl::<ChHub>(bench, scenario).await?;
+    bench.plot()
+}
+
+async fn bench_impl<I: MultiChOp>(bench: &mut Bench, scenario: Scenario) -> Result<()> {
+    let output = run_scenario::<I>(scenario, &bench.args).await?;
+    let p99 = output.latency.value_at_percentile(99.0).unwrap_or(0);
+    let recovery_max = output.recovery.max().unwrap_or(0);
+
+    bench.term.clear_line()?;
+    bench.term.write_line(&format!(
+        "{},{}us,recved={},lagged={},p99={},recovery_max={}",
+        I::name(),
+        output.elapsed.as_micros(),
+        output.recved,
+        output.lagged,
+        Unit::Nanos.format(p99 as f64),
+        Unit::Nanos.format(recovery_max as f64),
+    ))?;
+
+    bench.bars.push(BarRow {
+        label: I::name().into(),
+        count: output.elapsed.as_micros() as u64,
+    });
+    Ok(())
+}
+
+struct Bench {
+    term: Term,
+    bars: Vec<BarRow>,
+    args: ImplArgs,
+    is_plot: bool,
+}
+
+impl Bench {
+    pub fn plot(&mut self) -> Result<()>{
+        if self.is_plot {
+            let display = bars::display_with_width(&self.bars, self.term.size().1 as usize);
+            self.term.write_line(&format!("{}", display))?;
+            self.term.write_line("")?;
+        }
+        self.bars.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn check<I: MultiChOp>() {
+        let args = ImplArgs { msg_num: 20, ch_cap: 64, inbox_cap: 8 };
+        for scenario in [Scenario::OneToN(3), Scenario::NToOne(3), Scenario::MxK(2, 3)] {
+            let (m, k) = scenario.shape();
+            let output = run_scenario::<I>(scenario, &args).await.unwrap();
+            assert_eq!(output.recved, (m * k * args.msg_num) as u64, "{} {}", I::name(), scenario.name());
+            assert_eq!(output.lagged, 0, "{} {}", I::name(), scenario.name());
+            assert_eq!(output.latency.len(), output.recved);
+        }
+
+        // 发的比缓存的多，每个 suber 在每个 channel 上都落后，之后继续收到最后一条
+        let args = ImplArgs { msg_num: 40, ch_cap: 8, inbox_cap: 8 };
+        let output = run_scenario::<I>(Scenario::Lag(2, 2), &args).await.unwrap();
+        assert_eq!(output.lagged, 4, "{}", I::name());
+        assert_eq!(output.recovery.len(), 4, "{}", I::name());
+        assert!(output.recved >= 4 && output.recved < 4 * args.msg_num as u64, "{} {}", I::name(), output.recved);
+    }
+
+    #[tokio::test]
+    async fn test_scenarios() {
+        check::<Impl01>().await;
+        check::<Impl02>().await;
+        check::<Impl51>().await;
+        check::<ChHub>().await;
+        assert!(run_scenario::<Impl01>(Scenario::OneToN(1), &ImplArgs { msg_num: 0, ch_cap: 1, inbox_cap: 1 }).await.is_err());
+    }
+}
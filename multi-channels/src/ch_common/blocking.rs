@@ -0,0 +1,85 @@
+//! 在普通线程上阻塞等一个 future，不依赖 tokio runtime：
+//! waker 就是 unpark 当前线程，没 ready 就 park（有 deadline 时 park_timeout）
+//!
+//! 只适合内部不需要 runtime 的 future，比如各个 mpsc 的 async_recv
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    match poll_until(fut, None) {
+        Some(v) => v,
+        None => unreachable!("no deadline"),
+    }
+}
+
+/// 超时返回 None，future 直接丢掉
+pub fn block_on_timeout<F: Future>(fut: F, timeout: Duration) -> Option<F::Output> {
+    poll_until(fut, Instant::now().checked_add(timeout))
+}
+
+fn poll_until<F: Future>(fut: F, deadline: Option<Instant>) -> Option<F::Output> {
+    let mut fut = pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(v) = fut.as_mut().poll(&mut cx) {
+            return Some(v);
+        }
+
+        // park 可能被虚假唤醒，再 poll 一次就好
+        match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                thread::park_timeout(deadline - now);
+            },
+            None => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_block_on() {
+        assert_eq!(block_on(async { 1 }), 1);
+
+        let pending = futures::future::pending::<()>();
+        let time = Instant::now();
+        assert_eq!(block_on_timeout(pending, Duration::from_millis(20)), None);
+        assert!(time.elapsed() >= Duration::from_millis(20));
+
+        // 另一个线程唤醒
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let th = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send(7).unwrap();
+        });
+        assert_eq!(block_on_timeout(rx, Duration::from_secs(5)), Some(Ok(7)));
+        th.join().unwrap();
+    }
+}
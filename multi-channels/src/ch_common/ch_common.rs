@@ -1,6 +1,7 @@
 
 
 
+#[derive(Debug, PartialEq)]
 pub enum ReadQueOutput<T> {
     Value(T),
     Latest,
@@ -10,6 +11,10 @@ pub enum ReadQueOutput<T> {
 pub enum RecvOutput<K, T> {
     Value(K, T),
     Lagged(K),
+    /// channel 已关闭，已经自动退订
+    Closed(K),
+    /// channel 被重置，之前的消息都不再读取
+    Reset(K),
     None,
 }
 
@@ -29,6 +34,13 @@ impl<K, T> RecvOutput<K, T> {
             false
         }
     }
+
+    pub fn ch_id(&self) -> Option<&K> {
+        match self {
+            Self::Value(ch_id, _) | Self::Lagged(ch_id) | Self::Closed(ch_id) | Self::Reset(ch_id) => Some(ch_id),
+            Self::None => None,
+        }
+    }
 }
 
 impl <K, T> PartialEq for RecvOutput<K, T> 
@@ -40,6 +52,8 @@ where
         match (self, other) {
             (Self::Value(l0, l1), Self::Value(r0, r1)) => l0 == r0 && l1 == r1,
             (Self::Lagged(l0), Self::Lagged(r0)) => l0 == r0,
+            (Self::Closed(l0), Self::Closed(r0)) => l0 == r0,
+            (Self::Reset(l0), Self::Reset(r0)) => l0 == r0,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
@@ -60,6 +74,8 @@ where
         match self {
             Self::Value(arg0, arg1) => f.debug_tuple("Value").field(arg0).field(arg1).finish(),
             Self::Lagged(arg0) => f.debug_tuple("Lagged").field(arg0).finish(),
+            Self::Closed(arg0) => f.debug_tuple("Closed").field(arg0).finish(),
+            Self::Reset(arg0) => f.debug_tuple("Reset").field(arg0).finish(),
             Self::None => write!(f, "None"),
         }
     }
@@ -83,6 +99,7 @@ pub trait WithSeq {
 
 /// Seq Value
 #[derive(Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SeqVal<V>(pub(crate)u64, pub(crate)V);
 
 impl <V> SeqVal<V> {
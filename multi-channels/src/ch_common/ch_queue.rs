@@ -1,13 +1,26 @@
-
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use anyhow::{Result, bail};
 
 use super::*;
 
+/// 压缩 channel 里消息的 key，同一个 key 只保留最新的一条
+pub type CompactKeyFn<T> = fn(&T) -> u64;
+
 pub struct ChDeque<T> { 
     cap: usize,
     queue: VecDeque<T>,
     last_seq: u64,
+
+    /// 设置了 max_age 时记录每条消息 push 的时间，和 queue 一一对应
+    max_age: Option<Duration>,
+    pushed_at: VecDeque<Instant>,
+
+    /// key -> 这个 key 最新一条的 seq
+    compact: Option<(CompactKeyFn<T>, HashMap<u64, u64>)>,
+
+    /// 按容量、时间淘汰或者 clear 掉的最大 seq。压缩掉的不算，它们的 key 都有更新的值
+    lost_seq: u64,
 }
 
 impl<T> ChDeque<T> {
@@ -16,7 +29,26 @@ impl<T> ChDeque<T> {
     // }
 
     pub fn with_capacity(cap: usize) -> Self {
-        Self { queue: VecDeque::with_capacity(cap), last_seq: 0, cap }
+        Self::with_capacity_and_seq(cap, 0)
+    }
+
+    /// 从 last_seq 之后继续编号，用于从 store 恢复 channel
+    pub fn with_capacity_and_seq(cap: usize, last_seq: u64) -> Self {
+        Self { 
+            queue: VecDeque::with_capacity(cap), 
+            last_seq, 
+            cap,
+            max_age: None,
+            pushed_at: VecDeque::new(),
+            compact: None,
+            lost_seq: last_seq,
+        }
+    }
+
+    /// 消息超过 max_age 就淘汰，在 push 和 `expire` 时检查
+    pub fn with_max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
     }
 
     /// 获取下一个 seq，用户辅助生成seq。当数据 T 本身在生成时就有 seq 则不用调用此函数。
@@ -27,6 +59,40 @@ impl<T> ChDeque<T> {
     pub fn capacity(&self) -> usize {
         self.cap
     }
+
+    pub fn is_compacted(&self) -> bool {
+        self.compact.is_some()
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// 清空队列，seq 不回退
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.pushed_at.clear();
+        if let Some((_key_fn, keys)) = &mut self.compact {
+            keys.clear();
+        }
+        self.lost_seq = self.last_seq;
+    }
+
+    /// 最早的一条是否已经超过 max_age
+    pub fn has_expired(&self, now: Instant) -> bool {
+        match self.max_age {
+            Some(max_age) => self.pushed_at.front().is_some_and(|t| now.saturating_duration_since(*t) >= max_age),
+            None => false,
+        }
+    }
 }
 
 // impl<T> ChDeque<T>
@@ -43,6 +109,16 @@ impl<T> ChDeque<T>
 where
     T: GetSeq + Clone
 {
+    /// 压缩 channel：同一个 key 只保留最新的一条，已有的消息按 key 压缩一遍
+    pub fn set_compaction(&mut self, key_fn: CompactKeyFn<T>) {
+        let mut keys = HashMap::new();
+        for v in self.queue.iter() {
+            keys.insert(key_fn(v), v.get_seq());
+        }
+        self.compact = Some((key_fn, keys));
+        self.retain_latest();
+    }
+
     pub fn push_raw(&mut self, v: T) -> Result<()> { 
         // if let Some(last) = self.queue.back() {
         //     if v.get_seq() <= last.get_seq() {
@@ -54,30 +130,109 @@ where
             bail!("push seq inconsist, expect [{}] but [{}]", self.last_seq+1, v.get_seq())
         }
 
-        while self.queue.len() >= self.cap {
-            self.queue.pop_front();
+        // 没设置 max_age 时不取时间
+        let now = self.max_age.map(|_age| Instant::now());
+        if let Some(now) = now {
+            self.expire(now);
+        }
+
+        if let Some((key_fn, keys)) = &mut self.compact {
+            let old = keys.insert(key_fn(&v), v.get_seq());
+            if let Some(old) = old {
+                // 旧值一定还在队列里，被淘汰时会从 keys 里删掉。
+                // 从中间删是 O(n) 的，压缩 channel 的 capacity 不宜太大
+                let index = self.queue.partition_point(|x| x.get_seq() < old);
+                self.queue.remove(index);
+                if self.max_age.is_some() {
+                    self.pushed_at.remove(index);
+                }
+            }
+        }
+
+        while self.len() >= self.cap {
+            self.pop_front();
         }
 
         self.last_seq = v.get_seq();
         self.queue.push_back(v);
+        if let Some(now) = now {
+            self.pushed_at.push_back(now);
+        }
 
         Ok(())
     }
 
+    /// 淘汰最早的一条，seq 不回退
+    pub fn pop_front(&mut self) -> Option<T> {
+        let v = self.queue.pop_front()?;
+        self.pushed_at.pop_front();
+        self.lost_seq = self.lost_seq.max(v.get_seq());
+        if let Some((key_fn, keys)) = &mut self.compact {
+            let key = key_fn(&v);
+            if keys.get(&key) == Some(&v.get_seq()) {
+                keys.remove(&key);
+            }
+        }
+        Some(v)
+    }
+
+    /// 淘汰超过 max_age 的消息，返回淘汰的条数
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut n = 0;
+        while !self.is_empty() && self.has_expired(now) {
+            self.pop_front();
+            n += 1;
+        }
+        n
+    }
+
+    /// 每个 key 只留最新的一条
+    fn retain_latest(&mut self) {
+        let latest: Vec<bool> = match &self.compact {
+            Some((key_fn, keys)) => self.queue.iter().map(|v| keys.get(&key_fn(v)) == Some(&v.get_seq())).collect(),
+            None => return,
+        };
+        let mut flags = latest.iter();
+        self.queue.retain(|_v| *flags.next().unwrap_or(&true));
+        if self.max_age.is_some() {
+            let mut flags = latest.iter();
+            self.pushed_at.retain(|_t| *flags.next().unwrap_or(&true));
+        }
+    }
+
+    /// 队列里第一个 seq，队列为空时为 next_seq
+    pub fn first_seq(&self) -> u64 {
+        self.queue.front().map(|v|v.get_seq()).unwrap_or_else(||self.next_seq())
+    }
+
     pub fn read_next(&self, seq: u64) -> ReadQueOutput<T> {
         if let Some(first) = self.queue.front() {
             let start_seq = first.get_seq() ;
             if seq < start_seq {
-                ReadQueOutput::Lagged
+                // 压缩 channel 里被压缩掉的消息都有更新的值，没有真的丢
+                if self.compact.is_some() && seq > self.lost_seq {
+                    ReadQueOutput::Value(first.clone())
+                } else {
+                    ReadQueOutput::Lagged
+                }
             } else {
                 let delta = (seq - start_seq).min(usize::MAX as u64);
                 self.reverse_search_from(seq, delta as usize)
             }
+        } else if self.last_seq > 0 && seq <= self.last_seq {
+            // 队列为空但 seq 已经被用过（比如从 store 恢复），数据不在队列里
+            ReadQueOutput::Lagged
         } else {
             ReadQueOutput::Latest
         }
     }
 
+    /// 从 seq 开始最多读取 max 条，seq 已被淘汰时从第一条开始读
+    pub fn read_from(&self, seq: u64, max: usize) -> Vec<T> {
+        let start = self.queue.partition_point(|v|v.get_seq() < seq);
+        self.queue.range(start..).take(max).cloned().collect()
+    }
+
     fn reverse_search_from(&self, seq: u64, index0: usize) -> ReadQueOutput<T> { 
         if let Some(last) = self.queue.back() {
             if seq > last.get_seq() {
@@ -102,3 +257,63 @@ where
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Msg = SeqVal<(u64, &'static str)>;
+
+    fn push(que: &mut ChDeque<Msg>, key: u64, v: &'static str) {
+        let seq = que.next_seq();
+        que.push_raw(SeqVal::new(seq, (key, v))).unwrap();
+    }
+
+    fn seqs(que: &ChDeque<Msg>) -> Vec<u64> {
+        que.read_from(0, usize::MAX).iter().map(|v| v.get_seq()).collect()
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut que = ChDeque::with_capacity(16).with_max_age(Some(Duration::from_millis(20)));
+        for n in 1..=3 {
+            push(&mut que, n, "a");
+        }
+        assert!(!que.has_expired(Instant::now()));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(que.has_expired(Instant::now()));
+        push(&mut que, 4, "a");
+        assert_eq!(seqs(&que), vec![4]);
+        assert_eq!(que.read_next(1), ReadQueOutput::Lagged);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(que.expire(Instant::now()), 1);
+        assert!(que.is_empty());
+    }
+
+    #[test]
+    fn test_compaction() {
+        let mut que = ChDeque::with_capacity(4);
+        que.set_compaction(|v: &Msg| v.1.0);
+        push(&mut que, 1, "a");
+        push(&mut que, 2, "b");
+        push(&mut que, 1, "c");
+        assert_eq!(seqs(&que), vec![2, 3]);
+        // 压缩掉的 seq 1 有更新的值，不算 lagged
+        assert_eq!(que.read_next(1), ReadQueOutput::Value(SeqVal::new(2, (2, "b"))));
+        assert_eq!(que.read_next(3), ReadQueOutput::Value(SeqVal::new(3, (1, "c"))));
+
+        // 按容量淘汰掉 key 2 唯一的值，之前的 seq 都算 lagged
+        for key in 3..=5 {
+            push(&mut que, key, "d");
+        }
+        assert_eq!(seqs(&que), vec![3, 4, 5, 6]);
+        assert_eq!(que.read_next(2), ReadQueOutput::Lagged);
+
+        push(&mut que, 1, "e");
+        assert_eq!(seqs(&que), vec![4, 5, 6, 7]);
+        assert_eq!(que.read_next(3), ReadQueOutput::Value(SeqVal::new(4, (3, "d"))));
+        assert_eq!(que.len(), 4);
+    }
+}
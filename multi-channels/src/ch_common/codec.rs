@@ -0,0 +1,89 @@
+use anyhow::{Result, bail};
+
+use super::SeqVal;
+use super::uid::ChId;
+
+/// 二进制编解码，用于 store 落盘等场景
+///
+/// decode 的输入是 encode 的完整输出，不需要自描述长度
+pub trait Codec: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(data: &[u8]) -> Result<Self>;
+}
+
+impl Codec for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        match data.try_into() {
+            Ok(bytes) => Ok(u64::from_le_bytes(bytes)),
+            Err(_e) => bail!("decode u64 but [{}] bytes", data.len()),
+        }
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        Ok(data.to_vec())
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        Ok(std::str::from_utf8(data)?.to_string())
+    }
+}
+
+impl Codec for ChId {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.to().encode(buf)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        u64::decode(data).map(ChId::new)
+    }
+}
+
+impl<V> Codec for SeqVal<V> 
+where
+    V: Codec,
+{
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 {
+            bail!("decode SeqVal but [{}] bytes", data.len())
+        }
+        let seq = u64::decode(&data[..8])?;
+        let v = V::decode(&data[8..])?;
+        Ok(Self(seq, v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let msg = SeqVal::new(7, "hello".to_string());
+        let mut buf = Vec::new();
+        msg.encode(&mut buf);
+        assert_eq!(SeqVal::<String>::decode(&buf).unwrap(), msg);
+
+        assert!(SeqVal::<u64>::decode(&buf[..4]).is_err());
+    }
+}
@@ -9,3 +9,10 @@ mod ch_queue;
 pub use ch_queue::*;
 
 pub mod uid;
+
+pub mod sync;
+
+pub mod blocking;
+
+mod codec;
+pub use codec::*;
@@ -0,0 +1,73 @@
+//! 自己实现的同步原语（impl01、mpsc_crossbeam_que、mpsc_concurrent_que、mpsc_ring_buf）从这里取 Arc、原子变量、锁和 AtomicWaker，
+//! `--cfg multi_channels_loom` 时换成 loom 的版本，见 `test_loom`
+//!
+//! loom 的锁是 std 的接口，AtomicWaker 的 register 参数也不一样，这里包一层成原来用的接口
+
+#[cfg(not(multi_channels_loom))]
+pub use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+
+#[cfg(not(multi_channels_loom))]
+pub use futures::task::AtomicWaker;
+
+#[cfg(not(multi_channels_loom))]
+pub use parking_lot::{Mutex, RwLock};
+
+#[cfg(multi_channels_loom)]
+pub use loom::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+
+#[cfg(multi_channels_loom)]
+pub use self::loom_shim::{AtomicWaker, Mutex, RwLock};
+
+#[cfg(multi_channels_loom)]
+mod loom_shim {
+    use std::task::Waker;
+
+    pub use loom::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+    #[derive(Debug, Default)]
+    pub struct AtomicWaker(loom::future::AtomicWaker);
+
+    impl AtomicWaker {
+        pub fn new() -> Self {
+            Self(loom::future::AtomicWaker::new())
+        }
+
+        pub fn register(&self, waker: &Waker) {
+            self.0.register_by_ref(waker);
+        }
+
+        pub fn wake(&self) {
+            self.0.wake();
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Mutex<T>(loom::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub fn new(v: T) -> Self {
+            Self(loom::sync::Mutex::new(v))
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap()
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct RwLock<T>(loom::sync::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        pub fn new(v: T) -> Self {
+            Self(loom::sync::RwLock::new(v))
+        }
+
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap()
+        }
+
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap()
+        }
+    }
+}
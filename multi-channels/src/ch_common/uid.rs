@@ -5,7 +5,7 @@ use std::ops::Deref;
 macro_rules! define_immutable_id {
     ($id1:ident) => {
         #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Hash, Default)]
-        // #[derive(Serialize, Deserialize)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $id1(u64);
         
         impl $id1 {
@@ -0,0 +1,212 @@
+use std::{time::{Duration, Instant}, sync::Arc};
+use anyhow::Result;
+use console::Term;
+use crate::{
+    cli_graph::{bars::{BarRow, self}, table::LiveView},
+    ch_common::{SeqVal, uid::ChId},
+    mpsc_ch::mpsc_crossbeam_que::Mpsc,
+};
+use super::{
+    channel1::suber::Suber,
+    hub::{Hub, HubConfig},
+};
+
+type Message = SeqVal<u64>;
+type BenchHub = Hub<ChId, Message, Mpsc>;
+
+/// publish 时 dashboard 的刷新间隔
+const DASHBOARD_INTERVAL: Duration = Duration::from_millis(500);
+const DASHBOARD_TOP: usize = 8;
+
+#[derive(Debug)]
+struct BenchArgs {
+    inbox_cap: usize,
+    ch_num: usize,
+    task_num: usize,
+    msg_num: usize,
+}
+
+/// 比较不同分片数下 channel 表的 subscribe / publish 吞吐，shards=1 即单锁
+pub async fn run() -> Result<()> {
+    let mut bench = Bench {
+        args: BenchArgs{ inbox_cap: 256, ch_num: 300_000, task_num: 64, msg_num: 1 },
+        term: Term::stderr(),
+        bars: Vec::with_capacity(16),
+        is_plot: false,
+    };
+
+    // warm up
+    bench.term.write_line("warming up...")?;
+    bench_round(bench.task_num(64)).await?;
+    bench.term.write_line("warming up done")?;
+    bench.term.write_line("")?;
+
+    bench.is_plot = true;
+
+    bench_round(bench.task_num(1)).await?;
+    bench_round(bench.task_num(8)).await?;
+    bench_round(bench.task_num(64)).await?;
+    bench_round(bench.task_num(512)).await?;
+
+    Ok(())
+}
+
+async fn bench_round(bench: &mut Bench) -> Result<()> {
+    bench.term.write_line(&format!("subscribe round: {:?}", bench.args))?;
+    let mut hubs = Vec::new();
+    for shards in [1, 16, 64, 256] {
+        hubs.push(bench_subscribe(bench, shards).await?);
+    }
+    bench.plot()?;
+
+    bench.term.write_line(&format!("publish round: {:?}", bench.args))?;
+    for hub in hubs {
+        bench_publish(bench, hub).await?;
+    }
+    bench.plot()?;
+
+    Ok(())
+}
+
+/// task_num 个 task 并发订阅 ch_num 个 channel，每个 channel 第一次订阅时创建
+async fn bench_subscribe(bench: &mut Bench, shards: usize) -> Result<Arc<BenchHub>> {
+    let args = &bench.args;
+    let config = HubConfig { shards, ..Default::default() };
+    let hub = Arc::new(BenchHub::with_config(Default::default(), config));
+
+    let kick_time = Instant::now();
+    let mut tasks = Vec::with_capacity(args.task_num);
+    for n in 0..args.task_num {
+        let hub = hub.clone();
+        let chs = task_channels(args, n);
+        let inbox_cap = args.inbox_cap;
+        let h = tokio::spawn(async move {
+            let mut suber = Suber::with_inbox_cap(inbox_cap);
+            for ch_id in chs {
+                hub.subscribe(&ch_id, &mut suber, 1).await?;
+            }
+            Result::<()>::Ok(())
+        });
+        tasks.push(h);
+    }
+    for h in tasks {
+        h.await??;
+    }
+    let elapsed = kick_time.elapsed();
+
+    bench.output(&format!("shards-{}", shards), elapsed.as_millis() as u64)?;
+    Ok(hub)
+}
+
+/// 每条消息都经过 hub 查找 channel 再 push
+async fn bench_publish(bench: &mut Bench, hub: Arc<BenchHub>) -> Result<()> {
+    let args = &bench.args;
+    let shards = hub.config().shards;
+    let dashboard = if bench.is_plot { Dashboard::spawn(hub.clone()) } else { None };
+
+    let kick_time = Instant::now();
+    let mut tasks = Vec::with_capacity(args.task_num);
+    for n in 0..args.task_num {
+        let hub = hub.clone();
+        let chs = task_channels(args, n);
+        let msg_num = args.msg_num;
+        let h = tokio::spawn(async move {
+            for ch_id in chs {
+                for m in 0..msg_num {
+                    hub.puber(&ch_id).await?.push(m as u64).await?;
+                }
+            }
+            Result::<()>::Ok(())
+        });
+        tasks.push(h);
+    }
+    for h in tasks {
+        h.await??;
+    }
+    let elapsed = kick_time.elapsed();
+    if let Some(dashboard) = dashboard {
+        dashboard.stop().await?;
+    }
+
+    bench.output(&format!("shards-{}", shards), elapsed.as_millis() as u64)?;
+    Ok(())
+}
+
+/// publish 期间在终端原地刷新 hub 指标，输出不是终端时不启动
+struct Dashboard {
+    stop_tx: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl Dashboard {
+    fn spawn(hub: Arc<BenchHub>) -> Option<Self> {
+        let mut view = LiveView::stderr();
+        if !view.is_term() {
+            return None
+        }
+
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DASHBOARD_INTERVAL);
+            loop {
+                tokio::select! {
+                    _r = &mut stop_rx => break,
+                    _r = interval.tick() => {
+                        let mut table = hub.metrics().table(DASHBOARD_TOP);
+                        table.width(view.term_width());
+                        view.draw(&table.to_string())?;
+                    },
+                }
+            }
+            view.clear()
+        });
+        Some(Self { stop_tx, task })
+    }
+
+    async fn stop(self) -> Result<()> {
+        let _r = self.stop_tx.send(());
+        self.task.await?
+    }
+}
+
+/// 第 n 个 task 负责的 channel
+fn task_channels(args: &BenchArgs, n: usize) -> Vec<ChId> {
+    (n..args.ch_num).step_by(args.task_num)
+    .map(|id| ChId::new(id as u64 + 1))
+    .collect()
+}
+
+struct Bench {
+    term: Term,
+    bars: Vec<BarRow>,
+    args: BenchArgs,
+    is_plot: bool,
+}
+
+impl Bench {
+    pub fn task_num(&mut self, n: usize) -> &mut Self {
+        self.args.task_num = n;
+        self
+    }
+
+    fn output(&mut self, label: &str, millis: u64) -> Result<()> {
+        self.term.clear_line()?;
+        self.term.write_str(&format!("{},{}", label, millis))?;
+        self.bars.push(BarRow {
+            label: label.into(),
+            count: millis,
+        });
+        Ok(())
+    }
+
+    pub fn plot(&mut self) -> Result<()>{
+        self.term.clear_line()?;
+        if self.is_plot {
+            let display = bars::display_with_width(&self.bars, self.term.size().1 as usize);
+            self.term.write_line(&format!("{}", display))?;
+            self.term.write_line("")?;
+        }
+        self.bars.clear();
+        Ok(())
+    }
+}
@@ -1,26 +1,59 @@
 use std::sync::Arc;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::task::Waker;
+use std::time::Duration;
+
+use futures::task::AtomicWaker;
 
 use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
 use crate::{
     mpsc_ch::{
         mpsc_defs::{MpscOp, SenderOp}}, 
-        ch_common::{ChIdOp, uid::{next_suber_id, SuberId}, 
+        ch_common::{ChIdOp, GetSeq, uid::{next_suber_id, SuberId}, 
         VecMap
     }
 };
 
 use super::super::event::Event;
+use super::super::metrics::SuberMetrics;
+
+/// 某个 suber 的 inbox 满了以后怎么处理新事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BusPolicy {
+    /// 丢掉新事件并打上 lagged 标记，suber 看到标记后从 channel cache 补读
+    #[default]
+    DropNewest,
+
+    /// 丢掉最老的，suber 跳到该 channel 最新的 inbox_cap 条消息
+    DropOldest,
+
+    /// 发布者等 inbox 有空位，超时后按 DropNewest 处理
+    Block(Duration),
+
+    /// 把 suber 从这个 channel 上摘掉，suber 会收到一次 Lagged
+    Disconnect,
+}
 
-#[derive(Clone)]
 pub struct Bus<K, T, M: MpscOp<Event<K, T>>> {
     shared: Arc<Shared<K, T, M>>,
 }
 
+impl<K, T, M> Clone for Bus<K, T, M>
+where
+    M: MpscOp<Event<K, T>>, 
+{
+    fn clone(&self) -> Self {
+        Self { shared: self.shared.clone() }
+    }
+}
+
 impl<K, T, M> Bus<K, T, M>
 where
     K: ChIdOp,
-    T: Clone, // + GetSeq,
+    T: Clone + GetSeq,
     M: MpscOp<Event<K, T>>, 
 {
     pub fn new() -> Self {
@@ -32,31 +65,390 @@ where
         self.shared.subers.lock().len()
     }
 
-    pub fn watch(&self, watcher: &Watcher<K, T, M>) {
-        let key = watcher.id;
-        self.shared.subers.lock().insert(key, watcher.tx.clone());
+    pub fn watch(&self, handle: &WatchHandle<K, T, M>) {
+        let entry = Entry { tx: handle.tx.clone(), state: handle.state.clone() };
+        self.shared.subers.lock().insert(handle.id, entry);
     }
 
     pub fn unwatch(&self, key: &SuberId) -> bool {
         self.shared.subers.lock().remove(key).is_some()
     }
 
-    pub fn broadcast(&self, ev: Event<K, T>) {
+    /// dropped events of each suber, counted across all channels the suber watches
+    pub fn dropped(&self) -> Vec<(SuberId, u64)> {
+        self.shared.subers.lock().iter()
+        .map(|(id, entry)|(*id, entry.state.dropped()))
+        .collect()
+    }
+
+    /// 这个 channel 上每个 suber 的指标，tail_seq 用来算 cursor 落后的距离
+    pub fn metrics(&self, ch_id: &K, tail_seq: u64) -> Vec<SuberMetrics> {
+        self.shared.subers.lock().iter()
+        .map(|(id, entry)| entry.state.metrics(*id, ch_id, tail_seq))
+        .collect()
+    }
+
+    /// 先对所有 suber try_send，满了的按各自的 policy 处理；
+    /// Block 策略的 suber 最后逐个等到 inbox 有空位或者超时。
+    /// 超时从等待开始算，整个 broadcast 共用，多个 suber 满了也只卡一个 timeout
+    pub async fn broadcast(&self, ev: Event<K, T>) {
+        let mut blocked = Vec::new();
+        {
+            let mut subers = self.shared.subers.lock();
+            subers.retain(|_k, entry| {
+                let r = entry.send_or_apply_policy(&ev);
+                entry.state.wake();
+                match r {
+                    SendResult::Done => true,
+                    SendResult::Blocked(timeout) => {
+                        blocked.push((entry.tx.clone(), entry.state.clone(), timeout));
+                        true
+                    },
+                    SendResult::Disconnected => false,
+                }
+            });
+        }
+
+        let start = Instant::now();
+        for (mut tx, state, timeout) in blocked {
+            let deadline = start + timeout;
+            loop {
+                if !state.reserve_until(deadline).await {
+                    state.on_drop_newest();
+                    state.wake();
+                    break;
+                }
+
+                if tx.try_send(ev.clone()).is_ok() {
+                    state.wake();
+                    break;
+                }
+                state.release();
+            }
+        }
+    }
+
+    /// 通知所有 suber channel 已关闭，并清空 bus。end_seq 之前的消息 suber 仍然可以读完
+    pub(super) fn close(&self, ch_id: &K, end_seq: u64) {
+        let mut subers = self.shared.subers.lock();
+        for (_id, entry) in subers.iter_mut() {
+            entry.notify(Event::Closed(ch_id.clone()), WatchAction::Closed(end_seq));
+        }
+        subers.clear();
+    }
+
+    /// 通知所有 suber channel 已重置，之后从 seq 开始读
+    pub(super) fn reset(&self, ch_id: &K, seq: u64) {
         let mut subers = self.shared.subers.lock();
-        for (_k, tx) in subers.iter_mut() { 
-            let _r = tx.try_send(ev.clone());
+        for (_id, entry) in subers.iter_mut() {
+            entry.notify(Event::Reset(ch_id.clone(), seq), WatchAction::Reset(seq));
         }
     }
 }
 
 pub struct Shared<K, T, M: MpscOp<Event<K, T>>> {
-    subers: Mutex<VecMap<SuberId, M::Sender>>,
+    subers: Mutex<VecMap<SuberId, Entry<K, T, M>>>,
+}
+
+struct Entry<K, T, M: MpscOp<Event<K, T>>> {
+    tx: M::Sender,
+    state: Arc<WatchState<K>>,
+}
+
+enum SendResult {
+    /// 发出去了，或者按 policy 丢掉了
+    Done,
+    /// 需要异步等待 inbox 有空位
+    Blocked(Duration),
+    /// 需要从 bus 移除
+    Disconnected,
+}
+
+impl<K, T, M> Entry<K, T, M> 
+where
+    K: ChIdOp,
+    T: Clone + GetSeq,
+    M: MpscOp<Event<K, T>>, 
+{
+    /// 控制事件先记到 WatchState，保证不会因为 inbox 满了丢失；
+    /// 再尽量往 inbox 里发一个事件唤醒 suber
+    fn notify(&mut self, ev: Event<K, T>, action: WatchAction) {
+        self.state.add_action(ev.ch_id(), action);
+        if self.state.try_reserve() && self.tx.try_send(ev).is_err() {
+            self.state.release();
+        }
+        self.state.wake();
+    }
+
+    fn send_or_apply_policy(&mut self, ev: &Event<K, T>) -> SendResult {
+        if self.state.try_reserve() {
+            if self.tx.try_send(ev.clone()).is_ok() {
+                return SendResult::Done;
+            }
+            self.state.release();
+        }
+
+        let msg = match ev {
+            Event::Msg(msg) => msg,
+            // 控制事件已经记在 WatchState 里，inbox 满了 suber 也会很快醒来处理
+            _ => return SendResult::Done,
+        };
+        match self.state.policy {
+            BusPolicy::DropNewest => self.state.on_drop_newest(),
+            BusPolicy::DropOldest => self.state.on_drop_oldest(&msg.ch_id, msg.msg.get_seq()),
+            BusPolicy::Block(timeout) => return SendResult::Blocked(timeout),
+            BusPolicy::Disconnect => {
+                self.state.on_disconnect(&msg.ch_id);
+                return SendResult::Disconnected;
+            },
+        }
+        SendResult::Done
+    }
+}
+
+/// 处理 inbox 满了以后 suber 要做的事
+pub(super) enum WatchAction {
+    /// cursor 至少跳到这个 seq
+    SkipTo(u64),
+    /// 已经从 channel 上摘掉
+    Disconnected,
+    /// channel 已关闭，读到这个 seq 之前为止
+    Closed(u64),
+    /// channel 已重置，cursor 移到这个 seq
+    Reset(u64),
+}
+
+impl WatchAction {
+    /// 同一个 channel 上的多个 action 合并成一个，摘掉/关闭优先
+    fn merge(self, new: WatchAction) -> WatchAction {
+        use WatchAction::*;
+        match (self, new) {
+            (Closed(a), _) | (_, Closed(a)) => Closed(a),
+            (Disconnected, _) | (_, Disconnected) => Disconnected,
+            (Reset(a), Reset(b)) | (Reset(a), SkipTo(b)) | (SkipTo(b), Reset(a)) => Reset(a.max(b)),
+            (SkipTo(a), SkipTo(b)) => SkipTo(a.max(b)),
+        }
+    }
+}
+
+/// suber 在某个 channel 上的统计。只有 suber 自己写，收消息时不用加锁；
+/// WatchState 里共享一份给 `Hub::metrics` 读快照
+#[derive(Debug, Default)]
+pub(super) struct ChStat {
+    /// 下一个要读的 seq
+    next: AtomicU64,
+    delivered: AtomicU64,
+    lagged: AtomicU64,
+}
+
+impl ChStat {
+    fn with_next(seq: u64) -> Self {
+        Self { next: AtomicU64::new(seq), ..Default::default() }
+    }
+
+    /// suber 输出了一条消息。单写者，load + store 就够了
+    pub(super) fn on_delivered(&self, seq: u64) {
+        self.next.store(seq + 1, Ordering::Relaxed);
+        self.delivered.store(self.delivered.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+
+    pub(super) fn on_lagged(&self) {
+        self.lagged.store(self.lagged.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+}
+
+/// bus 和 suber 共享的状态。inbox 里的事件数由这里计数，
+/// 这样各个 MpscOp 实现在满的时候行为一致
+pub(super) struct WatchState<K> {
+    policy: BusPolicy,
+    cap: usize,
+    queued: AtomicUsize,
+    high_water: AtomicUsize,
+    /// 按 channel 的统计，给 `Hub::metrics` 用
+    stats: Mutex<VecMap<K, Arc<ChStat>>>,
+    overflowed: AtomicBool,
+    dropped: AtomicU64,
+    has_actions: AtomicBool,
+    actions: Mutex<VecMap<K, WatchAction>>,
+    /// 等 inbox 空位的发布者个数，没人等的时候 release 不碰 `space`
+    blocked: AtomicUsize,
+    /// inbox 腾出空位时通知 Block 策略的发布者
+    space: Notify,
+    /// poll 方式收消息的 suber 在这里等，有事件或 action 时唤醒
+    waker: AtomicWaker,
+}
+
+impl<K> WatchState<K> 
+where
+    K: ChIdOp,
+{
+    fn new(cap: usize, policy: BusPolicy) -> Self {
+        Self {
+            policy,
+            cap,
+            queued: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            stats: Mutex::new(VecMap::new()),
+            overflowed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            has_actions: AtomicBool::new(false),
+            actions: Mutex::new(VecMap::new()),
+            blocked: AtomicUsize::new(0),
+            space: Notify::new(),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    pub(super) fn register(&self, waker: &Waker) {
+        self.waker.register(waker);
+    }
+
+    fn wake(&self) {
+        self.waker.wake();
+    }
+
+    pub(super) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn try_reserve(&self) -> bool {
+        let r = self.queued.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            if n < self.cap { Some(n + 1) } else { None }
+        });
+        match r {
+            Ok(n) => {
+                self.high_water.fetch_max(n + 1, Ordering::Relaxed);
+                true
+            },
+            Err(_n) => false,
+        }
+    }
+
+    fn release(&self) {
+        let _r = self.queued.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        self.notify_space();
+    }
+
+    fn notify_space(&self) {
+        // 和 reserve_until 里 blocked 加一之后的 fence 配对，保证不会漏掉通知
+        fence(Ordering::SeqCst);
+        if self.blocked.load(Ordering::Relaxed) > 0 {
+            self.space.notify_waiters();
+        }
+    }
+
+    /// 等到 inbox 有空位并占住一个，到 deadline 还没有就返回 false
+    async fn reserve_until(&self, deadline: Instant) -> bool {
+        let _blocked = BlockedGuard::new(&self.blocked);
+        loop {
+            // 先拿 notified 再检查，检查之后的 notify_waiters 不会丢
+            let space = self.space.notified();
+            fence(Ordering::SeqCst);
+            if self.try_reserve() {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, space).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    fn on_drop_newest(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.overflowed.store(true, Ordering::Release);
+    }
+
+    fn on_drop_oldest(&self, ch_id: &K, seq: u64) {
+        // 保留包括 seq 在内最新的 cap 条
+        let skip_to = (seq + 1).saturating_sub(self.cap as u64);
+        self.add_action(ch_id, WatchAction::SkipTo(skip_to));
+        self.on_drop_newest();
+    }
+
+    fn on_disconnect(&self, ch_id: &K) {
+        self.add_action(ch_id, WatchAction::Disconnected);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_action(&self, ch_id: &K, action: WatchAction) {
+        {
+            let mut actions = self.actions.lock();
+            let action = match actions.swap_remove(ch_id) {
+                Some(old) => old.merge(action),
+                None => action,
+            };
+            actions.insert(ch_id.clone(), action);
+        }
+        self.has_actions.store(true, Ordering::Release);
+    }
+
+    /// suber 从 inbox 取到一个事件
+    pub(super) fn on_recved(&self) {
+        self.release();
+    }
+
+    /// suber 清空了 inbox
+    pub(super) fn on_cleared(&self) {
+        self.queued.store(0, Ordering::Release);
+        self.notify_space();
+    }
+
+    /// 返回的统计由 suber 自己更新
+    pub(super) fn on_subscribed(&self, ch_id: &K, seq: u64) -> Arc<ChStat> {
+        let stat = Arc::new(ChStat::with_next(seq));
+        self.stats.lock().insert(ch_id.clone(), stat.clone());
+        stat
+    }
+
+    pub(super) fn on_unsubscribed(&self, ch_id: &K) {
+        self.stats.lock().swap_remove(ch_id);
+    }
+
+    fn metrics(&self, id: SuberId, ch_id: &K, tail_seq: u64) -> SuberMetrics {
+        let stat = self.stats.lock().get(ch_id).cloned().unwrap_or_default();
+        SuberMetrics {
+            id,
+            delivered: stat.delivered.load(Ordering::Relaxed),
+            lagged: stat.lagged.load(Ordering::Relaxed),
+            distance: tail_seq.saturating_sub(stat.next.load(Ordering::Relaxed)),
+            dropped: self.dropped(),
+            inbox_len: self.queued.load(Ordering::Acquire),
+            inbox_high_water: self.high_water.load(Ordering::Relaxed),
+            inbox_cap: self.cap,
+        }
+    }
+
+    pub(super) fn take_overflowed(&self) -> bool {
+        self.overflowed.swap(false, Ordering::AcqRel)
+    }
+
+    pub(super) fn take_actions(&self) -> Option<VecMap<K, WatchAction>> {
+        if !self.has_actions.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        Some(std::mem::take(&mut *self.actions.lock()))
+    }
+}
+
+/// 发布者在 `reserve_until` 里等待期间计数，future 被 drop 也能减回去
+struct BlockedGuard<'a>(&'a AtomicUsize);
+
+impl<'a> BlockedGuard<'a> {
+    fn new(blocked: &'a AtomicUsize) -> Self {
+        blocked.fetch_add(1, Ordering::Relaxed);
+        Self(blocked)
+    }
+}
+
+impl<'a> Drop for BlockedGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 // pub type Sender<EV> = LSender<EV>;
 // pub type Receiver<EV> = LReceiver<EV>;
 
-#[derive(Clone)]
 pub struct Watcher<K, T, M> 
 where
     K: ChIdOp,
@@ -66,6 +458,7 @@ where
     tx: M::Sender, // LSender<Event<K, T>>, // Sender<Mail<K, T>>,
     rx: M::Receiver,
     id: SuberId,
+    state: Arc<WatchState<K>>,
 }
 
 impl<K, T, M> Watcher<K, T, M> 
@@ -74,12 +467,13 @@ where
     T: Clone, // + GetSeq,
     M: MpscOp<Event<K, T>>, 
 {
-    pub fn new(cap: usize) -> Self { 
+    pub fn with_policy(cap: usize, policy: BusPolicy) -> Self { 
         let (tx, rx) = M::channel(cap);
         Self {
             tx,
             rx,
             id: next_suber_id(),
+            state: Arc::new(WatchState::new(cap, policy)),
         }
     }
 
@@ -87,9 +481,34 @@ where
         &self.id
     }
 
+    pub fn policy(&self) -> BusPolicy {
+        self.state.policy
+    }
+
     pub(super) fn rx(&mut self) -> &mut M::Receiver {
         &mut self.rx
     }
+
+    pub(super) fn state(&self) -> &WatchState<K> {
+        &self.state
+    }
+
+    /// 不含 rx，可以交给别人用来把这个 suber 挂到 bus 上
+    pub(crate) fn handle(&self) -> WatchHandle<K, T, M> {
+        WatchHandle { id: self.id, tx: self.tx.clone(), state: self.state.clone() }
+    }
+}
+
+pub(crate) struct WatchHandle<K, T, M: MpscOp<Event<K, T>>> {
+    id: SuberId,
+    tx: M::Sender,
+    state: Arc<WatchState<K>>,
+}
+
+impl<K, T, M: MpscOp<Event<K, T>>> WatchHandle<K, T, M> {
+    pub(crate) fn id(&self) -> &SuberId {
+        &self.id
+    }
 }
 
 // // watcher
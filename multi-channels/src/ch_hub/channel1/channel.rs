@@ -9,16 +9,22 @@
 /// 
 
 use std::sync::Arc;
-use anyhow::Result;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{Result, bail};
 // use async_broadcast::{broadcast, Receiver, Sender, TryRecvError};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::Notify;
 use crate::ch_common::uid::SuberId;
-use crate::ch_common::{SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque};
+use crate::ch_common::{SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque, CompactKeyFn, VecMap};
 
 use crate::mpsc_ch::mpsc_defs::MpscOp;
 
-use super::bus::Bus;
+use super::bus::{Bus, WatchHandle};
+use super::group::GroupShared;
 use super::super::event::Event;
+use super::super::metrics::ChannelMetrics;
+use super::super::store::ColdLoad;
 use super::suber::Suber;
 
 pub fn impl_name() -> &'static str {
@@ -28,6 +34,25 @@ pub fn impl_name() -> &'static str {
 /// Seq Channel
 pub type SChannel<K, T, M> = Channel<K, SeqVal<T>, M>;
 
+/// 创建 channel 时的参数
+#[derive(Debug, Clone)]
+pub struct ChannelOptions {
+    /// channel cache 最多保留的消息数
+    pub capacity: usize,
+
+    /// channel cache 里的消息超过这个时间就淘汰，None 表示只按 capacity 淘汰
+    pub max_age: Option<Duration>,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 16,
+            max_age: None,
+        }
+    }
+}
+
 ///
 /// Generic types:
 /// - K is Channel Id
@@ -53,6 +78,38 @@ where
     }
 }
 
+impl<K, T, M> Channel<K, T, M> 
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    /// 有多少个 handle，包括 hub 自己、puber、suber 的 cursor 等
+    pub(crate) fn handles(&self) -> usize {
+        Arc::strong_count(&self.shared)
+    }
+
+    pub(crate) fn touch(&self) {
+        *self.shared.last_active.lock() = Instant::now();
+    }
+
+    pub(crate) fn idle_time(&self) -> Duration {
+        self.shared.last_active.lock().elapsed()
+    }
+
+    pub(crate) fn pushed(&self) -> &Notify {
+        &self.shared.pushed
+    }
+
+    /// 不存在时创建，从 seq 开始消费
+    pub(crate) fn group(&self, name: &str, seq: u64) -> Arc<GroupShared<T>> {
+        self.shared.groups.lock()
+        .entry(name.to_string())
+        .or_insert_with(||Arc::new(GroupShared::new(name, seq)))
+        .clone()
+    }
+}
+
 impl<K, T, M> Channel<K, T, M> 
 where
     K: ChIdOp,
@@ -60,13 +117,31 @@ where
     M: MpscOp<Event<K, T>>,
 {
     pub fn with_capacity(ch_id: K, cap: usize) -> Self {
+        Self::with_capacity_and_seq(ch_id, cap, 0)
+    }
+
+    /// seq 从 last_seq 之后继续，用于从 store 恢复 channel
+    pub fn with_capacity_and_seq(ch_id: K, cap: usize, last_seq: u64) -> Self {
+        let options = ChannelOptions { capacity: cap, ..Default::default() };
+        Self::with_options(ch_id, &options, last_seq, None)
+    }
+
+    /// cold 用来给落后于 cache 的 suber 补数据
+    pub(crate) fn with_options(ch_id: K, options: &ChannelOptions, last_seq: u64, cold: Option<Arc<dyn ColdLoad<K, T>>>) -> Self {
         Self {
             shared: Arc::new(ChShared { 
                 // queue: RwLock::new(ChDeque::new()),
                 // subers: Default::default(),
-                cache: ChCache::with_capacity(cap),
+                cache: ChCache::with_options(options, last_seq),
                 bus: Bus::new(),
                 // capacity: cap,
+                write_lock: tokio::sync::Mutex::new(()),
+                cold,
+                closed: AtomicBool::new(false),
+                published: AtomicU64::new(0),
+                last_active: Mutex::new(Instant::now()),
+                pushed: Notify::new(),
+                groups: Mutex::new(VecMap::new()),
                 ch_id,
             }
         )}
@@ -80,6 +155,15 @@ where
         self.shared.cache.queue.read().capacity()
     }
 
+    pub fn is_compacted(&self) -> bool {
+        self.shared.cache.queue.read().is_compacted()
+    }
+
+    /// cache 里同一个 key 只保留最新的一条，新订阅的 suber 从 first_seq 读起拿到的就是当前状态
+    pub(crate) fn set_compaction(&self, key_fn: CompactKeyFn<T>) {
+        self.shared.cache.set_compaction(key_fn);
+    }
+
     // pub fn puber(&self) -> Puber<K, T, M> {
     //     Puber { ch_shared: self.shared.clone() }
     // }
@@ -94,18 +178,89 @@ where
         self.shared.cache.tail_seq()
     }
 
+    /// first seq still in cache, equal to tail_seq if cache is empty
+    pub fn first_seq(&self) -> u64 {
+        self.shared.cache.expire();
+        self.shared.cache.queue.read().first_seq()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// 关闭后不能再 push，所有 suber 收到 `RecvOutput::Closed` 并自动退订
+    pub(crate) async fn close(&self) {
+        let _guard = self.lock_write().await;
+        if !self.shared.closed.swap(true, Ordering::AcqRel) {
+            self.shared.bus.close(self.ch_id(), self.tail_seq());
+            self.shared.pushed.notify_waiters();
+        }
+    }
+
+    /// 清空 cache，所有 suber 收到 `RecvOutput::Reset` 后从下一条新消息开始读
+    pub(crate) async fn reset(&self) -> Result<()> {
+        let _guard = self.lock_write().await;
+        self.check_open()?;
+        let seq = self.shared.cache.clear();
+        self.shared.bus.reset(self.ch_id(), seq);
+        Ok(())
+    }
+
+    pub(crate) fn check_open(&self) -> Result<()> {
+        if self.is_closed() {
+            bail!("channel [{:?}] closed", self.ch_id())
+        }
+        Ok(())
+    }
+
+
+    pub(crate) fn cold(&self) -> Option<&Arc<dyn ColdLoad<K, T>>> {
+        self.shared.cold.as_ref()
+    }
+
+    /// 串行化需要先写 store 再进 cache 的 push
+    pub(crate) async fn lock_write(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.shared.write_lock.lock().await
+    }
+
     pub(super) fn insert_suber(&self, suber: &Suber<K, T, M>) {
-        self.shared.bus.watch(suber.watcher());
+        self.watch(&suber.watcher().handle());
+    }
+
+    /// 还没有 cursor 时先挂到 bus 上，用于唤醒 suber
+    pub(crate) fn watch(&self, handle: &WatchHandle<K, T, M>) {
+        self.shared.bus.watch(handle);
         // let mut subers = self.shared.subers.lock();
         // let key = *suber.id();
         // subers.insert(key, suber.tx());
     }
 
-    pub(super) fn remove_suber(&self, key: &SuberId) {
+    pub(crate) fn remove_suber(&self, key: &SuberId) {
         self.shared.bus.unwatch(key);
+        self.touch();
         // self.shared.subers.lock().remove(key);
     }
 
+    /// dropped events of each suber watching this channel
+    pub fn dropped(&self) -> Vec<(SuberId, u64)> {
+        self.shared.bus.dropped()
+    }
+
+    pub fn published(&self) -> u64 {
+        self.shared.published.load(Ordering::Relaxed)
+    }
+
+    pub fn metrics(&self) -> ChannelMetrics<K> {
+        let tail_seq = self.tail_seq();
+        ChannelMetrics {
+            ch_id: self.ch_id().clone(),
+            published: self.published(),
+            first_seq: self.first_seq(),
+            tail_seq,
+            subers: self.shared.bus.metrics(self.ch_id(), tail_seq),
+        }
+    }
+
 }
 
 impl<K, T, M> Channel<K, T, M> 
@@ -114,14 +269,19 @@ where
     T: Clone + GetSeq + WithSeq,
     M: MpscOp<Event<K, T>>,
 {
-    pub(super) fn push_raw(&self, v: T) -> Result<()> {
+    /// 进 cache 后广播，`BusPolicy::Block` 的 suber 满了会在这里等
+    pub(crate) async fn push_raw(&self, v: T) -> Result<()> {
+        self.check_open()?;
         self.shared.cache.push_raw(v.clone())?;
-        self.broadcast_to_subers(v);
+        self.shared.published.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+        self.broadcast_to_subers(v).await;
+        self.shared.pushed.notify_waiters();
         Ok(())
     }
 
-    fn broadcast_to_subers(&self, v: T) {
-        self.shared.bus.broadcast(Event::msg(self.shared.ch_id.clone(), v.clone()));
+    async fn broadcast_to_subers(&self, v: T) {
+        self.shared.bus.broadcast(Event::msg(self.shared.ch_id.clone(), v)).await;
         // let ch_id = &self.shared.ch_id;
         // let mut subers = self.shared.subers.lock();
         // for (_k, tx) in subers.deref_mut() { 
@@ -136,16 +296,21 @@ where
     T: Clone + GetSeq + WithSeq,
     M: MpscOp<Event<K, T>>,
 {
-    pub(crate) fn push(&self, v: T::Value) -> Result<()> {
+    pub(crate) async fn push(&self, v: T::Value) -> Result<()> {
         // let v = {
         //     let mut queue = self.shared.queue.write();
         //     let v = T::with_seq(queue.next_seq(), v);   
         //     queue.push_raw(v.clone(), self.shared.capacity)?;
         //     v
         // };
+        // 在写锁里检查，close 之后不会再有 push 进来
+        let _guard = self.lock_write().await;
+        self.check_open()?;
         let v = self.shared.cache.push(v)?;
+        self.shared.published.fetch_add(1, Ordering::Relaxed);
 
-        self.broadcast_to_subers(v);
+        self.broadcast_to_subers(v).await;
+        self.shared.pushed.notify_waiters();
 
         Ok(())
     }
@@ -159,6 +324,7 @@ where
 {
     type Output = ReadQueOutput<T>;
     fn read_next(&self, seq: u64) -> Self::Output {
+        self.shared.cache.expire();
         self.shared.cache.queue.read().read_next(seq)
     }
 }
@@ -178,6 +344,16 @@ where
     
     bus: Bus<K, T, M>,
     cache: ChCache<T>,
+    write_lock: tokio::sync::Mutex<()>,
+    cold: Option<Arc<dyn ColdLoad<K, T>>>,
+    closed: AtomicBool,
+    /// push 进来的消息数
+    published: AtomicU64,
+    /// 最后一次 push 或者有 suber 退订的时间，用于淘汰空闲 channel
+    last_active: Mutex<Instant>,
+    /// 每次 push 和关闭时通知，消费组成员靠它等新消息
+    pushed: Notify,
+    groups: Mutex<VecMap<String, Arc<GroupShared<T>>>>,
 }
 
 // pub struct Cursor<K, T, M> 
@@ -288,22 +464,41 @@ impl<T> ChCache<T> {
     //     }
     // }
 
-    pub fn with_capacity(cap: usize) -> Self {
+    pub fn with_options(options: &ChannelOptions, last_seq: u64) -> Self {
         Self{
-            queue: RwLock::new(ChDeque::with_capacity(cap)),
+            queue: RwLock::new(ChDeque::with_capacity_and_seq(options.capacity, last_seq).with_max_age(options.max_age)),
         }
     }
 
     pub fn tail_seq(&self) -> u64 {
         self.queue.read().next_seq()
     }
+
+    /// 清空 cache，返回下一条消息的 seq
+    fn clear(&self) -> u64 {
+        let mut queue = self.queue.write();
+        queue.clear();
+        queue.next_seq()
+    }
 }
 
 impl<T> ChCache<T> 
 where
     T: Clone + GetSeq,
 {
-    pub(super) fn push_raw(&self, v: T) -> Result<()> { 
+    /// 淘汰超过 max_age 的消息，没有过期的只加读锁
+    fn expire(&self) {
+        let now = Instant::now();
+        if self.queue.read().has_expired(now) {
+            self.queue.write().expire(now);
+        }
+    }
+
+    fn set_compaction(&self, key_fn: CompactKeyFn<T>) {
+        self.queue.write().set_compaction(key_fn);
+    }
+
+    pub(crate) fn push_raw(&self, v: T) -> Result<()> { 
         self.queue.write().push_raw(v)
     }
 }
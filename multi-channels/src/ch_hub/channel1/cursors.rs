@@ -75,6 +75,20 @@ where
     //     (self.active_cursors.into_iter(), self.pending_cursors.into_iter())
     // }
 
+    pub fn get(&self, key: &K) -> Option<&Cursor<R>> {
+        match self.in_syncs.get(key) {
+            Some(cursor) => Some(cursor),
+            None => self.out_of_syncs.get(key),
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut Cursor<R>> {
+        match self.in_syncs.get_mut(key) {
+            Some(cursor) => Some(cursor),
+            None => self.out_of_syncs.get_mut(key),
+        }
+    }
+
     pub fn in_syncs_iter(&self) -> impl Iterator<Item = (&K, &Cursor<R>)> {
         self.in_syncs.iter()
     }
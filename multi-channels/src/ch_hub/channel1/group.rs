@@ -0,0 +1,369 @@
+//! 消费组：同一个组里每条消息只交给一个成员。
+//!
+//! - 成员主动拉取，谁空闲谁拿下一条，成员加入、离开时自然重新分摊
+//! - 交出去但还没 commit 的消息记在组里，成员离开时放回去重新投递给其他成员
+//! - 组的 committed seq 是连续 commit 到的最大 seq
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::ch_common::{ChIdOp, GetSeq, ReadQueOutput, RecvOutput};
+use crate::mpsc_ch::mpsc_defs::MpscOp;
+
+use super::super::event::Event;
+use super::channel::{Channel, ReadNext};
+
+pub type MemberId = u64;
+
+pub(crate) struct GroupShared<T> {
+    name: String,
+    state: Mutex<GroupState<T>>,
+    /// 有消息放回待重投时唤醒成员
+    requeued: Notify,
+    next_member_id: AtomicU64,
+}
+
+struct GroupState<T> {
+    /// 下一条还没交出去的 seq
+    next_seq: u64,
+    /// 已经交出去还没 commit 的
+    in_flight: BTreeMap<u64, (MemberId, T)>,
+    /// 离开的成员没 commit 的，优先重投
+    requeue: BTreeMap<u64, T>,
+    members: usize,
+}
+
+impl<T> GroupShared<T> {
+    pub(crate) fn new(name: &str, seq: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            state: Mutex::new(GroupState {
+                next_seq: seq,
+                in_flight: BTreeMap::new(),
+                requeue: BTreeMap::new(),
+                members: 0,
+            }),
+            requeued: Notify::new(),
+            next_member_id: AtomicU64::new(1),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn join(&self) -> MemberId {
+        self.state.lock().members += 1;
+        self.next_member_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn leave(&self, member: MemberId) {
+        let moved = {
+            let mut state = self.state.lock();
+            state.members -= 1;
+
+            let seqs: Vec<u64> = state.in_flight.iter()
+            .filter(|(_seq, (id, _v))| *id == member)
+            .map(|(seq, _v)| *seq)
+            .collect();
+
+            for seq in seqs.iter() {
+                if let Some((_id, v)) = state.in_flight.remove(seq) {
+                    state.requeue.insert(*seq, v);
+                }
+            }
+            !seqs.is_empty()
+        };
+
+        if moved {
+            self.requeued.notify_waiters();
+        }
+    }
+
+    /// 所有 <= committed 的消息都已经 commit
+    pub(crate) fn committed(&self) -> u64 {
+        let state = self.state.lock();
+        let first_in_flight = state.in_flight.keys().next();
+        let first_requeue = state.requeue.keys().next();
+        let first = match (first_in_flight, first_requeue) {
+            (Some(a), Some(b)) => *a.min(b),
+            (Some(a), None) => *a,
+            (None, Some(b)) => *b,
+            (None, None) => state.next_seq,
+        };
+        first.saturating_sub(1)
+    }
+
+    pub(crate) fn members(&self) -> usize {
+        self.state.lock().members
+    }
+}
+
+/// 消费组的一个成员，drop 时离开消费组
+pub struct GroupMember<K, T, M>
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    ch: Channel<K, T, M>,
+    group: Arc<GroupShared<T>>,
+    id: MemberId,
+}
+
+impl<K, T, M> GroupMember<K, T, M>
+where
+    K: ChIdOp,
+    T: Clone + GetSeq,
+    M: MpscOp<Event<K, T>>,
+{
+    pub(crate) fn join(ch: Channel<K, T, M>, group: Arc<GroupShared<T>>) -> Self {
+        let id = group.join();
+        Self { ch, group, id }
+    }
+
+    pub fn id(&self) -> MemberId {
+        self.id
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.name()
+    }
+
+    pub fn ch_id(&self) -> &K {
+        self.ch.ch_id()
+    }
+
+    /// 组里所有 <= 这个 seq 的消息都已经 commit
+    pub fn committed(&self) -> u64 {
+        self.group.committed()
+    }
+
+    pub fn members(&self) -> usize {
+        self.group.members()
+    }
+
+    /// 确认已经处理完 seq，不是自己拿到的消息返回 false
+    pub fn commit(&self, seq: u64) -> bool {
+        let mut state = self.group.state.lock();
+        match state.in_flight.get(&seq) {
+            Some((id, _v)) if *id == self.id => {
+                state.in_flight.remove(&seq);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// 先拿待重投的，再从 channel 里拿下一条。
+    /// 落后于 channel cache 时由其中一个成员收到 Lagged，然后从 cache 里最早的消息继续
+    pub fn try_recv(&self) -> RecvOutput<K, T> {
+        let mut state = self.group.state.lock();
+
+        if let Some((seq, v)) = state.requeue.pop_first() {
+            state.in_flight.insert(seq, (self.id, v.clone()));
+            return RecvOutput::Value(self.ch.ch_id().clone(), v);
+        }
+
+        match self.ch.read_next(state.next_seq) {
+            ReadQueOutput::Value(v) => {
+                let seq = v.get_seq();
+                state.next_seq = seq + 1;
+                state.in_flight.insert(seq, (self.id, v.clone()));
+                RecvOutput::Value(self.ch.ch_id().clone(), v)
+            },
+            ReadQueOutput::Lagged => {
+                state.next_seq = state.next_seq.max(self.ch.first_seq());
+                RecvOutput::Lagged(self.ch.ch_id().clone())
+            },
+            ReadQueOutput::Latest => {
+                if self.ch.is_closed() {
+                    RecvOutput::Closed(self.ch.ch_id().clone())
+                } else {
+                    RecvOutput::None
+                }
+            },
+        }
+    }
+
+    /// 没有消息时等 channel 有新消息或者有消息放回待重投
+    pub async fn recv_next(&self) -> RecvOutput<K, T> {
+        loop {
+            // 先注册再检查，避免错过通知
+            let pushed = self.ch.pushed().notified();
+            let requeued = self.group.requeued.notified();
+
+            let r = self.try_recv();
+            if !r.is_none() {
+                return r;
+            }
+
+            tokio::select! {
+                _r = pushed => {},
+                _r = requeued => {},
+            }
+        }
+    }
+}
+
+impl<K, T, M> Drop for GroupMember<K, T, M>
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    fn drop(&mut self) {
+        self.group.leave(self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{ch_common::{SeqVal, uid::ChId}, mpsc_ch::mpsc_crossbeam_que::Mpsc};
+    use super::super::channel::SChannel;
+    use super::*;
+
+    type TestChannel = SChannel<ChId, u64, Mpsc>;
+    type TestMember = GroupMember<ChId, SeqVal<u64>, Mpsc>;
+
+    fn value_seq(r: RecvOutput<ChId, SeqVal<u64>>) -> u64 {
+        match r {
+            RecvOutput::Value(_ch_id, v) => v.get_seq(),
+            r => panic!("expect value but {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_each_message_once() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let group = ch.group("g1", 1);
+        let m1 = TestMember::join(ch.clone(), group.clone());
+        let m2 = TestMember::join(ch.clone(), group.clone());
+        assert_eq!(m1.members(), 2);
+
+        for n in 1..=10 {
+            ch.push(n).await.unwrap();
+        }
+
+        let mut seqs1 = Vec::new();
+        let mut seqs2 = Vec::new();
+        for _ in 0..5 {
+            seqs1.push(value_seq(m1.try_recv()));
+            seqs2.push(value_seq(m2.try_recv()));
+        }
+        assert_eq!(m1.try_recv(), RecvOutput::None);
+        assert_eq!(m2.try_recv(), RecvOutput::None);
+
+        let mut all: Vec<_> = seqs1.iter().chain(seqs2.iter()).cloned().collect();
+        all.sort();
+        assert_eq!(all, (1..=10).collect::<Vec<_>>());
+
+        // 另一个组独立消费
+        let other = TestMember::join(ch.clone(), ch.group("g2", 1));
+        assert_eq!(value_seq(other.try_recv()), 1);
+    }
+
+    #[tokio::test]
+    async fn test_commit() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let group = ch.group("g1", 1);
+        let m1 = TestMember::join(ch.clone(), group.clone());
+        let m2 = TestMember::join(ch.clone(), group.clone());
+        for n in 1..=3 {
+            ch.push(n).await.unwrap();
+        }
+
+        assert_eq!(value_seq(m1.try_recv()), 1);
+        assert_eq!(value_seq(m2.try_recv()), 2);
+        assert_eq!(value_seq(m1.try_recv()), 3);
+        assert_eq!(m1.committed(), 0);
+
+        assert!(!m1.commit(2));
+        assert!(m2.commit(2));
+        assert_eq!(m1.committed(), 0);
+
+        assert!(m1.commit(1));
+        assert_eq!(m1.committed(), 2);
+        assert!(m1.commit(3));
+        assert_eq!(m2.committed(), 3);
+        assert!(!m1.commit(3));
+    }
+
+    #[tokio::test]
+    async fn test_redeliver_when_member_leaves() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let group = ch.group("g1", 1);
+        let m1 = TestMember::join(ch.clone(), group.clone());
+        let m2 = TestMember::join(ch.clone(), group.clone());
+        for n in 1..=4 {
+            ch.push(n).await.unwrap();
+        }
+
+        assert_eq!(value_seq(m1.try_recv()), 1);
+        assert_eq!(value_seq(m2.try_recv()), 2);
+        assert_eq!(value_seq(m2.try_recv()), 3);
+        assert!(m2.commit(2));
+        drop(m2);
+        assert_eq!(m1.members(), 1);
+        assert_eq!(m1.committed(), 0);
+
+        // 没 commit 的 3 先重投，然后才是 4
+        assert_eq!(value_seq(m1.try_recv()), 3);
+        assert_eq!(value_seq(m1.try_recv()), 4);
+        assert_eq!(m1.try_recv(), RecvOutput::None);
+
+        assert!(m1.commit(1));
+        assert_eq!(m1.committed(), 2);
+        assert!(m1.commit(3));
+        assert!(m1.commit(4));
+        assert_eq!(m1.committed(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_recv_next_wakeup() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let group = ch.group("g1", 1);
+        let m1 = TestMember::join(ch.clone(), group.clone());
+        let m2 = TestMember::join(ch.clone(), group.clone());
+
+        let task = tokio::spawn({
+            let ch = ch.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ch.push(1).await.unwrap();
+            }
+        });
+        assert_eq!(value_seq(m1.recv_next().await), 1);
+        task.await.unwrap();
+
+        // m1 离开后 m2 被唤醒拿到重投的消息
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(m1);
+        });
+        assert_eq!(value_seq(m2.recv_next().await), 1);
+        task.await.unwrap();
+
+        ch.close().await;
+        assert_eq!(m2.recv_next().await, RecvOutput::Closed(ChId::new(1)));
+    }
+
+    #[tokio::test]
+    async fn test_lagged() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 4);
+        let m1 = TestMember::join(ch.clone(), ch.group("g1", 1));
+        for n in 1..=10 {
+            ch.push(n).await.unwrap();
+        }
+        assert_eq!(m1.try_recv(), RecvOutput::Lagged(ChId::new(1)));
+        for n in 7..=10 {
+            assert_eq!(value_seq(m1.try_recv()), n);
+        }
+    }
+}
@@ -7,4 +7,8 @@ pub mod channel;
 
 pub mod cursors;
 
+pub mod group;
+
+pub mod stream;
+
 mod test;
@@ -0,0 +1,147 @@
+//! `Suber` 的 `futures::Stream` 实现和组合工具：
+//! - `merge` 把多个 suber 合成一个 stream，谁先有消息先输出谁
+//! - `filter_channels` 只保留指定 channel 的输出
+//!
+//! 批量接收见 `Suber::recv_many`
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Stream, StreamExt, future::ready, stream::SelectAll};
+
+use crate::ch_common::{ChIdOp, GetSeq, RecvOutput, VecSet};
+use crate::mpsc_ch::mpsc_defs::MpscOp;
+
+use super::super::event::Event;
+use super::suber::Suber;
+
+/// 和 `Suber::recv_next` 一样不会结束，也不会输出 `RecvOutput::None`
+impl<K, T, M> Stream for Suber<K, T, M>
+where
+    K: ChIdOp + Send + Sync + 'static,
+    T: Clone + GetSeq + Send + 'static,
+    M: MpscOp<Event<K, T>>,
+    Self: Unpin,
+{
+    type Item = RecvOutput<K, T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_recv(cx).map(Some)
+    }
+}
+
+pub fn merge<K, T, M, I>(subers: I) -> SelectAll<Suber<K, T, M>>
+where
+    K: ChIdOp + Send + Sync + 'static,
+    T: Clone + GetSeq + Send + 'static,
+    M: MpscOp<Event<K, T>>,
+    Suber<K, T, M>: Unpin,
+    I: IntoIterator<Item = Suber<K, T, M>>,
+{
+    futures::stream::select_all(subers)
+}
+
+pub fn filter_channels<K, T, S>(stream: S, ch_ids: &[K]) -> impl Stream<Item = RecvOutput<K, T>>
+where
+    K: ChIdOp,
+    S: Stream<Item = RecvOutput<K, T>>,
+{
+    let ch_ids: VecSet<K> = ch_ids.iter().cloned().collect();
+    stream.filter(move |r| ready(r.ch_id().is_some_and(|ch_id| ch_ids.contains(ch_id))))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{ch_common::{SeqVal, uid::ChId}, mpsc_ch::mpsc_crossbeam_que::Mpsc};
+    use super::super::channel::SChannel;
+    use super::*;
+
+    type TestChannel = SChannel<ChId, u64, Mpsc>;
+    type TestSuber = Suber<ChId, SeqVal<u64>, Mpsc>;
+
+    fn value(ch_id: u64, n: u64) -> RecvOutput<ChId, SeqVal<u64>> {
+        RecvOutput::Value(ChId::new(ch_id), SeqVal::new(n, n))
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let mut suber = TestSuber::with_inbox_cap(16);
+        suber.subscribe(&ch, 1).unwrap();
+
+        ch.push(1).await.unwrap();
+        assert_eq!(suber.next().await, Some(value(1, 1)));
+
+        // 等待中被 bus 唤醒
+        let task = tokio::spawn({
+            let ch = ch.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ch.push(2).await.unwrap();
+            }
+        });
+        assert_eq!(suber.next().await, Some(value(1, 2)));
+        task.await.unwrap();
+
+        // inbox 溢出后从 channel cache 补读
+        for n in 3..=40 {
+            ch.push(n).await.unwrap();
+        }
+        let outputs: Vec<_> = (&mut suber).take(38).collect().await;
+        assert_eq!(outputs, (3..=40).map(|n| value(1, n)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_filter() {
+        let ch1 = TestChannel::with_capacity(ChId::new(1), 64);
+        let ch2 = TestChannel::with_capacity(ChId::new(2), 64);
+        let ch3 = TestChannel::with_capacity(ChId::new(3), 64);
+
+        let mut suber1 = TestSuber::with_inbox_cap(16);
+        suber1.subscribe(&ch1, 1).unwrap();
+        let mut suber2 = TestSuber::with_inbox_cap(16);
+        suber2.subscribe(&ch2, 1).unwrap();
+        suber2.subscribe(&ch3, 1).unwrap();
+
+        let mut merged = merge([suber1, suber2]);
+        ch1.push(1).await.unwrap();
+        ch2.push(1).await.unwrap();
+        let mut outputs = vec![merged.next().await.unwrap(), merged.next().await.unwrap()];
+        outputs.sort_by_key(|r| r.ch_id().cloned());
+        assert_eq!(outputs, vec![value(1, 1), value(2, 1)]);
+
+        let mut filtered = Box::pin(filter_channels(merged, &[ChId::new(3)]));
+        ch1.push(2).await.unwrap();
+        ch2.push(2).await.unwrap();
+        ch3.push(1).await.unwrap();
+        assert_eq!(filtered.next().await, Some(value(3, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_recv_many() {
+        let ch = TestChannel::with_capacity(ChId::new(1), 64);
+        let mut suber = TestSuber::with_inbox_cap(16);
+        suber.subscribe(&ch, 1).unwrap();
+        assert!(suber.recv_many(0).await.is_empty());
+
+        for n in 1..=10 {
+            ch.push(n).await.unwrap();
+        }
+        let batch = suber.recv_many(4).await;
+        assert_eq!(batch, (1..=4).map(|n| value(1, n)).collect::<Vec<_>>());
+        let batch = suber.recv_many(100).await;
+        assert_eq!(batch, (5..=10).map(|n| value(1, n)).collect::<Vec<_>>());
+
+        let task = tokio::spawn({
+            let ch = ch.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ch.push(11).await.unwrap();
+            }
+        });
+        assert_eq!(suber.recv_many(100).await, vec![value(1, 11)]);
+        task.await.unwrap();
+    }
+}
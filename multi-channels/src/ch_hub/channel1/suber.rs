@@ -9,18 +9,25 @@
 /// 
 
 
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 use anyhow::{Result, bail};
+use futures::future::BoxFuture;
 // use async_broadcast::{broadcast, Receiver, Sender, TryRecvError};
 use parking_lot::{Mutex, RwLock};
 use crate::ch_common::uid::SuberId;
-use crate::ch_common::{RecvOutput, GetSeq, ChIdOp, ChDeque, VecMap};
+use crate::ch_common::{RecvOutput, GetSeq, ChIdOp, ChDeque, VecMap, blocking};
 
 use crate::mpsc_ch::mpsc_defs::error::TryRecvError;
 use crate::mpsc_ch::mpsc_defs::{MpscOp, AsyncRecvOp, TryRecvOp, ReceiverOp};
 
-use super::bus::Watcher;
+use super::bus::{BusPolicy, ChStat, Watcher, WatchAction};
 use super::channel::{Channel, Cursor};
 use super::super::event::{Event, Msg};
+use super::super::pattern::ChPattern;
+use super::super::store::{AckStore, ColdLoad};
 use super::cursors::Cursors;
 
 // pub struct Cursors<K, R> 
@@ -216,6 +223,30 @@ use super::cursors::Cursors;
 
 
 
+/// 按 pattern 订阅时，hub 把之后新建的匹配 channel 和起始 seq 放进来，suber 收消息时再挂上 cursor
+pub(crate) type PendingChannels<K, T, M> = Arc<Mutex<Vec<(Channel<K, T, M>, u64)>>>;
+
+struct PatternSub<K, T, M> 
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    pending: PendingChannels<K, T, M>,
+    /// 通过这个 pattern 订阅的 channel
+    channels: Vec<K>,
+}
+
+/// 从 store 加载的 (ch_id, seq, batch)
+type ColdLoading<K, T> = BoxFuture<'static, (K, u64, Result<Option<Vec<T>>>)>;
+
+/// 持久订阅的 ack 位置，ack 时保存到 store
+struct Durable<K, T> {
+    name: String,
+    acked: u64,
+    store: Arc<dyn AckStore<K, T>>,
+}
+
 pub struct Suber<K, T, M> 
 where
     K: ChIdOp,
@@ -224,6 +255,18 @@ where
 {
     cursors: Cursors<K, Channel<K, T, M>>,
     watcher: Watcher<K, T, M>,
+    /// 落后时从 store 加载的消息，先于 cursors 输出
+    history: VecDeque<(K, T)>,
+    /// bus 断开、channel 关闭或重置时的通知，先于消息输出
+    notices: VecDeque<RecvOutput<K, T>>,
+    /// 已关闭的 channel，读到这个 seq 之前再输出 Closed
+    closing: VecMap<K, u64>,
+    patterns: VecMap<ChPattern, PatternSub<K, T, M>>,
+    durables: VecMap<K, Durable<K, T>>,
+    /// `poll_recv` 正在从 store 加载的
+    catching: Option<ColdLoading<K, T>>,
+    /// 每个 channel 的统计，和 WatchState 共享，收消息时不加锁
+    stats: VecMap<K, Arc<ChStat>>,
 }
 
 impl<K, T, M> Suber<K, T, M> 
@@ -233,14 +276,35 @@ where
     M: MpscOp<Event<K, T>>,
 {
     pub fn with_inbox_cap(cap: usize) -> Self {
+        Self::with_policy(cap, BusPolicy::default())
+    }
+
+    /// inbox 满了以后按 policy 处理，见 [`BusPolicy`]
+    pub fn with_policy(cap: usize, policy: BusPolicy) -> Self {
         // let (tx, rx) = M::channel(cap);
 
         Self { 
             cursors: Cursors::new(),
-            watcher: Watcher::new(cap),
+            watcher: Watcher::with_policy(cap, policy),
+            history: VecDeque::new(),
+            notices: VecDeque::new(),
+            closing: VecMap::new(),
+            patterns: VecMap::new(),
+            durables: VecMap::new(),
+            catching: None,
+            stats: VecMap::new(),
         }
     }
 
+    pub fn policy(&self) -> BusPolicy {
+        self.watcher.policy()
+    }
+
+    /// events dropped by bus because the inbox was full
+    pub fn dropped(&self) -> u64 {
+        self.watcher.state().dropped()
+    }
+
     pub fn id(&self) -> &SuberId {
         &self.watcher.id()
     }
@@ -257,6 +321,8 @@ where
 
         
         ch.insert_suber(self);
+        let stat = self.watcher.state().on_subscribed(ch.ch_id(), seq);
+        self.stats.insert(ch.ch_id().clone(), stat);
         let cursor = Cursor { seq, ch: ch.clone() };
         // cursor.ch.insert_suber(self);
         // self.pending_cursors.insert(cursor.ch.ch_id().clone(), cursor);
@@ -265,7 +331,48 @@ where
         Ok(())
     }
 
+    /// 从 acked 之后开始收
+    pub(crate) fn subscribe_durable(&mut self, ch: &Channel<K, T, M>, name: &str, acked: u64, store: Arc<dyn AckStore<K, T>>) -> Result<()> {
+        self.subscribe(ch, acked + 1)?;
+        self.durables.insert(ch.ch_id().clone(), Durable { name: name.to_string(), acked, store });
+        Ok(())
+    }
+
+    /// 确认 <= seq 的消息都已经处理完，持久订阅重新订阅后从 seq 之后继续。
+    /// 比已经 ack 的小时忽略，还没收到的不能 ack
+    pub async fn ack(&mut self, ch_id: &K, seq: u64) -> Result<()> {
+        let next = self.position(ch_id).unwrap_or_default();
+        let durable = match self.durables.get_mut(ch_id) {
+            Some(durable) => durable,
+            None => bail!("not a durable subscription [{:?}]", ch_id),
+        };
+        if seq <= durable.acked {
+            return Ok(());
+        }
+        if seq >= next {
+            bail!("ack unreceived message, channel [{:?}], seq [{}], next [{}]", ch_id, seq, next)
+        }
+        durable.store.save_cursor_boxed(ch_id, &durable.name, seq).await?;
+        durable.acked = seq;
+        Ok(())
+    }
+
+    /// last acked seq of a durable subscription
+    pub fn acked(&self, ch_id: &K) -> Option<u64> {
+        self.durables.get(ch_id).map(|d| d.acked)
+    }
+
     pub fn unsubscribe(&mut self, ch_id: &K) -> Option<Channel<K, T, M>> { 
+        // 先处理掉旧的标记，免得作用到之后重新订阅的 cursor 上
+        self.apply_watch_state();
+        self.durables.swap_remove(ch_id);
+        self.history.retain(|(id, _v)| id != ch_id);
+        self.notices.retain(|r| !notice_of(r, ch_id));
+        self.closing.swap_remove(ch_id);
+        for (_pattern, sub) in self.patterns.iter_mut() {
+            sub.channels.retain(|id| id != ch_id);
+        }
+        self.remove_stat(ch_id);
         let r = self.cursors.remove(ch_id);
         if let Some(cursor) = r {
             cursor.ch.remove_suber(self.id());
@@ -285,10 +392,75 @@ where
         self.cursors.len()
     }
 
-    pub(super) fn watcher(&self) -> &Watcher<K, T, M>{
+    /// next seq to recv of the channel
+    pub fn position(&self, ch_id: &K) -> Option<u64> {
+        let cursor = self.cursors.get(ch_id)?;
+        let pending = self.history.iter().find(|(id, _v)| id == ch_id);
+        Some(pending.map(|(_id, v)|v.get_seq()).unwrap_or(cursor.seq))
+    }
+
+    pub(crate) fn watcher(&self) -> &Watcher<K, T, M>{
         &self.watcher
     }
 
+    pub fn patterns(&self) -> impl Iterator<Item = &ChPattern> {
+        self.patterns.keys()
+    }
+
+    pub(crate) fn add_pattern(&mut self, pattern: &ChPattern) -> Result<PendingChannels<K, T, M>> {
+        if self.patterns.contains_key(pattern) {
+            bail!("already subscribed pattern [{:?}]", pattern)
+        }
+        let pending: PendingChannels<K, T, M> = Default::default();
+        self.patterns.insert(pattern.clone(), PatternSub { pending: pending.clone(), channels: Vec::new() });
+        Ok(pending)
+    }
+
+    /// 已经订阅了的 channel 保持原样
+    pub(crate) fn attach(&mut self, pattern: &ChPattern, ch: &Channel<K, T, M>, seq: u64) -> Result<()> {
+        if self.exist_channel(ch.ch_id()) || ch.is_closed() {
+            return Ok(());
+        }
+
+        self.subscribe(ch, seq)?;
+        if let Some(sub) = self.patterns.get_mut(pattern) {
+            sub.channels.push(ch.ch_id().clone());
+        }
+        Ok(())
+    }
+
+    /// 退订 pattern 以及通过它订阅的所有 channel
+    pub(crate) fn remove_pattern(&mut self, pattern: &ChPattern) -> bool {
+        let sub = match self.patterns.swap_remove(pattern) {
+            Some(sub) => sub,
+            None => return false,
+        };
+
+        for (ch, _seq) in sub.pending.lock().drain(..) {
+            if !self.exist_channel(ch.ch_id()) {
+                ch.remove_suber(self.id());
+            }
+        }
+        for ch_id in sub.channels {
+            self.unsubscribe(&ch_id);
+        }
+        true
+    }
+
+    /// 给 hub 新建的匹配 channel 建 cursor
+    fn attach_pending(&mut self) {
+        let mut attaches = Vec::new();
+        for (pattern, sub) in self.patterns.iter() {
+            for (ch, seq) in sub.pending.lock().drain(..) {
+                attaches.push((pattern.clone(), ch, seq));
+            }
+        }
+
+        for (pattern, ch, seq) in attaches {
+            let _r = self.attach(&pattern, &ch, seq);
+        }
+    }
+
     fn exist_channel(&self, key: &K) -> bool {
         self.cursors.exist_channel(key)
         // let r = self.active_cursors.get(key);
@@ -314,6 +486,13 @@ where
 // }
 
 
+fn notice_of<K: ChIdOp, T>(r: &RecvOutput<K, T>, ch_id: &K) -> bool {
+    match r {
+        RecvOutput::Lagged(id) | RecvOutput::Closed(id) | RecvOutput::Reset(id) => id == ch_id,
+        _ => false,
+    }
+}
+
 impl<K, T, M> Drop for Suber<K, T, M> 
 where
     K: ChIdOp,
@@ -321,6 +500,14 @@ where
     M: MpscOp<Event<K, T>>,
 {
     fn drop(&mut self) { 
+        for (_pattern, sub) in self.patterns.iter() {
+            for (ch, _seq) in sub.pending.lock().iter() {
+                if !self.exist_channel(ch.ch_id()) {
+                    ch.remove_suber(self.id());
+                }
+            }
+        }
+
         for (_id, cursor) in self.cursors.in_syncs_iter() {
             cursor.ch.remove_suber(self.id());
         }
@@ -343,6 +530,44 @@ where
     }
 
     pub fn try_recv(&mut self) -> RecvOutput<K, T> { 
+        let r = self.try_recv_next();
+        self.on_output(r)
+    }
+
+    /// 返回给调用方的结果都要经过这里，更新统计和关闭状态
+    fn on_output(&mut self, r: RecvOutput<K, T>) -> RecvOutput<K, T> {
+        match &r {
+            RecvOutput::Value(ch_id, v) => {
+                if let Some(stat) = self.stats.get(ch_id) {
+                    stat.on_delivered(v.get_seq());
+                }
+                if !self.closing.is_empty() {
+                    self.check_closing(ch_id, v.get_seq() + 1);
+                }
+            },
+            RecvOutput::Lagged(ch_id) => {
+                if let Some(stat) = self.stats.get(ch_id) {
+                    stat.on_lagged();
+                }
+            },
+            _ => {},
+        }
+        r
+    }
+
+    fn try_recv_next(&mut self) -> RecvOutput<K, T> { 
+        if !self.patterns.is_empty() {
+            self.attach_pending();
+        }
+        self.apply_watch_state();
+
+        if let Some(r) = self.notices.pop_front() {
+            return r;
+        }
+
+        if let Some((ch_id, v)) = self.history.pop_front() {
+            return RecvOutput::Value(ch_id, v);
+        }
 
         loop { 
             let r = self.read_in_sync();
@@ -363,9 +588,17 @@ where
     }
 
 
+    /// 落后于 channel cache 时先从 store 补数据，store 也已淘汰时才返回 Lagged
     pub async fn recv_next(&mut self) -> RecvOutput<K, T> { 
         loop {
             let r = self.try_recv();
+            if let RecvOutput::Lagged(ch_id) = &r {
+                if self.catch_up(ch_id).await {
+                    continue;
+                }
+                self.skip_lagged(ch_id);
+            }
+
             if !r.is_none() {
                 return r;
             }
@@ -373,9 +606,10 @@ where
             let r = self.watcher.rx().async_recv().await;
             match r {
                 Ok(mail) => {
+                    self.watcher.state().on_recved();
                     let r = self.process_recved(mail);
                     if !r.is_none() {
-                        return r;
+                        return self.on_output(r);
                     }
                 },
                 Err(_e) => {
@@ -386,11 +620,141 @@ where
         }
     }
 
+    /// `recv_next` 的阻塞版本，给普通线程用，不需要 tokio runtime
+    pub fn recv_blocking(&mut self) -> RecvOutput<K, T> {
+        blocking::block_on(self.recv_next())
+    }
+
+    /// 超时返回 RecvOutput::None；超时时正在从 store 补的数据会丢掉，下次重新加载
+    pub fn recv_timeout(&mut self, timeout: Duration) -> RecvOutput<K, T> {
+        blocking::block_on_timeout(self.recv_next(), timeout).unwrap_or(RecvOutput::None)
+    }
+
+    /// 先等到一条，再把已经到了的最多 max 条一起取出来，减少唤醒次数
+    pub async fn recv_many(&mut self, max: usize) -> Vec<RecvOutput<K, T>> {
+        let mut batch = Vec::with_capacity(max.min(64));
+        if max == 0 {
+            return batch;
+        }
+
+        batch.push(self.recv_next().await);
+        while batch.len() < max {
+            let r = self.try_recv();
+            if let RecvOutput::Lagged(ch_id) = &r {
+                if self.catch_up(ch_id).await {
+                    continue;
+                }
+                self.skip_lagged(ch_id);
+            }
+
+            if r.is_none() {
+                break;
+            }
+            batch.push(r);
+        }
+        batch
+    }
+
+    /// `recv_next` 的 poll 版本，`Stream` 用它实现。
+    /// 从 store 加载的 future 存在 self 里，下次 poll 继续
+    pub(super) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<RecvOutput<K, T>> 
+    where
+        K: Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        let mut registered = false;
+        loop {
+            if let Some(fut) = self.catching.as_mut() {
+                let (ch_id, seq, batch) = futures::ready!(fut.as_mut().poll(cx));
+                self.catching = None;
+                if !self.on_cold_loaded(&ch_id, seq, batch) {
+                    self.skip_lagged(&ch_id);
+                    return Poll::Ready(RecvOutput::Lagged(ch_id));
+                }
+                continue;
+            }
+
+            let r = self.try_recv();
+            if let RecvOutput::Lagged(ch_id) = &r {
+                if let Some((cold, seq)) = self.cold_of(ch_id) {
+                    let ch_id = ch_id.clone();
+                    self.catching = Some(Box::pin(async move {
+                        let batch = cold.load_cold_boxed(&ch_id, seq).await;
+                        (ch_id, seq, batch)
+                    }));
+                    continue;
+                }
+                self.skip_lagged(ch_id);
+            }
+
+            if !r.is_none() {
+                return Poll::Ready(r);
+            }
+
+            // 先注册再检查一次，避免错过注册前到达的事件
+            if registered {
+                return Poll::Pending;
+            }
+            self.watcher.state().register(cx.waker());
+            registered = true;
+        }
+    }
+
+    /// 从 store 加载一批到 history，cursor 移到这批之后。
+    /// 返回 false 表示补不上，比如没有 store 或者 store 已淘汰这段数据
+    async fn catch_up(&mut self, ch_id: &K) -> bool {
+        let (cold, seq) = match self.cold_of(ch_id) {
+            Some(r) => r,
+            None => return false,
+        };
+        let batch = cold.load_cold_boxed(ch_id, seq).await;
+        self.on_cold_loaded(ch_id, seq, batch)
+    }
+
+    fn cold_of(&self, ch_id: &K) -> Option<(Arc<dyn ColdLoad<K, T>>, u64)> {
+        let cursor = self.cursors.get(ch_id)?;
+        Some((cursor.ch.cold()?.clone(), cursor.seq))
+    }
+
+    fn on_cold_loaded(&mut self, ch_id: &K, seq: u64, batch: Result<Option<Vec<T>>>) -> bool {
+        let mut batch = match batch {
+            Ok(Some(batch)) => batch,
+            _ => return false,
+        };
+
+        let (first, last) = match (batch.first(), batch.last()) {
+            (Some(first), Some(last)) => (first.get_seq(), last.get_seq()),
+            _ => return false,
+        };
+
+        if let Some(cursor) = self.cursors.get_mut(ch_id) {
+            if last < cursor.seq {
+                return false;
+            }
+            // 加载期间 cursor 可能已经前进
+            let next = cursor.seq;
+            batch.retain(|v| v.get_seq() >= next);
+            // 跳过 store 已淘汰的部分，后面照常返回 Lagged
+            cursor.seq = last + 1;
+        }
+        self.history.extend(batch.into_iter().map(|v|(ch_id.clone(), v)));
+
+        first <= seq
+    }
+
+    /// 补不上的部分直接跳过，下次从 cache 里最早的消息开始，避免一直返回 Lagged
+    fn skip_lagged(&mut self, ch_id: &K) {
+        if let Some(cursor) = self.cursors.get_mut(ch_id) {
+            cursor.seq = cursor.seq.max(cursor.ch.first_seq());
+        }
+    }
+
     fn try_recv_in_sync(&mut self) -> RecvOutput<K, T> { 
         loop {
             let r = self.watcher.rx().try_recv();
             match r {
                 Ok(mail) => {
+                    self.watcher.state().on_recved();
                     let r = self.process_recved(mail);
                     if !r.is_none() {
                         return r;
@@ -414,6 +778,11 @@ where
     fn process_recved(&mut self, event: Event<K, T>) -> RecvOutput<K, T>{ 
         match event {
             Event::Msg(msg) => self.process_msg(msg),
+            // 只是唤醒，具体内容在 WatchState 里
+            Event::Closed(_) | Event::Reset(..) => {
+                self.apply_watch_state();
+                self.notices.pop_front().unwrap_or(RecvOutput::None)
+            },
         }
     }
 
@@ -450,10 +819,76 @@ where
         if is_first_sync { 
             // 第一个 sync cursor，清理掉收件箱，避免反复 overflowed
             self.watcher.rx().clear();
+            self.watcher.state().on_cleared();
         }
         r
     }
 
+    fn remove_stat(&mut self, ch_id: &K) {
+        self.stats.swap_remove(ch_id);
+        self.watcher.state().on_unsubscribed(ch_id);
+    }
+
+    /// 关闭的 channel 读完之后退订并输出 Closed
+    fn check_closing(&mut self, ch_id: &K, next: u64) {
+        match self.closing.get(ch_id) {
+            Some(end_seq) if next >= *end_seq => {},
+            _ => return,
+        }
+
+        self.closing.swap_remove(ch_id);
+        self.remove_stat(ch_id);
+        if self.cursors.remove(ch_id).is_some() {
+            self.history.retain(|(id, _v)| id != ch_id);
+            self.notices.retain(|r| !notice_of(r, ch_id));
+            self.notices.push_back(RecvOutput::Closed(ch_id.clone()));
+        }
+    }
+
+    /// 处理 bus 因为 inbox 满了留下的标记
+    fn apply_watch_state(&mut self) {
+        let state = self.watcher.state();
+        if state.take_overflowed() {
+            self.cursors.move_all_insyncs_to_outofsyncs();
+        }
+
+        let actions = match state.take_actions() {
+            Some(actions) => actions,
+            None => return,
+        };
+
+        for (ch_id, action) in actions {
+            match action {
+                WatchAction::SkipTo(seq) => {
+                    if let Some(cursor) = self.cursors.get_mut(&ch_id) {
+                        cursor.seq = cursor.seq.max(seq);
+                    }
+                },
+                WatchAction::Disconnected => {
+                    // bus 里已经移除了，这里只清理本地状态
+                    self.remove_stat(&ch_id);
+                    if self.cursors.remove(&ch_id).is_some() {
+                        self.history.retain(|(id, _v)| *id != ch_id);
+                        self.notices.push_back(RecvOutput::Lagged(ch_id));
+                    }
+                },
+                WatchAction::Closed(end_seq) => {
+                    if let Some(next) = self.position(&ch_id) {
+                        self.closing.insert(ch_id.clone(), end_seq);
+                        self.check_closing(&ch_id, next);
+                    }
+                },
+                WatchAction::Reset(seq) => {
+                    if let Some(cursor) = self.cursors.get_mut(&ch_id) {
+                        cursor.seq = seq;
+                        self.history.retain(|(id, _v)| *id != ch_id);
+                        self.notices.push_back(RecvOutput::Reset(ch_id));
+                    }
+                },
+            }
+        }
+    }
+
     // fn is_pending_empty(&self) -> bool {
     //     self.pending_cursors.len() == 0 
     // }
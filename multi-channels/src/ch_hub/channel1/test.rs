@@ -1,9 +1,11 @@
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
     use anyhow::{Result, Context, bail};
 
     use super::super::channel::SChannel;
     use super::super::suber::Suber;
+    use super::super::bus::BusPolicy;
     use super::super::super::event::Event;
 
 
@@ -20,6 +22,7 @@ mod test {
             mpsc_crossbeam_que,
             mpsc_concurrent_que,
             mpsc_kanal, mpsc_flume, 
+            mpsc_ring_buf,
         },
     };
 
@@ -67,6 +70,56 @@ mod test {
         test_num::<mpsc_flume::Mpsc>().await
     }
 
+    #[tokio::test]
+    async fn test_mpsc_ring_buf9() -> Result<()> {
+        test_num::<mpsc_ring_buf::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_async_broadcast1() -> Result<()> {
+        test_policies::<mpsc_async_broadcast::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_async_channel2() -> Result<()> {
+        test_policies::<mpsc_async_channel::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_tokio_mpsc3() -> Result<()> {
+        test_policies::<mpsc_tokio_mpsc::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_tokio_broadcast4() -> Result<()> {
+        test_policies::<mpsc_tokio_broadcast::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_crossbeam_que5() -> Result<()> {
+        test_policies::<mpsc_crossbeam_que::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_kanal6() -> Result<()> {
+        test_policies::<mpsc_kanal::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_concurrent_que7() -> Result<()> {
+        test_policies::<mpsc_concurrent_que::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_flume8() -> Result<()> {
+        test_policies::<mpsc_flume::Mpsc>().await
+    }
+
+    #[tokio::test]
+    async fn test_policy_ring_buf9() -> Result<()> {
+        test_policies::<mpsc_ring_buf::Mpsc>().await
+    }
+
     type DefaultM = mpsc_crossbeam_que::Mpsc;
 
     #[tokio::test]
@@ -78,13 +131,13 @@ mod test {
         let r = tester.subscribe_all_and_check();
         assert_eq!(r.is_ok(), true, "r={:?}", r);
 
-        let r = tester.channels[0].push(10);
+        let r = tester.channels[0].push(10).await;
         assert_eq!(r.is_ok(), true, "r={:?}", r);
 
         let r = tester.suber.try_recv();
         assert_eq!(r, RecvOutput::Value(ChId::new(0), SeqVal(1, 10)), "r={:?}", r);
 
-        let r = tester.channels[0].push(11);
+        let r = tester.channels[0].push(11).await;
         assert_eq!(r.is_ok(), true, "r={:?}", r);
 
         let r = tester.suber.try_recv();
@@ -94,6 +147,71 @@ mod test {
 
     }
 
+    /// close 返回之后 push 都失败，tail_seq 不再变
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_push_race_close() -> Result<()> {
+        type M = DefaultM;
+
+        for _round in 0..20 {
+            let ch = SChannel::<ChId, TestVal, M>::with_capacity(ChId::new(1), 1024);
+            let pushers: Vec<_> = (0..4).map(|_n| {
+                let ch = ch.clone();
+                tokio::spawn(async move {
+                    let mut pushed = 0;
+                    while ch.push(1).await.is_ok() {
+                        pushed += 1;
+                        tokio::task::yield_now().await;
+                    }
+                    pushed
+                })
+            }).collect();
+
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            ch.close().await;
+            let tail_seq = ch.tail_seq();
+
+            let mut pushed = 0;
+            for task in pushers {
+                pushed += task.await?;
+            }
+            assert_eq!(ch.tail_seq(), tail_seq);
+            assert_eq!(ch.published(), pushed);
+        }
+        Ok(())
+    }
+
+    /// suber 在普通线程上阻塞收，push 在 tokio task 里
+    #[tokio::test]
+    async fn test_recv_blocking() -> Result<()> {
+        type M = DefaultM;
+        let msg_num = 50;
+
+        let ch = SChannel::<ChId, TestVal, M>::with_capacity(ChId::new(1), 64);
+        let mut suber = TestSuber::<M>::with_inbox_cap(64);
+        suber.subscribe(&ch, 1)?;
+        assert_eq!(suber.recv_timeout(Duration::from_millis(10)), RecvOutput::None);
+
+        let recv_thread = std::thread::spawn(move || {
+            let outputs: Vec<_> = (0..msg_num).map(|_n| suber.recv_blocking()).collect();
+            (suber, outputs)
+        });
+
+        let puber = ch.clone();
+        tokio::spawn(async move {
+            for n in 0..msg_num {
+                puber.push(n).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        }).await?;
+
+        let (mut suber, outputs) = recv_thread.join().map_err(|_e| anyhow::anyhow!("recv thread panicked"))?;
+        let expect: Vec<_> = (0..msg_num).map(|n| RecvOutput::Value(ChId::new(1), SeqVal(n as u64 + 1, n))).collect();
+        assert_eq!(outputs, expect);
+        assert_eq!(suber.recv_timeout(Duration::from_millis(10)), RecvOutput::None);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_mpsc_try_recv2() -> Result<()> { 
         type M = DefaultM;
@@ -144,8 +262,8 @@ mod test {
 
 
             let val_range = 1..tester.ch_cap + 1;
-            let r = tester.push_all(val_range.clone());
-            // let r = tester.push_all(1..tester.ch_cap + 1);
+            let r = tester.push_all(val_range.clone()).await;
+            // let r = tester.push_all(1..tester.ch_cap + 1).await;
             assert!(r.is_ok(), "r={:?}", r);
     
             let num = val_range.len() * tester.ch_ids.len();
@@ -216,8 +334,8 @@ mod test {
         assert_eq!(ch1.subers(), 1);
         assert_eq!(ch2.subers(), 1);
         
-        puber1.push(1)?;
-        puber2.push(2)?;
+        puber1.push(1).await?;
+        puber2.push(2).await?;
     
         let mut expect = vec![
             RecvOutput::Value(ch_id1, SeqVal(1, 1)),
@@ -239,8 +357,8 @@ mod test {
         assert_eq!(ch1.subers(), 0);
         assert_eq!(ch2.subers(), 1);
     
-        puber1.push(3)?;
-        puber2.push(4)?;
+        puber1.push(3).await?;
+        puber2.push(4).await?;
     
         assert_eq!(suber.try_recv(), RecvOutput::Value(ch_id2, SeqVal(2, 4)),);
         assert_eq!(suber.try_recv(), RecvOutput::None);
@@ -301,7 +419,7 @@ mod test {
         for _ in 0..10 { 
             tester.clear_values();
             
-            let r = tester.push_all(1..tester.ch_cap + 1);
+            let r = tester.push_all(1..tester.ch_cap + 1).await;
             assert!(r.is_ok(), "r={:?}", r);
     
             let num = tester.ch_cap * tester.ch_ids.len();
@@ -317,7 +435,7 @@ mod test {
 
         // test all lagged
         {
-            let r = tester.push_all(1..tester.ch_cap + 2);
+            let r = tester.push_all(1..tester.ch_cap + 2).await;
             assert!(r.is_ok(), "r={:?}", r);  
 
             let num = tester.channels.len();
@@ -336,11 +454,11 @@ mod test {
             tester.clear_values();
 
             // index 0: lagged 
-            let r = tester.push_ch(0, 1..tester.ch_cap + 2);
+            let r = tester.push_ch(0, 1..tester.ch_cap + 2).await;
             assert!(r.is_ok(), "r={:?}", r);  
 
             // index 1: value
-            let r = tester.push_ch(1, 1..tester.ch_cap + 1);
+            let r = tester.push_ch(1, 1..tester.ch_cap + 1).await;
             assert!(r.is_ok(), "r={:?}", r);  
 
             let mut laggeds = vec![0];
@@ -451,24 +569,24 @@ mod test {
             }
         }
 
-        fn push_ch<I>(&mut self, index: usize, iter: I) -> Result<()> 
+        async fn push_ch<I>(&mut self, index: usize, iter: I) -> Result<()> 
         where
             I: Iterator<Item = TestVal>,
         {
             for n in iter { 
-                self.pubers[index].push(n).with_context(||format!("push fail, n={}, index={}", n, index))?;
+                self.pubers[index].push(n).await.with_context(||format!("push fail, n={}, index={}", n, index))?;
             }
             Ok(())
         }
 
-        fn push_all<I>(&mut self, iter: I) -> Result<()> 
+        async fn push_all<I>(&mut self, iter: I) -> Result<()> 
         where
             I: Iterator<Item = TestVal>,
         {
             // for n in 0..self.ch_cap ;
             for n in iter { 
                 for puber in &mut self.pubers {
-                    puber.push(n)
+                    puber.push(n).await
                     .with_context(||format!("push fail, n={}", n))?;
                 }
             }
@@ -494,6 +612,9 @@ mod test {
             match output {
                 RecvOutput::Value(_ch_id, _v) => self.check_value(&output),
                 RecvOutput::Lagged(_) => self.check_lagged_and_unsub(laggeds, &output),
+                RecvOutput::Closed(_) | RecvOutput::Reset(_) => {
+                    bail!("check_output but unexpected {:?}", output)
+                },
                 RecvOutput::None => {
                     bail!("check_output but none")
                 },
@@ -541,6 +662,170 @@ mod test {
         
     }
 
+    const POLICY_INBOX_CAP: usize = 4;
+    const POLICY_PUSH_NUM: TestVal = 20;
+
+    type TestSuber<M> = Suber<ChId, SeqVal<TestVal>, M>;
+
+    async fn test_policies<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        test_drop_newest::<M>().await.with_context(||format!("drop newest [{}]", M::name()))?;
+        test_drop_oldest::<M>().await.with_context(||format!("drop oldest [{}]", M::name()))?;
+        test_block::<M>().await.with_context(||format!("block [{}]", M::name()))?;
+        test_block_deadline::<M>().await.with_context(||format!("block deadline [{}]", M::name()))?;
+        test_disconnect::<M>().await.with_context(||format!("disconnect [{}]", M::name()))?;
+        Ok(())
+    }
+
+    fn policy_suber<M>(policy: BusPolicy) -> Result<(SChannel<ChId, TestVal, M>, TestSuber<M>)>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        // channel cache 装得下所有消息，丢掉的都能从 cache 补回来
+        let ch = SChannel::<ChId, TestVal, M>::with_capacity(ChId::new(1), 64);
+        let mut suber = Suber::with_policy(POLICY_INBOX_CAP, policy);
+        suber.subscribe(&ch, 1)?;
+        Ok((ch, suber))
+    }
+
+    fn try_recv_values<M>(suber: &mut TestSuber<M>) -> Result<Vec<TestVal>>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        let mut values = Vec::new();
+        loop {
+            match suber.try_recv() {
+                RecvOutput::Value(_ch_id, v) => values.push(v.1),
+                RecvOutput::None => return Ok(values),
+                r => bail!("unexpected [{:?}]", r),
+            }
+        }
+    }
+
+    async fn test_drop_newest<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        let (ch, mut suber) = policy_suber::<M>(BusPolicy::DropNewest)?;
+        for n in 1..=POLICY_PUSH_NUM {
+            ch.push(n).await?;
+        }
+
+        let dropped = POLICY_PUSH_NUM - POLICY_INBOX_CAP;
+        assert_eq!(suber.dropped(), dropped as u64);
+        assert_eq!(ch.dropped(), vec![(*suber.id(), dropped as u64)]);
+
+        // 丢掉的从 channel cache 里补
+        assert_eq!(try_recv_values(&mut suber)?, (1..=POLICY_PUSH_NUM).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    async fn test_drop_oldest<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        let (ch, mut suber) = policy_suber::<M>(BusPolicy::DropOldest)?;
+        for n in 1..=POLICY_PUSH_NUM {
+            ch.push(n).await?;
+        }
+
+        let first = POLICY_PUSH_NUM - POLICY_INBOX_CAP + 1;
+        assert_eq!(suber.dropped(), (first - 1) as u64);
+        assert_eq!(try_recv_values(&mut suber)?, (first..=POLICY_PUSH_NUM).collect::<Vec<_>>());
+
+        // 之后的照常收
+        ch.push(POLICY_PUSH_NUM + 1).await?;
+        assert_eq!(try_recv_values(&mut suber)?, vec![POLICY_PUSH_NUM + 1]);
+        Ok(())
+    }
+
+    async fn test_block<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        // 有人消费时发布者等着，一条都不丢
+        let (ch, mut suber) = policy_suber::<M>(BusPolicy::Block(std::time::Duration::from_secs(5)))?;
+        let publish = async {
+            for n in 1..=POLICY_PUSH_NUM {
+                ch.push(n).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let consume = async {
+            let mut values = Vec::new();
+            while values.len() < POLICY_PUSH_NUM {
+                match suber.recv_next().await {
+                    RecvOutput::Value(_ch_id, v) => values.push(v.1),
+                    r => bail!("unexpected [{:?}]", r),
+                }
+            }
+            Ok(values)
+        };
+        let (r1, r2) = tokio::join!(publish, consume);
+        r1?;
+        assert_eq!(r2?, (1..=POLICY_PUSH_NUM).collect::<Vec<_>>());
+        assert_eq!(suber.dropped(), 0);
+
+        // 没人消费时超时后丢掉
+        let (ch, mut suber) = policy_suber::<M>(BusPolicy::Block(std::time::Duration::from_millis(5)))?;
+        for n in 1..=POLICY_PUSH_NUM {
+            ch.push(n).await?;
+        }
+        assert_eq!(suber.dropped(), (POLICY_PUSH_NUM - POLICY_INBOX_CAP) as u64);
+        assert_eq!(try_recv_values(&mut suber)?, (1..=POLICY_PUSH_NUM).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    async fn test_block_deadline<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        // 两个 suber 都满了，一次 push 最多卡一个 timeout
+        let timeout = Duration::from_millis(200);
+        let (ch, mut suber1) = policy_suber::<M>(BusPolicy::Block(timeout))?;
+        let mut suber2 = Suber::with_policy(POLICY_INBOX_CAP, BusPolicy::Block(timeout));
+        suber2.subscribe(&ch, 1)?;
+        for n in 1..=POLICY_INBOX_CAP {
+            ch.push(n).await?;
+        }
+
+        let start = std::time::Instant::now();
+        ch.push(POLICY_INBOX_CAP + 1).await?;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= timeout, "elapsed {:?}", elapsed);
+        assert!(elapsed < timeout * 3 / 2, "elapsed {:?}", elapsed);
+
+        assert_eq!(suber1.dropped(), 1);
+        assert_eq!(suber2.dropped(), 1);
+        let expect = (1..=POLICY_INBOX_CAP + 1).collect::<Vec<_>>();
+        assert_eq!(try_recv_values(&mut suber1)?, expect);
+        assert_eq!(try_recv_values(&mut suber2)?, expect);
+        Ok(())
+    }
+
+    async fn test_disconnect<M>() -> Result<()>
+    where
+        M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
+    {
+        let (ch, mut suber) = policy_suber::<M>(BusPolicy::Disconnect)?;
+        for n in 1..=POLICY_PUSH_NUM {
+            ch.push(n).await?;
+        }
+
+        assert_eq!(ch.subers(), 0);
+        assert_eq!(suber.dropped(), 1);
+        assert_eq!(suber.try_recv(), RecvOutput::Lagged(*ch.ch_id()));
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+        assert_eq!(suber.channels(), 0);
+
+        // 可以重新订阅
+        suber.subscribe(&ch, POLICY_PUSH_NUM as u64)?;
+        assert_eq!(try_recv_values(&mut suber)?, vec![POLICY_PUSH_NUM]);
+        Ok(())
+    }
+
     fn make_tester<M>() -> Result<Tester<M>>
     where
         M: MpscOp<Event<ChId, SeqVal<TestVal>>>,
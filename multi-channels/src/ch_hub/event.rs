@@ -1,15 +1,30 @@
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event<K, T> {
     Msg(Msg<K, T>),
+    /// channel 已关闭
+    Closed(K),
+    /// channel 被重置，之后的消息从 seq 开始
+    Reset(K, u64),
 }
 
 impl<K, T> Event<K, T> {
     pub(super) fn msg(ch_id: K, msg: T) -> Self {
         Self::Msg(Msg{ch_id, msg})
     }
+
+    pub fn ch_id(&self) -> &K {
+        match self {
+            Self::Msg(msg) => &msg.ch_id,
+            Self::Closed(ch_id) => ch_id,
+            Self::Reset(ch_id, _seq) => ch_id,
+        }
+    }
 }
 
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Msg<K, T> {
     pub(super) ch_id: K,
     pub(super) msg: T,
@@ -32,3 +47,39 @@ where
 }
 
 
+
+#[cfg(all(test, any(feature = "bincode", feature = "json", feature = "msgpack")))]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::ch_common::{SeqVal, uid::ChId};
+    use super::super::serde_codec::{self, SerdeFormat};
+
+    type TestEvent = Event<ChId, SeqVal<String>>;
+
+    fn event_strategy() -> impl Strategy<Value = TestEvent> {
+        prop_oneof![
+            (any::<u64>(), any::<u64>(), ".*").prop_map(|(ch, seq, s)| Event::msg(ChId::new(ch), SeqVal::new(seq, s))),
+            any::<u64>().prop_map(|ch| Event::Closed(ChId::new(ch))),
+            (any::<u64>(), any::<u64>()).prop_map(|(ch, seq)| Event::Reset(ChId::new(ch), seq)),
+        ]
+    }
+
+    fn check<F: SerdeFormat>(ev: &TestEvent) {
+        let r = serde_codec::test::round_trip::<F, TestEvent>(ev);
+        assert_eq!(&r, ev, "{} round trip fail", F::name());
+    }
+
+    proptest! {
+        #[test]
+        fn test_event_round_trip(ev in event_strategy()) {
+            #[cfg(feature = "bincode")]
+            check::<serde_codec::Bincode>(&ev);
+            #[cfg(feature = "json")]
+            check::<serde_codec::Json>(&ev);
+            #[cfg(feature = "msgpack")]
+            check::<serde_codec::MsgPack>(&ev);
+        }
+    }
+}
@@ -0,0 +1,835 @@
+//! 基于文件的 ChStore：
+//! - 每个 channel 一个目录 {root}/{ch_id}/，ch_id 里字母数字和 '-' '_' 以外的字节编码成 %XX，
+//!   字符串 id 带 '/' 或 ".." 也跳不出 root
+//! - 目录下是按 seq 滚动的 segment 文件 {first_seq:020}.log，
+//!   每条记录为 [seq: u64][len: u32][payload]，小端
+//! - 每个 segment 有一个稀疏索引 {first_seq:020}.idx，
+//!   每 index_interval 条记录写一个 [seq: u64][offset: u64]
+//! - 打开时截掉最后一个 segment 末尾写了一半的记录，没有完整记录的末尾 segment 直接删掉
+//! - 持久订阅的 ack 位置保存在 {root}/{ch_id}/cursors/{name}，8 字节 seq，
+//!   先写临时文件再 rename
+//! - 每个 channel 的日志单独加锁，一个 channel 的慢 IO 不影响其它 channel
+//! - 读写在 tokio 的 blocking 线程里执行，不在 runtime 里时（比如 `Suber::recv_blocking`）直接执行
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use parking_lot::Mutex;
+
+use crate::ch_common::{ChIdOp, Codec, GetSeq, ReadQueOutput, VecMap};
+
+use super::store::{ChStore, LOAD_BATCH};
+
+const RECORD_HEAD_LEN: u64 = 12;
+const INDEX_ENTRY_LEN: u64 = 16;
+
+#[derive(Debug, Clone)]
+pub struct FileStoreConfig {
+    /// roll to a new segment once the active one reaches this size
+    pub segment_bytes: u64,
+
+    /// write an index entry every N records
+    pub index_interval: u64,
+
+    /// drop oldest segments beyond this count, 0 means keep all
+    pub max_segments: usize,
+
+    /// fsync after each write
+    pub sync: bool,
+}
+
+impl Default for FileStoreConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 64 * 1024 * 1024,
+            index_interval: 64,
+            max_segments: 0,
+            sync: false,
+        }
+    }
+}
+
+/// 第一次用到时才打开
+type SharedLog = Arc<Mutex<Option<ChLog>>>;
+
+pub struct ChFileStore<K, T> {
+    root: PathBuf,
+    config: FileStoreConfig,
+    /// 只在查找 channel 时短暂持有，IO 在各 channel 自己的锁里做
+    channels: Mutex<VecMap<K, SharedLog>>,
+    none: PhantomData<T>,
+}
+
+impl<K, T> ChFileStore<K, T>
+where
+    K: ChIdOp + std::fmt::Display,
+    T: GetSeq + Codec + Send + 'static,
+{
+    pub fn open<P: AsRef<Path>>(root: P, config: FileStoreConfig) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)
+        .with_context(||format!("create store dir failed [{:?}]", root))?;
+
+        Ok(Self {
+            root,
+            config,
+            channels: Mutex::new(VecMap::new()),
+            none: PhantomData,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn log_of(&self, ch_id: &K) -> (SharedLog, PathBuf) {
+        let log = self.channels.lock().entry(ch_id.clone()).or_default().clone();
+        (log, self.ch_dir(ch_id))
+    }
+
+    fn ch_dir(&self, ch_id: &K) -> PathBuf {
+        self.root.join(ch_dir_name(&ch_id.to_string()))
+    }
+
+    /// 在 blocking 线程里操作 channel 日志
+    fn spawn_with_log<F, O>(&self, ch_id: &K, func: F) -> impl Future<Output = Result<O>> + Send + 'static
+    where
+        F: FnOnce(&mut ChLog) -> Result<O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let (log, dir) = self.log_of(ch_id);
+        run_blocking(move || lock_log(&log, dir, func))
+    }
+
+    fn cursor_path(&self, ch_id: &K, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("invalid cursor name [{}]", name)
+        }
+        Ok(self.ch_dir(ch_id).join("cursors").join(name))
+    }
+}
+
+impl<K, T> ChStore<K, T> for ChFileStore<K, T>
+where
+    K: ChIdOp + std::fmt::Display,
+    T: GetSeq + Codec + Send + 'static,
+{
+    /// nothing is kept in memory, always go to `load_cold`
+    fn read_hot(&self, _ch_id: &K, _seq: u64) -> ReadQueOutput<T> {
+        ReadQueOutput::Lagged
+    }
+
+    fn load_cold(&self, ch_id: &K, seq: u64) -> Self::ReadFut<'_> {
+        self.spawn_with_log(ch_id, move |log| {
+            let batch = log.read_from(seq, LOAD_BATCH)?;
+            if batch.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(batch))
+            }
+        })
+    }
+
+    type ReadFut<'a> = impl Future<Output = Result<Option<Vec<T>>>> + Send + 'a where Self: 'a;
+
+    fn async_write(&self, ch_id: &K, v: T) -> Self::WriteFut<'_> {
+        let config = self.config.clone();
+        self.spawn_with_log(ch_id, move |log| log.append(&v, &config))
+    }
+
+    type WriteFut<'a> = impl Future<Output = Result<()>> + Send + 'a where Self: 'a;
+
+    /// 第一次访问要打开并恢复日志，也放到 blocking 线程
+    fn last_seq(&self, ch_id: &K) -> Self::LastSeqFut<'_> {
+        self.spawn_with_log(ch_id, |log| Ok(log.last_seq))
+    }
+
+    type LastSeqFut<'a> = impl Future<Output = Result<u64>> + Send + 'a where Self: 'a;
+
+    fn load_cursor(&self, ch_id: &K, name: &str) -> Self::LoadCursorFut<'_> {
+        let path = self.cursor_path(ch_id, name);
+        run_blocking(move || read_cursor(&path?))
+    }
+
+    type LoadCursorFut<'a> = impl Future<Output = Result<Option<u64>>> + Send + 'a where Self: 'a;
+
+    /// ack 时调用，也放到 blocking 线程
+    fn save_cursor(&self, ch_id: &K, name: &str, seq: u64) -> Self::SaveCursorFut<'_> {
+        let path = self.cursor_path(ch_id, name);
+        let sync = self.config.sync;
+        run_blocking(move || write_cursor(&path?, seq, sync))
+    }
+
+    type SaveCursorFut<'a> = impl Future<Output = Result<()>> + Send + 'a where Self: 'a;
+}
+
+fn read_cursor(path: &Path) -> Result<Option<u64>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match data.try_into() {
+        Ok(bytes) => Ok(Some(u64::from_le_bytes(bytes))),
+        Err(data) => bail!("corrupted cursor [{:?}], [{}] bytes", path, data.len()),
+    }
+}
+
+fn write_cursor(path: &Path, seq: u64, sync: bool) -> Result<()> {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => bail!("invalid cursor path [{:?}]", path),
+    };
+    fs::create_dir_all(dir)
+    .with_context(||format!("create cursor dir failed [{:?}]", dir))?;
+
+    // cursor 名字不能以 '.' 开头，临时文件放在 '.' 开头的名字下不会和别的 cursor 撞上
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(&seq.to_le_bytes())?;
+    if sync {
+        file.sync_data()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+
+/// 数字 id 的目录名不变；空 id 编码成单独的 "%"，不会和其它编码结果重复
+fn ch_dir_name(ch_id: &str) -> String {
+    if ch_id.is_empty() {
+        return "%".into();
+    }
+
+    let mut name = String::with_capacity(ch_id.len());
+    for b in ch_id.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            name.push(b as char);
+        } else {
+            name.push_str(&format!("%{:02X}", b));
+        }
+    }
+    name
+}
+
+fn lock_log<F, O>(log: &SharedLog, dir: PathBuf, func: F) -> Result<O>
+where
+    F: FnOnce(&mut ChLog) -> Result<O>,
+{
+    let mut log = log.lock();
+    if log.is_none() {
+        *log = Some(ChLog::open(dir)?);
+    }
+    // 上面已经打开
+    func(log.as_mut().with_context(||"channel log gone")?)
+}
+
+/// 在 tokio runtime 里时放到 blocking 线程执行，否则直接执行
+async fn run_blocking<F, O>(func: F) -> Result<O>
+where
+    F: FnOnce() -> Result<O> + Send + 'static,
+    O: Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle.spawn_blocking(func).await?,
+        Err(_e) => func(),
+    }
+}
+
+struct ChLog {
+    dir: PathBuf,
+    /// sorted by first_seq
+    segments: Vec<Segment>,
+    /// opened lazily for the last segment
+    active: Option<ActiveSegment>,
+    last_seq: u64,
+}
+
+struct Segment {
+    first_seq: u64,
+    /// (seq, offset)
+    index: Vec<(u64, u64)>,
+    bytes: u64,
+    records: u64,
+}
+
+struct ActiveSegment {
+    log: File,
+    idx: File,
+}
+
+impl ActiveSegment {
+    fn write(&mut self, record: &[u8], index_entry: Option<(u64, u64)>, sync: bool) -> Result<()> {
+        self.log.write_all(record)?;
+        if let Some((seq, offset)) = index_entry {
+            let mut entry = [0u8; INDEX_ENTRY_LEN as usize];
+            entry[..8].copy_from_slice(&seq.to_le_bytes());
+            entry[8..].copy_from_slice(&offset.to_le_bytes());
+            self.idx.write_all(&entry)?;
+        }
+        if sync {
+            self.log.sync_data()?;
+            self.idx.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+impl ChLog {
+    fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)
+        .with_context(||format!("create channel dir failed [{:?}]", dir))?;
+
+        let mut first_seqs = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                let first_seq = path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok());
+                if let Some(first_seq) = first_seq {
+                    first_seqs.push(first_seq);
+                }
+            }
+        }
+        first_seqs.sort_unstable();
+
+        let mut log = Self {
+            dir,
+            segments: Vec::with_capacity(first_seqs.len()),
+            active: None,
+            last_seq: 0,
+        };
+
+        // 从后往前找最后一个还有完整记录的 segment 作为 active。
+        // roll 之后第一条还没写完就崩溃会留下空的或只有半条记录的 segment，直接删掉，
+        // 否则 last_seq 会变成 0，之后从 seq 1 重新写
+        let mut last = None;
+        while let Some(first_seq) = first_seqs.pop() {
+            match log.recover_last_segment(first_seq)? {
+                Some(segment) => {
+                    last = Some(segment);
+                    break;
+                },
+                None => log.remove_segment_files(first_seq)?,
+            }
+        }
+
+        for first_seq in first_seqs {
+            let segment = log.load_segment(first_seq)?;
+            log.segments.push(segment);
+        }
+        log.segments.extend(last);
+
+        Ok(log)
+    }
+
+    /// 只加载索引，不扫描记录
+    fn load_segment(&self, first_seq: u64) -> Result<Segment> {
+        let mut index = read_index(&self.idx_path(first_seq))?;
+        let bytes = fs::metadata(self.log_path(first_seq))?.len();
+        index.retain(|(_seq, offset)| *offset < bytes);
+        Ok(Segment { first_seq, index, bytes, records: 0 })
+    }
+
+    /// 扫描出 last_seq 并截掉末尾不完整的记录，没有一条完整记录时返回 None
+    fn recover_last_segment(&mut self, first_seq: u64) -> Result<Option<Segment>> {
+        let log_path = self.log_path(first_seq);
+        let idx_path = self.idx_path(first_seq);
+        let mut segment = self.load_segment(first_seq)?;
+
+        // 最后一个索引点指向的记录可能写了一半，往前退一个索引点重新扫
+        let (last_seq, offset, records) = loop {
+            let start = segment.index.last().map(|(_seq, offset)| *offset).unwrap_or(0);
+            let (last_seq, offset, records) = scan_records(&log_path, start, segment.bytes)?;
+            if last_seq.is_some() || segment.index.is_empty() {
+                break (last_seq, offset, records);
+            }
+            segment.index.pop();
+        };
+
+        let last_seq = match last_seq {
+            Some(last_seq) => last_seq,
+            None => return Ok(None),
+        };
+
+        if offset < segment.bytes {
+            OpenOptions::new().write(true).open(&log_path)?.set_len(offset)?;
+        }
+        if let Ok(file) = OpenOptions::new().write(true).open(&idx_path) {
+            file.set_len(segment.index.len() as u64 * INDEX_ENTRY_LEN)?;
+        }
+
+        self.last_seq = last_seq;
+        // records 只用来决定何时写索引，按最后一个索引点之后的条数算即可
+        segment.bytes = offset;
+        segment.records = records;
+        Ok(Some(segment))
+    }
+
+    fn remove_segment_files(&self, first_seq: u64) -> Result<()> {
+        fs::remove_file(self.log_path(first_seq))?;
+        let _r = fs::remove_file(self.idx_path(first_seq));
+        Ok(())
+    }
+
+    fn append<T>(&mut self, v: &T, config: &FileStoreConfig) -> Result<()>
+    where
+        T: GetSeq + Codec,
+    {
+        let seq = v.get_seq();
+        if seq <= self.last_seq {
+            bail!("append seq inconsist, expect > [{}] but [{}]", self.last_seq, seq)
+        }
+
+        let need_roll = match self.segments.last() {
+            Some(last) => last.bytes >= config.segment_bytes,
+            None => true,
+        };
+        if need_roll {
+            self.roll(seq, config)?;
+        }
+
+        if self.active.is_none() {
+            // 重启后第一次写，打开最后一个 segment 继续追加
+            let first_seq = self.segments.last().map(|s| s.first_seq).unwrap_or(seq);
+            self.active = Some(self.open_active(first_seq)?);
+        }
+
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&seq.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        v.encode(&mut buf);
+        let len = (buf.len() as u64 - RECORD_HEAD_LEN) as u32;
+        buf[8..12].copy_from_slice(&len.to_le_bytes());
+
+        let (segment, active) = match (self.segments.last_mut(), self.active.as_mut()) {
+            (Some(segment), Some(active)) => (segment, active),
+            _ => bail!("no active segment"),
+        };
+
+        let index_entry = (segment.records % config.index_interval.max(1) == 0)
+            .then_some((seq, segment.bytes));
+
+        // 全部写成功才更新内存里的 bytes/index/last_seq
+        if let Err(e) = active.write(&buf, index_entry, config.sync) {
+            let (first_seq, bytes, index_len) = (segment.first_seq, segment.bytes, segment.index.len());
+            self.active = None;
+            // 截掉写了一半的记录和索引；截不掉就重新打开，按文件内容恢复
+            if let Err(e2) = self.truncate_segment(first_seq, bytes, index_len) {
+                *self = Self::open(self.dir.clone())
+                .with_context(||format!("recover channel log failed, truncate error [{:?}], write error [{:?}]", e2, e))?;
+            }
+            return Err(e);
+        }
+
+        segment.index.extend(index_entry);
+        segment.bytes += buf.len() as u64;
+        segment.records += 1;
+        self.last_seq = seq;
+        Ok(())
+    }
+
+    fn truncate_segment(&self, first_seq: u64, bytes: u64, index_len: usize) -> Result<()> {
+        OpenOptions::new().write(true).open(self.log_path(first_seq))?.set_len(bytes)?;
+        OpenOptions::new().write(true).open(self.idx_path(first_seq))?.set_len(index_len as u64 * INDEX_ENTRY_LEN)?;
+        Ok(())
+    }
+
+    fn roll(&mut self, first_seq: u64, config: &FileStoreConfig) -> Result<()> {
+        self.active = Some(self.open_active(first_seq)?);
+        self.segments.push(Segment { first_seq, index: Vec::new(), bytes: 0, records: 0 });
+
+        if config.max_segments > 0 {
+            while self.segments.len() > config.max_segments {
+                let oldest = self.segments.remove(0);
+                self.remove_segment_files(oldest.first_seq)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open_active(&self, first_seq: u64) -> Result<ActiveSegment> {
+        let open = |path: PathBuf| {
+            OpenOptions::new().create(true).append(true).open(&path)
+            .with_context(||format!("open segment failed [{:?}]", path))
+        };
+        Ok(ActiveSegment {
+            log: open(self.log_path(first_seq))?,
+            idx: open(self.idx_path(first_seq))?,
+        })
+    }
+
+    fn read_from<T>(&self, seq: u64, max: usize) -> Result<Vec<T>>
+    where
+        T: Codec,
+    {
+        let mut output = Vec::new();
+        if seq > self.last_seq {
+            return Ok(output);
+        }
+
+        // 最后一个 first_seq <= seq 的 segment；seq 已被淘汰时从第一个开始
+        let start = self.segments.partition_point(|s| s.first_seq <= seq).max(1) - 1;
+        for segment in &self.segments[start..] {
+            let offset = match segment.index.partition_point(|(s, _)| *s <= seq) {
+                0 => 0,
+                n => segment.index[n - 1].1,
+            };
+
+            let mut reader = BufReader::new(File::open(self.log_path(segment.first_seq))?);
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut pos = offset;
+            while pos < segment.bytes {
+                let (record_seq, len) = match read_record_head(&mut reader)? {
+                    Some(head) => head,
+                    None => break,
+                };
+                pos += RECORD_HEAD_LEN + len as u64;
+                if record_seq < seq {
+                    reader.seek_relative(len as i64)?;
+                    continue;
+                }
+
+                let mut payload = vec![0; len as usize];
+                reader.read_exact(&mut payload)?;
+                output.push(T::decode(&payload)?);
+                if output.len() >= max {
+                    return Ok(output);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn log_path(&self, first_seq: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.log", first_seq))
+    }
+
+    fn idx_path(&self, first_seq: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.idx", first_seq))
+    }
+}
+
+fn read_index(path: &Path) -> Result<Vec<(u64, u64)>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(data.chunks_exact(INDEX_ENTRY_LEN as usize)
+        .map(|entry| {
+            let seq = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let offset = u64::from_le_bytes(entry[8..].try_into().unwrap());
+            (seq, offset)
+        })
+        .collect())
+}
+
+/// 从 start 开始扫描完整的记录，返回 (最后一条的 seq, 最后一条完整记录的结尾, 条数)
+fn scan_records(path: &Path, start: u64, bytes: u64) -> Result<(Option<u64>, u64, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.seek(SeekFrom::Start(start))?;
+    let mut last_seq = None;
+    let mut offset = start;
+    let mut records = 0;
+    while let Some((seq, len)) = read_record_head(&mut reader)? {
+        let end = offset + RECORD_HEAD_LEN + len as u64;
+        if end > bytes {
+            break;
+        }
+        reader.seek_relative(len as i64)?;
+        last_seq = Some(seq);
+        offset = end;
+        records += 1;
+    }
+    Ok((last_seq, offset, records))
+}
+
+/// return none at end of file or on a partial head
+fn read_record_head<R: Read>(reader: &mut R) -> Result<Option<(u64, u32)>> {
+    let mut head = [0u8; RECORD_HEAD_LEN as usize];
+    let mut filled = 0;
+    while filled < head.len() {
+        let n = reader.read(&mut head[filled..])?;
+        if n == 0 {
+            return Ok(None);
+        }
+        filled += n;
+    }
+    let seq = u64::from_le_bytes(head[..8].try_into()?);
+    let len = u32::from_le_bytes(head[8..].try_into()?);
+    Ok(Some((seq, len)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::ch_common::{SeqVal, uid::ChId};
+    use super::*;
+
+    type Message = SeqVal<u64>;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("ch-file-store-{}-{}", name, std::process::id()));
+        let _r = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[tokio::test]
+    async fn test_write_and_reopen() {
+        let root = temp_root("reopen");
+        let config = FileStoreConfig { segment_bytes: 200, index_interval: 4, ..Default::default() };
+        let ch_id = ChId::new(1);
+
+        {
+            let store = ChFileStore::<ChId, Message>::open(&root, config.clone()).unwrap();
+            assert_eq!(store.last_seq(&ch_id).await.unwrap(), 0);
+            for seq in 1..=100 {
+                store.async_write(&ch_id, Message::new(seq, seq * 10)).await.unwrap();
+            }
+            assert!(store.async_write(&ch_id, Message::new(100, 0)).await.is_err());
+        }
+
+        let store = ChFileStore::<ChId, Message>::open(&root, config).unwrap();
+        assert_eq!(store.last_seq(&ch_id).await.unwrap(), 100);
+
+        let batch = store.load_cold(&ch_id, 37).await.unwrap().unwrap();
+        assert_eq!(batch.len(), LOAD_BATCH);
+        assert_eq!(batch[0], Message::new(37, 370));
+        assert_eq!(batch.last().unwrap().get_seq(), 37 + LOAD_BATCH as u64 - 1);
+
+        let batch = store.load_cold(&ch_id, 99).await.unwrap().unwrap();
+        assert_eq!(batch, vec![Message::new(99, 990), Message::new(100, 1000)]);
+        assert!(store.load_cold(&ch_id, 101).await.unwrap().is_none());
+
+        store.async_write(&ch_id, Message::new(101, 1010)).await.unwrap();
+        let batch = store.load_cold(&ch_id, 101).await.unwrap().unwrap();
+        assert_eq!(batch, vec![Message::new(101, 1010)]);
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_partial_record() {
+        let root = temp_root("partial");
+        let ch_id = ChId::new(2);
+        {
+            let store = ChFileStore::<ChId, Message>::open(&root, Default::default()).unwrap();
+            store.async_write(&ch_id, Message::new(1, 1)).await.unwrap();
+            store.async_write(&ch_id, Message::new(2, 2)).await.unwrap();
+        }
+
+        // 模拟写到一半崩溃
+        let log_path = root.join("2").join(format!("{:020}.log", 1));
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&3_u64.to_le_bytes()).unwrap();
+        file.write_all(&100_u32.to_le_bytes()).unwrap();
+        drop(file);
+
+        let store = ChFileStore::<ChId, Message>::open(&root, Default::default()).unwrap();
+        assert_eq!(store.last_seq(&ch_id).await.unwrap(), 2);
+        store.async_write(&ch_id, Message::new(3, 3)).await.unwrap();
+        let batch = store.load_cold(&ch_id, 1).await.unwrap().unwrap();
+        assert_eq!(batch, vec![Message::new(1, 1), Message::new(2, 2), Message::new(3, 3)]);
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_rollback_failed_append() {
+        let root = temp_root("rollback");
+        let dir = root.join("6");
+        let config = FileStoreConfig { index_interval: 1, ..Default::default() };
+
+        let mut log = ChLog::open(dir.clone()).unwrap();
+        log.append(&Message::new(1, 1), &config).unwrap();
+        let log_path = log.log_path(1);
+        let idx_path = log.idx_path(1);
+        let log_len = fs::metadata(&log_path).unwrap().len();
+
+        // 记录写进去了，索引写失败
+        log.active.as_mut().unwrap().idx = File::open(&idx_path).unwrap();
+        assert!(log.append(&Message::new(2, 2), &config).is_err());
+        assert_eq!(log.last_seq, 1);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), log_len);
+        assert_eq!(fs::metadata(&idx_path).unwrap().len(), INDEX_ENTRY_LEN);
+
+        // 重试同一个 seq，前后都能读到
+        log.append(&Message::new(2, 2), &config).unwrap();
+        log.append(&Message::new(3, 3), &config).unwrap();
+        let batch: Vec<Message> = log.read_from(1, 10).unwrap();
+        assert_eq!(batch, vec![Message::new(1, 1), Message::new(2, 2), Message::new(3, 3)]);
+
+        let log = ChLog::open(dir).unwrap();
+        assert_eq!(log.last_seq, 3);
+        assert_eq!(log.segments[0].index.len(), 3);
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_reopen_broken_tail_segment() {
+        let root = temp_root("tail");
+        let ch_id = ChId::new(5);
+        let ch_dir = root.join("5");
+        let config = FileStoreConfig { index_interval: 1, ..Default::default() };
+        let write_1_to_10 = || async {
+            let _r = fs::remove_dir_all(&root);
+            let store = ChFileStore::<ChId, Message>::open(&root, config.clone()).unwrap();
+            for seq in 1..=10 {
+                store.async_write(&ch_id, Message::new(seq, seq)).await.unwrap();
+            }
+        };
+        let check_reopen = || async {
+            let store = ChFileStore::<ChId, Message>::open(&root, config.clone()).unwrap();
+            assert_eq!(store.last_seq(&ch_id).await.unwrap(), 10);
+            assert!(store.async_write(&ch_id, Message::new(1, 1)).await.is_err());
+            store.async_write(&ch_id, Message::new(11, 11)).await.unwrap();
+            let batch = store.load_cold(&ch_id, 1).await.unwrap().unwrap();
+            let seqs: Vec<u64> = batch.iter().map(|v| v.get_seq()).collect();
+            assert_eq!(seqs, (1..=11).collect::<Vec<_>>());
+        };
+
+        // roll 出来的空 segment
+        write_1_to_10().await;
+        File::create(ch_dir.join(format!("{:020}.log", 11))).unwrap();
+        check_reopen().await;
+
+        // 新 segment 只写了半个记录头
+        write_1_to_10().await;
+        fs::write(ch_dir.join(format!("{:020}.log", 11)), 11_u64.to_le_bytes()).unwrap();
+        fs::write(ch_dir.join(format!("{:020}.idx", 11)), [11_u64.to_le_bytes(), 0_u64.to_le_bytes()].concat()).unwrap();
+        check_reopen().await;
+
+        // 最后一个索引点指向的记录写了一半
+        write_1_to_10().await;
+        let log_path = ch_dir.join(format!("{:020}.log", 1));
+        let len = fs::metadata(&log_path).unwrap().len();
+        OpenOptions::new().write(true).open(&log_path).unwrap().set_len(len - 3).unwrap();
+        {
+            let store = ChFileStore::<ChId, Message>::open(&root, config.clone()).unwrap();
+            assert_eq!(store.last_seq(&ch_id).await.unwrap(), 9);
+            store.async_write(&ch_id, Message::new(10, 10)).await.unwrap();
+        }
+        check_reopen().await;
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_channels() {
+        let root = temp_root("concurrent");
+        let store = Arc::new(ChFileStore::<ChId, Message>::open(&root, Default::default()).unwrap());
+
+        let tasks: Vec<_> = (1..=8).map(|id| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let ch_id = ChId::new(id);
+                for seq in 1..=100 {
+                    store.async_write(&ch_id, Message::new(seq, id)).await.unwrap();
+                }
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for id in 1..=8 {
+            assert_eq!(store.last_seq(&ChId::new(id)).await.unwrap(), 100);
+        }
+
+        // 不在 runtime 里时直接在当前线程读
+        let store2 = store.clone();
+        let batch = std::thread::spawn(move || {
+            crate::ch_common::blocking::block_on(store2.load_cold(&ChId::new(3), 100))
+        }).join().unwrap().unwrap();
+        assert_eq!(batch, Some(vec![Message::new(100, 3)]));
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_evict_segments() {
+        let root = temp_root("evict");
+        let config = FileStoreConfig { segment_bytes: 100, max_segments: 2, ..Default::default() };
+        let ch_id = ChId::new(3);
+
+        let store = ChFileStore::<ChId, Message>::open(&root, config).unwrap();
+        for seq in 1..=50 {
+            store.async_write(&ch_id, Message::new(seq, seq)).await.unwrap();
+        }
+
+        // 早期 segment 已删除，返回的第一条 seq 大于请求的 seq
+        let batch = store.load_cold(&ch_id, 1).await.unwrap().unwrap();
+        assert!(batch[0].get_seq() > 1);
+        assert_eq!(batch.last().unwrap().get_seq(), 50);
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_string_ch_id_stays_in_root() {
+        let root = temp_root("string-id");
+        let store = ChFileStore::<String, Message>::open(root.join("store"), Default::default()).unwrap();
+        for (n, ch_id) in ["a/b", "..", "../escape", "", "%2E", "ok-1_x"].into_iter().enumerate() {
+            let ch_id = ch_id.to_string();
+            store.async_write(&ch_id, Message::new(1, n as u64)).await.unwrap();
+            store.save_cursor(&ch_id, "c1", 1).await.unwrap();
+        }
+
+        assert!(!root.join("escape").exists());
+        let mut dirs: Vec<String> = fs::read_dir(root.join("store")).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        dirs.sort();
+        assert_eq!(dirs, vec!["%", "%252E", "%2E%2E", "%2E%2E%2Fescape", "a%2Fb", "ok-1_x"]);
+
+        // 每个 id 各自一个目录
+        let batch = store.load_cold(&"..".to_string(), 1).await.unwrap().unwrap();
+        assert_eq!(batch, vec![Message::new(1, 1)]);
+        assert_eq!(store.load_cursor(&"%2E".to_string(), "c1").await.unwrap(), Some(1));
+
+        let _r = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_cursor() {
+        let root = temp_root("cursor");
+        let ch_id = ChId::new(4);
+        {
+            let store = ChFileStore::<ChId, Message>::open(&root, Default::default()).unwrap();
+            assert_eq!(store.load_cursor(&ch_id, "c1").await.unwrap(), None);
+            store.save_cursor(&ch_id, "c1", 5).await.unwrap();
+            store.save_cursor(&ch_id, "c1", 7).await.unwrap();
+            store.save_cursor(&ch_id, "c2", 3).await.unwrap();
+            // 带点的名字互不干扰，也不和 c1 的临时文件冲突
+            store.save_cursor(&ch_id, "a.x", 11).await.unwrap();
+            store.save_cursor(&ch_id, "a.y", 12).await.unwrap();
+            store.save_cursor(&ch_id, "c1.tmp", 13).await.unwrap();
+            assert!(store.save_cursor(&ch_id, ".c1.tmp", 3).await.is_err());
+            assert!(store.save_cursor(&ch_id, "../c3", 3).await.is_err());
+            assert!(store.save_cursor(&ch_id, "", 3).await.is_err());
+        }
+
+        // cursors 目录不影响 channel 日志的恢复
+        let store = ChFileStore::<ChId, Message>::open(&root, Default::default()).unwrap();
+        assert_eq!(store.load_cursor(&ch_id, "c1").await.unwrap(), Some(7));
+        assert_eq!(store.load_cursor(&ch_id, "c2").await.unwrap(), Some(3));
+        assert_eq!(store.load_cursor(&ch_id, "a.x").await.unwrap(), Some(11));
+        assert_eq!(store.load_cursor(&ch_id, "a.y").await.unwrap(), Some(12));
+        assert_eq!(store.load_cursor(&ch_id, "c1.tmp").await.unwrap(), Some(13));
+        store.save_cursor(&ch_id, "c1", 8).await.unwrap();
+        assert_eq!(store.load_cursor(&ch_id, "c1").await.unwrap(), Some(8));
+        assert_eq!(store.load_cursor(&ch_id, "c1.tmp").await.unwrap(), Some(13));
+        store.async_write(&ch_id, Message::new(1, 1)).await.unwrap();
+        assert_eq!(store.last_seq(&ch_id).await.unwrap(), 1);
+
+        let _r = fs::remove_dir_all(&root);
+    }
+}
@@ -1,10 +1,14 @@
 use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use parking_lot::Mutex;
-use crate::{ch_common::{GetSeq, ChIdOp, VecMap}, mpsc_ch::mpsc_defs::MpscOp};
+use tokio::task::JoinHandle;
+use crate::{ch_common::{GetSeq, WithSeq, ChIdOp, CompactKeyFn, VecMap}, mpsc_ch::mpsc_defs::MpscOp};
 
-use super::{store::ChStore, event::Event, channel1::{channel::Channel, suber::Suber}};
+use super::{store::{AckStore, ChStore, ColdLoad, NoStore}, event::Event, metrics::HubMetrics, pattern::ChPattern, registry::{ChRegistry, DEFAULT_SHARDS}};
+use super::channel1::{bus::WatchHandle, channel::{Channel, ChannelOptions}, group::GroupMember, suber::{PendingChannels, Suber}};
 
 
 // /// T: Seq Message
@@ -31,49 +35,381 @@ use super::{store::ChStore, event::Event, channel1::{channel::Channel, suber::Su
 
 
 
-pub struct Hub<K, T, M> 
+#[derive(Debug, Clone)]
+pub struct HubConfig {
+    /// 自动创建 channel 时用的参数
+    pub channel: ChannelOptions,
+
+    /// 没有 puber 和 suber 超过这个时间的 channel 会被 `Hub::evict_idle` 移除，
+    /// None 表示不淘汰
+    pub idle_timeout: Option<Duration>,
+
+    /// channel 表的分片数，1 表示所有 channel 共用一把锁
+    pub shards: usize,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            channel: ChannelOptions::default(),
+            idle_timeout: None,
+            shards: DEFAULT_SHARDS,
+        }
+    }
+}
+
+pub struct Hub<K, T, M, S = NoStore> 
 where
     K: ChIdOp,
     T: Clone,
     M: MpscOp<Event<K, T>>,
 {
     none: PhantomData<T>,
-    channels: Mutex<VecMap<K, Channel<K, T, M>>>,
+    channels: ChRegistry<K, Channel<K, T, M>>,
+    /// 新建 channel 时在持有所在分片锁时访问，增删 pattern 时持有所有分片锁
+    patterns: Mutex<Vec<PatternWatch<K, T, M>>>,
+    /// `open_compacted_channel` 打开过的 channel，被关闭或淘汰后自动重建时照样压缩。
+    /// 在持有分片锁时访问
+    compacted: Mutex<VecMap<K, CompactedSpec<T>>>,
+    store: Arc<S>,
+    config: HubConfig,
 }
 
-impl<K, T, M> Hub<K, T, M> 
+struct CompactedSpec<T> {
+    options: ChannelOptions,
+    key_fn: CompactKeyFn<T>,
+}
+
+struct PatternWatch<K, T, M> 
 where
     K: ChIdOp,
-    T: Clone + GetSeq,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    pattern: ChPattern,
+    matcher: Box<dyn Fn(&K) -> bool + Send + Sync>,
+    handle: WatchHandle<K, T, M>,
+    pending: PendingChannels<K, T, M>,
+}
+
+impl<K, T, M> Hub<K, T, M> 
+where
+    K: ChIdOp + Sync + 'static,
+    T: Clone + GetSeq + WithSeq + Send + 'static,
     M: MpscOp<Event<K, T>>,
 {
     pub fn new() -> Self {
+        Self::with_store(NoStore)
+    }
+}
+
+impl<K, T, M> Default for Hub<K, T, M> 
+where
+    K: ChIdOp + Sync + 'static,
+    T: Clone + GetSeq + WithSeq + Send + 'static,
+    M: MpscOp<Event<K, T>>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T, M, S> Hub<K, T, M, S> 
+where
+    K: ChIdOp + Sync + 'static,
+    T: Clone + GetSeq + WithSeq + Send + 'static,
+    M: MpscOp<Event<K, T>>,
+    S: ChStore<K, T> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    pub fn with_store(store: S) -> Self {
+        Self::with_config(store, HubConfig::default())
+    }
+
+    pub fn with_config(store: S, config: HubConfig) -> Self {
         Self { 
             none: Default::default(),
-            channels: Mutex::new(VecMap::new()),
+            channels: ChRegistry::with_shards(config.shards),
+            patterns: Mutex::new(Vec::new()),
+            compacted: Mutex::new(VecMap::new()),
+            store: Arc::new(store),
+            config,
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn config(&self) -> &HubConfig {
+        &self.config
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// 所有 channel 及其 suber 的指标快照。先复制 channel 列表再逐个统计，不会一直锁着 channel 表
+    pub fn metrics(&self) -> HubMetrics<K> {
+        let channels: Vec<_> = self.channels.lock_all().iter()
+        .flat_map(|channels| channels.values().cloned().collect::<Vec<_>>())
+        .collect();
+        HubMetrics { channels: channels.iter().map(|ch| ch.metrics()).collect() }
+    }
+
+    /// 按指定参数创建 channel，已存在时返回错误。之前按压缩 channel 打开过的，之后不再压缩
+    pub async fn open_channel(&self, ch_id: &K, options: &ChannelOptions) -> Result<Channel<K, T, M>> {
+        let last_seq = self.store.last_seq(ch_id).await?;
+        let mut channels = self.channels.lock(ch_id);
+        if channels.contains_key(ch_id) {
+            bail!("channel [{:?}] already exists", ch_id)
+        }
+        self.compacted.lock().swap_remove(ch_id);
+        Ok(self.add_ch(&mut channels, ch_id, options, None, last_seq).clone())
+    }
+
+    /// 创建压缩 channel，cache 里每个 key 只保留最新的一条，key 由 key_fn 从消息里取。
+    /// 适合 presence / 状态类 channel：新的 suber 从 first_seq 订阅，不用重放历史就能拿到当前状态。
+    /// 
+    /// hub 会记住 options 和 key_fn，channel 被 `close_channel` / `evict_idle` 移除后再自动创建时仍然压缩。
+    /// 
+    /// 注意：
+    /// - 同一个 key 的新消息到来时要从 cache 中间删掉旧的一条，是 O(capacity) 的，capacity 不宜太大
+    /// - 只有 cache 是压缩的，store 里是完整历史；suber 落后到 cache 之外从 store 补读时会重放没压缩的历史
+    pub async fn open_compacted_channel(&self, ch_id: &K, options: &ChannelOptions, key_fn: CompactKeyFn<T>) -> Result<Channel<K, T, M>> {
+        let last_seq = self.store.last_seq(ch_id).await?;
+        let mut channels = self.channels.lock(ch_id);
+        if channels.contains_key(ch_id) {
+            bail!("channel [{:?}] already exists", ch_id)
         }
+        self.compacted.lock().insert(ch_id.clone(), CompactedSpec { options: options.clone(), key_fn });
+        Ok(self.add_ch(&mut channels, ch_id, options, Some(key_fn), last_seq).clone())
+    }
+
+    /// 从 hub 移除并关闭 channel，suber 收到 `RecvOutput::Closed`，puber 之后 push 都会失败。
+    /// 之后再用这个 ch_id 会创建新的 channel，seq 从 store 里继续
+    pub async fn close_channel(&self, ch_id: &K) -> bool {
+        let ch = self.channels.remove(ch_id);
+        match ch {
+            Some(ch) => {
+                ch.close().await;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// 清空 channel cache，suber 收到 `RecvOutput::Reset` 后从下一条新消息开始读
+    pub async fn reset_channel(&self, ch_id: &K) -> Result<bool> {
+        let ch = self.channels.get(ch_id);
+        match ch {
+            Some(ch) => {
+                ch.reset().await?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// 移除空闲超过 `HubConfig::idle_timeout` 的 channel，返回被移除的 ch_id
+    pub fn evict_idle(&self) -> Vec<K> {
+        let timeout = match self.config.idle_timeout {
+            Some(timeout) => timeout,
+            None => return Vec::new(),
+        };
+
+        let mut evicted = Vec::new();
+        self.channels.retain(|ch_id, ch| {
+            // 只有 hub 自己持有时才算空闲；持有锁时不会有新的 handle 被创建
+            if ch.handles() > 1 {
+                ch.touch();
+                return true;
+            }
+            if ch.idle_time() < timeout {
+                return true;
+            }
+            evicted.push(ch_id.clone());
+            false
+        });
+        evicted
+    }
+
+    /// 后台每隔 interval 调用一次 `evict_idle`，hub 释放后自动退出
+    pub fn spawn_evictor(hub: &Arc<Self>, interval: Duration) -> JoinHandle<()>
+    where
+        Self: Send + Sync,
+        M: 'static,
+    {
+        let hub: Weak<Self> = Arc::downgrade(hub);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match hub.upgrade() {
+                    Some(hub) => { hub.evict_idle(); },
+                    None => return,
+                }
+            }
+        })
     }
 
     /// auto create channel if NOT exist
+    /// 
+    /// 已经不在 channel cache 里的消息由 `Suber::recv_next` 从 store 加载
     pub async fn subscribe(&self, ch_id: &K, suber: &mut Suber<K, T, M>, seq: u64) -> Result<()> {
         let ch = self.get_or_add_ch(ch_id).await?;
         suber.subscribe(&ch, seq)
     }
 
+    /// 持久订阅：从 name 在 store 里最后 ack 的 seq 之后继续，没有记录时从 seq 开始。
+    /// 处理完用 `Suber::ack` 确认，重新订阅后没 ack 的消息会再收到一次
+    pub async fn subscribe_durable(&self, ch_id: &K, name: &str, suber: &mut Suber<K, T, M>, seq: u64) -> Result<()> {
+        let acked = match self.store.load_cursor(ch_id, name).await? {
+            Some(acked) => acked,
+            None => seq.saturating_sub(1),
+        };
+        let ch = self.get_or_add_ch(ch_id).await?;
+        let store: Arc<dyn AckStore<K, T>> = self.store.clone();
+        suber.subscribe_durable(&ch, name, acked, store)
+    }
+
+    /// 加入 channel 上的消费组，组内每条消息只交给一个成员。
+    /// 组不存在时创建，从 seq 开始消费；已存在时忽略 seq
+    pub async fn join_group(&self, ch_id: &K, group: &str, seq: u64) -> Result<GroupMember<K, T, M>> {
+        let ch = self.get_or_add_ch(ch_id).await?;
+        let group = ch.group(group, seq);
+        Ok(GroupMember::join(ch, group))
+    }
+
+    /// 订阅所有 id 匹配 pattern 的 channel，包括之后新建的。
+    /// 已有的 channel 从最新消息开始，新建的 channel 从第一条消息开始
+    pub fn subscribe_pattern(&self, pattern: &ChPattern, suber: &mut Suber<K, T, M>) -> Result<()> 
+    where
+        K: AsRef<str>,
+    {
+        let shards = self.channels.lock_all();
+        let pending = suber.add_pattern(pattern)?;
+        for (ch_id, ch) in shards.iter().flat_map(|channels| channels.iter()) {
+            if pattern.matches(ch_id.as_ref()) {
+                suber.attach(pattern, ch, ch.tail_seq())?;
+            }
+        }
+
+        let matcher = {
+            let pattern = pattern.clone();
+            Box::new(move |ch_id: &K| pattern.matches(ch_id.as_ref()))
+        };
+        self.patterns.lock().push(PatternWatch { 
+            pattern: pattern.clone(), 
+            matcher, 
+            handle: suber.watcher().handle(), 
+            pending, 
+        });
+        Ok(())
+    }
+
+    /// 退订 pattern 以及通过它订阅的 channel
+    pub fn unsubscribe_pattern(&self, pattern: &ChPattern, suber: &mut Suber<K, T, M>) -> bool {
+        {
+            let _shards = self.channels.lock_all();
+            self.patterns.lock().retain(|w| !(w.handle.id() == suber.id() && w.pattern == *pattern));
+        }
+        suber.remove_pattern(pattern)
+    }
+
     /// auto create channel if NOT exist
-    pub async fn puber(&self, ch_id: &K) -> Result<Puber<K, T, M>> {
+    pub async fn puber(&self, ch_id: &K) -> Result<Puber<K, T, M, S>> {
         let ch = self.get_or_add_ch(ch_id).await?;
-        Ok(ch.clone())
+        Ok(Puber { ch, store: self.store.clone() })
     }
 
     async fn get_or_add_ch(&self, ch_id: &K) -> Result<Channel<K, T, M>> {
-        let mut channels = self.channels.lock();
-        let ch = channels.entry(ch_id.clone()).or_insert_with(||Channel::with_capacity(ch_id.clone(), 16));
+        if let Some(ch) = self.channels.get(ch_id) {
+            return Ok(ch)
+        }
+
+        // store 可能要读文件，不能持有分片锁，否则一个慢 channel 卡住整个分片
+        let last_seq = self.store.last_seq(ch_id).await?;
+        let mut channels = self.channels.lock(ch_id);
+        if let Some(ch) = channels.get(ch_id) {
+            return Ok(ch.clone())
+        }
+
+        let spec = self.compacted.lock().get(ch_id).map(|spec| (spec.options.clone(), spec.key_fn));
+        let ch = match spec {
+            Some((options, key_fn)) => self.add_ch(&mut channels, ch_id, &options, Some(key_fn), last_seq),
+            None => self.add_ch(&mut channels, ch_id, &self.config.channel, None, last_seq),
+        };
         Ok(ch.clone())
     }
+
+    /// last_seq 由调用方在拿分片锁之前从 store 读出来
+    fn add_ch<'a>(&self, channels: &'a mut VecMap<K, Channel<K, T, M>>, ch_id: &K, options: &ChannelOptions, key_fn: Option<CompactKeyFn<T>>, last_seq: u64) -> &'a Channel<K, T, M> {
+        let cold: Arc<dyn ColdLoad<K, T>> = self.store.clone();
+        let ch = Channel::with_options(ch_id.clone(), options, last_seq, Some(cold));
+        if let Some(key_fn) = key_fn {
+            ch.set_compaction(key_fn);
+        }
+        self.attach_patterns(&ch);
+        let (index, _old) = channels.insert_full(ch_id.clone(), ch);
+        &channels[index]
+    }
+
+    /// 新建的 channel 交给匹配的 pattern 订阅者，同时挂上 bus 好唤醒 suber
+    fn attach_patterns(&self, ch: &Channel<K, T, M>) {
+        let mut patterns = self.patterns.lock();
+        // suber 已经释放
+        patterns.retain(|w| Arc::strong_count(&w.pending) > 1);
+        for w in patterns.iter() {
+            if (w.matcher)(ch.ch_id()) {
+                ch.watch(&w.handle);
+                w.pending.lock().push((ch.clone(), ch.tail_seq()));
+            }
+        }
+    }
 }
 
-pub type Puber<K, T, M> = Channel<K, T, M>;
+pub struct Puber<K, T, M, S = NoStore> 
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    ch: Channel<K, T, M>,
+    store: Arc<S>,
+}
+
+impl<K, T, M, S> Puber<K, T, M, S> 
+where
+    K: ChIdOp,
+    T: Clone + GetSeq + WithSeq,
+    M: MpscOp<Event<K, T>>,
+    S: ChStore<K, T>,
+{
+    pub fn channel(&self) -> &Channel<K, T, M> {
+        &self.ch
+    }
+
+    /// 先写 store 再进 cache 和广播，subers 看到的消息都已经持久化
+    pub async fn push(&self, v: T::Value) -> Result<T> {
+        let _guard = self.ch.lock_write().await;
+        self.ch.check_open()?;
+        let msg = T::with_seq(self.ch.tail_seq(), v);
+        self.store.async_write(self.ch.ch_id(), msg.clone()).await?;
+        self.ch.push_raw(msg.clone()).await?;
+        Ok(msg)
+    }
+}
+
+impl<K, T, M, S> Drop for Puber<K, T, M, S> 
+where
+    K: ChIdOp,
+    T: Clone,
+    M: MpscOp<Event<K, T>>,
+{
+    fn drop(&mut self) {
+        self.ch.touch();
+    }
+}
 
 // pub struct Puber<T> {
 //     none: PhantomData<T>,
@@ -99,7 +435,7 @@ pub type Puber<K, T, M> = Channel<K, T, M>;
 
 #[cfg(test)]
 mod test {
-    use crate::{ch_common::{SeqVal, uid::ChId}, ch_hub::store::mem_store::ChMemStore, mpsc_ch::mpsc_crossbeam_que::Mpsc};
+    use crate::{ch_common::{SeqVal, RecvOutput, uid::ChId}, ch_hub::{store::mem_store::ChMemStore, file_store::{ChFileStore, FileStoreConfig}}, mpsc_ch::mpsc_crossbeam_que::Mpsc};
 
     use super::*;
     
@@ -114,15 +450,544 @@ mod test {
         assert!(r.is_ok(), "r={:?}", r);
         
         let puber = hub.puber(&ChId::new(1)).await.unwrap();
-        puber.push(1_u64).unwrap();
+        puber.push(1_u64).await.unwrap();
 
         let r: Message = suber.async_recv().await.unwrap();
         assert_eq!(r, Message::new(1, 1));
     }
 
+    #[tokio::test]
+    async fn test_metrics() {
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let mut suber = Suber::with_inbox_cap(4);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=100 {
+            puber.push(n).await.unwrap();
+        }
+
+        let metrics = hub.metrics();
+        assert_eq!(metrics.channels.len(), 1);
+        assert_eq!(metrics.published(), 100);
+        let ch = &metrics.channels[0];
+        assert_eq!(ch.tail_seq, 101);
+        assert_eq!(ch.subers.len(), 1);
+        assert_eq!(ch.subers[0].id, *suber.id());
+        assert_eq!(ch.subers[0].distance, 100);
+        assert_eq!(ch.subers[0].inbox_high_water, 4);
+        assert_eq!(ch.subers[0].inbox_cap, 4);
+
+        // 没有 store，落后超过 channel cache 的部分收到 Lagged
+        let mut values = 0;
+        while let Ok(r) = tokio::time::timeout(Duration::from_millis(20), suber.recv_next()).await {
+            match r {
+                RecvOutput::Value(..) => values += 1,
+                RecvOutput::Lagged(_id) => {},
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+        let metrics = hub.metrics();
+        let stat = &metrics.channels[0].subers[0];
+        assert_eq!(stat.delivered, values);
+        assert!(stat.lagged >= 1);
+        assert_eq!(stat.distance, 0);
+        assert_eq!(metrics.table(10).rows(), 2);
+
+        suber.unsubscribe(&ch_id);
+        assert!(hub.metrics().channels[0].subers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_when_waiting() {
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        let puber = hub.puber(&ch_id).await.unwrap();
+
+        // suber 已经在等，消息从收件箱直接送到 recv_next
+        let task = tokio::spawn(async move {
+            for _ in 0..5 {
+                match suber.recv_next().await {
+                    RecvOutput::Value(..) => {},
+                    r => panic!("unexpected {:?}", r),
+                }
+            }
+            suber
+        });
+        for n in 1..=5 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            puber.push(n).await.unwrap();
+        }
+        let suber = task.await.unwrap();
+
+        let metrics = hub.metrics();
+        let stat = &metrics.channels[0].subers[0];
+        assert_eq!(stat.id, *suber.id());
+        assert_eq!(stat.delivered, 5);
+        assert_eq!(stat.distance, 0);
+    }
+
+    #[tokio::test]
+    async fn test_history_from_store() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(ChMemStore::with_capacity(1000));
+
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=100 {
+            puber.push(n).await.unwrap();
+        }
+        // channel cache 只保留最后 16 条
+        assert!(puber.channel().first_seq() > 1);
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        for n in 1..=100 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+
+        puber.push(101).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(101, 101)));
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_when_lagged() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(ChMemStore::with_capacity(1000));
+        let puber = hub.puber(&ch_id).await.unwrap();
+
+        let mut suber = Suber::with_inbox_cap(4);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        puber.push(1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(1, 1)));
+
+        // 超过 inbox 和 channel cache 的容量
+        for n in 2..=200 {
+            puber.push(n).await.unwrap();
+        }
+        for n in 2..=200 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+
+        // 追上之后回到 in sync
+        puber.push(201).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(201, 201)));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_when_store_evicted() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(ChMemStore::with_capacity(20));
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=100 {
+            puber.push(n).await.unwrap();
+        }
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Lagged(ch_id));
+        for n in 81..=100 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagged_without_store() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=100 {
+            puber.push(n).await.unwrap();
+        }
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Lagged(ch_id));
+
+        // 跳过丢失的部分，从 cache 里最早的消息继续
+        for n in 85..=100 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resubscribe_after_restart() { 
+        let root = std::env::temp_dir().join(format!("ch-hub-restart-{}", std::process::id()));
+        let _r = std::fs::remove_dir_all(&root);
+        let config = FileStoreConfig { segment_bytes: 256, ..Default::default() };
+        let ch_id = ChId::new(7);
+
+        {
+            let store = ChFileStore::open(&root, config.clone()).unwrap();
+            let hub = Hub::<ChId, Message, Mpsc, _>::with_store(store);
+            let puber = hub.puber(&ch_id).await.unwrap();
+            for n in 1..=50 {
+                puber.push(n).await.unwrap();
+            }
+        }
+
+        let store = ChFileStore::open(&root, config).unwrap();
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(store);
+        let puber = hub.puber(&ch_id).await.unwrap();
+        assert_eq!(puber.channel().tail_seq(), 51);
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 20).await.unwrap();
+        puber.push(51).await.unwrap();
+        for n in 20..=51 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+
+        let _r = std::fs::remove_dir_all(&root);
+    }
+
     type Message = SeqVal<u64>;
-}
 
+    #[tokio::test]
+    async fn test_channel_options() { 
+        let config = HubConfig { 
+            channel: ChannelOptions { capacity: 4, ..Default::default() }, 
+            ..Default::default() 
+        };
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_config(NoStore, config);
 
+        let puber = hub.puber(&ChId::new(1)).await.unwrap();
+        assert_eq!(puber.channel().capacity(), 4);
+
+        let ch = hub.open_channel(&ChId::new(2), &ChannelOptions { capacity: 100, ..Default::default() }).await.unwrap();
+        assert_eq!(ch.capacity(), 100);
+        assert!(hub.open_channel(&ChId::new(2), &ChannelOptions::default()).await.is_err());
+        assert_eq!(hub.channels(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_age() { 
+        let ch_id = ChId::new(1);
+        let options = ChannelOptions { capacity: 100, max_age: Some(std::time::Duration::from_millis(50)) };
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let ch = hub.open_channel(&ch_id, &options).await.unwrap();
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=10 {
+            puber.push(n).await.unwrap();
+        }
+        assert_eq!(ch.first_seq(), 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(80)).await;
+        puber.push(11).await.unwrap();
+        assert_eq!(ch.first_seq(), 11);
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Lagged(ch_id));
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(11, 11)));
+    }
+
+    #[tokio::test]
+    async fn test_compacted_channel() { 
+        // 消息是 user_id * 100 + 状态，按 user_id 压缩
+        fn user_of(v: &Message) -> u64 {
+            v.1 / 100
+        }
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let ch = hub.open_compacted_channel(&ch_id, &ChannelOptions::default(), user_of).await.unwrap();
+        assert!(ch.is_compacted());
+        assert!(hub.open_compacted_channel(&ch_id, &ChannelOptions::default(), user_of).await.is_err());
 
+        let puber = hub.puber(&ch_id).await.unwrap();
+        for round in 1..=5 {
+            for user in 1..=3 {
+                puber.push(user * 100 + round).await.unwrap();
+            }
+        }
+
+        // 新订阅的只收到每个 user 最新的状态
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        for (seq, user) in (13..=15).zip(1..=3) {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(seq, user * 100 + 5)));
+        }
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+
+        puber.push(206).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(16, 206)));
+
+        // 关闭后自动重建的 channel 仍然压缩，没有 store 所以 seq 从头开始
+        drop(puber);
+        assert!(hub.close_channel(&ch_id).await);
+        let puber = hub.puber(&ch_id).await.unwrap();
+        assert!(puber.ch.is_compacted());
+        for round in 1..=3 {
+            puber.push(100 + round).await.unwrap();
+        }
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(3, 103)));
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+
+        // 重新按普通 channel 打开就不再压缩
+        drop(puber);
+        assert!(hub.close_channel(&ch_id).await);
+        assert!(!hub.open_channel(&ch_id, &ChannelOptions::default()).await.unwrap().is_compacted());
+        assert!(hub.close_channel(&ch_id).await);
+        assert!(!hub.puber(&ch_id).await.unwrap().ch.is_compacted());
+    }
+
+    #[tokio::test]
+    async fn test_compacted_channel_evicted() { 
+        fn user_of(v: &Message) -> u64 {
+            v.1 / 100
+        }
+        let ch_id = ChId::new(1);
+        let config = HubConfig { idle_timeout: Some(Duration::ZERO), ..Default::default() };
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_config(ChMemStore::with_capacity(1000), config);
+        let options = ChannelOptions { capacity: 8, ..Default::default() };
+        drop(hub.open_compacted_channel(&ch_id, &options, user_of).await.unwrap());
+        assert_eq!(hub.evict_idle(), vec![ch_id]);
+
+        // 按原来的 options 和 key_fn 重建
+        let puber = hub.puber(&ch_id).await.unwrap();
+        assert!(puber.ch.is_compacted());
+        for user in 1..=10 {
+            puber.push(user * 100).await.unwrap();
+            puber.push(user * 100 + 1).await.unwrap();
+        }
+        assert_eq!(puber.ch.metrics().first_seq, 6);
+    }
 
+    #[tokio::test]
+    async fn test_close_channel() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(ChMemStore::with_capacity(1000));
+        let puber = hub.puber(&ch_id).await.unwrap();
+
+        let mut suber1 = Suber::with_inbox_cap(16);
+        let mut suber2 = Suber::with_inbox_cap(2);
+        hub.subscribe(&ch_id, &mut suber1, 1).await.unwrap();
+        hub.subscribe(&ch_id, &mut suber2, 1).await.unwrap();
+        for n in 1..=5 {
+            puber.push(n).await.unwrap();
+        }
+
+        assert!(hub.close_channel(&ch_id).await);
+        assert!(!hub.close_channel(&ch_id).await);
+        assert_eq!(hub.channels(), 0);
+        assert!(puber.push(6).await.is_err());
+
+        // suber 先收到 close 之前的消息
+        for n in 1..=5 {
+            assert_eq!(suber1.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+        assert_eq!(suber1.recv_next().await, RecvOutput::Closed(ch_id));
+        assert_eq!(suber1.channels(), 0);
+        assert_eq!(suber1.try_recv(), RecvOutput::None);
+
+        // inbox 满了也能收到 Closed
+        for n in 1..=5 {
+            assert_eq!(suber2.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+        assert_eq!(suber2.recv_next().await, RecvOutput::Closed(ch_id));
+        assert_eq!(suber2.channels(), 0);
+
+        // 重新创建的 channel 从 store 里的 seq 继续
+        let puber = hub.puber(&ch_id).await.unwrap();
+        assert_eq!(puber.push(6).await.unwrap(), Message::new(6, 6));
+    }
+
+    #[tokio::test]
+    async fn test_reset_channel() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let puber = hub.puber(&ch_id).await.unwrap();
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ch_id, &mut suber, 1).await.unwrap();
+        for n in 1..=5 {
+            puber.push(n).await.unwrap();
+        }
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(1, 1)));
+
+        assert!(hub.reset_channel(&ch_id).await.unwrap());
+        assert!(!hub.reset_channel(&ChId::new(2)).await.unwrap());
+        assert_eq!(puber.channel().first_seq(), 6);
+
+        // 没读的都不要了
+        assert_eq!(suber.recv_next().await, RecvOutput::Reset(ch_id));
+        puber.push(6).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(6, 6)));
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+    }
+
+    #[tokio::test]
+    async fn test_evict_idle() { 
+        let idle = std::time::Duration::from_millis(50);
+        let config = HubConfig { idle_timeout: Some(idle), ..Default::default() };
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_config(NoStore, config);
+
+        let puber = hub.puber(&ChId::new(1)).await.unwrap();
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&ChId::new(2), &mut suber, 1).await.unwrap();
+        let _ch = hub.puber(&ChId::new(3)).await.unwrap();
+        drop(_ch);
+
+        tokio::time::sleep(idle * 2).await;
+        assert_eq!(hub.evict_idle(), vec![ChId::new(3)]);
+
+        // 有 puber 或者 suber 的不会被移除，释放后重新计时
+        drop(puber);
+        suber.unsubscribe(&ChId::new(2));
+        assert!(hub.evict_idle().is_empty());
+        assert_eq!(hub.channels(), 2);
+
+        let hub = Arc::new(hub);
+        let task = Hub::spawn_evictor(&hub, idle / 5);
+        tokio::time::sleep(idle * 3).await;
+        assert_eq!(hub.channels(), 0);
+        drop(hub);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_pattern() { 
+        let hub = Hub::<String, Message, Mpsc>::new();
+        let room1 = "tenant-x/room-1".to_string();
+        let room2 = "tenant-x/room-2".to_string();
+        let other = "tenant-y/room-1".to_string();
+
+        let puber1 = hub.puber(&room1).await.unwrap();
+        puber1.push(1).await.unwrap();
+
+        let mut suber = Suber::with_inbox_cap(16);
+        let pattern = ChPattern::prefix("tenant-x/");
+        hub.subscribe_pattern(&pattern, &mut suber).unwrap();
+        assert!(hub.subscribe_pattern(&pattern, &mut suber).is_err());
+        assert_eq!(suber.channels(), 1);
+
+        // 已有的 channel 从最新消息开始
+        puber1.push(2).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(room1.clone(), Message::new(2, 2)));
+
+        // 之后新建的 channel 自动订阅，不丢第一条
+        let puber2 = hub.puber(&room2).await.unwrap();
+        let puber3 = hub.puber(&other).await.unwrap();
+        puber3.push(1).await.unwrap();
+        puber2.push(1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(room2.clone(), Message::new(1, 1)));
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+        assert_eq!(suber.channels(), 2);
+
+        assert!(hub.unsubscribe_pattern(&pattern, &mut suber));
+        assert!(!hub.unsubscribe_pattern(&pattern, &mut suber));
+        assert_eq!(suber.channels(), 0);
+        assert_eq!(puber2.channel().subers(), 0);
+        puber2.push(2).await.unwrap();
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+
+        hub.puber(&"tenant-x/room-3".to_string()).await.unwrap();
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+        assert_eq!(suber.channels(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_glob_with_exact() { 
+        let hub = Hub::<String, Message, Mpsc>::new();
+        let room1 = "tenant-x/room-1".to_string();
+        let lobby = "tenant-x/lobby".to_string();
+
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe(&room1, &mut suber, 1).await.unwrap();
+        let pattern = ChPattern::glob("tenant-*/room-?");
+        hub.subscribe_pattern(&pattern, &mut suber).unwrap();
+        assert_eq!(suber.patterns().collect::<Vec<_>>(), vec![&pattern]);
+
+        let puber = hub.puber(&lobby).await.unwrap();
+        puber.push(1).await.unwrap();
+        assert_eq!(suber.try_recv(), RecvOutput::None);
+
+        // 精确订阅的 channel 不受 pattern 退订影响
+        hub.unsubscribe_pattern(&pattern, &mut suber);
+        assert_eq!(suber.channels(), 1);
+        let puber = hub.puber(&room1).await.unwrap();
+        puber.push(1).await.unwrap();
+        assert_eq!(suber.recv_next().await, RecvOutput::Value(room1.clone(), Message::new(1, 1)));
+
+        // suber 释放后 hub 不再挂它
+        hub.subscribe_pattern(&pattern, &mut suber).unwrap();
+        drop(suber);
+        let puber = hub.puber(&"tenant-z/room-9".to_string()).await.unwrap();
+        assert_eq!(puber.channel().subers(), 0);
+        assert_eq!(hub.patterns.lock().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_join_group() { 
+        let ch_id = ChId::new(1);
+        let hub = Hub::<ChId, Message, Mpsc>::new();
+        let puber = hub.puber(&ch_id).await.unwrap();
+        puber.push(1).await.unwrap();
+
+        let m1 = hub.join_group(&ch_id, "workers", 1).await.unwrap();
+        // 组已存在，seq 被忽略
+        let m2 = hub.join_group(&ch_id, "workers", 100).await.unwrap();
+        assert_eq!(m2.group(), "workers");
+        assert_eq!(m2.members(), 2);
+
+        puber.push(2).await.unwrap();
+        assert_eq!(m2.recv_next().await, RecvOutput::Value(ch_id, Message::new(1, 1)));
+        assert_eq!(m1.recv_next().await, RecvOutput::Value(ch_id, Message::new(2, 2)));
+        assert_eq!(m1.try_recv(), RecvOutput::None);
+        assert_eq!(m2.try_recv(), RecvOutput::None);
+    }
+
+    #[tokio::test]
+    async fn test_durable_subscribe() { 
+        let root = std::env::temp_dir().join(format!("ch-hub-durable-{}", std::process::id()));
+        let _r = std::fs::remove_dir_all(&root);
+        let ch_id = ChId::new(8);
+
+        {
+            let store = ChFileStore::open(&root, FileStoreConfig::default()).unwrap();
+            let hub = Hub::<ChId, Message, Mpsc, _>::with_store(store);
+            let puber = hub.puber(&ch_id).await.unwrap();
+            for n in 1..=10 {
+                puber.push(n).await.unwrap();
+            }
+
+            let mut suber = Suber::with_inbox_cap(16);
+            hub.subscribe_durable(&ch_id, "c1", &mut suber, 3).await.unwrap();
+            assert_eq!(suber.acked(&ch_id), Some(2));
+            for n in 3..=6 {
+                assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+            }
+            // 还没收到的不能 ack
+            assert!(suber.ack(&ch_id, 7).await.is_err());
+            suber.ack(&ch_id, 5).await.unwrap();
+            suber.ack(&ch_id, 4).await.unwrap();
+            assert_eq!(suber.acked(&ch_id), Some(5));
+
+            let mut plain = Suber::with_inbox_cap(16);
+            hub.subscribe(&ch_id, &mut plain, 1).await.unwrap();
+            assert!(plain.ack(&ch_id, 1).await.is_err());
+            assert_eq!(plain.acked(&ch_id), None);
+        }
+
+        // 重启后从最后 ack 的之后继续，6 没 ack 会再收到一次
+        let store = ChFileStore::open(&root, FileStoreConfig::default()).unwrap();
+        let hub = Hub::<ChId, Message, Mpsc, _>::with_store(store);
+        let mut suber = Suber::with_inbox_cap(16);
+        hub.subscribe_durable(&ch_id, "c1", &mut suber, 1).await.unwrap();
+        for n in 6..=10 {
+            assert_eq!(suber.recv_next().await, RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+
+        // 另一个名字没有记录，从指定的 seq 开始
+        let mut other = Suber::with_inbox_cap(16);
+        hub.subscribe_durable(&ch_id, "c2", &mut other, 9).await.unwrap();
+        assert_eq!(other.recv_next().await, RecvOutput::Value(ch_id, Message::new(9, 9)));
+
+        let _r = std::fs::remove_dir_all(&root);
+    }
+}
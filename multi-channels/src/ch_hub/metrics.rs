@@ -0,0 +1,109 @@
+//! hub 的运行指标快照，见 `Hub::metrics`、`Channel::metrics`
+//!
+//! - channel：发布的消息数、cache 范围
+//! - suber（按所在 channel）：收到的消息数、Lagged 次数、cursor 落后 tail 的距离
+//! - suber inbox：当前长度、最高水位、bus 丢掉的事件数（这几个是 suber 在所有 channel 上合计的）
+//!
+//! `table` 转成 `cli_graph::table::Table` 在终端显示
+
+use crate::ch_common::uid::SuberId;
+use crate::cli_graph::table::Table;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuberMetrics {
+    pub id: SuberId,
+    /// 从这个 channel 收到的消息数
+    pub delivered: u64,
+    /// 在这个 channel 上收到 Lagged 的次数
+    pub lagged: u64,
+    /// channel tail_seq 减去 suber 下一个要读的 seq
+    pub distance: u64,
+    pub dropped: u64,
+    pub inbox_len: usize,
+    pub inbox_high_water: usize,
+    pub inbox_cap: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMetrics<K> {
+    pub ch_id: K,
+    pub published: u64,
+    pub first_seq: u64,
+    pub tail_seq: u64,
+    pub subers: Vec<SuberMetrics>,
+}
+
+impl<K> ChannelMetrics<K> {
+    pub fn delivered(&self) -> u64 {
+        self.subers.iter().map(|s| s.delivered).sum()
+    }
+
+    pub fn lagged(&self) -> u64 {
+        self.subers.iter().map(|s| s.lagged).sum()
+    }
+
+    /// 落后最多的 suber 的距离
+    pub fn max_distance(&self) -> u64 {
+        self.subers.iter().map(|s| s.distance).max().unwrap_or(0)
+    }
+
+    pub fn max_inbox_high_water(&self) -> usize {
+        self.subers.iter().map(|s| s.inbox_high_water).max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubMetrics<K> {
+    pub channels: Vec<ChannelMetrics<K>>,
+}
+
+impl<K> HubMetrics<K>
+where
+    K: std::fmt::Debug,
+{
+    pub fn published(&self) -> u64 {
+        self.channels.iter().map(|c| c.published).sum()
+    }
+
+    pub fn delivered(&self) -> u64 {
+        self.channels.iter().map(|c| c.delivered()).sum()
+    }
+
+    pub fn lagged(&self) -> u64 {
+        self.channels.iter().map(|c| c.lagged()).sum()
+    }
+
+    /// 第一行是合计，之后是落后最多的 top 个 channel，bar 按落后距离画
+    pub fn table(&self, top: usize) -> Table {
+        let mut table = Table::new(&["published", "delivered", "lagged", "subers", "inbox_hw", "distance"]);
+        table.bar_column(5);
+        table.row(
+            format!("total({})", self.channels.len()),
+            vec![
+                self.published(),
+                self.delivered(),
+                self.lagged(),
+                self.channels.iter().map(|c| c.subers.len() as u64).sum(),
+                self.channels.iter().map(|c| c.max_inbox_high_water() as u64).max().unwrap_or(0),
+                self.channels.iter().map(|c| c.max_distance()).max().unwrap_or(0),
+            ],
+        );
+
+        let mut channels: Vec<_> = self.channels.iter().collect();
+        channels.sort_by_key(|c| std::cmp::Reverse((c.max_distance(), c.published)));
+        for c in channels.into_iter().take(top) {
+            table.row(
+                format!("{:?}", c.ch_id),
+                vec![
+                    c.published,
+                    c.delivered(),
+                    c.lagged(),
+                    c.subers.len() as u64,
+                    c.max_inbox_high_water() as u64,
+                    c.max_distance(),
+                ],
+            );
+        }
+        table
+    }
+}
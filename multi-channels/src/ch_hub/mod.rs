@@ -3,7 +3,25 @@ pub mod hub;
 
 pub mod store;
 
+pub mod file_store;
+
 mod channel1;
+pub use channel1::stream::{merge, filter_channels};
+pub use channel1::{suber::Suber, channel::ChannelOptions};
 
 pub mod event;
 
+pub mod pattern;
+
+pub mod registry;
+
+pub mod bench_hub;
+
+pub mod net;
+
+pub mod rpc;
+
+pub mod metrics;
+
+#[cfg(feature = "serde")]
+pub mod serde_codec;
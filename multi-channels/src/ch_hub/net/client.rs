@@ -0,0 +1,429 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::ch_common::{ChIdOp, Codec, GetSeq, RecvOutput, VecMap, WithSeq};
+
+use super::frame::{Frame, FrameReader};
+use super::server::encode;
+
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(50);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+struct Conn {
+    rd: OwnedReadHalf,
+    wr: OwnedWriteHalf,
+    reader: FrameReader,
+}
+
+impl Conn {
+    async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let (rd, wr) = stream.into_split();
+        Ok(Self { rd, wr, reader: FrameReader::new() })
+    }
+}
+
+/// 远程版本的 `Suber`，断线后自动重连，并从每个 channel 最后收到的 seq 之后继续订阅
+pub struct RemoteSuber<K, T> {
+    addr: String,
+    conn: Option<Conn>,
+    /// next seq of each subscribed channel
+    positions: VecMap<K, u64>,
+    none: PhantomData<T>,
+}
+
+impl<K, T> RemoteSuber<K, T>
+where
+    K: ChIdOp + Codec,
+    T: GetSeq + Codec,
+{
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let conn = Conn::connect(addr).await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            conn: Some(conn),
+            positions: VecMap::new(),
+            none: PhantomData,
+        })
+    }
+
+    /// 断线时只记录下来，重连后再订阅
+    pub async fn subscribe(&mut self, ch_id: &K, seq: u64) -> Result<()> {
+        if self.positions.contains_key(ch_id) {
+            bail!("already subscribed channel [{:?}]", ch_id)
+        }
+        self.positions.insert(ch_id.clone(), seq);
+        self.send(Frame::Subscribe { ch_id: encode(ch_id), seq }).await;
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&mut self, ch_id: &K) -> bool {
+        if self.positions.swap_remove(ch_id).is_none() {
+            return false;
+        }
+        self.send(Frame::Unsubscribe { ch_id: encode(ch_id) }).await;
+        true
+    }
+
+    pub fn channels(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// 断线时在这里重连，调用方只会看到消息和 Lagged。
+    /// 服务端返回的错误（比如订阅失败）作为 Err 返回，之后可以继续收
+    pub async fn recv_next(&mut self) -> Result<RecvOutput<K, T>> {
+        loop {
+            let conn = match self.conn.as_mut() {
+                Some(conn) => conn,
+                None => {
+                    self.reconnect().await;
+                    continue;
+                }
+            };
+
+            let frame = match conn.reader.read_frame(&mut conn.rd).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) | Err(_) => {
+                    self.conn = None;
+                    continue;
+                }
+            };
+
+            match frame {
+                Frame::Msg { ch_id, msg } => {
+                    let (ch_id, msg) = match (K::decode(&ch_id), T::decode(&msg)) {
+                        (Ok(ch_id), Ok(msg)) => (ch_id, msg),
+                        // 跳过解不出来的消息，重连也拿不到别的结果
+                        _ => continue,
+                    };
+
+                    // 已经退订的或者重连后重复收到的都丢掉
+                    match self.positions.get_mut(&ch_id) {
+                        Some(next) if msg.get_seq() >= *next => {
+                            *next = msg.get_seq() + 1;
+                            return Ok(RecvOutput::Value(ch_id, msg));
+                        }
+                        _ => {}
+                    }
+                }
+                Frame::Lagged { ch_id } => {
+                    if let Ok(ch_id) = K::decode(&ch_id) {
+                        if self.positions.contains_key(&ch_id) {
+                            return Ok(RecvOutput::Lagged(ch_id));
+                        }
+                    }
+                }
+                Frame::Closed { ch_id } => {
+                    if let Ok(ch_id) = K::decode(&ch_id) {
+                        if self.positions.swap_remove(&ch_id).is_some() {
+                            return Ok(RecvOutput::Closed(ch_id));
+                        }
+                    }
+                }
+                Frame::Reset { ch_id, seq } => {
+                    if let Ok(ch_id) = K::decode(&ch_id) {
+                        if let Some(next) = self.positions.get_mut(&ch_id) {
+                            // 重连后从 reset 之后订阅，不再收到之前的消息
+                            *next = (*next).max(seq);
+                            return Ok(RecvOutput::Reset(ch_id));
+                        }
+                    }
+                }
+                Frame::Error { text } => return Err(anyhow!("server error [{}]", text)),
+                _ => {}
+            }
+        }
+    }
+
+    async fn send(&mut self, frame: Frame) {
+        if let Some(conn) = self.conn.as_mut() {
+            if frame.write_to(&mut conn.wr).await.is_err() {
+                self.conn = None;
+            }
+        }
+    }
+
+    async fn reconnect(&mut self) {
+        let mut delay = MIN_RETRY_DELAY;
+        loop {
+            if let Ok(conn) = self.try_reconnect().await {
+                self.conn = Some(conn);
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    async fn try_reconnect(&self) -> Result<Conn> {
+        let mut conn = Conn::connect(&self.addr).await?;
+        for (ch_id, seq) in self.positions.iter() {
+            Frame::Subscribe { ch_id: encode(ch_id), seq: *seq }.write_to(&mut conn.wr).await?;
+        }
+        Ok(conn)
+    }
+}
+
+/// 远程版本的 `Puber`，push 等服务端写入后返回带 seq 的消息。
+/// 断线后下一次 push 时重连，失败的那次不会重发，避免重复写入。
+/// push 被取消时连接也丢掉，否则下一次 push 会读到上一次的 PubAck
+pub struct RemotePuber<K, T> {
+    addr: String,
+    ch_id: K,
+    conn: tokio::sync::Mutex<Option<Conn>>,
+    none: PhantomData<T>,
+}
+
+impl<K, T> RemotePuber<K, T>
+where
+    K: ChIdOp + Codec,
+    T: WithSeq,
+    T::Value: Codec,
+{
+    pub async fn connect(addr: &str, ch_id: &K) -> Result<Self> {
+        let conn = Conn::connect(addr).await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            ch_id: ch_id.clone(),
+            conn: tokio::sync::Mutex::new(Some(conn)),
+            none: PhantomData,
+        })
+    }
+
+    pub fn ch_id(&self) -> &K {
+        &self.ch_id
+    }
+
+    pub async fn push(&self, v: T::Value) -> Result<T> {
+        let mut guard = self.conn.lock().await;
+        // 拿出来用，成功后才放回去
+        let mut conn = match guard.take() {
+            Some(conn) => conn,
+            None => Conn::connect(&self.addr).await?,
+        };
+
+        let seq = Self::publish(&mut conn, &self.ch_id, &v).await?;
+        *guard = Some(conn);
+        Ok(T::with_seq(seq, v))
+    }
+
+    async fn publish(conn: &mut Conn, ch_id: &K, v: &T::Value) -> Result<u64> {
+        Frame::Publish { ch_id: encode(ch_id), value: encode(v) }.write_to(&mut conn.wr).await?;
+        loop {
+            match conn.reader.read_frame(&mut conn.rd).await? {
+                Some(Frame::PubAck { seq }) => return Ok(seq),
+                Some(Frame::Error { text }) => bail!("publish failed [{}]", text),
+                Some(_frame) => {}
+                None => bail!("connection closed"),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    use crate::{ch_common::{SeqVal, uid::ChId}, ch_hub::{hub::{Hub, HubConfig}, store::mem_store::ChMemStore, net::HubServer}, mpsc_ch::mpsc_crossbeam_que::Mpsc};
+
+    use super::*;
+
+    type Message = SeqVal<u64>;
+    type TestHub = Hub<ChId, Message, Mpsc, ChMemStore<ChId, Message>>;
+
+    async fn start_server(hub: &Arc<TestHub>, addr: &str) -> HubServer {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        HubServer::start(listener, hub.clone(), 64).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pub_sub() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+        let ch_id = ChId::new(1);
+
+        let puber = RemotePuber::<ChId, Message>::connect(&addr, &ch_id).await.unwrap();
+        for n in 1..=10 {
+            assert_eq!(puber.push(n).await.unwrap(), Message::new(n, n));
+        }
+
+        // 历史消息和之后的实时消息
+        let mut suber = RemoteSuber::<ChId, Message>::connect(&addr).await.unwrap();
+        suber.subscribe(&ch_id, 3).await.unwrap();
+        assert!(suber.subscribe(&ch_id, 3).await.is_err());
+        for n in 3..=10 {
+            assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+        puber.push(11).await.unwrap();
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(11, 11)));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_idle_puber_released() {
+        let idle = std::time::Duration::from_millis(50);
+        let config = HubConfig { idle_timeout: Some(idle), ..Default::default() };
+        let hub = Arc::new(TestHub::with_config(ChMemStore::with_capacity(1000), config));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+        let ch_id = ChId::new(1);
+
+        // 连接还在，但是很久没发布，服务端不再持有这个 channel
+        let puber = RemotePuber::<ChId, Message>::connect(&addr, &ch_id).await.unwrap();
+        puber.push(1).await.unwrap();
+        tokio::time::sleep(idle * 4).await;
+        assert_eq!(hub.evict_idle(), vec![ch_id]);
+
+        // 再发布时重新创建，seq 从 store 继续
+        assert_eq!(puber.push(2).await.unwrap(), Message::new(2, 2));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_reconnect() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+        let ch_id = ChId::new(1);
+        let local_puber = hub.puber(&ch_id).await.unwrap();
+
+        let mut suber = RemoteSuber::<ChId, Message>::connect(&addr).await.unwrap();
+        suber.subscribe(&ch_id, 1).await.unwrap();
+        for n in 1..=5 {
+            local_puber.push(n).await.unwrap();
+            assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+
+        // 服务端断开期间继续发布
+        server.stop().await;
+        for n in 6..=30 {
+            local_puber.push(n).await.unwrap();
+        }
+
+        let server = start_server(&hub, &addr).await;
+        for n in 6..=30 {
+            assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(n, n)));
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_push() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+        let ch_id = ChId::new(1);
+
+        // 发出去了但没等到 PubAck 就取消
+        let puber = RemotePuber::<ChId, Message>::connect(&addr, &ch_id).await.unwrap();
+        let mut push = Box::pin(puber.push(1));
+        assert!(futures::poll!(&mut push).is_pending());
+        drop(push);
+
+        // 下一次 push 拿到的是自己的 seq，不是上一次的
+        let msg = puber.push(2).await.unwrap();
+        let mut suber = RemoteSuber::<ChId, Message>::connect(&addr).await.unwrap();
+        suber.subscribe(&ch_id, 1).await.unwrap();
+        let mut recved = Vec::new();
+        for _ in 0..2 {
+            match suber.recv_next().await.unwrap() {
+                RecvOutput::Value(_ch_id, msg) => recved.push(msg),
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+        recved.sort_by_key(|msg| msg.get_seq());
+        assert!(recved.contains(&msg));
+        assert_eq!(recved.iter().map(|msg| msg.get_seq()).collect::<Vec<_>>(), vec![1, 2]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_error() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+
+        let mut suber = RemoteSuber::<ChId, Message>::connect(&addr).await.unwrap();
+        for id in 0..=super::super::server::MAX_CONN_SUBS as u64 {
+            suber.subscribe(&ChId::new(id), 1).await.unwrap();
+        }
+        let r = suber.recv_next().await;
+        assert!(format!("{:#}", r.unwrap_err()).contains("too many subscriptions"));
+
+        // 出错之后还能继续收
+        hub.puber(&ChId::new(0)).await.unwrap().push(1).await.unwrap();
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ChId::new(0), Message::new(1, 1)));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_max_conn_subs() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let mut conn = Conn::connect(&server.local_addr().to_string()).await.unwrap();
+
+        for id in 0..=super::super::server::MAX_CONN_SUBS as u64 {
+            let frame = Frame::Subscribe { ch_id: encode(&ChId::new(id)), seq: 1 };
+            frame.write_to(&mut conn.wr).await.unwrap();
+        }
+        // 只有超出的那个订阅失败，连接还在
+        let frame = conn.reader.read_frame(&mut conn.rd).await.unwrap();
+        assert!(matches!(frame, Some(Frame::Error { text }) if text.contains("too many subscriptions")));
+        assert_eq!(hub.channels(), super::super::server::MAX_CONN_SUBS);
+
+        Frame::Publish { ch_id: encode(&ChId::new(0)), value: encode(&1_u64) }.write_to(&mut conn.wr).await.unwrap();
+        loop {
+            match conn.reader.read_frame(&mut conn.rd).await.unwrap() {
+                Some(Frame::PubAck { seq }) => {
+                    assert_eq!(seq, 1);
+                    break;
+                }
+                Some(Frame::Msg { .. }) => {}
+                frame => panic!("unexpected {:?}", frame),
+            }
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_close() {
+        let hub = Arc::new(TestHub::with_store(ChMemStore::with_capacity(1000)));
+        let server = start_server(&hub, "127.0.0.1:0").await;
+        let addr = server.local_addr().to_string();
+        let ch_id = ChId::new(1);
+        let local_puber = hub.puber(&ch_id).await.unwrap();
+        for n in 1..=5 {
+            local_puber.push(n).await.unwrap();
+        }
+
+        let mut suber = RemoteSuber::<ChId, Message>::connect(&addr).await.unwrap();
+        suber.subscribe(&ch_id, 6).await.unwrap();
+        local_puber.push(6).await.unwrap();
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(6, 6)));
+
+        hub.reset_channel(&ch_id).await.unwrap();
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Reset(ch_id));
+        local_puber.push(7).await.unwrap();
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Value(ch_id, Message::new(7, 7)));
+
+        hub.close_channel(&ch_id).await;
+        assert_eq!(suber.recv_next().await.unwrap(), RecvOutput::Closed(ch_id));
+        assert_eq!(suber.channels(), 0);
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,248 @@
+//! 帧格式（小端）：[len: u32][kind: u8][payload]，len 不含自身 4 字节
+//!
+//! | kind | frame       | payload                              |
+//! |------|-------------|--------------------------------------|
+//! | 1    | SUBSCRIBE   | [ch_len: u32][ch_id][seq: u64]       |
+//! | 2    | UNSUBSCRIBE | [ch_id]                              |
+//! | 3    | PUBLISH     | [ch_len: u32][ch_id][value]          |
+//! | 4    | PUB_ACK     | [seq: u64]                           |
+//! | 5    | MSG         | [ch_len: u32][ch_id][msg]            |
+//! | 6    | LAGGED      | [ch_id]                              |
+//! | 7    | ERROR       | [utf8 text]                          |
+//! | 8    | CLOSED      | [ch_id]                              |
+//! | 9    | RESET       | [ch_len: u32][ch_id][seq: u64]       |
+//!
+//! ch_id / value / msg 都是 `Codec` 编码后的字节
+
+use anyhow::{Result, bail};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 超过这个长度的帧直接断开连接
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Subscribe { ch_id: Vec<u8>, seq: u64 },
+    Unsubscribe { ch_id: Vec<u8> },
+    Publish { ch_id: Vec<u8>, value: Vec<u8> },
+    PubAck { seq: u64 },
+    Msg { ch_id: Vec<u8>, msg: Vec<u8> },
+    Lagged { ch_id: Vec<u8> },
+    Error { text: String },
+    Closed { ch_id: Vec<u8> },
+    /// 之后的消息从 seq 开始
+    Reset { ch_id: Vec<u8>, seq: u64 },
+}
+
+impl Frame {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        match self {
+            Frame::Subscribe { ch_id, seq } => {
+                buf.push(1);
+                put_ch_id(buf, ch_id);
+                buf.extend_from_slice(&seq.to_le_bytes());
+            }
+            Frame::Unsubscribe { ch_id } => {
+                buf.push(2);
+                buf.extend_from_slice(ch_id);
+            }
+            Frame::Publish { ch_id, value } => {
+                buf.push(3);
+                put_ch_id(buf, ch_id);
+                buf.extend_from_slice(value);
+            }
+            Frame::PubAck { seq } => {
+                buf.push(4);
+                buf.extend_from_slice(&seq.to_le_bytes());
+            }
+            Frame::Msg { ch_id, msg } => {
+                buf.push(5);
+                put_ch_id(buf, ch_id);
+                buf.extend_from_slice(msg);
+            }
+            Frame::Lagged { ch_id } => {
+                buf.push(6);
+                buf.extend_from_slice(ch_id);
+            }
+            Frame::Error { text } => {
+                buf.push(7);
+                buf.extend_from_slice(text.as_bytes());
+            }
+            Frame::Closed { ch_id } => {
+                buf.push(8);
+                buf.extend_from_slice(ch_id);
+            }
+            Frame::Reset { ch_id, seq } => {
+                buf.push(9);
+                put_ch_id(buf, ch_id);
+                buf.extend_from_slice(&seq.to_le_bytes());
+            }
+        }
+        let len = (buf.len() - start - 4) as u32;
+        buf[start..start+4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// data 为去掉长度前缀后的 [kind][payload]
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (kind, payload) = match data.split_first() {
+            Some(r) => r,
+            None => bail!("empty frame"),
+        };
+
+        let frame = match kind {
+            1 => {
+                let (ch_id, rest) = get_ch_id(payload)?;
+                Frame::Subscribe { ch_id, seq: get_u64(rest)? }
+            }
+            2 => Frame::Unsubscribe { ch_id: payload.to_vec() },
+            3 => {
+                let (ch_id, rest) = get_ch_id(payload)?;
+                Frame::Publish { ch_id, value: rest.to_vec() }
+            }
+            4 => Frame::PubAck { seq: get_u64(payload)? },
+            5 => {
+                let (ch_id, rest) = get_ch_id(payload)?;
+                Frame::Msg { ch_id, msg: rest.to_vec() }
+            }
+            6 => Frame::Lagged { ch_id: payload.to_vec() },
+            7 => Frame::Error { text: String::from_utf8_lossy(payload).to_string() },
+            8 => Frame::Closed { ch_id: payload.to_vec() },
+            9 => {
+                let (ch_id, rest) = get_ch_id(payload)?;
+                Frame::Reset { ch_id, seq: get_u64(rest)? }
+            }
+            _ => bail!("unknown frame kind [{}]", kind),
+        };
+        Ok(frame)
+    }
+
+    pub async fn write_to<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        writer.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+fn put_ch_id(buf: &mut Vec<u8>, ch_id: &[u8]) {
+    buf.extend_from_slice(&(ch_id.len() as u32).to_le_bytes());
+    buf.extend_from_slice(ch_id);
+}
+
+fn get_ch_id(data: &[u8]) -> Result<(Vec<u8>, &[u8])> {
+    if data.len() < 4 {
+        bail!("frame too short for ch_id")
+    }
+    let len = u32::from_le_bytes(data[..4].try_into()?) as usize;
+    let data = &data[4..];
+    if data.len() < len {
+        bail!("frame too short, ch_id len [{}] but [{}]", len, data.len())
+    }
+    Ok((data[..len].to_vec(), &data[len..]))
+}
+
+fn get_u64(data: &[u8]) -> Result<u64> {
+    match data.try_into() {
+        Ok(bytes) => Ok(u64::from_le_bytes(bytes)),
+        Err(_e) => bail!("expect u64 but [{}] bytes", data.len()),
+    }
+}
+
+/// 按帧读取。`read_frame` 是 cancel safe 的，没读完的数据留在 buf 里
+#[derive(Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// return none if connection closed
+    pub async fn read_frame<R>(&mut self, reader: &mut R) -> Result<Option<Frame>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        loop {
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
+
+            let n = reader.read_buf(&mut self.buf).await?;
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                bail!("connection closed in the middle of frame")
+            }
+        }
+    }
+
+    fn parse_frame(&mut self) -> Result<Option<Frame>> {
+        if self.buf.len() < 4 {
+            return Ok(None);
+        }
+
+        let len = u32::from_le_bytes(self.buf[..4].try_into()?) as usize;
+        if len > MAX_FRAME_LEN {
+            bail!("frame too large [{}]", len)
+        }
+        if self.buf.len() < 4 + len {
+            self.buf.reserve(4 + len - self.buf.len());
+            return Ok(None);
+        }
+
+        let frame = Frame::decode(&self.buf[4..4+len]);
+        self.buf.drain(..4+len);
+        frame.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let frames = vec![
+            Frame::Subscribe { ch_id: vec![1, 2], seq: 7 },
+            Frame::Unsubscribe { ch_id: vec![1] },
+            Frame::Publish { ch_id: vec![3], value: vec![4, 5, 6] },
+            Frame::PubAck { seq: 9 },
+            Frame::Msg { ch_id: vec![], msg: vec![8] },
+            Frame::Lagged { ch_id: vec![1, 2, 3] },
+            Frame::Error { text: "oops".to_string() },
+            Frame::Closed { ch_id: vec![4] },
+            Frame::Reset { ch_id: vec![5, 6], seq: 11 },
+        ];
+
+        let mut buf = Vec::new();
+        for frame in &frames {
+            frame.encode(&mut buf);
+        }
+
+        // 逐字节喂进去，检查半帧的处理
+        let (mut tx, mut rx) = tokio::io::duplex(1);
+        tokio::spawn(async move { tx.write_all(&buf).await });
+
+        let mut reader = FrameReader::new();
+        for frame in &frames {
+            assert_eq!(reader.read_frame(&mut rx).await.unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(reader.read_frame(&mut rx).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_bad_frame() {
+        assert!(Frame::decode(&[]).is_err());
+        assert!(Frame::decode(&[99]).is_err());
+        assert!(Frame::decode(&[1, 10, 0, 0, 0, 1]).is_err());
+        assert!(Frame::decode(&[4, 1, 2]).is_err());
+    }
+}
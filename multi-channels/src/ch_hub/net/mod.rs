@@ -0,0 +1,9 @@
+//! 通过 TCP 远程访问 Hub，帧格式见 `frame`
+
+pub mod frame;
+
+mod server;
+pub use server::HubServer;
+
+mod client;
+pub use client::{RemotePuber, RemoteSuber};
@@ -0,0 +1,264 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, Context, bail};
+use parking_lot::Mutex;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::ch_common::{ChIdOp, Codec, GetSeq, RecvOutput, VecMap, WithSeq};
+use crate::mpsc_ch::mpsc_defs::{AsyncRecvOp, MpscOp};
+
+use super::super::channel1::suber::Suber;
+use super::super::event::Event;
+use super::super::hub::{Hub, Puber};
+use super::super::store::ChStore;
+use super::frame::{Frame, FrameReader};
+
+/// 每个连接缓存的 puber 数上限，超过时丢掉最久没用的
+const MAX_CONN_PUBERS: usize = 64;
+
+/// 每个连接订阅的 channel 数上限，超过时订阅返回错误
+pub(super) const MAX_CONN_SUBS: usize = 256;
+
+/// accept 出错（比如 fd 用完）后等一会再试，避免空转
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(1);
+
+/// 每个连接一个 Suber，收到的消息按 MSG/LAGGED 帧转发给客户端
+pub struct HubServer {
+    local_addr: SocketAddr,
+    accept: Mutex<Option<JoinHandle<()>>>,
+    conns: Arc<Mutex<VecMap<u64, JoinHandle<()>>>>,
+}
+
+impl HubServer {
+    pub async fn start<K, T, M, S>(listener: TcpListener, hub: Arc<Hub<K, T, M, S>>, inbox_cap: usize) -> Result<Self>
+    where
+        K: ChIdOp + Codec + Send + Sync + 'static,
+        T: Clone + GetSeq + WithSeq + Codec + Send + Sync + 'static,
+        T::Value: Codec + Send,
+        M: MpscOp<Event<K, T>> + Send + Sync + 'static,
+        M::Receiver: Send,
+        for<'a> <M::Receiver as AsyncRecvOp<Event<K, T>>>::Fut<'a>: Send,
+        M::Sender: Send + Sync,
+        S: ChStore<K, T> + Send + Sync + 'static,
+        for<'a> S::ReadFut<'a>: Send,
+        for<'a> S::WriteFut<'a>: Send,
+    {
+        let local_addr = listener.local_addr()?;
+        let conns: Arc<Mutex<VecMap<u64, JoinHandle<()>>>> = Default::default();
+
+        let accept = tokio::spawn({
+            let conns = conns.clone();
+            async move {
+                let mut next_id = 0_u64;
+                let mut delay = MIN_ACCEPT_DELAY;
+                loop {
+                    let (stream, peer) = match listener.accept().await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("hub server [{}] accept failed, retry in [{:?}], [{}]", local_addr, delay, e);
+                            tokio::time::sleep(delay).await;
+                            delay = (delay * 2).min(MAX_ACCEPT_DELAY);
+                            continue;
+                        }
+                    };
+                    delay = MIN_ACCEPT_DELAY;
+
+                    next_id += 1;
+                    let conn_id = next_id;
+                    let hub = hub.clone();
+                    let task_conns = conns.clone();
+
+                    // 持有锁再 spawn，保证连接结束时移除在插入之后
+                    let mut guard = conns.lock();
+                    let task = tokio::spawn(async move {
+                        if let Err(e) = serve_conn(stream, hub, inbox_cap).await {
+                            eprintln!("hub server conn [{}] from [{}] closed with error [{:#}]", conn_id, peer, e);
+                        }
+                        task_conns.lock().swap_remove(&conn_id);
+                    });
+                    guard.insert(conn_id, task);
+                }
+            }
+        });
+
+        Ok(Self { local_addr, accept: Mutex::new(Some(accept)), conns })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// stop accepting and close all connections
+    pub async fn stop(&self) {
+        let accept = self.accept.lock().take();
+        if let Some(task) = accept {
+            task.abort();
+            let _r = task.await;
+        }
+
+        let conns: Vec<_> = self.conns.lock().drain(..).map(|(_id, task)|task).collect();
+        for task in conns {
+            task.abort();
+            let _r = task.await;
+        }
+    }
+}
+
+impl Drop for HubServer {
+    fn drop(&mut self) {
+        if let Some(task) = self.accept.lock().as_ref() {
+            task.abort();
+        }
+        for (_id, task) in self.conns.lock().iter() {
+            task.abort();
+        }
+    }
+}
+
+async fn serve_conn<K, T, M, S>(stream: TcpStream, hub: Arc<Hub<K, T, M, S>>, inbox_cap: usize) -> Result<()>
+where
+    K: ChIdOp + Codec + Send + Sync + 'static,
+    T: Clone + GetSeq + WithSeq + Codec + Send + Sync + 'static,
+    T::Value: Codec + Send,
+    M: MpscOp<Event<K, T>> + Send + Sync + 'static,
+    M::Receiver: Send,
+    for<'a> <M::Receiver as AsyncRecvOp<Event<K, T>>>::Fut<'a>: Send,
+    M::Sender: Send + Sync,
+    S: ChStore<K, T> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+    for<'a> S::WriteFut<'a>: Send,
+{
+    stream.set_nodelay(true)?;
+    let (mut rd, mut wr) = stream.into_split();
+    let mut reader = FrameReader::new();
+    let mut conn = ServerConn {
+        hub,
+        suber: Suber::with_inbox_cap(inbox_cap),
+        pubers: VecMap::new(),
+    };
+
+    // 定期丢掉空闲的 puber，否则连接不断开 channel 就一直不会被 evict_idle 回收
+    let idle_timeout = conn.hub.config().idle_timeout;
+    let mut sweep = tokio::time::interval(idle_timeout.unwrap_or(Duration::from_secs(60)));
+
+    loop {
+        tokio::select! {
+            _r = sweep.tick(), if idle_timeout.is_some() => {
+                conn.drop_idle_pubers();
+            }
+
+            // CANCEL SAFETY: FrameReader::read_frame keeps partial data in its buffer
+            r = reader.read_frame(&mut rd) => {
+                let frame = match r? {
+                    Some(frame) => frame,
+                    None => return Ok(()),
+                };
+                if let Some(reply) = conn.handle_frame(frame).await {
+                    reply.write_to(&mut wr).await?;
+                }
+            }
+
+            r = conn.suber.recv_next(), if conn.suber.channels() > 0 => {
+                let frame = match r {
+                    RecvOutput::Value(ch_id, msg) => Frame::Msg { ch_id: encode(&ch_id), msg: encode(&msg) },
+                    RecvOutput::Lagged(ch_id) => Frame::Lagged { ch_id: encode(&ch_id) },
+                    RecvOutput::Closed(ch_id) => Frame::Closed { ch_id: encode(&ch_id) },
+                    RecvOutput::Reset(ch_id) => {
+                        let seq = conn.suber.position(&ch_id).unwrap_or_default();
+                        Frame::Reset { ch_id: encode(&ch_id), seq }
+                    },
+                    RecvOutput::None => continue,
+                };
+                frame.write_to(&mut wr).await?;
+            }
+        }
+    }
+}
+
+/// puber 和最后一次使用的时间
+type UsedPuber<K, T, M, S> = (Puber<K, T, M, S>, Instant);
+
+struct ServerConn<K, T, M, S>
+where
+    K: ChIdOp,
+    T: Clone + GetSeq,
+    M: MpscOp<Event<K, T>>,
+{
+    hub: Arc<Hub<K, T, M, S>>,
+    suber: Suber<K, T, M>,
+    /// 按最近使用排序，最后一个是最近用过的
+    pubers: VecMap<K, UsedPuber<K, T, M, S>>,
+}
+
+impl<K, T, M, S> ServerConn<K, T, M, S>
+where
+    K: ChIdOp + Codec + Sync + 'static,
+    T: Clone + GetSeq + WithSeq + Codec + Send + 'static,
+    T::Value: Codec,
+    M: MpscOp<Event<K, T>>,
+    S: ChStore<K, T> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    async fn handle_frame(&mut self, frame: Frame) -> Option<Frame> {
+        let r = match frame {
+            Frame::Subscribe { ch_id, seq } => self.subscribe(&ch_id, seq).await,
+            Frame::Unsubscribe { ch_id } => {
+                K::decode(&ch_id).map(|ch_id| {
+                    self.suber.unsubscribe(&ch_id);
+                    None
+                })
+            }
+            Frame::Publish { ch_id, value } => self.publish(&ch_id, &value).await,
+            frame => Err(anyhow::anyhow!("unexpected frame from client [{:?}]", frame)),
+        };
+
+        match r {
+            Ok(reply) => reply,
+            Err(e) => Some(Frame::Error { text: format!("{:#}", e) }),
+        }
+    }
+
+    async fn subscribe(&mut self, ch_id: &[u8], seq: u64) -> Result<Option<Frame>> {
+        let ch_id = K::decode(ch_id).with_context(||"invalid ch_id")?;
+        if self.suber.channels() >= MAX_CONN_SUBS {
+            bail!("too many subscriptions, max [{}]", MAX_CONN_SUBS)
+        }
+        self.hub.subscribe(&ch_id, &mut self.suber, seq).await?;
+        Ok(None)
+    }
+
+    async fn publish(&mut self, ch_id: &[u8], value: &[u8]) -> Result<Option<Frame>> {
+        let ch_id = K::decode(ch_id).with_context(||"invalid ch_id")?;
+        let value = T::Value::decode(value).with_context(||"invalid value")?;
+
+        let puber = match self.pubers.shift_remove(&ch_id) {
+            Some((puber, _used)) => puber,
+            None => self.hub.puber(&ch_id).await?,
+        };
+        // 失败的（比如 channel 已关闭）不再缓存
+        let msg = puber.push(value).await?;
+
+        self.pubers.insert(ch_id, (puber, Instant::now()));
+        if self.pubers.len() > MAX_CONN_PUBERS {
+            self.pubers.shift_remove_index(0);
+        }
+        Ok(Some(Frame::PubAck { seq: msg.get_seq() }))
+    }
+
+    /// 丢掉超过 hub idle_timeout 没用过的 puber
+    fn drop_idle_pubers(&mut self) {
+        if let Some(timeout) = self.hub.config().idle_timeout {
+            self.pubers.retain(|_ch_id, (_puber, used)| used.elapsed() < timeout);
+        }
+    }
+}
+
+pub(super) fn encode<V: Codec>(v: &V) -> Vec<u8> {
+    let mut buf = Vec::new();
+    v.encode(&mut buf);
+    buf
+}
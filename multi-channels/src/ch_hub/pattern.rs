@@ -0,0 +1,80 @@
+//! 按字符串 channel id 匹配的订阅规则，见 `Hub::subscribe_pattern`
+
+/// - `Prefix("tenant-x/")` 匹配所有以它开头的 channel
+/// - `Glob("tenant-*/room-?")` 里 `*` 匹配任意个字符，`?` 匹配一个字符
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChPattern {
+    Prefix(String),
+    Glob(String),
+}
+
+impl ChPattern {
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self::Prefix(prefix.into())
+    }
+
+    pub fn glob(glob: impl Into<String>) -> Self {
+        Self::Glob(glob.into())
+    }
+
+    pub fn matches(&self, ch_id: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => ch_id.starts_with(prefix.as_str()),
+            Self::Glob(glob) => glob_match(glob, ch_id),
+        }
+    }
+}
+
+/// 回溯到最近一个 `*`，最坏 O(n*m)
+fn glob_match(glob: &str, s: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    let (mut g, mut i) = (0, 0);
+    // 最近一个 `*` 的位置，以及它当前匹配到 s 的位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == s[i]) {
+            g += 1;
+            i += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            star = Some((g, i));
+            g += 1;
+        } else if let Some((sg, si)) = star {
+            // 让 `*` 多吃一个字符再试
+            g = sg + 1;
+            i = si + 1;
+            star = Some((sg, si + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let p = ChPattern::prefix("tenant-x/");
+        assert!(p.matches("tenant-x/room-1"));
+        assert!(p.matches("tenant-x/"));
+        assert!(!p.matches("tenant-y/room-1"));
+
+        let p = ChPattern::glob("tenant-*/room-?");
+        assert!(p.matches("tenant-x/room-1"));
+        assert!(p.matches("tenant-abc/room-2"));
+        assert!(!p.matches("tenant-x/room-12"));
+        assert!(!p.matches("tenant-x/lobby"));
+
+        assert!(ChPattern::glob("*").matches(""));
+        assert!(ChPattern::glob("a*b*c").matches("aXbYbZc"));
+        assert!(!ChPattern::glob("a*b*c").matches("aXbYbZ"));
+        assert!(ChPattern::glob("**x").matches("yyx"));
+        assert!(!ChPattern::glob("").matches("a"));
+    }
+}
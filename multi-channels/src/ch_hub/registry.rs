@@ -0,0 +1,112 @@
+//! 按 key 的 hash 分片的 channel 表，不同分片的查找、插入互不阻塞。
+//! 分片数为 1 时就是原来的单个 Mutex<VecMap>
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::ch_common::{ChIdOp, VecMap};
+
+pub const DEFAULT_SHARDS: usize = 16;
+
+pub struct ChRegistry<K, V> {
+    shards: Vec<Mutex<VecMap<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K, V> ChRegistry<K, V>
+where
+    K: ChIdOp,
+{
+    /// shards 为 0 时按 1 处理
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Mutex::new(VecMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 锁住 key 所在的分片
+    pub fn lock(&self, key: &K) -> MutexGuard<'_, VecMap<K, V>> {
+        let index = if self.shards.len() == 1 {
+            0
+        } else {
+            (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+        };
+        self.shards[index].lock()
+    }
+
+    /// 按顺序锁住所有分片，期间不会有 key 被插入或移除
+    pub fn lock_all(&self) -> Vec<MutexGuard<'_, VecMap<K, V>>> {
+        self.shards.iter().map(|shard| shard.lock()).collect()
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.lock(key).get(key).cloned()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.lock(key).swap_remove(key)
+    }
+
+    /// 逐个分片执行，不会同时锁住所有分片
+    pub fn retain<F>(&self, mut func: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for shard in self.shards.iter() {
+            shard.lock().retain(|k, v| func(k, v));
+        }
+    }
+}
+
+impl<K, V> Default for ChRegistry<K, V>
+where
+    K: ChIdOp,
+{
+    fn default() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        for shards in [0, 1, 7] {
+            let reg = ChRegistry::<u64, u64>::with_shards(shards);
+            assert_eq!(reg.shards(), shards.max(1));
+
+            for n in 0..100 {
+                reg.lock(&n).insert(n, n * 10);
+            }
+            assert_eq!(reg.len(), 100);
+            assert_eq!(reg.get(&7), Some(70));
+            assert_eq!(reg.remove(&7), Some(70));
+            assert_eq!(reg.get(&7), None);
+
+            reg.retain(|k, _v| k % 2 == 0);
+            assert_eq!(reg.len(), 50);
+            let total: usize = reg.lock_all().iter().map(|shard| shard.len()).sum();
+            assert_eq!(total, 50);
+        }
+    }
+}
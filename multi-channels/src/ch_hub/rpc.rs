@@ -0,0 +1,347 @@
+//! 在 hub channel 上做请求/应答：
+//! - 服务端订阅一个服务 channel，每个请求交给 `AsyncCall` 处理，应答发到请求里带的 reply_to channel
+//! - 客户端有自己的 inbox channel，请求带上递增的 call_id，等 inbox 里 call_id 对上的应答，超时返回错误
+//! - 超时之后才到的应答按 call_id 丢掉
+//!
+//! 请求和应答走同一个 hub，消息类型都是 `RpcMsg`
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use tokio::time::Interval;
+
+use crate::async_call::AsyncCall;
+use crate::ch_common::{ChIdOp, RecvOutput, SeqVal, VecMap};
+use crate::mpsc_ch::mpsc_defs::MpscOp;
+
+use super::event::Event;
+use super::hub::{Hub, Puber};
+use super::store::{ChStore, NoStore};
+use super::Suber;
+
+pub const DEFAULT_INBOX_CAP: usize = 64;
+
+/// 服务端缓存的应答 puber 数上限，超过时丢掉最久没用的
+const MAX_REPLIERS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcMsg<K, Req, Resp> {
+    Request { call_id: u64, reply_to: K, body: Req },
+    Reply { call_id: u64, body: Resp },
+}
+
+pub type RpcVal<K, Req, Resp> = SeqVal<RpcMsg<K, Req, Resp>>;
+
+pub type RpcHub<K, Req, Resp, M, S = NoStore> = Hub<K, RpcVal<K, Req, Resp>, M, S>;
+
+type RpcPuber<K, Req, Resp, M, S> = Puber<K, RpcVal<K, Req, Resp>, M, S>;
+
+/// puber 和最后一次使用的时间
+type UsedPuber<K, Req, Resp, M, S> = (RpcPuber<K, Req, Resp, M, S>, Instant);
+
+/// 从 channel 当前的末尾开始订阅，只收之后的消息
+async fn subscribe_latest<K, Req, Resp, M, S>(
+    hub: &RpcHub<K, Req, Resp, M, S>,
+    ch_id: &K,
+    inbox_cap: usize,
+) -> Result<Suber<K, RpcVal<K, Req, Resp>, M>>
+where
+    K: ChIdOp + Send + Sync + 'static,
+    Req: Clone + Send + 'static,
+    Resp: Clone + Send + 'static,
+    M: MpscOp<Event<K, RpcVal<K, Req, Resp>>>,
+    S: ChStore<K, RpcVal<K, Req, Resp>> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    let tail = hub.puber(ch_id).await?.channel().tail_seq();
+    let mut suber = Suber::with_inbox_cap(inbox_cap);
+    hub.subscribe(ch_id, &mut suber, tail).await?;
+    Ok(suber)
+}
+
+pub struct RpcServer<K, Req, Resp, M, S = NoStore>
+where
+    K: ChIdOp,
+    Req: Clone,
+    Resp: Clone,
+    M: MpscOp<Event<K, RpcVal<K, Req, Resp>>>,
+{
+    hub: Arc<RpcHub<K, Req, Resp, M, S>>,
+    suber: Suber<K, RpcVal<K, Req, Resp>, M>,
+    /// 按最近使用排序，最后一个是最近用过的
+    repliers: VecMap<K, UsedPuber<K, Req, Resp, M, S>>,
+    /// 定期丢掉空闲的 replier，否则调用方走了它的 inbox 也不会被 evict_idle 回收
+    sweep: Interval,
+    served: u64,
+    missed: u64,
+}
+
+impl<K, Req, Resp, M, S> RpcServer<K, Req, Resp, M, S>
+where
+    K: ChIdOp + Send + Sync + 'static,
+    Req: Clone + Send + 'static,
+    Resp: Clone + Send + 'static,
+    M: MpscOp<Event<K, RpcVal<K, Req, Resp>>>,
+    S: ChStore<K, RpcVal<K, Req, Resp>> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    /// 订阅服务 channel，注册之前发的请求不处理
+    pub async fn bind(hub: Arc<RpcHub<K, Req, Resp, M, S>>, service: &K) -> Result<Self> {
+        let suber = subscribe_latest(&hub, service, DEFAULT_INBOX_CAP).await?;
+        let sweep = tokio::time::interval(hub.config().idle_timeout.unwrap_or(Duration::from_secs(60)));
+        Ok(Self { hub, suber, repliers: VecMap::new(), sweep, served: 0, missed: 0 })
+    }
+
+    /// 已经应答的请求数
+    pub fn served(&self) -> u64 {
+        self.served
+    }
+
+    /// 落后太多或者服务 channel 被 reset 的次数，每次都可能丢了请求
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// 一直处理请求，直到服务 channel 被关闭
+    pub async fn serve<C>(&mut self, handler: &mut C) -> Result<()>
+    where
+        for<'s> C: AsyncCall<'s, Req, Output = Resp>,
+    {
+        while self.serve_one(handler).await? {}
+        Ok(())
+    }
+
+    /// 处理一个请求，channel 关闭时返回 false。
+    /// 落后太多丢掉的请求不处理，调用方会超时
+    pub async fn serve_one<C>(&mut self, handler: &mut C) -> Result<bool>
+    where
+        for<'s> C: AsyncCall<'s, Req, Output = Resp>,
+    {
+        let idle_timeout = self.hub.config().idle_timeout;
+        loop {
+            let r = tokio::select! {
+                _r = self.sweep.tick(), if idle_timeout.is_some() => {
+                    self.drop_idle_repliers();
+                    continue;
+                }
+                r = self.suber.recv_next() => r,
+            };
+
+            match r {
+                RecvOutput::Value(_ch_id, SeqVal(_seq, RpcMsg::Request { call_id, reply_to, body })) => {
+                    let resp = handler.async_call(body).await;
+                    self.reply(&reply_to, call_id, resp).await?;
+                    return Ok(true);
+                },
+                RecvOutput::Closed(_ch_id) => return Ok(false),
+                // 应答不会发到服务 channel 上
+                RecvOutput::Value(_ch_id, _v) => {},
+                RecvOutput::Lagged(_) | RecvOutput::Reset(_) => self.missed += 1,
+                RecvOutput::None => {},
+            }
+        }
+    }
+
+    async fn reply(&mut self, reply_to: &K, call_id: u64, body: Resp) -> Result<()> {
+        let puber = match self.repliers.shift_remove(reply_to) {
+            Some((puber, _used)) => puber,
+            None => self.hub.puber(reply_to).await?,
+        };
+        // 失败的（比如 inbox 已关闭）不再缓存
+        puber.push(RpcMsg::Reply { call_id, body }).await?;
+        self.served += 1;
+
+        self.repliers.insert(reply_to.clone(), (puber, Instant::now()));
+        if self.repliers.len() > MAX_REPLIERS {
+            self.repliers.shift_remove_index(0);
+        }
+        Ok(())
+    }
+
+    /// 丢掉超过 hub idle_timeout 没用过的 replier
+    fn drop_idle_repliers(&mut self) {
+        if let Some(timeout) = self.hub.config().idle_timeout {
+            self.repliers.retain(|_ch_id, (_puber, used)| used.elapsed() < timeout);
+        }
+    }
+}
+
+pub struct RpcClient<K, Req, Resp, M, S = NoStore>
+where
+    K: ChIdOp,
+    Req: Clone,
+    Resp: Clone,
+    M: MpscOp<Event<K, RpcVal<K, Req, Resp>>>,
+{
+    hub: Arc<RpcHub<K, Req, Resp, M, S>>,
+    inbox: K,
+    suber: Suber<K, RpcVal<K, Req, Resp>, M>,
+    services: VecMap<K, RpcPuber<K, Req, Resp, M, S>>,
+    next_call_id: u64,
+}
+
+impl<K, Req, Resp, M, S> RpcClient<K, Req, Resp, M, S>
+where
+    K: ChIdOp + Send + Sync + 'static,
+    Req: Clone + Send + 'static,
+    Resp: Clone + Send + 'static,
+    M: MpscOp<Event<K, RpcVal<K, Req, Resp>>>,
+    S: ChStore<K, RpcVal<K, Req, Resp>> + Send + Sync + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    /// inbox 是这个调用方专用的应答 channel，不能和别的调用方共用
+    pub async fn new(hub: Arc<RpcHub<K, Req, Resp, M, S>>, inbox: K) -> Result<Self> {
+        let suber = subscribe_latest(&hub, &inbox, DEFAULT_INBOX_CAP).await?;
+        Ok(Self { hub, inbox, suber, services: VecMap::new(), next_call_id: 1 })
+    }
+
+    pub fn inbox(&self) -> &K {
+        &self.inbox
+    }
+
+    /// 发请求到 service channel，等对应的应答
+    pub async fn call(&mut self, service: &K, req: Req, timeout: Duration) -> Result<Resp> {
+        let call_id = self.next_call_id;
+        self.next_call_id += 1;
+
+        if self.services.get(service).is_none() {
+            let puber = self.hub.puber(service).await?;
+            self.services.insert(service.clone(), puber);
+        }
+        let request = RpcMsg::Request { call_id, reply_to: self.inbox.clone(), body: req };
+        self.services[service].push(request).await?;
+
+        let r = tokio::time::timeout(timeout, self.wait_reply(call_id)).await;
+        match r {
+            Ok(r) => r,
+            Err(_elapsed) => bail!("call [{:?}] id [{}] timeout after {:?}", service, call_id, timeout),
+        }
+    }
+
+    async fn wait_reply(&mut self, call_id: u64) -> Result<Resp> {
+        loop {
+            let r = self.suber.recv_next().await;
+            match r {
+                RecvOutput::Value(_ch_id, SeqVal(_seq, RpcMsg::Reply { call_id: id, body })) => {
+                    if id == call_id {
+                        return Ok(body)
+                    }
+                    // 对不上的是之前超时的调用的应答，丢掉
+                },
+                RecvOutput::Closed(_ch_id) => bail!("inbox [{:?}] closed", self.inbox),
+                RecvOutput::Value(_ch_id, _v) => {},
+                RecvOutput::Lagged(_) | RecvOutput::Reset(_) | RecvOutput::None => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{async_call::async_call_fn, ch_common::uid::ChId, ch_hub::hub::HubConfig, mpsc_ch::mpsc_crossbeam_que::Mpsc};
+
+    type TestHub = RpcHub<ChId, u64, u64, Mpsc>;
+
+    #[tokio::test]
+    async fn test_rpc() -> Result<()> {
+        let hub = Arc::new(TestHub::new());
+        let service = ChId::new(100);
+
+        let mut server = RpcServer::bind(hub.clone(), &service).await?;
+        let server_task = tokio::spawn(async move {
+            async fn double(req: u64) -> u64 {
+                req * 2
+            }
+            let mut handler = async_call_fn(double);
+            server.serve(&mut handler).await.unwrap();
+            server.served()
+        });
+
+        let mut client1 = RpcClient::new(hub.clone(), ChId::new(1)).await?;
+        let mut client2 = RpcClient::new(hub.clone(), ChId::new(2)).await?;
+        for n in 1..=10 {
+            assert_eq!(client1.call(&service, n, Duration::from_secs(5)).await?, n * 2);
+            assert_eq!(client2.call(&service, n + 100, Duration::from_secs(5)).await?, (n + 100) * 2);
+        }
+
+        // 没有服务的 channel 超时
+        let r = client1.call(&ChId::new(200), 1, Duration::from_millis(20)).await;
+        assert!(r.is_err());
+        assert_eq!(client1.call(&service, 7, Duration::from_secs(5)).await?, 14);
+
+        assert!(hub.close_channel(&service).await);
+        assert_eq!(server_task.await?, 21);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idle_replier_released() -> Result<()> {
+        let idle = Duration::from_millis(50);
+        let config = HubConfig { idle_timeout: Some(idle), ..Default::default() };
+        let hub = Arc::new(TestHub::with_config(NoStore, config));
+        let service = ChId::new(100);
+
+        let mut server = RpcServer::bind(hub.clone(), &service).await?;
+        let server_task = tokio::spawn(async move {
+            let mut handler = async_call_fn(|req: u64| async move { req + 1 });
+            server.serve(&mut handler).await.unwrap();
+        });
+
+        // 调用方走了，服务端不再持有它的 inbox
+        let mut client = RpcClient::new(hub.clone(), ChId::new(1)).await?;
+        assert_eq!(client.call(&service, 1, Duration::from_secs(5)).await?, 2);
+        drop(client);
+        tokio::time::sleep(idle * 4).await;
+        assert_eq!(hub.evict_idle(), vec![ChId::new(1)]);
+
+        assert!(hub.close_channel(&service).await);
+        server_task.await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_repliers() -> Result<()> {
+        let hub = Arc::new(TestHub::new());
+        let service = ChId::new(100);
+
+        let mut server = RpcServer::bind(hub.clone(), &service).await?;
+        let server_task = tokio::spawn(async move {
+            let mut handler = async_call_fn(|req: u64| async move { req + 1 });
+            server.serve(&mut handler).await.unwrap();
+            server.repliers.len()
+        });
+
+        for id in 0..MAX_REPLIERS as u64 + 10 {
+            let mut client = RpcClient::new(hub.clone(), ChId::new(1000 + id)).await?;
+            assert_eq!(client.call(&service, id, Duration::from_secs(5)).await?, id + 1);
+        }
+        assert!(hub.close_channel(&service).await);
+        assert_eq!(server_task.await?, MAX_REPLIERS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rpc_stale_reply() -> Result<()> {
+        let hub = Arc::new(TestHub::new());
+        let service = ChId::new(100);
+        let mut server = RpcServer::bind(hub.clone(), &service).await?;
+        let mut client = RpcClient::new(hub.clone(), ChId::new(1)).await?;
+
+        // 服务端还没处理，调用超时
+        let r = client.call(&service, 1, Duration::from_millis(20)).await;
+        assert!(r.is_err());
+
+        // 迟到的应答被丢掉，下一次调用拿到自己的应答
+        let server_task = tokio::spawn(async move {
+            let mut handler = async_call_fn(|req: u64| async move { req + 1 });
+            for _n in 0..2 {
+                assert!(server.serve_one(&mut handler).await.unwrap());
+            }
+        });
+        assert_eq!(client.call(&service, 10, Duration::from_secs(5)).await?, 11);
+        server_task.await?;
+        Ok(())
+    }
+}
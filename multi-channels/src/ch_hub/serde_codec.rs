@@ -0,0 +1,188 @@
+//! 基于 serde 的编解码，格式由 cargo feature 打开：
+//! - `bincode`：`Bincode`
+//! - `json`：`Json`
+//! - `msgpack`：`MsgPack`
+//!
+//! id、`SeqVal`、`Event` 在打开 `serde` feature 时都实现了 Serialize / Deserialize。
+//! `Serde<F, V>` 把任意 serde 类型接到 `Codec` 上，比如 `SeqVal<Serde<Json, MyMsg>>`
+//! 可以直接用在 file store 和 net 里
+
+use std::marker::PhantomData;
+
+use anyhow::Result;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::ch_common::Codec;
+
+pub trait SerdeFormat {
+    fn name() -> &'static str;
+    fn encode<V: Serialize>(v: &V, buf: &mut Vec<u8>) -> Result<()>;
+    fn decode<V: DeserializeOwned>(data: &[u8]) -> Result<V>;
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl SerdeFormat for Bincode {
+    fn name() -> &'static str {
+        "bincode"
+    }
+
+    fn encode<V: Serialize>(v: &V, buf: &mut Vec<u8>) -> Result<()> {
+        bincode::serialize_into(buf, v)?;
+        Ok(())
+    }
+
+    fn decode<V: DeserializeOwned>(data: &[u8]) -> Result<V> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl SerdeFormat for Json {
+    fn name() -> &'static str {
+        "json"
+    }
+
+    fn encode<V: Serialize>(v: &V, buf: &mut Vec<u8>) -> Result<()> {
+        serde_json::to_writer(buf, v)?;
+        Ok(())
+    }
+
+    fn decode<V: DeserializeOwned>(data: &[u8]) -> Result<V> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl SerdeFormat for MsgPack {
+    fn name() -> &'static str {
+        "msgpack"
+    }
+
+    fn encode<V: Serialize>(v: &V, buf: &mut Vec<u8>) -> Result<()> {
+        rmp_serde::encode::write(buf, v)?;
+        Ok(())
+    }
+
+    fn decode<V: DeserializeOwned>(data: &[u8]) -> Result<V> {
+        Ok(rmp_serde::from_slice(data)?)
+    }
+}
+
+/// 用格式 F 编解码 V
+pub struct Serde<F, V>(pub V, PhantomData<F>);
+
+impl<F, V> Serde<F, V> {
+    pub fn new(v: V) -> Self {
+        Self(v, PhantomData)
+    }
+}
+
+impl<F, V> Clone for Serde<F, V>
+where
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+
+impl<F, V> PartialEq for Serde<F, V>
+where
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<F, V> std::fmt::Debug for Serde<F, V>
+where
+    V: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Serde").field(&self.0).finish()
+    }
+}
+
+impl<F, V> Codec for Serde<F, V>
+where
+    F: SerdeFormat,
+    V: Serialize + DeserializeOwned,
+{
+    /// `Codec::encode` 不能失败，编码出错只会是类型本身不能用这个格式表示（比如 json 的 map key 不是字符串），当作 bug 处理
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if let Err(e) = F::encode(&self.0, buf) {
+            panic!("{} encode fail: {:?}", F::name(), e)
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        F::decode(data).map(Self::new)
+    }
+}
+
+#[cfg(all(test, any(feature = "bincode", feature = "json", feature = "msgpack")))]
+pub(crate) mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::ch_common::{SeqVal, uid::{ChId, SuberId}};
+
+    pub(crate) fn round_trip<F, V>(v: &V) -> V
+    where
+        F: SerdeFormat,
+        V: Serialize + DeserializeOwned,
+    {
+        let mut buf = Vec::new();
+        F::encode(v, &mut buf).unwrap();
+        F::decode(&buf).unwrap()
+    }
+
+    fn check_all<V>(v: &V)
+    where
+        V: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        #[cfg(feature = "bincode")]
+        assert_eq!(&round_trip::<Bincode, V>(v), v);
+        #[cfg(feature = "json")]
+        assert_eq!(&round_trip::<Json, V>(v), v);
+        #[cfg(feature = "msgpack")]
+        assert_eq!(&round_trip::<MsgPack, V>(v), v);
+    }
+
+    proptest! {
+        #[test]
+        fn test_id_round_trip(id in any::<u64>()) {
+            check_all(&ChId::new(id));
+            check_all(&SuberId::new(id));
+        }
+
+        #[test]
+        fn test_seq_val_round_trip(seq in any::<u64>(), s in ".*", bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            check_all(&SeqVal::new(seq, s));
+            check_all(&SeqVal::new(seq, bytes));
+            check_all(&SeqVal::new(seq, ChId::new(seq)));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_serde_codec() {
+        let msg = SeqVal::new(7, Serde::<Json, _>::new(vec!["a".to_string(), "b".to_string()]));
+        let mut buf = Vec::new();
+        msg.encode(&mut buf);
+        assert_eq!(&buf[8..], br#"["a","b"]"#);
+        assert_eq!(SeqVal::decode(&buf).unwrap(), msg);
+
+        assert!(SeqVal::<Serde<Json, Vec<String>>>::decode(&buf[..10]).is_err());
+    }
+}
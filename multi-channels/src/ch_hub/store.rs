@@ -1,80 +1,207 @@
 
 use anyhow::Result;
+use futures::future::BoxFuture;
 use std::future::Future;
-use crate::ch_common::{ReadQueOutput, WithSeq};
+use crate::ch_common::ReadQueOutput;
 
+/// max messages returned by one `load_cold`
+pub const LOAD_BATCH: usize = 64;
 
-pub trait ChStore<'s, K, T> 
-where
-    T: WithSeq,
-{
-    fn read_hot(&self, seq: u64) -> ReadOutput<T>;
+/// 每个 Hub 一个 store，按 ch_id 区分 channel。
+/// seq 由 channel 分配，store 只负责按 seq 顺序保存。
+pub trait ChStore<K, T> {
+    fn read_hot(&self, ch_id: &K, seq: u64) -> ReadOutput<T>;
 
-    /// return none if reach tail
-    fn load_cold(&self, ch_id: &K, seq: u64) -> Self::ReadFut;
-    type ReadFut: Future<Output = Result<Option<Vec<T>>>>;
+    /// load a batch starting from seq, return none if reach tail.
+    /// 如果 seq 已经被 store 淘汰，返回的第一条 seq 会大于请求的 seq
+    fn load_cold(&self, ch_id: &K, seq: u64) -> Self::ReadFut<'_>;
+    type ReadFut<'a>: Future<Output = Result<Option<Vec<T>>>> where Self: 'a;
     
-    fn async_write(&'s self, v: T::Value) -> Self::WriteFut;
-    type WriteFut: Future<Output = Result<T>>;
+    /// append a message whose seq is already assigned
+    fn async_write(&self, ch_id: &K, v: T) -> Self::WriteFut<'_>;
+    type WriteFut<'a>: Future<Output = Result<()>> where Self: 'a;
+
+    /// last stored seq of channel, 0 if empty
+    fn last_seq(&self, ch_id: &K) -> Self::LastSeqFut<'_>;
+    type LastSeqFut<'a>: Future<Output = Result<u64>> + Send where Self: 'a;
+
+    /// 持久订阅 name 在这个 channel 上最后 ack 的 seq
+    fn load_cursor(&self, ch_id: &K, name: &str) -> Self::LoadCursorFut<'_>;
+    type LoadCursorFut<'a>: Future<Output = Result<Option<u64>>> + Send where Self: 'a;
+
+    fn save_cursor(&self, ch_id: &K, name: &str, seq: u64) -> Self::SaveCursorFut<'_>;
+    type SaveCursorFut<'a>: Future<Output = Result<()>> + Send where Self: 'a;
 }
 
 type ReadOutput<T> = ReadQueOutput<T>;
 
+/// `ChStore::load_cold` 的类型擦除版本，channel 持有它让 suber 追赶落后的数据
+pub trait ColdLoad<K, T>: Send + Sync {
+    fn load_cold_boxed<'a>(&'a self, ch_id: &'a K, seq: u64) -> BoxFuture<'a, Result<Option<Vec<T>>>>;
+}
+
+impl<K, T, S> ColdLoad<K, T> for S 
+where
+    S: ChStore<K, T> + Send + Sync,
+    K: Sync + 'static,
+    T: Send + 'static,
+    for<'a> S::ReadFut<'a>: Send,
+{
+    fn load_cold_boxed<'a>(&'a self, ch_id: &'a K, seq: u64) -> BoxFuture<'a, Result<Option<Vec<T>>>> {
+        Box::pin(async move {
+            self.load_cold(ch_id, seq).await
+        })
+    }
+}
+
+/// `ChStore::save_cursor` 的类型擦除版本，持久订阅的 suber 持有它来保存 ack
+pub trait AckStore<K, T>: Send + Sync {
+    fn save_cursor_boxed<'a>(&'a self, ch_id: &K, name: &str, seq: u64) -> BoxFuture<'a, Result<()>>;
+}
+
+impl<K, T, S> AckStore<K, T> for S 
+where
+    S: ChStore<K, T> + Send + Sync,
+    K: 'static,
+    T: 'static,
+{
+    fn save_cursor_boxed<'a>(&'a self, ch_id: &K, name: &str, seq: u64) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.save_cursor(ch_id, name, seq))
+    }
+}
+
+
+/// 不保存任何数据，channel 只有内存里的 cache
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoStore;
+
+impl<K, T> ChStore<K, T> for NoStore {
+    fn read_hot(&self, _ch_id: &K, _seq: u64) -> ReadOutput<T> {
+        ReadQueOutput::Latest
+    }
+
+    fn load_cold(&self, _ch_id: &K, _seq: u64) -> Self::ReadFut<'_> {
+        futures::future::ready(Ok(None))
+    }
+
+    type ReadFut<'a> = futures::future::Ready<Result<Option<Vec<T>>>> where Self: 'a;
+
+    fn async_write(&self, _ch_id: &K, _v: T) -> Self::WriteFut<'_> {
+        futures::future::ready(Ok(()))
+    }
+
+    type WriteFut<'a> = futures::future::Ready<Result<()>> where Self: 'a;
+
+    fn last_seq(&self, _ch_id: &K) -> Self::LastSeqFut<'_> {
+        futures::future::ready(Ok(0))
+    }
+
+    type LastSeqFut<'a> = futures::future::Ready<Result<u64>> where Self: 'a;
+
+    fn load_cursor(&self, _ch_id: &K, _name: &str) -> Self::LoadCursorFut<'_> {
+        futures::future::ready(Ok(None))
+    }
+
+    type LoadCursorFut<'a> = futures::future::Ready<Result<Option<u64>>> where Self: 'a;
+
+    fn save_cursor(&self, _ch_id: &K, _name: &str, _seq: u64) -> Self::SaveCursorFut<'_> {
+        futures::future::ready(Ok(()))
+    }
+
+    type SaveCursorFut<'a> = futures::future::Ready<Result<()>> where Self: 'a;
+}
+
 
 pub mod mem_store {
 
     use anyhow::Result;
+    use futures::future::{Ready, ready};
     use parking_lot::RwLock;
-    use std::{future::Future, marker::PhantomData};
-    use crate::{ch_common::{ChDeque, WithSeq, GetSeq}};
+    use crate::ch_common::{ChDeque, GetSeq, ChIdOp, VecMap, ReadQueOutput};
 
-    use super::{ChStore, ReadOutput};
+    use super::{ChStore, ReadOutput, LOAD_BATCH};
 
+    /// keep the last `cap` messages of each channel in memory
     pub struct ChMemStore<K, T> { 
-        hot: RwLock<ChDeque<T>>,
-        cold: RwLock<ChDeque<T>>,
-        none: PhantomData<(K, T)>,
+        cap: usize,
+        channels: RwLock<VecMap<K, ChDeque<T>>>,
+        cursors: RwLock<VecMap<(K, String), u64>>,
     }
     
     impl<K, T> ChMemStore<K, T> {
         pub fn with_capacity(cap: usize) -> Self {
             Self {
-                hot: RwLock::new(ChDeque::with_capacity(cap)),
-                cold: RwLock::new(ChDeque::with_capacity(cap)),
-                none: PhantomData,
+                cap,
+                channels: RwLock::new(VecMap::new()),
+                cursors: RwLock::new(VecMap::new()),
             }
         }
     }
-    
-    impl<'s, K: 's, T> ChStore<'s, K, T> for ChMemStore<K, T> 
+
+    impl<K, T> ChMemStore<K, T> 
     where
-        T: Clone + GetSeq + WithSeq + 's,
+        K: ChIdOp,
+        T: Clone + GetSeq,
     {
-        fn read_hot(&self, seq: u64) -> ReadOutput<T> {
-            self.hot.read().read_next(seq)
+        fn load(&self, ch_id: &K, seq: u64) -> Option<Vec<T>> {
+            let channels = self.channels.read();
+            let batch = channels.get(ch_id)?.read_from(seq, LOAD_BATCH);
+            if batch.is_empty() {
+                None
+            } else {
+                Some(batch)
+            }
+        }
+
+        fn write(&self, ch_id: &K, v: T) -> Result<()> {
+            let mut channels = self.channels.write();
+            let que = channels.entry(ch_id.clone()).or_insert_with(||ChDeque::with_capacity(self.cap));
+            que.push_raw(v)
         }
+    }
     
-        /// return none if reach tail
-        fn load_cold(&self, ch_id: &K, seq: u64) -> Self::ReadFut {
-            async move {
-                Ok(None)
+    impl<K, T> ChStore<K, T> for ChMemStore<K, T> 
+    where
+        K: ChIdOp,
+        T: Clone + GetSeq,
+    {
+        fn read_hot(&self, ch_id: &K, seq: u64) -> ReadOutput<T> {
+            match self.channels.read().get(ch_id) {
+                Some(que) => que.read_next(seq),
+                None => ReadQueOutput::Latest,
             }
         }
     
-        type ReadFut = impl Future<Output = Result<Option<Vec<T>>>>;
+        fn load_cold(&self, ch_id: &K, seq: u64) -> Self::ReadFut<'_> {
+            ready(Ok(self.load(ch_id, seq)))
+        }
     
-        fn async_write(&'s self, v: <T as WithSeq>::Value) -> Self::WriteFut { 
-            async move { 
-                let mut que = self.hot.write();
-                let msg = T::with_seq(que.next_seq(), v);
-                que.push_raw(msg.clone())?;
+        type ReadFut<'a> = Ready<Result<Option<Vec<T>>>> where Self: 'a;
     
-                self.cold.write().push_raw(msg.clone())?;
-                Ok(msg)
-            }
+        fn async_write(&self, ch_id: &K, v: T) -> Self::WriteFut<'_> { 
+            ready(self.write(ch_id, v))
         }
     
-        type WriteFut = impl Future<Output = Result<T>> + 's;
+        type WriteFut<'a> = Ready<Result<()>> where Self: 'a;
+
+        fn last_seq(&self, ch_id: &K) -> Self::LastSeqFut<'_> {
+            ready(Ok(self.channels.read().get(ch_id).map(|que|que.last_seq()).unwrap_or(0)))
+        }
+
+        type LastSeqFut<'a> = Ready<Result<u64>> where Self: 'a;
+
+        fn load_cursor(&self, ch_id: &K, name: &str) -> Self::LoadCursorFut<'_> {
+            ready(Ok(self.cursors.read().get(&(ch_id.clone(), name.to_string())).cloned()))
+        }
+
+        type LoadCursorFut<'a> = Ready<Result<Option<u64>>> where Self: 'a;
+
+        fn save_cursor(&self, ch_id: &K, name: &str, seq: u64) -> Self::SaveCursorFut<'_> {
+            self.cursors.write().insert((ch_id.clone(), name.to_string()), seq);
+            ready(Ok(()))
+        }
+
+        type SaveCursorFut<'a> = Ready<Result<()>> where Self: 'a;
     }
     
 }
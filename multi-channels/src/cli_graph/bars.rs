@@ -62,7 +62,7 @@ impl<'a> fmt::Display for BarsDisplay<'a> {
         
 
         for row in self.rows.iter() {
-            let bar_len = bar_width as Count * row.count / self.max_count;
+            let bar_len = bar_width as Count * row.count / self.max_count.max(1);
             // let bar = Red.paint(format!("{:∎<width$}", "", width = bar_len as usize));
             let bar = Color::Yellow.paint(align_left(bar_chars(bar_len as usize), bar_width));
             // let count = Green.paint(format!("{units:width$}", units=row.count, width=count_width));
@@ -0,0 +1,313 @@
+//! `Histogram` 的终端显示：
+//! - `percentiles` 百分位表，每行附一个按值缩放的 bar
+//! - `histogram` 按对数刻度分组的分布图
+//! - `curve` 百分位曲线，横轴是 p0 .. p99.99（按 9 的个数均分）
+
+use std::fmt;
+
+use yansi::Color;
+
+use super::{Count, histogram::Histogram, format::{align_left, align_right, bar_chars, n_chars}};
+
+pub const DEFAULT_PERCENTILES: &[f64] = &[50.0, 90.0, 99.0, 99.9, 99.99, 100.0];
+
+/// 记录值的单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Raw,
+    Nanos,
+}
+
+impl Unit {
+    pub fn format(&self, v: f64) -> String {
+        match self {
+            Unit::Raw => {
+                if v.fract() == 0.0 { format!("{}", v) } else { format!("{:.2}", v) }
+            },
+            Unit::Nanos => {
+                if v < 1_000.0 {
+                    format!("{:.0}ns", v)
+                } else if v < 1_000_000.0 {
+                    format!("{:.2}µs", v / 1_000.0)
+                } else if v < 1_000_000_000.0 {
+                    format!("{:.2}ms", v / 1_000_000.0)
+                } else {
+                    format!("{:.2}s", v / 1_000_000_000.0)
+                }
+            },
+        }
+    }
+}
+
+fn label_of(p: f64) -> String {
+    if p >= 100.0 { "max".into() } else { format!("p{}", p) }
+}
+
+fn write_summary(f: &mut fmt::Formatter, hist: &Histogram, unit: Unit) -> fmt::Result {
+    writeln!(
+        f,
+        "count={} min={} mean={} max={}",
+        hist.len(),
+        unit.format(hist.min().unwrap_or(0) as f64),
+        unit.format(hist.mean().unwrap_or(0.0)),
+        unit.format(hist.max().unwrap_or(0) as f64),
+    )
+}
+
+pub fn percentiles(hist: &Histogram, unit: Unit) -> PercentilesDisplay<'_> {
+    PercentilesDisplay::new(hist, DEFAULT_PERCENTILES, unit, 100)
+}
+
+pub fn percentiles_with<'a>(hist: &'a Histogram, ps: &'a [f64], unit: Unit, width: usize) -> PercentilesDisplay<'a> {
+    PercentilesDisplay::new(hist, ps, unit, width)
+}
+
+pub struct PercentilesDisplay<'a> {
+    hist: &'a Histogram,
+    ps: &'a [f64],
+    unit: Unit,
+    width: usize,
+}
+
+impl<'a> PercentilesDisplay<'a> {
+    pub fn new(hist: &'a Histogram, ps: &'a [f64], unit: Unit, width: usize) -> Self {
+        Self { hist, ps, unit, width }
+    }
+}
+
+impl<'a> fmt::Display for PercentilesDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_summary(f, self.hist, self.unit)?;
+        let max = match self.hist.max() {
+            Some(max) => max.max(1),
+            None => return Ok(()),
+        };
+
+        let rows: Vec<(String, u64)> = self.ps.iter()
+        .map(|p| (label_of(*p), self.hist.value_at_percentile(*p).unwrap_or(0)))
+        .collect();
+        let label_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+        let value_width = rows.iter().map(|r| self.unit.format(r.1 as f64).chars().count()).max().unwrap_or(0);
+        let term_width = f.width().unwrap_or(self.width);
+        let bar_width = term_width.saturating_sub(label_width + value_width + 10).max(1);
+
+        for (label, value) in rows.iter() {
+            let bar_len = (bar_width as u128 * *value as u128 / max as u128) as usize;
+            writeln!(
+                f,
+                "[{label}] [{value}] [{bar}]",
+                label = Color::Blue.paint(align_left(label, label_width)),
+                value = Color::Green.paint(align_right(self.unit.format(*value as f64), value_width)),
+                bar = Color::Yellow.paint(align_left(bar_chars(bar_len), bar_width)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn histogram(hist: &Histogram, unit: Unit, rows: usize, width: usize) -> HistogramDisplay<'_> {
+    HistogramDisplay::new(hist, unit, rows, width)
+}
+
+pub struct HistogramDisplay<'a> {
+    hist: &'a Histogram,
+    unit: Unit,
+    rows: usize,
+    width: usize,
+}
+
+impl<'a> HistogramDisplay<'a> {
+    pub fn new(hist: &'a Histogram, unit: Unit, rows: usize, width: usize) -> Self {
+        Self { hist, unit, rows: rows.max(1), width }
+    }
+
+    /// 在 [min, max] 上按对数刻度均分成 rows 组，返回 (组下界, 组上界, count)
+    pub fn groups(&self) -> Vec<(f64, f64, Count)> {
+        let (min, max) = match (self.hist.min(), self.hist.max()) {
+            (Some(min), Some(max)) => (min.max(1) as f64, max.max(1) as f64),
+            _ => return Vec::new(),
+        };
+        let rows = if max > min { self.rows } else { 1 };
+        let ratio = (max / min).ln();
+        let edge = |i: usize| min * (ratio * i as f64 / rows as f64).exp();
+
+        let mut groups: Vec<_> = (0..rows).map(|i| (edge(i), edge(i + 1), 0)).collect();
+        for (low, high, n) in self.hist.buckets() {
+            // 用桶的上界（不超出实际范围）决定属于哪一组
+            let v = (high.max(low) as f64).clamp(min, max);
+            let i = if ratio > 0.0 {
+                ((v / min).ln() / ratio * rows as f64) as usize
+            } else {
+                0
+            };
+            groups[i.min(rows - 1)].2 += n;
+        }
+        groups
+    }
+}
+
+impl<'a> fmt::Display for HistogramDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_summary(f, self.hist, self.unit)?;
+        let groups = self.groups();
+        let max_count = groups.iter().map(|g| g.2).max().unwrap_or(0).max(1);
+
+        let ranges: Vec<String> = groups.iter()
+        .map(|g| format!("{} .. {}", self.unit.format(g.0), self.unit.format(g.1)))
+        .collect();
+        let range_width = ranges.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+        let count_width = format!("{}", max_count).len();
+        let term_width = f.width().unwrap_or(self.width);
+        let bar_width = term_width.saturating_sub(range_width + count_width + 10).max(1);
+
+        for (range, group) in ranges.iter().zip(groups.iter()) {
+            let bar_len = (bar_width as Count * group.2 / max_count) as usize;
+            writeln!(
+                f,
+                "[{range}] [{count}] [{bar}]",
+                range = Color::Blue.paint(align_left(range, range_width)),
+                count = Color::Green.paint(align_right(group.2, count_width)),
+                bar = Color::Yellow.paint(align_left(bar_chars(bar_len), bar_width)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 横轴最多到 p99.99
+const CURVE_NINES: f64 = 4.0;
+const CURVE_AXIS: &[&str] = &["p0", "p90", "p99", "p99.9", "p99.99"];
+
+pub fn curve(hist: &Histogram, unit: Unit, width: usize, height: usize) -> CurveDisplay<'_> {
+    CurveDisplay::new(hist, unit, width, height)
+}
+
+pub struct CurveDisplay<'a> {
+    hist: &'a Histogram,
+    unit: Unit,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> CurveDisplay<'a> {
+    pub fn new(hist: &'a Histogram, unit: Unit, width: usize, height: usize) -> Self {
+        Self { hist, unit, width, height: height.max(2) }
+    }
+
+    /// 第 x 列对应的百分位
+    fn percentile_of(x: usize, cols: usize) -> f64 {
+        let nines = CURVE_NINES * x as f64 / (cols - 1).max(1) as f64;
+        100.0 * (1.0 - 10_f64.powf(-nines))
+    }
+}
+
+impl<'a> fmt::Display for CurveDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_summary(f, self.hist, self.unit)?;
+        let max = match self.hist.max() {
+            Some(max) => max.max(1),
+            None => return Ok(()),
+        };
+
+        let label_width = [max, max / 2, 0].iter()
+        .map(|v| self.unit.format(*v as f64).chars().count())
+        .max().unwrap_or(0);
+        let term_width = f.width().unwrap_or(self.width);
+        let cols = term_width.saturating_sub(label_width + 2).max(CURVE_NINES as usize + 1);
+
+        // 每列的高度，0 在最下面
+        let heights: Vec<usize> = (0..cols).map(|x| {
+            let v = self.hist.value_at_percentile(Self::percentile_of(x, cols)).unwrap_or(0);
+            ((v as u128 * (self.height - 1) as u128 + max as u128 / 2) / max as u128) as usize
+        }).collect();
+
+        for y in (0..self.height).rev() {
+            let label = if y == self.height - 1 {
+                self.unit.format(max as f64)
+            } else if y == (self.height - 1) / 2 && y > 0 {
+                self.unit.format((max as u128 * y as u128 / (self.height - 1) as u128) as f64)
+            } else if y == 0 {
+                self.unit.format(0.0)
+            } else {
+                String::new()
+            };
+            let line: String = heights.iter().map(|h| if *h == y { '•' } else { ' ' }).collect();
+            writeln!(
+                f,
+                "{label} │{line}",
+                label = Color::Blue.paint(align_right(label, label_width)),
+                line = Color::Yellow.paint(line),
+            )?;
+        }
+
+        writeln!(f, "{} └{}", n_chars(' ', label_width), n_chars('─', cols))?;
+        let mut axis = vec![' '; cols + 8];
+        for (k, label) in CURVE_AXIS.iter().enumerate() {
+            let x = k * (cols - 1) / CURVE_NINES as usize;
+            let start = x.min(cols + 8 - label.len());
+            for (i, c) in label.chars().enumerate() {
+                axis[start + i] = c;
+            }
+        }
+        writeln!(
+            f,
+            "{} {}",
+            n_chars(' ', label_width + 1),
+            Color::Blue.paint(axis.into_iter().collect::<String>().trim_end()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Histogram {
+        let mut hist = Histogram::new();
+        for v in 1..=10_000_u64 {
+            hist.record(v * 1_000);
+        }
+        hist.record(50_000_000);
+        hist
+    }
+
+    #[test]
+    fn test_unit() {
+        assert_eq!(Unit::Nanos.format(999.0), "999ns");
+        assert_eq!(Unit::Nanos.format(1_500.0), "1.50µs");
+        assert_eq!(Unit::Nanos.format(2_500_000.0), "2.50ms");
+        assert_eq!(Unit::Nanos.format(3_000_000_000.0), "3.00s");
+        assert_eq!(Unit::Raw.format(42.0), "42");
+    }
+
+    #[test]
+    fn test_display() {
+        let hist = sample();
+
+        let text = format!("{}", percentiles(&hist, Unit::Nanos));
+        assert!(text.starts_with("count=10001"));
+        assert_eq!(text.lines().count(), 1 + DEFAULT_PERCENTILES.len());
+        assert!(text.contains("p99.9"));
+        assert!(text.contains("50.00ms"));
+
+        let display = histogram(&hist, Unit::Nanos, 8, 80);
+        let groups = display.groups();
+        assert_eq!(groups.len(), 8);
+        assert_eq!(groups.iter().map(|g| g.2).sum::<Count>(), hist.len());
+        assert_eq!(groups[7].2, 1);
+        assert_eq!(format!("{}", display).lines().count(), 1 + 8);
+
+        let text = format!("{}", curve(&hist, Unit::Nanos, 60, 10));
+        assert_eq!(text.lines().count(), 1 + 10 + 2);
+        assert!(text.contains("p99.99"));
+
+        // 空的和只有一个值的
+        let empty = Histogram::new();
+        assert_eq!(format!("{}", percentiles(&empty, Unit::Raw)).lines().count(), 1);
+        assert!(histogram(&empty, Unit::Raw, 4, 80).groups().is_empty());
+        let mut one = Histogram::new();
+        one.record(7);
+        assert_eq!(histogram(&one, Unit::Raw, 4, 80).groups(), vec![(7.0, 7.0, 1)]);
+        assert_eq!(format!("{}", curve(&one, Unit::Raw, 40, 4)).lines().count(), 1 + 4 + 2);
+    }
+}
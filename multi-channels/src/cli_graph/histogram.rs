@@ -0,0 +1,232 @@
+//! HDR 风格的直方图：按 2 的幂分段，每段再线性分成 2^(sub_bits-1) 个桶，
+//! 相对误差不超过 1/2^(sub_bits-1)。桶按需增长，记录一个值是 O(1)。
+//!
+//! 值的单位由调用方决定，延迟一般记纳秒，显示见 `hist_plot`
+
+use std::time::Duration;
+
+use super::Count;
+
+pub const DEFAULT_SUB_BITS: u32 = 7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    sub_bits: u32,
+    counts: Vec<Count>,
+    len: Count,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::with_sub_bits(DEFAULT_SUB_BITS)
+    }
+
+    /// sub_bits 在 [1, 16] 之间，越大越精确
+    pub fn with_sub_bits(sub_bits: u32) -> Self {
+        Self {
+            sub_bits: sub_bits.clamp(1, 16),
+            counts: Vec::new(),
+            len: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, v: u64) {
+        self.record_n(v, 1);
+    }
+
+    pub fn record_n(&mut self, v: u64, n: Count) {
+        if n == 0 {
+            return;
+        }
+        let index = self.index_of(v);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += n;
+        self.len += n;
+        self.sum += v as u128 * n as u128;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+
+    /// 按纳秒记录
+    pub fn record_duration(&mut self, d: Duration) {
+        self.record(d.as_nanos().min(u64::MAX as u128) as u64);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.sub_bits == self.sub_bits {
+            if other.counts.len() > self.counts.len() {
+                self.counts.resize(other.counts.len(), 0);
+            }
+            for (index, n) in other.counts.iter().enumerate() {
+                self.counts[index] += n;
+            }
+            self.len += other.len;
+            self.sum += other.sum;
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        } else {
+            // 精度不同时按对方每个桶的上界重新记录
+            for (low, high, n) in other.buckets() {
+                self.record_n(high.max(low), n);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::with_sub_bits(self.sub_bits);
+    }
+
+    pub fn len(&self) -> Count {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        if self.is_empty() { None } else { Some(self.min) }
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        if self.is_empty() { None } else { Some(self.max) }
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.is_empty() { None } else { Some(self.sum as f64 / self.len as f64) }
+    }
+
+    /// 至少 p% 的值都不大于返回值，p 在 [0, 100] 之间。
+    /// 返回所在桶的上界，并且不超过实际记录过的最大值
+    pub fn value_at_percentile(&self, p: f64) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        let p = p.clamp(0.0, 100.0);
+        let rank = ((p / 100.0 * self.len as f64).ceil() as Count).max(1);
+
+        let mut seen = 0;
+        for (index, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let (_low, high) = self.range_of(index);
+                return Some(high.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// 非空的桶 (low, high, count)，low/high 都包含在桶里
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, Count)> + '_ {
+        self.counts.iter().enumerate()
+        .filter(|(_index, n)| **n > 0)
+        .map(|(index, n)| {
+            let (low, high) = self.range_of(index);
+            (low, high, *n)
+        })
+    }
+
+    fn half(&self) -> u64 {
+        1 << (self.sub_bits - 1)
+    }
+
+    fn index_of(&self, v: u64) -> usize {
+        let sub = 1_u64 << self.sub_bits;
+        if v < sub {
+            return v as usize;
+        }
+        let shift = (63 - v.leading_zeros()) - self.sub_bits + 1;
+        (shift as u64 * self.half() + (v >> shift)) as usize
+    }
+
+    fn range_of(&self, index: usize) -> (u64, u64) {
+        let index = index as u64;
+        let half = self.half();
+        if index < 2 * half {
+            return (index, index);
+        }
+        let shift = index / half - 1;
+        let mantissa = index - shift * half;
+        let low = mantissa << shift;
+        let high = ((mantissa + 1) << shift).wrapping_sub(1);
+        (low, if high < low { u64::MAX } else { high })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_and_range() {
+        let hist = Histogram::with_sub_bits(3);
+        let mut last = 0;
+        for v in 0..10_000_u64 {
+            let index = hist.index_of(v);
+            // 桶号单调且连续
+            assert!(index == last || index == last + 1, "v={} index={} last={}", v, index, last);
+            last = index;
+            let (low, high) = hist.range_of(index);
+            assert!(low <= v && v <= high, "v={} range=({}, {})", v, low, high);
+        }
+
+        let index = hist.index_of(u64::MAX);
+        assert_eq!(hist.range_of(index).1, u64::MAX);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut hist = Histogram::new();
+        assert_eq!(hist.value_at_percentile(50.0), None);
+        for v in 1..=10_000 {
+            hist.record(v);
+        }
+        assert_eq!(hist.len(), 10_000);
+        assert_eq!(hist.min(), Some(1));
+        assert_eq!(hist.max(), Some(10_000));
+        assert!((hist.mean().unwrap() - 5000.5).abs() < 1e-9);
+
+        // 相对误差不超过 1/64
+        for (p, expect) in [(50.0, 5000.0), (90.0, 9000.0), (99.0, 9900.0), (99.9, 9990.0)] {
+            let v = hist.value_at_percentile(p).unwrap() as f64;
+            assert!(v >= expect && v <= expect * (1.0 + 1.0 / 64.0), "p={} v={}", p, v);
+        }
+        assert_eq!(hist.value_at_percentile(100.0), Some(10_000));
+        assert_eq!(hist.value_at_percentile(0.0), Some(1));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        a.record_n(10, 3);
+        b.record(1_000_000);
+        b.record_duration(Duration::from_micros(5));
+        a.merge(&b);
+        assert_eq!(a.len(), 5);
+        assert_eq!(a.max(), Some(1_000_000));
+        assert_eq!(a.min(), Some(10));
+
+        let mut c = Histogram::with_sub_bits(4);
+        c.merge(&a);
+        assert_eq!(c.len(), 5);
+        assert_eq!(c.buckets().map(|(_l, _h, n)| n).sum::<Count>(), 5);
+
+        a.clear();
+        assert!(a.is_empty());
+    }
+}
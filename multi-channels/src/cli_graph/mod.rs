@@ -5,3 +5,9 @@ type Count = u64;
 pub mod format;
 
 pub mod bars;
+
+pub mod histogram;
+
+pub mod hist_plot;
+
+pub mod table;
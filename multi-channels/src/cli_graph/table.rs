@@ -0,0 +1,151 @@
+//! 多列数值表，可以选一列画 bar；`LiveView` 在终端原地刷新，用来在 bench 时显示实时指标
+
+use std::fmt;
+
+use anyhow::Result;
+use console::Term;
+use yansi::Color;
+
+use super::{Count, format::{align_left, align_right, bar_chars}};
+
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<(String, Vec<Count>)>,
+    bar_column: Option<usize>,
+    width: usize,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            bar_column: None,
+            width: 100,
+        }
+    }
+
+    /// 按第 index 列的值画 bar
+    pub fn bar_column(&mut self, index: usize) -> &mut Self {
+        self.bar_column = Some(index);
+        self
+    }
+
+    pub fn width(&mut self, width: usize) -> &mut Self {
+        self.width = width;
+        self
+    }
+
+    /// 列数不够的补 0，多的忽略
+    pub fn row(&mut self, label: impl Into<String>, mut values: Vec<Count>) -> &mut Self {
+        values.resize(self.headers.len(), 0);
+        self.rows.push((label.into(), values));
+        self
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label_width = self.rows.iter().map(|r| r.0.chars().count()).max().unwrap_or(0);
+        let widths: Vec<usize> = self.headers.iter().enumerate()
+        .map(|(i, h)| {
+            let max_value = self.rows.iter().map(|r| format!("{}", r.1[i]).len()).max().unwrap_or(0);
+            h.len().max(max_value)
+        })
+        .collect();
+        let used = label_width + widths.iter().map(|w| w + 1).sum::<usize>() + 3;
+        let term_width = f.width().unwrap_or(self.width);
+        let bar_width = term_width.saturating_sub(used).max(1);
+
+        let mut header = format!("{} ", align_left("", label_width));
+        for (h, w) in self.headers.iter().zip(widths.iter()) {
+            header.push_str(&format!(" {}", align_right(h, *w)));
+        }
+        writeln!(f, "{}", Color::Blue.paint(header.trim_end()))?;
+
+        let max = self.bar_column
+        .map(|i| self.rows.iter().map(|r| r.1[i]).max().unwrap_or(0))
+        .unwrap_or(0)
+        .max(1);
+
+        for (label, values) in self.rows.iter() {
+            write!(f, "{} ", Color::Blue.paint(align_left(label, label_width)))?;
+            for (v, w) in values.iter().zip(widths.iter()) {
+                write!(f, " {}", Color::Green.paint(align_right(v, *w)))?;
+            }
+            if let Some(i) = self.bar_column {
+                let bar_len = (bar_width as Count * values[i] / max) as usize;
+                write!(f, "  {}", Color::Yellow.paint(bar_chars(bar_len)))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// 每次 `draw` 先擦掉上一次画的行再重画
+pub struct LiveView {
+    term: Term,
+    lines: usize,
+}
+
+impl LiveView {
+    pub fn new(term: Term) -> Self {
+        Self { term, lines: 0 }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Term::stderr())
+    }
+
+    pub fn is_term(&self) -> bool {
+        self.term.is_term()
+    }
+
+    pub fn term_width(&self) -> usize {
+        self.term.size().1 as usize
+    }
+
+    pub fn draw(&mut self, content: &str) -> Result<()> {
+        self.clear()?;
+        let content = content.trim_end_matches('\n');
+        self.term.write_line(content)?;
+        self.lines = content.lines().count();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        if self.lines > 0 {
+            self.term.clear_last_lines(self.lines)?;
+            self.lines = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["a", "bbbb"]);
+        table.bar_column(1).width(60);
+        table.row("x", vec![1, 2000]);
+        table.row("longer", vec![30]);
+        assert_eq!(table.rows(), 2);
+
+        let text = format!("{}", table);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("bbbb"));
+        assert!(lines[1].contains("2000") && lines[1].contains('∎'));
+        // 缺的列补 0，bar 长度是 0
+        assert!(lines[2].contains("longer") && !lines[2].contains('∎'));
+    }
+}
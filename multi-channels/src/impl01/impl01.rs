@@ -7,12 +7,11 @@
 ///   - 唤醒 suber
 /// 
 
-use std::{sync::Arc, pin::Pin, task::Poll};
+use std::{pin::Pin, task::Poll};
 use std::ops::Deref;
 use anyhow::{Result, bail};
-use futures::{task::AtomicWaker, Future};
-use parking_lot::{Mutex, RwLock};
-use crate::{ch_common::{SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque, VecSet, VecMap}, define_arc_hash};
+use futures::Future;
+use crate::{ch_common::{sync::{Arc, AtomicWaker, Mutex, RwLock}, SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque, VecSet, VecMap}, define_arc_hash};
 
 pub fn impl_name() -> &'static str {
     "impl01-AtomicWaker-Custom"
@@ -92,6 +91,10 @@ where
 
         let mut subers = ch.shared.subers.lock();
         subers.insert(HashSuberShared(self.suber_shared.clone()));
+        drop(subers);
+
+        // 订阅之前 push 的消息不会再通知，先当作 ready 读一次游标
+        self.ready_ch_ids.insert(ch.ch_id().clone());
 
         Ok(())
     }
@@ -285,9 +288,14 @@ where
     T: Clone + GetSeq,
 {
     pub fn read_next(&mut self) -> RecvOutput<K, T> {
-        let r =  {
+        let r = {
             let queue = self.ch.shared.queue.read();
-            queue.read_next(self.seq)
+            let r = queue.read_next(self.seq);
+            if let ReadQueOutput::Lagged = r {
+                // 跳过丢失的部分，从 cache 里最早的消息继续
+                self.seq = self.seq.max(queue.first_seq());
+            }
+            r
         };
 
         match r {
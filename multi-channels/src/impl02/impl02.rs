@@ -10,11 +10,12 @@
 
 use std::sync::Arc;
 use std::ops::DerefMut;
+use std::time::Duration;
 use anyhow::{Result, bail};
 // use async_broadcast::{broadcast, Receiver, Sender, TryRecvError};
 use parking_lot::{Mutex, RwLock};
 use crate::ch_common::uid::{SuberId, next_suber_id};
-use crate::ch_common::{SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque, VecMap};
+use crate::ch_common::{SeqVal, RecvOutput, GetSeq, ReadQueOutput, ChIdOp, WithSeq, ChDeque, VecMap, blocking};
 
 use crate::mpsc_ch::mpsc_defs::error::TryRecvError;
 use crate::mpsc_ch::mpsc_defs::{SenderOp, MpscOp, AsyncRecvOp, TryRecvOp, ReceiverOp};
@@ -249,6 +250,16 @@ where
         }
     }
 
+    /// 普通线程上阻塞等下一条，不需要 tokio runtime
+    pub fn recv_blocking(&mut self) -> RecvOutput<K, T> {
+        blocking::block_on(self.recv_next())
+    }
+
+    /// 超时返回 RecvOutput::None
+    pub fn recv_timeout(&mut self, timeout: Duration) -> RecvOutput<K, T> {
+        blocking::block_on_timeout(self.recv_next(), timeout).unwrap_or(RecvOutput::None)
+    }
+
     fn try_recv_active(&mut self) -> RecvOutput<K, T> { 
         loop {
             let r = self.rx.try_recv();
@@ -455,7 +466,12 @@ where
         
         let r = {
             let queue = self.ch.shared.queue.read();
-            queue.read_next(self.seq)
+            let r = queue.read_next(self.seq);
+            if let ReadQueOutput::Lagged = r {
+                // 跳过丢失的部分，从 cache 里最早的消息继续
+                self.seq = self.seq.max(queue.first_seq());
+            }
+            r
         };
 
         match r {
@@ -21,6 +21,7 @@ pub mod impl51;
 mod ch_common;
 mod bench_impl;
 mod test_impl;
+mod test_loom;
 
 pub mod mpsc_ch;
 
@@ -31,9 +32,19 @@ pub mod ch_hub;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|s| s.as_str()) == Some("bench") {
+        return mpsc_ch::bench_cli::run(&args[1..]).await;
+    }
+    if args.first().map(|s| s.as_str()) == Some("bench-impl") {
+        return bench_impl::run().await;
+    }
+
     let rtype = 0;
     match rtype {
         0 => mpsc_ch::bench_mpsc::run().await,
+        1 => ch_hub::bench_hub::run().await,
+        2 => bench_impl::run().await,
         51 => impl51::run().await,
         81 => poc_futures::run().await,
         82 => poc_async_broadcast::run().await,
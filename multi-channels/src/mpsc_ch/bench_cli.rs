@@ -0,0 +1,525 @@
+//! 命令行 bench，参数都可以给多个值（逗号分隔），按笛卡尔积逐个跑：
+//!
+//! ```text
+//! multi-channels bench [--backends kanal,flume] [--cases 1_to_n,1_to_n_sendonly]
+//!     [--ch-len 256] [--ch-num 10000] [--msg-num 1,10] [--senders 1,4]
+//!     [--trials 5] [--warmup 1]
+//!     [--json out.json] [--csv out.csv]
+//!     [--baseline base.csv] [--threshold 10]
+//!     [--latency table|histogram|curve|none]
+//! ```
+//!
+//! - 每组参数先跑 warmup 次不计入结果，再跑 trials 次，报告 mean / p50 / p99 / throughput
+//! - 1_to_n 还会统计每条消息从发送到收到的延迟，合并所有 trials 后按 `--latency` 显示
+//! - baseline 是之前 `--csv` 写出的文件，mean 比 baseline 慢超过 threshold% 的算回归，
+//!   有回归时返回错误，方便在提交之间比较
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use console::Term;
+
+use crate::cli_graph::{histogram::Histogram, hist_plot::{self, Unit}};
+
+use super::{
+    bench_mpsc::{BenchArgs, Message, measure_1_to_n, measure_1_to_n_sendonly},
+    mpsc_defs::{MpscOp, AsyncRecvOp},
+    mpsc_async_broadcast,
+    mpsc_tokio_mpsc,
+    mpsc_async_channel,
+    mpsc_tokio_broadcast,
+    mpsc_crossbeam_que,
+    mpsc_kanal, mpsc_concurrent_que, mpsc_flume, mpsc_ring_buf,
+};
+
+pub const BACKENDS: &[&str] = &[
+    "crossbeam_que",
+    "kanal",
+    "async_broadcast",
+    "async_channel",
+    "concurrent_que",
+    "flume",
+    "tokio_mpsc",
+    "tokio_broadcast",
+    "ring_buf",
+];
+
+const CSV_HEADER: &str = "case,backend,ch_len,ch_num,msg_num,senders,trials,mean_ms,p50_ms,p99_ms,throughput";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// 发完并且所有接收 task 都收完
+    OneToN,
+    /// 只发不收
+    OneToNSendOnly,
+}
+
+impl Case {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Case::OneToN => "1_to_n",
+            Case::OneToNSendOnly => "1_to_n_sendonly",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "1_to_n" => Ok(Case::OneToN),
+            "1_to_n_sendonly" => Ok(Case::OneToNSendOnly),
+            _ => bail!("unknown case [{}]", s),
+        }
+    }
+}
+
+/// 延迟的显示方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyView {
+    None,
+    Table,
+    Histogram,
+    Curve,
+}
+
+impl LatencyView {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(LatencyView::None),
+            "table" => Ok(LatencyView::Table),
+            "histogram" => Ok(LatencyView::Histogram),
+            "curve" => Ok(LatencyView::Curve),
+            _ => bail!("unknown latency view [{}]", s),
+        }
+    }
+
+    fn render(&self, latency: &Histogram, width: usize) -> Option<String> {
+        match self {
+            LatencyView::None => None,
+            LatencyView::Table => Some(format!("{}", hist_plot::percentiles_with(latency, hist_plot::DEFAULT_PERCENTILES, Unit::Nanos, width))),
+            LatencyView::Histogram => Some(format!("{}", hist_plot::histogram(latency, Unit::Nanos, 12, width))),
+            LatencyView::Curve => Some(format!("{}", hist_plot::curve(latency, Unit::Nanos, width, 12))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOpts {
+    pub backends: Vec<String>,
+    pub cases: Vec<Case>,
+    pub ch_lens: Vec<usize>,
+    pub ch_nums: Vec<usize>,
+    pub msg_nums: Vec<usize>,
+    pub senders: Vec<usize>,
+    pub trials: usize,
+    pub warmup: usize,
+    pub json: Option<String>,
+    pub csv: Option<String>,
+    pub baseline: Option<String>,
+    /// percent
+    pub threshold: f64,
+    pub latency: LatencyView,
+}
+
+impl Default for BenchOpts {
+    fn default() -> Self {
+        Self {
+            backends: BACKENDS.iter().map(|s| s.to_string()).collect(),
+            cases: vec![Case::OneToN],
+            ch_lens: vec![256],
+            ch_nums: vec![10_000],
+            msg_nums: vec![1],
+            senders: vec![1],
+            trials: 5,
+            warmup: 1,
+            json: None,
+            csv: None,
+            baseline: None,
+            threshold: 10.0,
+            latency: LatencyView::Table,
+        }
+    }
+}
+
+impl BenchOpts {
+    /// 支持 `--name value` 和 `--name=value`
+    pub fn parse<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut opts = Self::default();
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            let arg = arg.as_ref();
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = iter.next().with_context(||format!("missing value of [{}]", arg))?;
+                    (arg.to_string(), value.as_ref().to_string())
+                }
+            };
+
+            match name.as_str() {
+                "--backends" => {
+                    let backends = split(&value);
+                    for backend in backends.iter() {
+                        if !BACKENDS.contains(&backend.as_str()) {
+                            bail!("unknown backend [{}], expect one of {:?}", backend, BACKENDS)
+                        }
+                    }
+                    opts.backends = backends;
+                }
+                "--cases" => opts.cases = split(&value).iter().map(|s| Case::parse(s)).collect::<Result<_>>()?,
+                "--ch-len" => opts.ch_lens = parse_list(&name, &value)?,
+                "--ch-num" => opts.ch_nums = parse_list(&name, &value)?,
+                "--msg-num" => opts.msg_nums = parse_list(&name, &value)?,
+                "--senders" => opts.senders = parse_list(&name, &value)?,
+                "--trials" => opts.trials = parse_one(&name, &value)?,
+                "--warmup" => opts.warmup = parse_one(&name, &value)?,
+                "--json" => opts.json = Some(value),
+                "--csv" => opts.csv = Some(value),
+                "--baseline" => opts.baseline = Some(value),
+                "--threshold" => opts.threshold = parse_one(&name, &value)?,
+                "--latency" => opts.latency = LatencyView::parse(value.trim())?,
+                _ => bail!("unknown option [{}]", name),
+            }
+        }
+
+        if opts.trials == 0 {
+            bail!("--trials must > 0")
+        }
+        Ok(opts)
+    }
+}
+
+fn split(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
+
+fn parse_one<V: std::str::FromStr>(name: &str, value: &str) -> Result<V> {
+    match value.trim().parse() {
+        Ok(v) => Ok(v),
+        Err(_e) => bail!("invalid value of [{}]: [{}]", name, value),
+    }
+}
+
+fn parse_list<V: std::str::FromStr>(name: &str, value: &str) -> Result<Vec<V>> {
+    let list = split(value).iter().map(|s| parse_one(name, s)).collect::<Result<Vec<V>>>()?;
+    if list.is_empty() {
+        bail!("empty value of [{}]", name)
+    }
+    Ok(list)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchRecord {
+    pub case: String,
+    pub backend: String,
+    pub ch_len: usize,
+    pub ch_num: usize,
+    pub msg_num: usize,
+    pub senders: usize,
+    pub trials: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    /// messages per second, based on mean
+    pub throughput: f64,
+}
+
+impl BenchRecord {
+    fn new(case: Case, backend: &str, args: &BenchArgs, samples: &[Duration]) -> Self {
+        let (mean, p50, p99) = stats(samples);
+        let msgs = (args.ch_num * args.msg_num) as f64;
+        Self {
+            case: case.name().to_string(),
+            backend: backend.to_string(),
+            ch_len: args.ch_len,
+            ch_num: args.ch_num,
+            msg_num: args.msg_num,
+            senders: args.senders,
+            trials: samples.len(),
+            mean_ms: mean,
+            p50_ms: p50,
+            p99_ms: p99,
+            throughput: if mean > 0.0 { msgs * 1000.0 / mean } else { 0.0 },
+        }
+    }
+
+    /// 和 baseline 对比时用的 key
+    fn key(&self) -> String {
+        format!("{}/{}/len={}/num={}/msg={}/senders={}",
+            self.case, self.backend, self.ch_len, self.ch_num, self.msg_num, self.senders)
+    }
+
+    fn to_csv(&self) -> String {
+        format!("{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.1}",
+            self.case, self.backend, self.ch_len, self.ch_num, self.msg_num, self.senders,
+            self.trials, self.mean_ms, self.p50_ms, self.p99_ms, self.throughput)
+    }
+
+    fn from_csv(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+        if fields.len() != 11 {
+            bail!("expect 11 fields but [{}], line [{}]", fields.len(), line)
+        }
+        Ok(Self {
+            case: fields[0].to_string(),
+            backend: fields[1].to_string(),
+            ch_len: parse_one("ch_len", fields[2])?,
+            ch_num: parse_one("ch_num", fields[3])?,
+            msg_num: parse_one("msg_num", fields[4])?,
+            senders: parse_one("senders", fields[5])?,
+            trials: parse_one("trials", fields[6])?,
+            mean_ms: parse_one("mean_ms", fields[7])?,
+            p50_ms: parse_one("p50_ms", fields[8])?,
+            p99_ms: parse_one("p99_ms", fields[9])?,
+            throughput: parse_one("throughput", fields[10])?,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        format!("{{\"case\":\"{}\",\"backend\":\"{}\",\"ch_len\":{},\"ch_num\":{},\"msg_num\":{},\"senders\":{},\"trials\":{},\"mean_ms\":{:.3},\"p50_ms\":{:.3},\"p99_ms\":{:.3},\"throughput\":{:.1}}}",
+            self.case, self.backend, self.ch_len, self.ch_num, self.msg_num, self.senders,
+            self.trials, self.mean_ms, self.p50_ms, self.p99_ms, self.throughput)
+    }
+}
+
+/// (mean, p50, p99) in millis，百分位按 nearest-rank
+fn stats(samples: &[Duration]) -> (f64, f64, f64) {
+    if samples.is_empty() {
+        return (0.0, 0.0, 0.0);
+    }
+    let mut millis: Vec<f64> = samples.iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+    millis.sort_by(|a, b| a.total_cmp(b));
+    let mean = millis.iter().sum::<f64>() / millis.len() as f64;
+    let rank = |p: f64| {
+        let n = (p / 100.0 * millis.len() as f64).ceil() as usize;
+        millis[n.clamp(1, millis.len()) - 1]
+    };
+    (mean, rank(50.0), rank(99.0))
+}
+
+pub fn to_csv(records: &[BenchRecord]) -> String {
+    let mut text = String::new();
+    let _r = writeln!(text, "{}", CSV_HEADER);
+    for record in records {
+        let _r = writeln!(text, "{}", record.to_csv());
+    }
+    text
+}
+
+pub fn from_csv(text: &str) -> Result<Vec<BenchRecord>> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    match lines.next() {
+        Some(header) if header.trim() == CSV_HEADER => {},
+        _ => bail!("invalid csv header, expect [{}]", CSV_HEADER),
+    }
+    lines.map(BenchRecord::from_csv).collect()
+}
+
+pub fn to_json(records: &[BenchRecord]) -> String {
+    let rows: Vec<String> = records.iter().map(|r| format!("  {}", r.to_json())).collect();
+    format!("[\n{}\n]\n", rows.join(",\n"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub key: String,
+    pub base_ms: f64,
+    pub mean_ms: f64,
+    /// percent
+    pub change: f64,
+}
+
+/// mean 比 baseline 慢超过 threshold% 的记录，baseline 里没有的跳过
+pub fn compare(records: &[BenchRecord], baseline: &[BenchRecord], threshold: f64) -> Vec<Regression> {
+    let mut regressions = Vec::new();
+    for record in records {
+        let key = record.key();
+        let base = match baseline.iter().find(|b| b.key() == key) {
+            Some(base) if base.mean_ms > 0.0 => base,
+            _ => continue,
+        };
+        let change = (record.mean_ms - base.mean_ms) * 100.0 / base.mean_ms;
+        if change > threshold {
+            regressions.push(Regression { key, base_ms: base.mean_ms, mean_ms: record.mean_ms, change });
+        }
+    }
+    regressions
+}
+
+pub async fn run<S: AsRef<str>>(args: &[S]) -> Result<()> {
+    let opts = BenchOpts::parse(args)?;
+    let term = Term::stderr();
+
+    let baseline = match &opts.baseline {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).with_context(||format!("read baseline failed [{}]", path))?;
+            Some(from_csv(&text).with_context(||format!("parse baseline failed [{}]", path))?)
+        },
+        None => None,
+    };
+
+    let mut records = Vec::new();
+    for &case in opts.cases.iter() {
+        for &ch_len in opts.ch_lens.iter() {
+            for &ch_num in opts.ch_nums.iter() {
+                for &msg_num in opts.msg_nums.iter() {
+                    for &senders in opts.senders.iter() {
+                        let args = BenchArgs { ch_len, ch_num, msg_num, senders };
+                        for backend in opts.backends.iter() {
+                            term.write_line(&format!("{} {} {:?}", case.name(), backend, args))?;
+                            for _ in 0..opts.warmup {
+                                run_trial(case, backend, &args).await?;
+                            }
+                            let mut samples = Vec::with_capacity(opts.trials);
+                            let mut latency = Histogram::new();
+                            for _ in 0..opts.trials {
+                                let (elapsed, trial_latency) = run_trial(case, backend, &args).await?;
+                                samples.push(elapsed);
+                                if let Some(trial_latency) = trial_latency {
+                                    latency.merge(&trial_latency);
+                                }
+                            }
+
+                            let record = BenchRecord::new(case, backend, &args, &samples);
+                            term.write_line(&format!(
+                                "  mean {:.3} ms, p50 {:.3} ms, p99 {:.3} ms, {:.0} msg/s",
+                                record.mean_ms, record.p50_ms, record.p99_ms, record.throughput,
+                            ))?;
+                            if !latency.is_empty() {
+                                if let Some(text) = opts.latency.render(&latency, term.size().1 as usize) {
+                                    term.write_line("  latency:")?;
+                                    term.write_str(&text)?;
+                                }
+                            }
+                            records.push(record);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &opts.json {
+        std::fs::write(path, to_json(&records)).with_context(||format!("write json failed [{}]", path))?;
+    }
+    if let Some(path) = &opts.csv {
+        std::fs::write(path, to_csv(&records)).with_context(||format!("write csv failed [{}]", path))?;
+    }
+
+    if let Some(baseline) = &baseline {
+        let regressions = compare(&records, baseline, opts.threshold);
+        for r in regressions.iter() {
+            term.write_line(&format!(
+                "REGRESSION {}: {:.3} ms -> {:.3} ms (+{:.1}%)",
+                r.key, r.base_ms, r.mean_ms, r.change,
+            ))?;
+        }
+        if !regressions.is_empty() {
+            bail!("[{}] regressions over {}% against baseline", regressions.len(), opts.threshold)
+        }
+        term.write_line("no regression against baseline")?;
+    }
+
+    Ok(())
+}
+
+/// 返回耗时，case 有统计时同时返回消息延迟
+async fn run_trial(case: Case, backend: &str, args: &BenchArgs) -> Result<(Duration, Option<Histogram>)> {
+    match backend {
+        "crossbeam_que" => run_case::<mpsc_crossbeam_que::Mpsc>(case, args).await,
+        "kanal" => run_case::<mpsc_kanal::Mpsc>(case, args).await,
+        "async_broadcast" => run_case::<mpsc_async_broadcast::Mpsc>(case, args).await,
+        "async_channel" => run_case::<mpsc_async_channel::Mpsc>(case, args).await,
+        "concurrent_que" => run_case::<mpsc_concurrent_que::Mpsc>(case, args).await,
+        "flume" => run_case::<mpsc_flume::Mpsc>(case, args).await,
+        "tokio_mpsc" => run_case::<mpsc_tokio_mpsc::Mpsc>(case, args).await,
+        "tokio_broadcast" => run_case::<mpsc_tokio_broadcast::Mpsc>(case, args).await,
+        "ring_buf" => run_case::<mpsc_ring_buf::Mpsc>(case, args).await,
+        _ => bail!("unknown backend [{}]", backend),
+    }
+}
+
+async fn run_case<M>(case: Case, args: &BenchArgs) -> Result<(Duration, Option<Histogram>)>
+where
+    M: MpscOp<Message>,
+    M::Sender: Send + 'static,
+    M::Receiver: Send + 'static,
+    for<'a> <M::Receiver as AsyncRecvOp<Message>>::Fut<'a>: Send,
+{
+    match case {
+        Case::OneToN => measure_1_to_n::<M>(args).await.map(|(_sent, recved, latency)| (recved, Some(latency))),
+        Case::OneToNSendOnly => measure_1_to_n_sendonly::<M>(args).await.map(|sent| (sent, None)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let opts = BenchOpts::parse(["--backends", "kanal,flume", "--ch-num=10,20", "--trials", "3", "--cases", "1_to_n_sendonly"]).unwrap();
+        assert_eq!(opts.backends, vec!["kanal".to_string(), "flume".to_string()]);
+        assert_eq!(opts.ch_nums, vec![10, 20]);
+        assert_eq!(opts.trials, 3);
+        assert_eq!(opts.cases, vec![Case::OneToNSendOnly]);
+        assert_eq!(opts.msg_nums, BenchOpts::default().msg_nums);
+        assert_eq!(opts.latency, LatencyView::Table);
+        assert_eq!(BenchOpts::parse(["--latency=curve"]).unwrap().latency, LatencyView::Curve);
+        assert!(BenchOpts::parse(["--latency", "pie"]).is_err());
+
+        assert!(BenchOpts::parse(["--backends", "nope"]).is_err());
+        assert!(BenchOpts::parse(["--ch-num"]).is_err());
+        assert!(BenchOpts::parse(["--ch-num", "x"]).is_err());
+        assert!(BenchOpts::parse(["--trials", "0"]).is_err());
+        assert!(BenchOpts::parse(["--what", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let samples: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        let (mean, p50, p99) = stats(&samples);
+        assert!((mean - 50.5).abs() < 1e-9);
+        assert!((p50 - 50.0).abs() < 1e-9);
+        assert!((p99 - 99.0).abs() < 1e-9);
+
+        let (mean, p50, p99) = stats(&[Duration::from_millis(7)]);
+        assert_eq!((mean, p50, p99), (7.0, 7.0, 7.0));
+    }
+
+    #[test]
+    fn test_csv_and_compare() {
+        let args = BenchArgs { ch_len: 16, ch_num: 100, msg_num: 2, senders: 1 };
+        let fast = BenchRecord::new(Case::OneToN, "kanal", &args, &[Duration::from_millis(10)]);
+        let slow = BenchRecord::new(Case::OneToN, "kanal", &args, &[Duration::from_millis(12)]);
+        assert!((fast.throughput - 20_000.0).abs() < 1e-6);
+
+        let baseline = vec![fast];
+        let parsed = from_csv(&to_csv(&baseline)).unwrap();
+        assert_eq!(parsed, baseline);
+        assert!(from_csv("a,b\n").is_err());
+        assert!(to_json(&baseline).contains("\"backend\":\"kanal\""));
+
+        assert!(compare(&baseline, &parsed, 10.0).is_empty());
+        let slow = vec![slow];
+        let regressions = compare(&slow, &parsed, 10.0);
+        assert_eq!(regressions.len(), 1);
+        assert!((regressions[0].change - 20.0).abs() < 1e-6);
+        assert!(compare(&slow, &parsed, 25.0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_trial() {
+        let args = BenchArgs { ch_len: 4, ch_num: 8, msg_num: 2, senders: 3 };
+        for backend in BACKENDS {
+            let (_elapsed, latency) = run_trial(Case::OneToN, backend, &args).await.unwrap();
+            assert_eq!(latency.map(|l| l.len()), Some((args.ch_num * args.msg_num) as u64));
+            let (_elapsed, latency) = run_trial(Case::OneToNSendOnly, backend, &args).await.unwrap();
+            assert!(latency.is_none());
+        }
+        assert!(run_trial(Case::OneToN, "nope", &args).await.is_err());
+    }
+}
@@ -3,7 +3,7 @@ use anyhow::Result;
 use console::Term;
 use tokio::time::sleep;
 use crate::{
-    cli_graph::bars::{BarRow, self} 
+    cli_graph::{bars::{BarRow, self}, histogram::Histogram, hist_plot::{self, Unit}},
 };
 use super::{
     mpsc_defs::{MpscOp, SenderOp, AsyncRecvOp},
@@ -12,21 +12,24 @@ use super::{
     mpsc_async_channel, 
     mpsc_tokio_broadcast,
     mpsc_crossbeam_que,
-    mpsc_kanal, mpsc_concurrent_que, mpsc_flume,
+    mpsc_kanal, mpsc_concurrent_que, mpsc_flume, mpsc_ring_buf,
 };
 
-#[derive(Debug)]
-struct BenchArgs {
-    ch_len: usize,
-    ch_num: usize,
-    msg_num: usize,
+#[derive(Debug, Clone)]
+pub(super) struct BenchArgs {
+    pub ch_len: usize,
+    pub ch_num: usize,
+    pub msg_num: usize,
+    /// 发送 task 数，channel 平均分给各个 task
+    pub senders: usize,
 }
 
 pub async fn run() -> Result<()> { 
     let mut bench = Bench {
-        args: BenchArgs{ ch_len: 256, ch_num: 300_000 , msg_num: 1 },
+        args: BenchArgs{ ch_len: 256, ch_num: 300_000 , msg_num: 1, senders: 1 },
         term: Term::stderr(),
         bars: Vec::with_capacity(16),
+        latency: Vec::with_capacity(16),
         is_plot: false,
     };
 
@@ -65,6 +68,7 @@ async fn bench_1_to_n_round(bench: &mut Bench) -> Result<()> {
     bench_1_to_n::<mpsc_flume::Mpsc>(bench).await?;
     bench_1_to_n::<mpsc_tokio_mpsc::Mpsc>(bench).await?;
     bench_1_to_n::<mpsc_tokio_broadcast::Mpsc>(bench).await?;
+    bench_1_to_n::<mpsc_ring_buf::Mpsc>(bench).await?;
 
     bench.plot()?;
 
@@ -79,55 +83,66 @@ where
     <M as MpscOp<Message>>::Receiver: Send + 'static,
     for<'a> <<M as MpscOp<Message>>::Receiver as AsyncRecvOp<Message>>::Fut<'a>: Send,
 { 
-    let args = &bench.args;
+    let (sent_elpased, recved_elpased, latency) = measure_1_to_n::<M>(&bench.args).await?;
 
+    bench.term.clear_line()?;
+    bench.term.write_str(&format!(
+        "{},{},{},p99={}",
+        M::name(),
+        sent_elpased.as_millis(),
+        recved_elpased.as_millis(),
+        Unit::Nanos.format(latency.value_at_percentile(99.0).unwrap_or(0) as f64),
+    ))?;
+    bench.latency.push((M::name().into(), latency));
+
+    bench.bars.push(BarRow { 
+        label: M::name().into(), 
+        count: recved_elpased.as_millis() as u64 
+    });
+
+    Ok(())
+}
+
+/// ch_num 个接收 task 各收 msg_num 条，返回 (发完的耗时, 全部收完的耗时, 每条消息从发送到收到的延迟)
+pub(super) async fn measure_1_to_n<M>(args: &BenchArgs) -> Result<(Duration, Duration, Histogram)>
+where
+    M: MpscOp<Message>,
+    <M as MpscOp<Message>>::Sender: Send + 'static,
+    <M as MpscOp<Message>>::Receiver: Send + 'static,
+    for<'a> <<M as MpscOp<Message>>::Receiver as AsyncRecvOp<Message>>::Fut<'a>: Send,
+{ 
     let mut senders = Vec::with_capacity(args.ch_num);
     let mut tasks = Vec::with_capacity(args.ch_num);
     let msg_num = args.msg_num;
+    let base = Instant::now();
 
     for _i in 1..args.ch_num+1 {
         let (tx, mut rx) = M::channel(args.ch_len);
         senders.push(tx);
 
         let h = tokio::spawn(async move { 
+            let mut latency = Histogram::new();
             for _ in 0..msg_num {
-                rx.async_recv().await?;
+                let (stamp, _, _) = rx.async_recv().await?;
+                latency.record(nanos_since(base).saturating_sub(stamp));
             }
-            Result::<()>::Ok(())
+            Result::<Histogram>::Ok(latency)
         });
         tasks.push(h);
     }
     sleep(Duration::from_millis(100)).await; // wait for all task ready
 
-    let value = (1, 2, Arc::new(3));
-
     let kick_time = Instant::now();
-    for tx in &mut senders { 
-        for _ in 0..args.msg_num {
-            let _r = tx.try_send(value.clone());
-        }
-    }
+    send_all::<M>(senders, args, base).await?;
     let sent_elpased = kick_time.elapsed();
 
+    let mut latency = Histogram::new();
     for h in tasks {
-        let _r = h.await??;
+        latency.merge(&h.await??);
     }
     let recved_elpased = kick_time.elapsed();
 
-    bench.term.clear_line()?;
-    bench.term.write_str(&format!(
-        "{},{},{}",
-        M::name(),
-        sent_elpased.as_millis(),
-        recved_elpased.as_millis(),
-    ))?;
-
-    bench.bars.push(BarRow { 
-        label: M::name().into(), 
-        count: recved_elpased.as_millis() as u64 
-    });
-
-    Ok(())
+    Ok((sent_elpased, recved_elpased, latency))
 }
 
 async fn bench_1_to_n_sendonly_round(bench: &mut Bench) -> Result<()> { 
@@ -143,6 +158,7 @@ async fn bench_1_to_n_sendonly_round(bench: &mut Bench) -> Result<()> {
     bench_1_to_n_sendonly::<mpsc_flume::Mpsc>(bench).await?;
     bench_1_to_n_sendonly::<mpsc_tokio_mpsc::Mpsc>(bench).await?;
     bench_1_to_n_sendonly::<mpsc_tokio_broadcast::Mpsc>(bench).await?;
+    bench_1_to_n_sendonly::<mpsc_ring_buf::Mpsc>(bench).await?;
     
     bench.plot()?;
 
@@ -156,25 +172,7 @@ where
     <M as MpscOp<Message>>::Receiver: Send + 'static,
     for<'a> <<M as MpscOp<Message>>::Receiver as AsyncRecvOp<Message>>::Fut<'a>: Send,
 { 
-    let args = &bench.args;
-    let mut senders = Vec::with_capacity(args.ch_num);
-    let mut recvers = Vec::with_capacity(args.ch_num);
-
-    for _i in 1..args.ch_num+1 {
-        let (tx, rx) = M::channel(args.ch_len);
-        senders.push(tx);
-        recvers.push(rx);
-    }
-
-    let value = (1, 2, Arc::new(3));
-
-    let kick_time = Instant::now();
-    for tx in &mut senders { 
-        for _ in 0..args.msg_num {
-            let _r = tx.try_send(value.clone());
-        }
-    }
-    let sent_elpased = kick_time.elapsed();
+    let sent_elpased = measure_1_to_n_sendonly::<M>(&bench.args).await?;
 
     bench.term.clear_line()?;
     bench.term.write_str(&format!(
@@ -192,9 +190,69 @@ where
     Ok(())
 }
 
+/// 只发不收，返回发完的耗时
+pub(super) async fn measure_1_to_n_sendonly<M>(args: &BenchArgs) -> Result<Duration>
+where
+    M: MpscOp<Message>,
+    <M as MpscOp<Message>>::Sender: Send + 'static,
+{ 
+    let mut senders = Vec::with_capacity(args.ch_num);
+    let mut recvers = Vec::with_capacity(args.ch_num);
+
+    for _i in 1..args.ch_num+1 {
+        let (tx, rx) = M::channel(args.ch_len);
+        senders.push(tx);
+        recvers.push(rx);
+    }
+
+    let kick_time = Instant::now();
+    send_all::<M>(senders, args, kick_time).await?;
+    Ok(kick_time.elapsed())
+}
+
+/// 每个 sender 发 msg_num 条；senders > 1 时分给多个 task 并发发送。
+/// 消息的第一个字段是发送时刻（base 之后的纳秒数），用于统计延迟
+async fn send_all<M>(mut senders: Vec<M::Sender>, args: &BenchArgs, base: Instant) -> Result<()>
+where
+    M: MpscOp<Message>,
+    <M as MpscOp<Message>>::Sender: Send + 'static,
+{
+    let value = (1, 2, Arc::new(3));
+    let msg_num = args.msg_num;
+
+    if args.senders <= 1 {
+        for tx in &mut senders { 
+            for _ in 0..msg_num {
+                let _r = tx.try_send((nanos_since(base), value.1, value.2.clone()));
+            }
+        }
+        return Ok(());
+    }
+
+    let chunk = senders.len().div_ceil(args.senders);
+    let mut tasks = Vec::with_capacity(args.senders);
+    while !senders.is_empty() {
+        let mut part: Vec<_> = senders.drain(..chunk.min(senders.len())).collect();
+        let value = value.clone();
+        tasks.push(tokio::spawn(async move {
+            for tx in &mut part { 
+                for _ in 0..msg_num {
+                    let _r = tx.try_send((nanos_since(base), value.1, value.2.clone()));
+                }
+            }
+        }));
+    }
+    for h in tasks {
+        h.await?;
+    }
+    Ok(())
+}
+
 struct Bench {
     term: Term,
     bars: Vec<BarRow>,
+    /// 每个实现的消息延迟，1_to_n round 才有
+    latency: Vec<(String, Histogram)>,
     args: BenchArgs,
     is_plot: bool,
 }
@@ -211,12 +269,24 @@ impl Bench {
             let display = bars::display_with_width(&self.bars, self.term.size().1 as usize);
             self.term.write_line(&format!("{}", display))?;
             self.term.write_line("")?;
+
+            for (name, latency) in self.latency.iter() {
+                self.term.write_line(&format!("{} latency", name))?;
+                let display = hist_plot::percentiles_with(latency, hist_plot::DEFAULT_PERCENTILES, Unit::Nanos, self.term.size().1 as usize);
+                self.term.write_line(&format!("{}", display))?;
+            }
         }
         self.bars.clear();
+        self.latency.clear();
         Ok(())
     }
 }
 
-type Message = (u64, usize, Arc<u64>);
+fn nanos_since(base: Instant) -> u64 {
+    base.elapsed().as_nanos() as u64
+}
+
+/// (发送时刻, _, _)
+pub(super) type Message = (u64, usize, Arc<u64>);
 
 
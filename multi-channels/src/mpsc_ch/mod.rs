@@ -17,5 +17,8 @@ pub mod mpsc_kanal;
 
 pub mod mpsc_flume;
 
+pub mod mpsc_ring_buf;
+
 mod test_mpsc;
 pub mod bench_mpsc;
+pub mod bench_cli;
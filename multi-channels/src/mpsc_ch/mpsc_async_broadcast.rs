@@ -12,7 +12,7 @@ use super::mpsc_defs::{
     SenderOp, 
     ReceiverOp,
     TryRecvOp,
-    AsyncRecvOp, MpscOp,
+    AsyncRecvOp, SyncRecvOp, MpscOp,
 };
 
 
@@ -66,6 +66,12 @@ where
 {
 }
 
+impl<T> SyncRecvOp<T> for Receiver<T> 
+where 
+    T: Clone,
+{
+}
+
 
 impl<T> TryRecvOp<T> for Receiver<T> 
 where 
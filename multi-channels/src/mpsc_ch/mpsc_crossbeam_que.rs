@@ -1,12 +1,10 @@
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::{task::Poll, pin::Pin};
 
 use std::future::Future;
 
 use crossbeam::queue::ArrayQueue;
-use futures::task::AtomicWaker;
+use crate::ch_common::sync::{Arc, AtomicBool, AtomicWaker, Ordering};
 
 use super::mpsc_defs::{
     error::{
@@ -17,7 +15,7 @@ use super::mpsc_defs::{
     SenderOp, 
     ReceiverOp,
     TryRecvOp,
-    AsyncRecvOp, MpscOp,
+    AsyncRecvOp, SyncRecvOp, MpscOp,
 };
 
 
@@ -82,13 +80,14 @@ where
 {
     fn try_send(&mut self, msg: T) -> Result<(), TrySendError<T>> { 
         let r = self.shared.que.push(msg);
+        if r.is_err() {
+            // 先置溢出标记再唤醒；用 swap 而不是 store，和接收端的 fetch_and 同在一条修改序上
+            self.shared.overflowed.swap(true, Ordering::AcqRel);
+        }
         self.shared.waker.wake();
         match r {
             Ok(_r) => Ok(()),
-            Err(v) => { 
-                self.shared.overflowed.store(true, Ordering::Release);
-                Err(TrySendError(v))
-            },
+            Err(v) => Err(TrySendError(v)),
         }
     }
 }
@@ -122,6 +121,12 @@ where
 {
 }
 
+impl<T> SyncRecvOp<T> for Receiver<T> 
+where 
+    T: Clone,
+{
+}
+
 
 impl<T> TryRecvOp<T> for Receiver<T> 
 where 
@@ -2,7 +2,10 @@
 /// mpsc channel defines
 /// 
 
+use std::time::Duration;
+
 use futures::Future;
+use crate::ch_common::blocking;
 use self::error::*;
 
 
@@ -20,7 +23,7 @@ pub trait SenderOp<T> : Clone {
     fn try_send(&mut self, msg: T) -> Result<(), TrySendError<T>>;
 }
 
-pub trait ReceiverOp<T>: TryRecvOp<T> + AsyncRecvOp<T> { 
+pub trait ReceiverOp<T>: TryRecvOp<T> + AsyncRecvOp<T> + SyncRecvOp<T> { 
     fn clear(&mut self) {
         loop {
             let r = self.try_recv();
@@ -46,6 +49,22 @@ pub trait AsyncRecvOp<T>
     fn async_recv(&mut self) -> Self::Fut<'_>;
 }
 
+/// 给普通线程用的阻塞接收，默认在当前线程上 park 着等 async_recv，不需要 tokio runtime。
+/// 有原生阻塞接口的实现可以覆盖
+pub trait SyncRecvOp<T>: AsyncRecvOp<T> {
+    fn recv_blocking(&mut self) -> Result<T, RecvError> {
+        blocking::block_on(self.async_recv())
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match blocking::block_on_timeout(self.async_recv(), timeout) {
+            Some(Ok(v)) => Ok(v),
+            Some(Err(_e)) => Err(RecvTimeoutError::Overflowed),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+}
+
 
 
 pub mod error { 
@@ -131,4 +150,27 @@ pub mod error {
             }
         }
     }
+
+    /// An error returned from [`SyncRecvOp::recv_timeout()`].
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub enum RecvTimeoutError {
+        /// The channel has overflowed since the last element was seen.
+        Overflowed,
+
+        /// Nothing received before the timeout.
+        Timeout,
+    }
+
+    impl error::Error for RecvTimeoutError {}
+
+    impl fmt::Display for RecvTimeoutError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                RecvTimeoutError::Timeout => write!(f, "receiving timeout"),
+                RecvTimeoutError::Overflowed => {
+                    write!(f, "receiving operation but see overflowed")
+                }
+            }
+        }
+    }
 }
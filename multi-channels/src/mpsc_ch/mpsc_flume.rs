@@ -1,6 +1,7 @@
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use std::future::Future;
 
@@ -9,11 +10,12 @@ use super::mpsc_defs::{
         TrySendError,
         TryRecvError,
         RecvError,
+        RecvTimeoutError,
     }, 
     SenderOp, 
     ReceiverOp,
     TryRecvOp,
-    AsyncRecvOp, MpscOp,
+    AsyncRecvOp, SyncRecvOp, MpscOp,
 };
 
 
@@ -83,6 +85,33 @@ where
 {
 }
 
+impl<T> SyncRecvOp<T> for Receiver<T> 
+where 
+    T: Clone,
+{
+    fn recv_blocking(&mut self) -> Result<T, RecvError> {
+        let overflowed = self.overflowed.fetch_and(false, Ordering::Acquire);
+        if overflowed {
+            return Err(RecvError)
+        }
+        self.rx.recv().map_err(|_e| RecvError)
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let overflowed = self.overflowed.fetch_and(false, Ordering::Acquire);
+        if overflowed {
+            return Err(RecvTimeoutError::Overflowed)
+        }
+
+        self.rx.recv_timeout(timeout).map_err(|e|{
+            match e {
+                flume::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+                flume::RecvTimeoutError::Disconnected => RecvTimeoutError::Overflowed,
+            }
+        })
+    }
+}
+
 
 impl<T> TryRecvOp<T> for Receiver<T> 
 where 
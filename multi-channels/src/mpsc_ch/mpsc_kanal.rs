@@ -13,7 +13,7 @@ use super::mpsc_defs::{
     SenderOp, 
     ReceiverOp,
     TryRecvOp,
-    AsyncRecvOp, MpscOp,
+    AsyncRecvOp, SyncRecvOp, MpscOp,
 };
 
 
@@ -83,6 +83,12 @@ where
 {
 }
 
+impl<T> SyncRecvOp<T> for Receiver<T> 
+where 
+    T: Clone,
+{
+}
+
 
 impl<T> TryRecvOp<T> for Receiver<T> 
 where 
@@ -0,0 +1,296 @@
+//!
+//! disruptor 风格的广播环形缓冲：
+//! - 预先分配 cap 个 slot，发送方用 fetch_add 申请序号，写进 `seq % cap` 的 slot，每条消息只写一次
+//! - 每个 Receiver 自己记下一个要读的序号，slot 里的序号比它大说明被覆盖了，报 Overflowed 后跳到最老的一条
+//! - 满了不拒绝发送，直接覆盖最老的（和 tokio broadcast 一样）
+//! - `Receiver::resubscribe` 再开一个接收端，共用同一份 slot
+//!
+
+use std::{task::Poll, pin::Pin};
+
+use std::future::Future;
+
+use crate::ch_common::sync::{Arc, AtomicU64, AtomicWaker, Mutex, Ordering, RwLock};
+
+use super::mpsc_defs::{
+    error::{
+        TrySendError,
+        TryRecvError,
+        RecvError,
+    },
+    SenderOp,
+    ReceiverOp,
+    TryRecvOp,
+    AsyncRecvOp, SyncRecvOp, MpscOp,
+};
+
+
+pub struct Mpsc;
+
+impl<T> MpscOp<T> for Mpsc
+where
+    T: Clone,
+{
+    type Sender = Sender<T>;
+
+    type Receiver = Receiver<T>;
+
+    fn channel(cap: usize) -> (Self::Sender, Self::Receiver) {
+        let cap = cap.max(1);
+        let shared = Arc::new(Shared {
+            slots: (0..cap).map(|_| Mutex::new(None)).collect(),
+            tail: AtomicU64::new(0),
+            next_rx_id: AtomicU64::new(0),
+            wakers: RwLock::new(Vec::new()),
+        });
+
+        let tx = Sender {
+            shared: shared.clone(),
+        };
+
+        let rx = Receiver::new(shared, 0);
+
+        (tx, rx)
+    }
+
+    fn name() -> &'static str {
+        "ring_buf"
+    }
+}
+
+struct Shared<T> {
+    /// (序号, 值)，None 表示还没写过
+    slots: Vec<Mutex<Option<(u64, T)>>>,
+
+    /// 下一个要申请的序号
+    tail: AtomicU64,
+
+    next_rx_id: AtomicU64,
+
+    /// 每个 Receiver 一个 waker
+    wakers: RwLock<Vec<(u64, Arc<AtomicWaker>)>>,
+}
+
+impl<T> Shared<T> {
+    fn cap(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    fn slot(&self, seq: u64) -> &Mutex<Option<(u64, T)>> {
+        &self.slots[(seq % self.cap()) as usize]
+    }
+
+    fn wake_all(&self) {
+        for (_id, waker) in self.wakers.read().iter() {
+            waker.wake();
+        }
+    }
+}
+
+
+pub struct Sender<T>{
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Sender<T>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> SenderOp<T> for Sender<T>
+where
+    T: Clone,
+{
+    fn try_send(&mut self, msg: T) -> Result<(), TrySendError<T>> {
+        let seq = self.shared.tail.fetch_add(1, Ordering::AcqRel);
+        {
+            let mut slot = self.shared.slot(seq).lock();
+            // 多个发送方时，慢的那个不能盖掉已经绕了一圈的新值
+            let newer = matches!(&*slot, Some((exist, _v)) if *exist > seq);
+            if !newer {
+                *slot = Some((seq, msg));
+            }
+        }
+        self.shared.wake_all();
+        Ok(())
+    }
+}
+
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    next: u64,
+    id: u64,
+    waker: Arc<AtomicWaker>,
+}
+
+impl<T> Receiver<T>
+where
+    T: Clone,
+{
+    fn new(shared: Arc<Shared<T>>, next: u64) -> Self {
+        let id = shared.next_rx_id.fetch_add(1, Ordering::Relaxed);
+        let waker = Arc::new(AtomicWaker::new());
+        shared.wakers.write().push((id, waker.clone()));
+        Self { shared, next, id, waker }
+    }
+
+    /// 新的接收端，从当前最新的位置开始收
+    pub fn resubscribe(&self) -> Self {
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        Self::new(self.shared.clone(), tail)
+    }
+
+    /// 还没读的消息数（包含已申请序号但还没写完的）
+    pub fn len(&self) -> usize {
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        tail.saturating_sub(self.next).min(self.shared.cap()) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check_recv(&mut self) -> Option<Result<T, RecvError>> {
+        let r = self.try_recv();
+        match r {
+            Ok(v) => Some(Ok(v)),
+            Err(e) => {
+                match e {
+                    TryRecvError::Overflowed => Some(Err(RecvError)),
+                    TryRecvError::Empty => None,
+                }
+            },
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.wakers.write().retain(|(id, _w)| *id != self.id);
+    }
+}
+
+impl<T> ReceiverOp<T> for Receiver<T>
+where
+    T: Clone,
+{
+}
+
+impl<T> SyncRecvOp<T> for Receiver<T>
+where
+    T: Clone,
+{
+}
+
+
+impl<T> TryRecvOp<T> for Receiver<T>
+where
+    T: Clone,
+{
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        if self.next >= tail {
+            return Err(TryRecvError::Empty)
+        }
+
+        let slot = self.shared.slot(self.next).lock();
+        match &*slot {
+            Some((seq, v)) if *seq == self.next => {
+                let v = v.clone();
+                drop(slot);
+                self.next += 1;
+                Ok(v)
+            },
+            Some((seq, _v)) if *seq > self.next => {
+                // 被覆盖了，跳到还在缓冲里的最老一条
+                drop(slot);
+                let tail = self.shared.tail.load(Ordering::Acquire);
+                self.next = tail.saturating_sub(self.shared.cap()).max(self.next + 1);
+                Err(TryRecvError::Overflowed)
+            },
+            // 序号申请了但还没写完
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> AsyncRecvOp<T> for Receiver<T>
+where
+    T: Clone,
+{
+    type Fut<'a> = RecvFut<'a, T> where T: 'a;
+
+    fn async_recv(&mut self) -> Self::Fut<'_> {
+        RecvFut(self)
+    }
+}
+
+pub struct RecvFut<'a, T>(&'a mut Receiver<T>);
+
+impl<'a, T> Future for RecvFut<'a, T>
+where
+    T: Clone,
+{
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+
+        let r = self.0.check_recv();
+        if let Some(r) = r {
+            return Poll::Ready(r)
+        }
+
+        self.0.waker.register(cx.waker());
+
+        let r = self.0.check_recv();
+        if let Some(r) = r {
+            return Poll::Ready(r)
+        }
+
+        Poll::Pending
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_buf_broadcast() {
+        let (mut tx, mut rx1) = <Mpsc as MpscOp<u64>>::channel(4);
+        tx.try_send(1).unwrap();
+
+        // resubscribe 只收之后的
+        let mut rx2 = rx1.resubscribe();
+        tx.try_send(2).unwrap();
+        assert_eq!(rx1.len(), 2);
+        assert_eq!(rx1.try_recv(), Ok(1));
+        assert_eq!(rx1.try_recv(), Ok(2));
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(rx2.try_recv(), Ok(2));
+        assert!(rx2.is_empty());
+
+        // rx2 跟上了，rx1 落后一圈
+        for v in 3..=8 {
+            tx.try_send(v).unwrap();
+            assert_eq!(rx2.try_recv(), Ok(v));
+        }
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Overflowed));
+        assert_eq!(rx1.try_recv(), Ok(5));
+        assert_eq!(rx1.try_recv(), Ok(6));
+        for v in 9..=13 {
+            tx.try_send(v).unwrap();
+        }
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Overflowed));
+        assert_eq!(rx1.try_recv(), Ok(10));
+
+        drop(rx2);
+        assert_eq!(tx.shared.wakers.read().len(), 1);
+    }
+}
@@ -4,9 +4,10 @@
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
     use anyhow::Result;
     use super::super::{
-            mpsc_defs::{MpscOp, SenderOp, TryRecvOp, error::{TryRecvError, RecvError}, AsyncRecvOp}, 
+            mpsc_defs::{MpscOp, SenderOp, TryRecvOp, SyncRecvOp, error::{TryRecvError, RecvError, RecvTimeoutError}, AsyncRecvOp}, 
             mpsc_async_broadcast, 
             mpsc_tokio_mpsc, 
             mpsc_async_channel, 
@@ -15,6 +16,7 @@ mod test {
             mpsc_kanal, 
             mpsc_concurrent_que,
             mpsc_flume,
+            mpsc_ring_buf,
     };
 
     #[tokio::test]
@@ -57,6 +59,11 @@ mod test {
         test_num::<mpsc_kanal::Mpsc>().await
     }
 
+    #[tokio::test]
+    async fn test_mpsc_ring_buf() -> Result<()> {
+        test_num::<mpsc_ring_buf::Mpsc>().await
+    }
+
     async fn test_num<M>() -> Result<()>
     where
         M: MpscOp<TestVal> + 'static,
@@ -81,6 +88,11 @@ mod test {
             assert!(r.is_ok(), "impl={}, n={}", n, M::name());
         }
 
+        for n in 1..=MAX_RUNS {
+            let r = test_sync_recv::<M>().await;
+            assert!(r.is_ok(), "impl={}, n={}", n, M::name());
+        }
+
         Ok(())
     }
 
@@ -238,5 +250,43 @@ mod test {
     }
 
 
+    /// 普通线程上阻塞收，tokio task 里发
+    async fn test_sync_recv<M>() -> Result<()>
+    where
+        M: MpscOp<TestVal> + 'static,
+        <M as MpscOp<usize>>::Receiver: Send + 'static,
+        <M as MpscOp<usize>>::Sender: Send + 'static,
+    {
+        let capacity = 128;
+        let msg_num = 100;
+
+        let (mut tx, mut rx) = M::channel(capacity);
+        assert_eq!(rx.recv_timeout(Duration::from_millis(5)), Err(RecvTimeoutError::Timeout));
+
+        let recv_thread = std::thread::spawn(move || {
+            let values: Vec<_> = (0..msg_num).map(|_n| rx.recv_timeout(Duration::from_secs(5))).collect();
+            (rx, values)
+        });
+
+        let mut tx2 = tx.clone();
+        tokio::spawn(async move {
+            for n in 0..msg_num {
+                let r = tx2.try_send(n);
+                assert!(r.is_ok(), "n={}", n);
+                tokio::task::yield_now().await;
+            }
+        }).await?;
+
+        let (mut rx, values) = recv_thread.join().unwrap();
+        assert_eq!(values, (0..msg_num).map(Ok).collect::<Vec<_>>());
+
+        let r = tx.try_send(msg_num);
+        assert!(r.is_ok());
+        assert_eq!(rx.recv_blocking(), Ok(msg_num));
+        assert_eq!(rx.recv_timeout(Duration::from_millis(5)), Err(RecvTimeoutError::Timeout));
+
+        Ok(())
+    }
+
     type TestVal = usize;
 }
@@ -5,6 +5,7 @@
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
     use anyhow::{Result, Context, bail};
 
     use crate::{
@@ -20,6 +21,7 @@ mod test {
             mpsc_crossbeam_que,
             mpsc_concurrent_que,
             mpsc_kanal, mpsc_flume, 
+            mpsc_ring_buf,
         },
     };
 
@@ -64,6 +66,11 @@ mod test {
         test_num::<mpsc_flume::Mpsc>().await
     }
 
+    #[tokio::test]
+    async fn test_mpsc_ring_buf9() -> Result<()> {
+        test_num::<mpsc_ring_buf::Mpsc>().await
+    }
+
     type DefaultM = mpsc_crossbeam_que::Mpsc;
 
     #[tokio::test]
@@ -165,6 +172,8 @@ mod test {
     async fn test_num<M>() -> Result<()>
     where
         M: MpscOp<Mail<ChId, SeqVal<TestVal>>> + 'static,
+        M::Sender: Send + Sync,
+        M::Receiver: Send,
     {
         const MAX_RUNS: usize = 16;
 
@@ -180,6 +189,8 @@ mod test {
         for n in 1..=MAX_RUNS {
             test_async_recv_and_lagged::<M>().await.with_context(||format!("fail at NO.{} when test_async_recv_and_lagged [{}]", n, impl_name()))?;
         }
+
+        test_recv_blocking::<M>().await.with_context(||format!("fail when test_recv_blocking [{}]", impl_name()))?;
         
         Ok(())
     }
@@ -252,6 +263,45 @@ mod test {
         Ok(())
     }
 
+    /// suber 在普通线程上阻塞收，puber 在 tokio task 里发
+    async fn test_recv_blocking<M>() -> Result<()>
+    where
+        M: MpscOp<Mail<ChId, SeqVal<TestVal>>> + 'static,
+        M::Sender: Send + Sync,
+        M::Receiver: Send,
+    {
+        let ch_cap = 64;
+        let inbox_cap = 64;
+        let msg_num = 50;
+        let ch_id: ChId = 1.into();
+
+        let ch = SChannel::<ChId, TestVal, M>::with_capacity(ch_id, ch_cap);
+        let puber = ch.puber();
+
+        let mut suber = Suber::with_inbox_cap(inbox_cap);
+        suber.subscribe(&ch, 1)?;
+        assert_eq!(suber.recv_timeout(Duration::from_millis(10)), RecvOutput::None);
+
+        let recv_thread = std::thread::spawn(move || {
+            let outputs: Vec<_> = (0..msg_num).map(|_n| suber.recv_blocking()).collect();
+            (suber, outputs)
+        });
+
+        tokio::spawn(async move {
+            for n in 0..msg_num {
+                puber.push(n).unwrap();
+                tokio::task::yield_now().await;
+            }
+        }).await?;
+
+        let (mut suber, outputs) = recv_thread.join().map_err(|_e| anyhow::anyhow!("recv thread panicked"))?;
+        let expect: Vec<_> = (0..msg_num).map(|n| RecvOutput::Value(ch_id, SeqVal(n as u64 + 1, n))).collect();
+        assert_eq!(outputs, expect);
+        assert_eq!(suber.recv_timeout(Duration::from_millis(10)), RecvOutput::None);
+
+        Ok(())
+    }
+
     async fn test_sync_recv_and_lagged<M>() -> Result<()>
     where
         M: MpscOp<Mail<ChId, SeqVal<TestVal>>>+ 'static,
@@ -493,6 +543,9 @@ mod test {
             match output {
                 RecvOutput::Value(_ch_id, _v) => self.check_value(&output),
                 RecvOutput::Lagged(_) => self.check_lagged_and_unsub(laggeds, &output),
+                RecvOutput::Closed(_) | RecvOutput::Reset(_) => {
+                    bail!("check_output but unexpected {:?}", output)
+                },
                 RecvOutput::None => {
                     bail!("check_output but none")
                 },
@@ -0,0 +1,176 @@
+///
+/// loom 模型测试，遍历 impl01 和自己实现的 mpsc 在多线程下的各种交错，查丢失唤醒和订阅竞争：
+///
+/// ```text
+/// RUSTFLAGS="--cfg multi_channels_loom -Zcrate-attr=feature(impl_trait_in_assoc_type)" \
+///     cargo +nightly test --release test_loom
+/// ```
+///
+/// 这个 cfg 下 `ch_common::sync` 换成 loom 的类型，只能在 `loom::model` 里用，所以只跑 test_loom
+///
+
+
+#[cfg(all(test, multi_channels_loom))]
+mod test {
+    use loom::{future::block_on, thread};
+
+    use crate::{
+        ch_common::{RecvOutput, SeqVal, uid::ChId},
+        impl01::{SChannel, Suber},
+        mpsc_ch::{
+            mpsc_defs::{AsyncRecvOp, MpscOp, SenderOp, TryRecvOp, error::TryRecvError},
+            mpsc_concurrent_que,
+            mpsc_crossbeam_que,
+        },
+    };
+
+    fn model<F>(f: F)
+    where
+        F: Fn() + Sync + Send + 'static,
+    {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(f);
+    }
+
+    /// 发送和 async_recv 的注册交错时不能丢唤醒
+    fn mpsc_no_lost_wakeup<M: MpscOp<u64>>()
+    where
+        M::Sender: Send + 'static,
+    {
+        model(|| {
+            let (mut tx, mut rx) = M::channel(4);
+            let th = thread::spawn(move || {
+                tx.try_send(1).unwrap();
+            });
+            assert_eq!(block_on(rx.async_recv()), Ok(1));
+            th.join().unwrap();
+        });
+    }
+
+    /// 溢出时要么都收到，要么少的那条报告一次 overflowed
+    fn mpsc_overflow<M: MpscOp<u64>>()
+    where
+        M::Sender: Send + 'static,
+    {
+        model(|| {
+            let (mut tx, mut rx) = M::channel(1);
+            let th = thread::spawn(move || {
+                let _r = tx.try_send(1);
+                let _r = tx.try_send(2);
+            });
+            let first = block_on(rx.async_recv());
+            th.join().unwrap();
+
+            let mut values = Vec::new();
+            let mut overflows = 0;
+            let mut on_recv = |r: Result<u64, TryRecvError>| match r {
+                Ok(v) => { values.push(v); true },
+                Err(TryRecvError::Overflowed) => { overflows += 1; true },
+                Err(TryRecvError::Empty) => false,
+            };
+            on_recv(first.map_err(|_e| TryRecvError::Overflowed));
+            while on_recv(rx.try_recv()) {}
+
+            match overflows {
+                0 => assert_eq!(values, vec![1, 2]),
+                1 => assert_eq!(values.len(), 1),
+                _ => panic!("overflowed reported [{}] times, values {:?}", overflows, values),
+            }
+        });
+    }
+
+    #[test]
+    fn test_loom_crossbeam_que() {
+        mpsc_no_lost_wakeup::<mpsc_crossbeam_que::Mpsc>();
+        mpsc_overflow::<mpsc_crossbeam_que::Mpsc>();
+    }
+
+    #[test]
+    fn test_loom_concurrent_que() {
+        mpsc_no_lost_wakeup::<mpsc_concurrent_que::Mpsc>();
+        mpsc_overflow::<mpsc_concurrent_que::Mpsc>();
+    }
+
+    type TestChannel = SChannel<ChId, u64>;
+    type TestSuber = Suber<ChId, SeqVal<u64>>;
+
+    #[test]
+    fn test_loom_impl01_publish() {
+        // push 和 recv_next 的等待交错
+        model(|| {
+            let ch = TestChannel::with_capacity(ChId::new(1), 4);
+            let mut suber = TestSuber::new();
+            suber.subscribe(&ch, 1).unwrap();
+
+            let mut puber = ch.puber();
+            let th = thread::spawn(move || {
+                puber.push(10).unwrap();
+            });
+            assert_eq!(block_on(suber.recv_next()), RecvOutput::Value(ChId::new(1), SeqVal(1, 10)));
+            th.join().unwrap();
+        });
+
+        // 两个 channel 同时唤醒同一个 suber
+        model(|| {
+            let ch1 = TestChannel::with_capacity(ChId::new(1), 4);
+            let ch2 = TestChannel::with_capacity(ChId::new(2), 4);
+            let mut suber = TestSuber::new();
+            suber.subscribe(&ch1, 1).unwrap();
+            suber.subscribe(&ch2, 1).unwrap();
+
+            let ths: Vec<_> = [&ch1, &ch2].into_iter().map(|ch| {
+                let mut puber = ch.puber();
+                thread::spawn(move || {
+                    puber.push(10).unwrap();
+                })
+            }).collect();
+
+            let mut ch_ids: Vec<_> = (0..2).map(|_| match block_on(suber.recv_next()) {
+                RecvOutput::Value(ch_id, _v) => ch_id,
+                r => panic!("expect value but {:?}", r),
+            }).collect();
+            ch_ids.sort();
+            assert_eq!(ch_ids, vec![ChId::new(1), ChId::new(2)]);
+
+            for th in ths {
+                th.join().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_loom_impl01_subscribe_race() {
+        // 订阅和 push 交错，订阅之前 push 的消息也要能收到
+        model(|| {
+            let ch = TestChannel::with_capacity(ChId::new(1), 4);
+            let mut puber = ch.puber();
+            let th = thread::spawn(move || {
+                puber.push(10).unwrap();
+            });
+
+            let mut suber = TestSuber::new();
+            suber.subscribe(&ch, 1).unwrap();
+            assert_eq!(block_on(suber.recv_next()), RecvOutput::Value(ChId::new(1), SeqVal(1, 10)));
+            th.join().unwrap();
+        });
+
+        // 退订和 push 交错，退订之后 channel 上不能留下 suber
+        model(|| {
+            let ch = TestChannel::with_capacity(ChId::new(1), 4);
+            let mut suber = TestSuber::new();
+            suber.subscribe(&ch, 1).unwrap();
+
+            let mut puber = ch.puber();
+            let th = thread::spawn(move || {
+                puber.push(10).unwrap();
+            });
+
+            assert!(suber.unsubscribe(ch.ch_id()).is_some());
+            assert_eq!(suber.try_recv(), RecvOutput::None);
+            th.join().unwrap();
+            assert_eq!(ch.subers(), 0);
+            assert_eq!(suber.try_recv(), RecvOutput::None);
+        });
+    }
+}
@@ -32,8 +32,12 @@ async fn run_oddity() -> Result<()> {
                 kind: cfg::MediaKind::File, 
                 // source: "https://storage.googleapis.com/gtv-videos-bucket/sample/BigBuckBunny.mp4".into(),
                 source: "/tmp/sample-data/sample.mp4".into(),
+                transcode: None,
+                record: None,
+                hls: false,
             }, 
         ],
+        hls: None,
     };
 
     crate::oddity_rtsp_server::run(config).await;
@@ -7,6 +7,9 @@ pub(crate) mod cmd_server;
 pub mod oddity_rtsp_server;
 mod simple_rtsp_server;
 
+#[cfg(test)]
+mod rtsp_test_client;
+
 fn main() -> Result<()> {
     return init::init_log_and_async(async move {
         let cmd = 1;
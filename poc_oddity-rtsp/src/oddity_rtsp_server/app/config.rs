@@ -1,18 +1,24 @@
 use std::error::Error;
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::Deserialize;
 
 use config::{Config, ConfigError};
 
 use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::hls::HlsSettings;
+use thiz_root::media::video::transcoder::TranscodeSettings;
 use thiz_root::media::MediaDescriptor;
+use thiz_root::source::recorder::RecordSettings;
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
     pub server: Server,
     pub media: Vec<Item>,
+    #[serde(default)]
+    pub hls: Option<Hls>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,12 +27,56 @@ pub struct Server {
     pub port: u16,
 }
 
+/// HTTP listener that serves items with `hls` enabled to browsers.
+#[derive(Debug, Deserialize)]
+pub struct Hls {
+    pub host: String,
+    pub port: u16,
+    /// Target duration of each segment in seconds.
+    #[serde(default = "Hls::default_segment_secs")]
+    pub segment_secs: u64,
+    /// Target duration of each LL-HLS partial segment in milliseconds.
+    #[serde(default = "Hls::default_part_millis")]
+    pub part_millis: u64,
+    /// Number of segments listed in the playlist.
+    #[serde(default = "Hls::default_window")]
+    pub window: usize,
+}
+
+impl Hls {
+    pub fn as_hls_settings(&self) -> HlsSettings {
+        HlsSettings {
+            target_duration: Duration::from_secs(self.segment_secs),
+            part_target: Duration::from_millis(self.part_millis),
+            window: self.window,
+        }
+    }
+
+    fn default_segment_secs() -> u64 {
+        2
+    }
+
+    fn default_part_millis() -> u64 {
+        500
+    }
+
+    fn default_window() -> usize {
+        6
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Item {
     pub name: String,
     pub path: String,
     pub kind: MediaKind,
     pub source: String,
+    #[serde(default)]
+    pub transcode: Option<Transcode>,
+    #[serde(default)]
+    pub record: Option<Record>,
+    #[serde(default)]
+    pub hls: bool,
 }
 
 impl Item {
@@ -36,6 +86,76 @@ impl Item {
             MediaKind::Stream => MediaDescriptor::Stream(self.source.parse()?),
         })
     }
+
+    pub fn as_transcode_settings(&self) -> Option<TranscodeSettings> {
+        self.transcode.as_ref().map(|transcode| TranscodeSettings {
+            bitrate: transcode.bitrate,
+            size: transcode.width.zip(transcode.height),
+        })
+    }
+
+    pub fn as_record_settings(&self) -> Option<RecordSettings> {
+        self.record.as_ref().map(|record| RecordSettings {
+            dir: PathBuf::from(&record.dir),
+            segment_duration: Duration::from_secs(record.segment_secs),
+            retention: Duration::from_secs(record.retention_secs),
+        })
+    }
+
+    /// Directory of the recordings to serve, if serving them is enabled.
+    /// Each segment is served under `<path>/recordings/<segment name>`.
+    pub fn served_recordings_dir(&self) -> Option<PathBuf> {
+        self.record
+            .as_ref()
+            .filter(|record| record.serve)
+            .map(|record| PathBuf::from(&record.dir))
+    }
+}
+
+/// Record the source to rolling segments on disk.
+#[derive(Debug, Deserialize)]
+pub struct Record {
+    /// Directory to write segments to.
+    pub dir: String,
+    /// Duration of each segment in seconds.
+    #[serde(default = "Record::default_segment_secs")]
+    pub segment_secs: u64,
+    /// Segments older than this many seconds are removed.
+    #[serde(default = "Record::default_retention_secs")]
+    pub retention_secs: u64,
+    /// Serve finished segments as file items. A segment is opened when it is
+    /// first requested.
+    #[serde(default)]
+    pub serve: bool,
+}
+
+impl Record {
+    fn default_segment_secs() -> u64 {
+        60
+    }
+
+    fn default_retention_secs() -> u64 {
+        24 * 60 * 60
+    }
+}
+
+/// Re-encode the source to H.264 baseline, for sources in a codec that
+/// clients cannot play.
+#[derive(Debug, Deserialize)]
+pub struct Transcode {
+    /// Target bitrate in kbit/s.
+    #[serde(default = "Transcode::default_bitrate")]
+    pub bitrate: u32,
+    /// Maximum output width, only used together with `height`.
+    pub width: Option<u32>,
+    /// Maximum output height, only used together with `width`.
+    pub height: Option<u32>,
+}
+
+impl Transcode {
+    fn default_bitrate() -> u32 {
+        2000
+    }
 }
 
 impl fmt::Display for Item {
@@ -44,7 +164,17 @@ impl fmt::Display for Item {
             f,
             "{} ({}): {} ({})",
             self.name, self.path, self.source, self.kind,
-        )
+        )?;
+        if let Some(transcode) = self.transcode.as_ref() {
+            write!(f, " transcoded to h264 @ {}kbps", transcode.bitrate)?;
+        }
+        if let Some(record) = self.record.as_ref() {
+            write!(f, " recorded to {}", record.dir)?;
+        }
+        if self.hls {
+            write!(f, " served over hls")?;
+        }
+        Ok(())
     }
 }
 
@@ -72,6 +202,7 @@ impl Default for AppConfig {
                 port: 554,
             },
             media: Vec::new(),
+            hls: None,
         }
     }
 }
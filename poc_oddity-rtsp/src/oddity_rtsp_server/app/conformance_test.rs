@@ -0,0 +1,183 @@
+//! Drives `AppHandler` through the real `Server` over loopback TCP. No
+//! sources are registered, so these only cover the request handling that
+//! does not need media.
+
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::RwLock;
+
+use crate::oddity_rtsp_server as thiz_root;
+use crate::rtsp_test_client::RtspTestClient;
+use thiz_root::app::handler::AppHandler;
+use thiz_root::app::initialize_context;
+use thiz_root::net::server::Server;
+use thiz_root::runtime::Runtime;
+
+const TCP_TRANSPORT: &str = "RTP/AVP/TCP;unicast;interleaved=0-1";
+
+/// Run `script` against a fresh server listening on a loopback port.
+async fn with_app<F, T>(script: F) -> Result<()>
+where
+    F: FnOnce(SocketAddr) -> T,
+    T: Future<Output = Result<()>>,
+{
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    // `Server` does not report the port it bound to, so find a free one
+    // first.
+    let port = TcpListener::bind((host, 0))?.local_addr()?.port();
+
+    let runtime = Arc::new(Runtime::new());
+    let context = Arc::new(RwLock::new(initialize_context(runtime.clone()).await));
+    let mut server = Server::start(
+        host,
+        port,
+        AppHandler::new(context.clone()),
+        runtime.clone(),
+    )
+    .await?;
+
+    let result = script(SocketAddr::new(host, port)).await;
+
+    server.stop().await;
+    context.write().await.session_manager.stop().await;
+    context.write().await.source_manager.stop().await;
+    runtime.stop().await;
+    result
+}
+
+#[tokio::test]
+async fn test_options() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client
+            .request("OPTIONS", "/example", &[])
+            .await?
+            .expect_status(200)?
+            .expect_header("Public", "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN")?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_describe() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client
+            .request("DESCRIBE", "/missing", &[("Accept", "application/sdp")])
+            .await?
+            .expect_status(404)?;
+        client
+            .request("DESCRIBE", "/missing", &[("Accept", "text/plain")])
+            .await?
+            .expect_status(406)?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_missing_cseq() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let response = client.request_raw("OPTIONS", "/example", &[]).await?;
+        response.expect_status(400)?;
+        assert_eq!(response.header("CSeq"), None);
+
+        // The connection survives the bad request.
+        client
+            .request("OPTIONS", "/example", &[])
+            .await?
+            .expect_status(200)?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_unsupported_require() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client
+            .request("DESCRIBE", "/example", &[("Require", "funky-feature")])
+            .await?
+            .expect_status(551)?
+            .expect_header("Unsupported", "funky-feature")?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_unsupported_methods() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        for method in ["ANNOUNCE", "GET_PARAMETER", "SET_PARAMETER", "PAUSE", "RECORD"] {
+            client
+                .request(method, "/example", &[])
+                .await?
+                .expect_status(405)?;
+        }
+        // REDIRECT is only ever sent by servers.
+        client
+            .request("REDIRECT", "/example", &[])
+            .await?
+            .expect_status(455)?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_setup() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client
+            .request("SETUP", "/example", &[("Transport", "bogus")])
+            .await?
+            .expect_status(461)?;
+        client
+            .request("SETUP", "/missing", &[("Transport", TCP_TRANSPORT)])
+            .await?
+            .expect_status(404)?;
+        // Changing the transport of a running session is not supported.
+        client
+            .request(
+                "SETUP",
+                "/example",
+                &[("Transport", TCP_TRANSPORT), ("Session", "12345678")],
+            )
+            .await?
+            .expect_status(459)?;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_wrong_session() -> Result<()> {
+    with_app(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client
+            .request("PLAY", "/example", &[])
+            .await?
+            .expect_status(454)?;
+        client
+            .request("PLAY", "/example", &[("Session", "12345678")])
+            .await?
+            .expect_status(454)?;
+        client
+            .request("TEARDOWN", "/example", &[])
+            .await?
+            .expect_status(454)?;
+        client
+            .request("TEARDOWN", "/example", &[("Session", "12345678")])
+            .await?
+            .expect_status(454)?;
+        Ok(())
+    })
+    .await
+}
@@ -29,6 +29,12 @@ impl AppHandler {
     pub async fn handle(&self, request: &Request, responder: &ResponseSenderTx) -> Response {
         tracing::trace!(%request, "handling request");
 
+        // Every request must carry a CSeq (RFC 2326 section 12.17), without
+        // it the client can't match our response to its request.
+        if !is_request_cseq_present(request) {
+            return reply_bad_request(request);
+        }
+
         // Check the Require header and make sure all requested options are
         // supported or return response with 551 Option Not Supported.
         if !is_request_require_supported(request) {
@@ -278,6 +284,11 @@ impl AppHandler {
     }
 }
 
+#[inline]
+fn is_request_cseq_present(request: &Request) -> bool {
+    request.headers.contains_key("CSeq")
+}
+
 #[inline]
 fn is_request_require_supported(request: &Request) -> bool {
     // We don't support any features at this point
@@ -352,6 +363,7 @@ fn reply_option_not_supported(request: &Request) -> Response {
     Response::error(Status::OptionNotSupported)
         .with_cseq_of(request)
         .with_header("Server", SERVER)
+        .with_header("Unsupported", request.require().unwrap_or_default())
         .build()
 }
 
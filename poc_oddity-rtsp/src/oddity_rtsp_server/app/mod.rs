@@ -1,6 +1,9 @@
 pub mod config;
 pub mod handler;
 
+#[cfg(test)]
+mod conformance_test;
+
 use std::error::Error;
 use std::sync::Arc;
 
@@ -9,9 +12,11 @@ use tokio::sync::RwLock;
 use crate::oddity_rtsp_server as thiz_root;
 use thiz_root::app::config::AppConfig;
 use thiz_root::app::handler::AppHandler;
+use thiz_root::hls::HlsServer;
 use thiz_root::net::server::Server;
 use thiz_root::runtime::Runtime;
 use thiz_root::session::session_manager::SessionManager;
+use thiz_root::source;
 use thiz_root::source::source_manager::SourceManager;
 
 macro_rules! handle_err {
@@ -28,6 +33,7 @@ macro_rules! handle_err {
 
 pub struct App {
     server: Server,
+    hls_server: Option<HlsServer>,
     context: Arc<RwLock<AppContext>>,
     runtime: Arc<Runtime>,
 }
@@ -42,6 +48,11 @@ impl App {
             register_sources_with_context(&config, &mut context,).await
         )?;
 
+        let hls_server = handle_err!(
+            runtime,
+            initialize_hls_server(&config, &context, runtime.clone()).await
+        )?;
+
         let context = Arc::new(RwLock::new(context));
         let server = handle_err!(
             runtime,
@@ -50,6 +61,7 @@ impl App {
 
         Ok(Self {
             server,
+            hls_server,
             context,
             runtime,
         })
@@ -57,6 +69,9 @@ impl App {
 
     pub async fn stop(&mut self) {
         self.server.stop().await;
+        if let Some(hls_server) = self.hls_server.as_mut() {
+            hls_server.stop().await;
+        }
         self.context.write().await.session_manager.stop().await;
         self.context.write().await.source_manager.stop().await;
         self.runtime.stop().await;
@@ -79,6 +94,29 @@ async fn initialize_server(
     .map_err(|err| err.into())
 }
 
+async fn initialize_hls_server(
+    config: &AppConfig,
+    context: &AppContext,
+    runtime: Arc<Runtime>,
+) -> Result<Option<HlsServer>, Box<dyn Error>> {
+    let hls = match config.hls.as_ref() {
+        Some(hls) => hls,
+        None => return Ok(None),
+    };
+
+    let hls_server =
+        HlsServer::start(hls.host.parse()?, hls.port, hls.as_hls_settings(), runtime).await?;
+    for item in config.media.iter().filter(|item| item.hls) {
+        let path = source::normalize_path(item.path.clone());
+        match context.source_manager.subscribe(&path).await {
+            Some(source_delegate) => hls_server.publish(&path, source_delegate).await,
+            None => tracing::error!(%path, "failed to subscribe to source for hls"),
+        }
+    }
+
+    Ok(Some(hls_server))
+}
+
 async fn initialize_context(runtime: Arc<Runtime>) -> AppContext {
     AppContext {
         source_manager: SourceManager::start(runtime.clone()).await,
@@ -99,8 +137,17 @@ async fn register_sources_with_context(
                 item.name.as_str(),
                 item.path.clone(),
                 item.as_media_descriptor()?,
+                item.as_transcode_settings(),
+                item.as_record_settings(),
             )
             .await?;
+
+        if let Some(dir) = item.served_recordings_dir() {
+            context
+                .source_manager
+                .register_recordings(item.name.as_str(), item.path.clone(), dir)
+                .await;
+        }
     }
     tracing::trace!("registered sources");
     Ok(())
@@ -0,0 +1,131 @@
+pub mod playlist;
+mod segmenter;
+mod server;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::time::timeout;
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::hls::playlist::{Msn, Playlist};
+use thiz_root::hls::segmenter::Segmenter;
+use thiz_root::hls::server::HttpServer;
+use thiz_root::runtime::Runtime;
+use thiz_root::source::{SourceDelegate, SourcePath, SourcePathRef};
+
+type Result<T> = std::result::Result<T, std::io::Error>;
+
+type StreamMap = Arc<RwLock<HashMap<SourcePath, Arc<HlsStream>>>>;
+
+#[derive(Debug, Clone)]
+pub struct HlsSettings {
+    /// Minimum duration of a segment. Segments are only cut on key frames
+    /// so they may be a bit longer.
+    pub target_duration: Duration,
+    /// Target duration of an LL-HLS partial segment.
+    pub part_target: Duration,
+    /// Number of finished segments listed in the playlist.
+    pub window: usize,
+}
+
+/// Playlist of a single source, shared between its segmenter and the HTTP
+/// server.
+pub struct HlsStream {
+    pub playlist: RwLock<Playlist>,
+    updated: watch::Sender<()>,
+}
+
+impl HlsStream {
+    fn new(settings: &HlsSettings) -> Self {
+        let (updated, _) = watch::channel(());
+        Self {
+            playlist: RwLock::new(Playlist::new(
+                settings.target_duration,
+                settings.part_target,
+                settings.window,
+            )),
+            updated,
+        }
+    }
+
+    /// Wake up requests that are waiting for the playlist to change.
+    pub fn notify(&self) {
+        self.updated.send_replace(());
+    }
+
+    /// Wait until the playlist contains the given segment or part, for at
+    /// most `max_wait`. Returns whether it does.
+    pub async fn wait_for(&self, msn: Msn, part: Option<usize>, max_wait: Duration) -> bool {
+        let mut updated_rx = self.updated.subscribe();
+        let wait = async {
+            loop {
+                if self.playlist.read().await.contains(msn, part) {
+                    return true;
+                }
+                if updated_rx.changed().await.is_err() {
+                    return false;
+                }
+            }
+        };
+        timeout(max_wait, wait).await.unwrap_or(false)
+    }
+}
+
+/// Serves sources as HLS with LL-HLS partial segments over HTTP, so that
+/// browsers can play the same sources that are served over RTSP.
+///
+/// Each published source is available at `<path>/index.m3u8`.
+pub struct HlsServer {
+    settings: HlsSettings,
+    streams: StreamMap,
+    segmenters: Mutex<Vec<Segmenter>>,
+    server: HttpServer,
+    runtime: Arc<Runtime>,
+}
+
+impl HlsServer {
+    pub async fn start(
+        host: IpAddr,
+        port: u16,
+        settings: HlsSettings,
+        runtime: Arc<Runtime>,
+    ) -> Result<Self> {
+        let streams = Arc::new(RwLock::new(HashMap::new()));
+        let server = HttpServer::start(host, port, streams.clone(), runtime.clone()).await?;
+
+        Ok(Self {
+            settings,
+            streams,
+            segmenters: Mutex::new(Vec::new()),
+            server,
+            runtime,
+        })
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("stopping hls server");
+        self.server.stop().await;
+        for mut segmenter in self.segmenters.lock().await.drain(..) {
+            segmenter.stop().await;
+        }
+        self.streams.write().await.clear();
+        tracing::trace!("stopped hls server");
+    }
+
+    /// Start producing HLS segments for the source at `path`.
+    pub async fn publish(&self, path: &SourcePathRef, source_delegate: SourceDelegate) {
+        let stream = Arc::new(HlsStream::new(&self.settings));
+        self.streams
+            .write()
+            .await
+            .insert(path.to_string(), stream.clone());
+
+        let segmenter = Segmenter::start(path, stream, source_delegate, &self.runtime).await;
+        self.segmenters.lock().await.push(segmenter);
+        tracing::info!(%path, "publishing source over hls");
+    }
+}
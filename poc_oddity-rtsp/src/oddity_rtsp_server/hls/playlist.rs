@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+/// Media sequence number of a segment.
+pub type Msn = u64;
+
+/// Rolling LL-HLS media playlist of a single source. Holds the finished
+/// segments within the playlist window and the segment currently being
+/// written, of which only the finished parts are visible.
+pub struct Playlist {
+    target_duration: Duration,
+    part_target: Duration,
+    window: usize,
+    segments: VecDeque<Segment>,
+    current: Segment,
+}
+
+pub struct Segment {
+    pub msn: Msn,
+    pub discontinuity: bool,
+    pub parts: Vec<Part>,
+}
+
+pub struct Part {
+    pub duration: f64,
+    pub independent: bool,
+    pub data: Bytes,
+}
+
+impl Playlist {
+    /// Number of most recent segments for which parts are listed. Older
+    /// segments are only listed as a whole.
+    const SEGMENTS_WITH_PARTS: usize = 2;
+
+    pub fn new(target_duration: Duration, part_target: Duration, window: usize) -> Self {
+        Self {
+            target_duration,
+            part_target,
+            window,
+            segments: VecDeque::new(),
+            current: Segment::new(0, false),
+        }
+    }
+
+    pub fn part_target(&self) -> Duration {
+        self.part_target
+    }
+
+    pub fn target_duration(&self) -> Duration {
+        self.target_duration
+    }
+
+    /// Append a finished part to the segment currently being written.
+    pub fn push_part(&mut self, part: Part) {
+        self.current.parts.push(part);
+    }
+
+    /// Finish the current segment and start the next one. Empty segments
+    /// are not published, only their discontinuity carries over.
+    pub fn finish_segment(&mut self, discontinuity: bool) {
+        if self.current.parts.is_empty() {
+            self.current.discontinuity |= discontinuity;
+            return;
+        }
+
+        let next = Segment::new(self.current.msn + 1, discontinuity);
+        let finished = std::mem::replace(&mut self.current, next);
+        self.segments.push_back(finished);
+        while self.segments.len() > self.window {
+            self.segments.pop_front();
+        }
+    }
+
+    /// Whether the playlist already contains the given segment, or part of
+    /// the current segment. Used to answer blocking playlist reloads.
+    pub fn contains(&self, msn: Msn, part: Option<usize>) -> bool {
+        match part {
+            Some(part) if msn == self.current.msn => part < self.current.parts.len(),
+            _ => msn < self.current.msn,
+        }
+    }
+
+    /// Next media sequence number that will be published.
+    pub fn next_msn(&self) -> Msn {
+        self.current.msn
+    }
+
+    pub fn segment(&self, msn: Msn) -> Option<Bytes> {
+        self.segments
+            .iter()
+            .find(|segment| segment.msn == msn)
+            .map(Segment::data)
+    }
+
+    pub fn part(&self, msn: Msn, part: usize) -> Option<Bytes> {
+        self.segments
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .find(|segment| segment.msn == msn)
+            .and_then(|segment| segment.parts.get(part))
+            .map(|part| part.data.clone())
+    }
+
+    pub fn render(&self) -> String {
+        // Segments are cut on key frames, so they may run longer than the
+        // configured target. The advertised target must cover all of them.
+        let target_duration = self
+            .segments
+            .iter()
+            .map(Segment::duration)
+            .fold(self.target_duration.as_secs_f64(), f64::max)
+            .ceil() as u64;
+        let part_target = self.part_target.as_secs_f64();
+
+        let mut out = String::new();
+        let _ = writeln!(out, "#EXTM3U");
+        let _ = writeln!(out, "#EXT-X-VERSION:9");
+        let _ = writeln!(out, "#EXT-X-TARGETDURATION:{}", target_duration);
+        let _ = writeln!(out, "#EXT-X-PART-INF:PART-TARGET={:.3}", part_target);
+        let _ = writeln!(
+            out,
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}",
+            part_target * 3.0,
+        );
+        let first_msn = self
+            .segments
+            .front()
+            .map_or(self.current.msn, |segment| segment.msn);
+        let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", first_msn);
+
+        let with_parts_from = self
+            .segments
+            .len()
+            .saturating_sub(Self::SEGMENTS_WITH_PARTS);
+        for (index, segment) in self.segments.iter().enumerate() {
+            if segment.discontinuity {
+                let _ = writeln!(out, "#EXT-X-DISCONTINUITY");
+            }
+            if index >= with_parts_from {
+                segment.render_parts(&mut out);
+            }
+            let _ = writeln!(out, "#EXTINF:{:.3},", segment.duration());
+            let _ = writeln!(out, "{}.ts", segment.msn);
+        }
+
+        if self.current.discontinuity && !self.current.parts.is_empty() {
+            let _ = writeln!(out, "#EXT-X-DISCONTINUITY");
+        }
+        self.current.render_parts(&mut out);
+        let _ = writeln!(
+            out,
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}.{}.ts\"",
+            self.current.msn,
+            self.current.parts.len(),
+        );
+
+        out
+    }
+}
+
+impl Segment {
+    fn new(msn: Msn, discontinuity: bool) -> Self {
+        Self {
+            msn,
+            discontinuity,
+            parts: Vec::new(),
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        self.parts.iter().map(|part| part.duration).sum()
+    }
+
+    /// A segment is the concatenation of its parts.
+    fn data(&self) -> Bytes {
+        let mut data = BytesMut::with_capacity(self.parts.iter().map(|part| part.data.len()).sum());
+        for part in self.parts.iter() {
+            data.extend_from_slice(&part.data);
+        }
+        data.freeze()
+    }
+
+    fn render_parts(&self, out: &mut String) {
+        for (index, part) in self.parts.iter().enumerate() {
+            let _ = write!(
+                out,
+                "#EXT-X-PART:DURATION={:.3},URI=\"{}.{}.ts\"",
+                part.duration, self.msn, index,
+            );
+            if part.independent {
+                let _ = write!(out, ",INDEPENDENT=YES");
+            }
+            let _ = writeln!(out);
+        }
+    }
+}
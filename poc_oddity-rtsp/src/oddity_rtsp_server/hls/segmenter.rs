@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use tokio::select;
+use tokio::sync::broadcast;
+
+use video_rs as video;
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::hls::playlist::Part;
+use thiz_root::hls::HlsStream;
+use thiz_root::media::video::buf_muxer;
+use thiz_root::runtime::task_manager::{Task, TaskContext};
+use thiz_root::runtime::Runtime;
+use thiz_root::source::{SourceDelegate, SourcePath, SourcePathRef};
+
+/// Cuts the packets of a source into MPEG-TS parts and segments and
+/// publishes them to an [`HlsStream`].
+///
+/// Segments start on key frames. Every segment gets a fresh muxer so it
+/// begins with its own PAT/PMT and can be decoded without the previous one.
+/// Parts are cut on any packet boundary once they reach the part target.
+pub struct Segmenter {
+    worker: Task,
+}
+
+impl Segmenter {
+    const SEGMENT_FORMAT: &'static str = "mpegts";
+
+    pub async fn start(
+        path: &SourcePathRef,
+        stream: Arc<HlsStream>,
+        source_delegate: SourceDelegate,
+        runtime: &Runtime,
+    ) -> Self {
+        tracing::trace!(%path, "starting hls segmenter");
+        let worker = runtime
+            .task()
+            .spawn({
+                let path = path.to_string();
+                move |task_context| Self::run(path, stream, source_delegate, task_context)
+            })
+            .await;
+        tracing::trace!(%path, "started hls segmenter");
+
+        Self { worker }
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("sending stop signal to hls segmenter");
+        self.worker.stop().await;
+        tracing::trace!("stopped hls segmenter");
+    }
+
+    async fn run(
+        path: SourcePath,
+        stream: Arc<HlsStream>,
+        mut source_delegate: SourceDelegate,
+        mut task_context: TaskContext,
+    ) {
+        let mut media_info = match source_delegate.query_media_info().await {
+            Some(media_info) => media_info,
+            None => {
+                tracing::error!(%path, "failed to query media info for hls");
+                return;
+            }
+        };
+
+        let (target_duration, part_target) = {
+            let playlist = stream.playlist.read().await;
+            (
+                playlist.target_duration().as_secs_f64(),
+                playlist.part_target().as_secs_f64(),
+            )
+        };
+
+        let (mut source_reset_rx, mut source_packet_rx) = source_delegate.into_parts();
+        let mut state = SegmenterState::default();
+
+        loop {
+            select! {
+              // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
+              reset = source_reset_rx.recv() => {
+                match reset {
+                  Ok(new_media_info) => {
+                    tracing::trace!(%path, "source reset, starting discontinuity");
+                    state.close(&stream, None, true).await;
+                    media_info = new_media_info;
+                  },
+                  Err(_) => {
+                    tracing::error!(%path, "source broken");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
+              packet = source_packet_rx.recv() => {
+                match packet {
+                  Ok(packet) => {
+                    let time = packet.dts().as_secs_f64();
+                    let is_key = packet.is_key();
+
+                    let need_new_segment = state
+                      .segment_elapsed(time)
+                      .map_or(true, |elapsed| elapsed >= target_duration);
+                    if is_key && need_new_segment {
+                      state.close(&stream, Some(time), false).await;
+                      match buf_muxer::make_buf_muxer(Self::SEGMENT_FORMAT, media_info.clone()).await {
+                        Ok(muxer) => state.open(muxer, time),
+                        Err(err) => tracing::error!(%path, %err, "failed to create hls muxer"),
+                      }
+                    } else if state.part_elapsed(time).map_or(false, |elapsed| elapsed >= part_target) {
+                      state.publish_part(&stream, time).await;
+                    }
+
+                    if let Err(err) = state.mux(packet, is_key).await {
+                      tracing::error!(%path, %err, "failed to mux hls packet");
+                      state.close(&stream, None, true).await;
+                    }
+                  },
+                  Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(%path, skipped, "hls segmenter lagged behind source");
+                    state.close(&stream, None, true).await;
+                  },
+                  Err(broadcast::error::RecvError::Closed) => {
+                    tracing::error!(%path, "source broken");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+              _ = task_context.wait_for_stop() => {
+                tracing::trace!(%path, "stopping hls segmenter");
+                break;
+              },
+            }
+        }
+
+        state.close(&stream, None, false).await;
+    }
+}
+
+/// Muxer and timing of the segment and part that are being written.
+#[derive(Default)]
+struct SegmenterState {
+    muxer: Option<video::BufMuxer>,
+    buf: Vec<u8>,
+    part_independent: bool,
+    segment_start: f64,
+    part_start: f64,
+}
+
+impl SegmenterState {
+    fn open(&mut self, muxer: video::BufMuxer, time: f64) {
+        self.muxer = Some(muxer);
+        self.buf.clear();
+        self.part_independent = false;
+        self.segment_start = time;
+        self.part_start = time;
+    }
+
+    fn segment_elapsed(&self, time: f64) -> Option<f64> {
+        self.muxer.as_ref().map(|_| time - self.segment_start)
+    }
+
+    fn part_elapsed(&self, time: f64) -> Option<f64> {
+        self.muxer.as_ref().map(|_| time - self.part_start)
+    }
+
+    async fn mux(&mut self, packet: video::Packet, is_key: bool) -> Result<(), video::Error> {
+        if let Some(muxer) = self.muxer.take() {
+            let (muxer, muxed) = buf_muxer::muxed(muxer, packet).await;
+            let buf = muxed?;
+            if self.buf.is_empty() {
+                self.part_independent = is_key;
+            }
+            self.buf.extend_from_slice(&buf);
+            self.muxer = Some(muxer);
+        }
+        Ok(())
+    }
+
+    async fn publish_part(&mut self, stream: &HlsStream, time: f64) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let part = Part {
+            duration: time - self.part_start,
+            independent: self.part_independent,
+            data: std::mem::take(&mut self.buf).into(),
+        };
+        self.part_start = time;
+        stream.playlist.write().await.push_part(part);
+        stream.notify();
+    }
+
+    /// Publish what is left of the current segment and finish it. `end` is
+    /// the time of the packet that starts the next segment, if known.
+    async fn close(&mut self, stream: &HlsStream, end: Option<f64>, discontinuity: bool) {
+        if let Some(muxer) = self.muxer.take() {
+            if let Ok(Some(trailer)) = buf_muxer::finish(muxer).await {
+                self.buf.extend_from_slice(&trailer);
+            }
+            // Without a next packet the duration of the last part is unknown,
+            // assume it took as long as the part target.
+            let end = match end {
+                Some(end) => end,
+                None => self.part_start + stream.playlist.read().await.part_target().as_secs_f64(),
+            };
+            self.publish_part(stream, end).await;
+        }
+        stream.playlist.write().await.finish_segment(discontinuity);
+        stream.notify();
+    }
+}
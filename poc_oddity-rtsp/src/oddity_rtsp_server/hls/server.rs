@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net;
+use tokio::select;
+use tokio::sync::mpsc;
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::hls::playlist::Msn;
+use thiz_root::hls::StreamMap;
+use thiz_root::runtime::task_manager::{Task, TaskContext};
+use thiz_root::runtime::Runtime;
+
+type Result<T> = std::result::Result<T, std::io::Error>;
+
+/// Minimal HTTP/1.1 server that answers `GET` requests for playlists,
+/// segments and parts. Every connection handles a single request.
+pub struct HttpServer {
+    worker: Task,
+}
+
+impl HttpServer {
+    /// Largest request head that is accepted. Players only send a request
+    /// line and a handful of headers.
+    const MAX_REQUEST_SIZE: usize = 8 * 1024;
+    /// How long blocking playlist reloads and part requests may wait for
+    /// the requested segment or part to be published.
+    const BLOCKING_TIMEOUT: Duration = Duration::from_secs(10);
+    /// Blocking reloads further than this many segments ahead of the live
+    /// edge are rejected, as required by the LL-HLS spec.
+    const MAX_MSN_AHEAD: Msn = 2;
+
+    pub async fn start(
+        host: IpAddr,
+        port: u16,
+        streams: StreamMap,
+        runtime: Arc<Runtime>,
+    ) -> Result<Self> {
+        tracing::trace!(%host, port, "starting hls http server");
+        let listener = match net::TcpListener::bind((host, port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!(%err, %host, port, "failed to listen for hls connections");
+                return Err(err);
+            }
+        };
+        tracing::info!(%host, port, "hls server listening for incoming connections");
+
+        let worker = runtime
+            .task()
+            .spawn({
+                let runtime = runtime.clone();
+                move |task_context| Self::run(listener, streams, runtime, task_context)
+            })
+            .await;
+        tracing::trace!(%host, port, "started hls http server");
+
+        Ok(Self { worker })
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("sending stop signal to hls http server");
+        self.worker.stop().await;
+        tracing::trace!("hls http server stopped");
+    }
+
+    async fn run(
+        listener: net::TcpListener,
+        streams: StreamMap,
+        runtime: Arc<Runtime>,
+        mut task_context: TaskContext,
+    ) {
+        // Dropping a `Task` stops it, so the task of every connection is kept
+        // until the connection reports that it is done.
+        let mut connections: HashMap<u64, Task> = HashMap::new();
+        let mut next_connection_id: u64 = 0;
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+
+        loop {
+            select! {
+              // CANCEL SAFETY: `tokio::net::TcpListener::accept` is cancel safe.
+              incoming = listener.accept() => {
+                match incoming {
+                  Ok((incoming, peer_addr)) => {
+                    tracing::trace!(%peer_addr, "accepted hls client");
+                    let connection_id = next_connection_id;
+                    next_connection_id += 1;
+                    let task = runtime
+                      .task()
+                      .spawn({
+                        let streams = streams.clone();
+                        let done_tx = done_tx.clone();
+                        move |task_context| async move {
+                          Self::serve(incoming, streams, task_context).await;
+                          let _ = done_tx.send(connection_id);
+                        }
+                      })
+                      .await;
+                    connections.insert(connection_id, task);
+                  },
+                  Err(err) => {
+                    tracing::error!(%err, "failed to accept hls connection");
+                  },
+                }
+              },
+              // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+              Some(connection_id) = done_rx.recv() => {
+                connections.remove(&connection_id);
+              },
+              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+              _ = task_context.wait_for_stop() => {
+                tracing::trace!("hls http server stopping");
+                break;
+              },
+            }
+        }
+
+        for (_, mut connection) in connections.drain() {
+            connection.stop().await;
+        }
+    }
+
+    async fn serve(mut stream: net::TcpStream, streams: StreamMap, mut task_context: TaskContext) {
+        select! {
+          result = Self::handle(&mut stream, &streams) => {
+            if let Err(err) = result {
+              tracing::debug!(%err, "failed to handle hls request");
+            }
+          },
+          // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+          _ = task_context.wait_for_stop() => {},
+        }
+    }
+
+    async fn handle(stream: &mut net::TcpStream, streams: &StreamMap) -> Result<()> {
+        let response = match Self::read_request_target(stream).await? {
+            Some(target) => Self::respond(&target, streams).await,
+            None => Response::status(400, "Bad Request"),
+        };
+        response.write(stream).await
+    }
+
+    /// Read the request head and return the target of a `GET` request.
+    async fn read_request_target(stream: &mut net::TcpStream) -> Result<Option<String>> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+            if buf.len() > Self::MAX_REQUEST_SIZE {
+                return Ok(None);
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        let head = String::from_utf8_lossy(&buf);
+        let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+        match (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) {
+            (Some("GET"), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                Ok(Some(target.to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn respond(target: &str, streams: &StreamMap) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (source_path, file) = match path.rsplit_once('/') {
+            Some(split) => split,
+            None => return Response::status(400, "Bad Request"),
+        };
+
+        let stream = match streams.read().await.get(source_path).cloned() {
+            Some(stream) => stream,
+            None => return Response::status(404, "Not Found"),
+        };
+
+        if file == "index.m3u8" {
+            let mut msn = None;
+            let mut part = None;
+            for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+                match key {
+                    "_HLS_msn" => msn = value.parse::<Msn>().ok(),
+                    "_HLS_part" => part = value.parse::<usize>().ok(),
+                    _ => {}
+                }
+            }
+
+            // Blocking playlist reload: hold the response until the playlist
+            // contains the requested segment or part.
+            if let Some(msn) = msn {
+                let next_msn = stream.playlist.read().await.next_msn();
+                if msn > next_msn + Self::MAX_MSN_AHEAD {
+                    return Response::status(400, "Bad Request");
+                }
+                if !stream.wait_for(msn, part, Self::BLOCKING_TIMEOUT).await {
+                    return Response::status(503, "Service Unavailable");
+                }
+            }
+
+            let playlist = stream.playlist.read().await.render();
+            return Response::ok(Response::PLAYLIST_CONTENT_TYPE, playlist.into());
+        }
+
+        let name = match file.strip_suffix(".ts") {
+            Some(name) => name,
+            None => return Response::status(404, "Not Found"),
+        };
+        let data = match name.split_once('.') {
+            Some((msn, part)) => {
+                let (msn, part) = match (msn.parse::<Msn>(), part.parse::<usize>()) {
+                    (Ok(msn), Ok(part)) => (msn, part),
+                    _ => return Response::status(400, "Bad Request"),
+                };
+                // The part advertised by the preload hint is requested before
+                // it exists, so wait for it to be published.
+                stream
+                    .wait_for(msn, Some(part), Self::BLOCKING_TIMEOUT)
+                    .await;
+                stream.playlist.read().await.part(msn, part)
+            }
+            None => match name.parse::<Msn>() {
+                Ok(msn) => stream.playlist.read().await.segment(msn),
+                Err(_) => return Response::status(400, "Bad Request"),
+            },
+        };
+
+        match data {
+            Some(data) => Response::ok(Response::SEGMENT_CONTENT_TYPE, data),
+            None => Response::status(404, "Not Found"),
+        }
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: Bytes,
+}
+
+impl Response {
+    const PLAYLIST_CONTENT_TYPE: &'static str = "application/vnd.apple.mpegurl";
+    const SEGMENT_CONTENT_TYPE: &'static str = "video/mp2t";
+
+    fn ok(content_type: &'static str, body: Bytes) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn status(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            content_type: "text/plain",
+            body: Bytes::from_static(reason.as_bytes()),
+        }
+    }
+
+    async fn write(&self, stream: &mut net::TcpStream) -> Result<()> {
+        // Playlists change all the time, segments and parts never do.
+        let is_media = self.status == 200 && self.content_type == Self::SEGMENT_CONTENT_TYPE;
+        let cache_control = if is_media { "max-age=3600" } else { "no-cache" };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: {}\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Connection: close\r\n\
+             \r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len(),
+            cache_control,
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    use anyhow::Result;
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use thiz_root::hls::{HlsSettings, HlsStream};
+
+    /// Send a `GET` over a real connection and return the whole response.
+    async fn get(host: IpAddr, port: u16, target: &str) -> Result<String> {
+        let mut stream = net::TcpStream::connect((host, port)).await?;
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_get_playlist() -> Result<()> {
+        let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        // `HttpServer` does not report the port it bound to, so find a free
+        // one first.
+        let port = TcpListener::bind((host, 0))?.local_addr()?.port();
+
+        let settings = HlsSettings {
+            target_duration: Duration::from_secs(2),
+            part_target: Duration::from_millis(500),
+            window: 6,
+        };
+        let streams: StreamMap = Arc::new(RwLock::new(HashMap::new()));
+        streams
+            .write()
+            .await
+            .insert("/example".to_string(), Arc::new(HlsStream::new(&settings)));
+
+        let runtime = Arc::new(Runtime::new());
+        let mut server = HttpServer::start(host, port, streams, runtime.clone()).await?;
+
+        let response = get(host, port, "/example/index.m3u8").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Type: application/vnd.apple.mpegurl\r\n"));
+        assert!(response.contains("\r\n\r\n#EXTM3U\n"), "{}", response);
+
+        let response = get(host, port, "/missing/index.m3u8").await?;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+
+        server.stop().await;
+        runtime.stop().await;
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use oddity_sdp_protocol::{CodecInfo, Direction, Kind, Protocol, TimeRange};
 use crate::oddity_rtsp_server as thiz_root;
 use thiz_root::media::video::reader;
 use thiz_root::media::video::rtp_muxer;
-use thiz_root::media::MediaDescriptor;
+use thiz_root::media::{MediaDescriptor, MediaInfo};
 
 pub use oddity_sdp_protocol::Sdp;
 
@@ -22,10 +22,6 @@ pub use oddity_sdp_protocol::Sdp;
 /// * `name` - Name of stream.
 /// * `descriptor` - Media stream descriptor.
 pub async fn create(name: &str, descriptor: &MediaDescriptor) -> Result<Sdp, SdpError> {
-    const ORIGIN_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
-    const TARGET_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
-    const TARGET_DUMMY_PORT: u16 = 0;
-
     tracing::trace!("sdp: initializing reader");
     let reader = reader::backend::make_reader_with_sane_settings(descriptor.clone().into())
         .await
@@ -43,10 +39,38 @@ pub async fn create(name: &str, descriptor: &MediaDescriptor) -> Result<Sdp, Sdp
 
     tracing::debug!(best_video_stream, best_audio_stream, "sdp: initialized reader");
 
+    let media_info = MediaInfo {
+        streams: vec![reader
+            .stream_info(best_video_stream)
+            .map_err(SdpError::Media)?],
+    };
+    create_from_media_info(name, media_info).await
+}
+
+/// Create a new SDP description from media information that was already
+/// queried from a running source, instead of opening the source again.
+/// Used for transcoded sources, of which only the running source knows
+/// the output stream.
+///
+/// # Arguments
+///
+/// * `name` - Name of stream.
+/// * `media_info` - Media information with the video stream first.
+pub async fn create_from_media_info(name: &str, media_info: MediaInfo) -> Result<Sdp, SdpError> {
+    const ORIGIN_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
+    const TARGET_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
+    const TARGET_DUMMY_PORT: u16 = 0;
+
+    let stream_info = media_info
+        .streams
+        .into_iter()
+        .next()
+        .ok_or(SdpError::MediaInfoUnavailable)?;
+
     tracing::trace!("sdp: initializing muxer");
     let muxer = rtp_muxer::make_rtp_muxer()
         .await
-        .and_then(|muxer| muxer.with_stream(reader.stream_info(best_video_stream)?))
+        .and_then(|muxer| muxer.with_stream(stream_info))
         .map_err(SdpError::Media)?;
     tracing::trace!("sdp: initialized muxer");
 
@@ -114,6 +138,7 @@ pub async fn create(name: &str, descriptor: &MediaDescriptor) -> Result<Sdp, Sdp
 #[derive(Debug)]
 pub enum SdpError {
     CodecNotSupported,
+    MediaInfoUnavailable,
     Media(video_rs::Error),
 }
 
@@ -121,6 +146,7 @@ impl fmt::Display for SdpError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             SdpError::CodecNotSupported => write!(f, "codec not supported"),
+            SdpError::MediaInfoUnavailable => write!(f, "media info unavailable"),
             SdpError::Media(error) => write!(f, "media error: {}", error),
         }
     }
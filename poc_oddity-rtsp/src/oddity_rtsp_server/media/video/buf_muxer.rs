@@ -0,0 +1,35 @@
+//! Async wrapper functions for [`video_rs::BufMuxer`].
+
+use tokio::task;
+
+use video_rs::{self as video, BufMuxer};
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::media::MediaInfo;
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+pub async fn make_buf_muxer(format: &'static str, media_info: MediaInfo) -> Result<BufMuxer> {
+    task::spawn_blocking(move || {
+        let mut muxer = BufMuxer::new_to_buf(format)?;
+        for stream_info in media_info.streams {
+            muxer = muxer.with_stream(stream_info)?;
+        }
+        Ok(muxer)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn muxed(mut muxer: BufMuxer, packet: video::Packet) -> (BufMuxer, Result<video::Buf>) {
+    task::spawn_blocking(move || {
+        let out = muxer.mux(packet);
+        (muxer, out)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn finish(mut muxer: BufMuxer) -> Result<Option<video::Buf>> {
+    task::spawn_blocking(move || muxer.finish()).await.unwrap()
+}
@@ -1,2 +1,5 @@
+pub mod buf_muxer;
 pub mod reader;
 pub mod rtp_muxer;
+pub mod segment_muxer;
+pub mod transcoder;
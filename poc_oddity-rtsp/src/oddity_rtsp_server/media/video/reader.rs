@@ -6,26 +6,46 @@ use tokio::task;
 use video_rs as video;
 
 use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::media::video::transcoder::{TranscodeSettings, Transcoder};
 use thiz_root::media::{MediaDescriptor, MediaInfo};
 
 type Result<T> = std::result::Result<T, video::Error>;
 
 pub struct StreamReader {
     pub info: MediaInfo,
+    transcoder: Option<Transcoder>,
     handle: Option<thread::JoinHandle<()>>,
     packet_rx: mpsc::UnboundedReceiver<Result<video::Packet>>,
     stop_tx: mpsc::UnboundedSender<()>,
 }
 
 impl StreamReader {
-    pub async fn new(descriptor: &MediaDescriptor) -> Result<Self> {
-        let is_file = matches!(descriptor, MediaDescriptor::File(_));
+    pub async fn new(
+        descriptor: &MediaDescriptor,
+        transcode: Option<&TranscodeSettings>,
+    ) -> Result<Self> {
+        // When transcoding, we read the transcoder output instead of the
+        // original source.
+        let mut transcoder = transcode
+            .map(|settings| Transcoder::start(descriptor, settings))
+            .transpose()?;
+        let descriptor = match transcoder.as_ref() {
+            Some(transcoder) => transcoder.output.clone(),
+            None => descriptor.clone(),
+        };
+        let descriptor = &descriptor;
 
-        tracing::trace!(%descriptor, "initializing reader");
-        let inner = backend::make_reader_with_sane_settings(descriptor.clone().into()).await?;
-        tracing::trace!(%descriptor, "initialized reader");
+        let is_file = matches!(descriptor, MediaDescriptor::File(_));
 
-        let info = MediaInfo::from_reader_best_video_stream(&inner)?;
+        let (inner, info) = match Self::open(descriptor).await {
+            Ok(opened) => opened,
+            Err(err) => {
+                if let Some(transcoder) = transcoder.as_mut() {
+                    transcoder.stop().await;
+                }
+                return Err(err);
+            }
+        };
         let stream_index = info.streams[0].index;
         tracing::trace!(%descriptor, stream_index=stream_index, "selected video stream");
 
@@ -38,6 +58,7 @@ impl StreamReader {
         tracing::trace!(%descriptor, "started stream reader");
 
         Ok(Self {
+            transcoder,
             handle: Some(handle),
             info,
             packet_rx,
@@ -45,6 +66,15 @@ impl StreamReader {
         })
     }
 
+    async fn open(descriptor: &MediaDescriptor) -> Result<(video::Reader, MediaInfo)> {
+        tracing::trace!(%descriptor, "initializing reader");
+        let inner = backend::make_reader_with_sane_settings(descriptor.clone().into()).await?;
+        tracing::trace!(%descriptor, "initialized reader");
+
+        let info = MediaInfo::from_reader_best_video_stream(&inner)?;
+        Ok((inner, info))
+    }
+
     pub async fn read(&mut self) -> Option<Result<video::Packet>> {
         self.packet_rx.recv().await
     }
@@ -57,6 +87,9 @@ impl StreamReader {
                 tracing::trace!("stopped stream reader");
             }
         }
+        if let Some(transcoder) = self.transcoder.as_mut() {
+            transcoder.stop().await;
+        }
     }
 
     fn run(
@@ -165,19 +198,19 @@ pub mod backend {
     use video_rs::{Error, Locator, Options, Reader};
 
     pub async fn make_reader_with_sane_settings(locator: Locator) -> Result<Reader, Error> {
-        task::spawn_blocking(move || {
-            let options = match locator {
-                Locator::Path(_) => Default::default(),
-                Locator::Url(_) => {
-                    // For streaming sources (live sources), we want to use TCP transport
-                    // over UDP and have sane timeouts.
-                    Options::new_with_rtsp_transport_tcp_and_sane_timeouts()
-                }
-            };
+        task::spawn_blocking(move || Reader::new_with_options(&locator, &reader_options(&locator)))
+            .await
+            .unwrap()
+    }
 
-            Reader::new_with_options(&locator, &options)
-        })
-        .await
-        .unwrap()
+    pub fn reader_options(locator: &Locator) -> Options<'static> {
+        match locator {
+            Locator::Path(_) => Default::default(),
+            Locator::Url(_) => {
+                // For streaming sources (live sources), we want to use TCP transport
+                // over UDP and have sane timeouts.
+                Options::new_with_rtsp_transport_tcp_and_sane_timeouts()
+            }
+        }
     }
 }
@@ -0,0 +1,44 @@
+//! Async wrapper functions for [`video_rs::FileMuxer`], used to remux
+//! source packets into recording segments without re-encoding them.
+
+use std::path::PathBuf;
+
+use tokio::task;
+
+use video_rs::{self as video, FileMuxer};
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::media::MediaInfo;
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+pub async fn make_segment_muxer(
+    dest: PathBuf,
+    format: &'static str,
+    media_info: MediaInfo,
+) -> Result<FileMuxer> {
+    task::spawn_blocking(move || {
+        let mut muxer = FileMuxer::new_to_file_with_format(&dest.into(), format)?.interleaved();
+        for stream_info in media_info.streams {
+            muxer = muxer.with_stream(stream_info)?;
+        }
+        Ok(muxer)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn muxed(mut muxer: FileMuxer, packet: video::Packet) -> (FileMuxer, Result<()>) {
+    task::spawn_blocking(move || {
+        let out = muxer.mux(packet);
+        (muxer, out)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn finish(mut muxer: FileMuxer) -> Result<()> {
+    task::spawn_blocking(move || muxer.finish().map(|_| ()))
+        .await
+        .unwrap()
+}
@@ -0,0 +1,338 @@
+//! Re-encodes sources that clients cannot play (VP8, MJPEG, HEVC, ...) to
+//! H.264 baseline.
+//!
+//! The transcoder decodes the original source on a dedicated thread and
+//! writes the re-encoded stream as MPEG-TS to a loopback TCP socket. The
+//! regular [`StreamReader`](super::reader::StreamReader) then reads that
+//! socket like any other live stream, so packets, stream info and muxers
+//! are all produced by `video_rs` as usual.
+//!
+//! Both the encoder and the reader connect to listeners owned by the
+//! transcoder, which relays the bytes between them. The ports stay bound for
+//! as long as the transcoder runs, so no other process can take them.
+
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use tokio::sync::mpsc;
+use tokio::task;
+
+use video_rs as video;
+use video_rs::{DecoderSplit, Encoder, EncoderSettings, Locator, Options, PixelFormat, Resize};
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::media::video::reader::backend;
+use thiz_root::media::MediaDescriptor;
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+#[derive(Debug, Clone)]
+pub struct TranscodeSettings {
+    /// Target bitrate in kbit/s.
+    pub bitrate: u32,
+    /// Maximum output resolution. The picture is scaled to fit while keeping
+    /// its aspect ratio. Keeps the source resolution if not set.
+    pub size: Option<(u32, u32)>,
+}
+
+pub struct Transcoder {
+    /// Local stream carrying the transcoded output.
+    pub output: MediaDescriptor,
+    handle: Option<thread::JoinHandle<()>>,
+    relay: Option<thread::JoinHandle<()>>,
+    stop_tx: mpsc::UnboundedSender<()>,
+    /// Tells the relay to give up waiting for its connections.
+    stopped: Arc<AtomicBool>,
+}
+
+impl Transcoder {
+    /// How often the relay checks for new connections while waiting.
+    const ACCEPT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(20);
+
+    pub fn start(descriptor: &MediaDescriptor, settings: &TranscodeSettings) -> Result<Self> {
+        let encoder_listener = Self::bind_local()?;
+        let reader_listener = Self::bind_local()?;
+        let output = MediaDescriptor::Stream(Self::local_url(&reader_listener)?);
+        let target = Self::local_url(&encoder_listener)?;
+
+        let (stop_tx, stop_rx) = mpsc::unbounded_channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        tracing::trace!(%descriptor, %target, %output, "starting transcoder");
+        let relay = thread::spawn({
+            let stopped = stopped.clone();
+            move || Self::relay(encoder_listener, reader_listener, stopped)
+        });
+        let handle = thread::spawn({
+            let descriptor = descriptor.clone();
+            let settings = settings.clone();
+            move || Self::run(descriptor, settings, target, stop_rx)
+        });
+        tracing::trace!(%descriptor, "started transcoder");
+
+        Ok(Self {
+            output,
+            handle: Some(handle),
+            relay: Some(relay),
+            stop_tx,
+            stopped,
+        })
+    }
+
+    pub async fn stop(&mut self) {
+        // The thread may already have ended by itself (e.g. the source broke),
+        // in which case sending fails but it still needs to be joined.
+        let _ = self.stop_tx.send(());
+        // The encoder may be stuck writing to a reader that never connected,
+        // the relay drops its connection to get it out.
+        self.stopped.store(true, Ordering::Release);
+        if let Some(relay) = self.relay.take() {
+            let _ = task::spawn_blocking(|| relay.join()).await;
+        }
+        if let Some(handle) = self.handle.take() {
+            tracing::trace!("sending stop signal to transcoder");
+            let _ = task::spawn_blocking(|| handle.join()).await;
+            tracing::trace!("stopped transcoder");
+        }
+    }
+
+    fn run(
+        descriptor: MediaDescriptor,
+        settings: TranscodeSettings,
+        target: video::Url,
+        mut stop_rx: mpsc::UnboundedReceiver<()>,
+    ) {
+        let is_file = matches!(descriptor, MediaDescriptor::File(_));
+        let locator: Locator = descriptor.clone().into();
+
+        let mut reader =
+            match video::Reader::new_with_options(&locator, &backend::reader_options(&locator)) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    tracing::error!(%err, %descriptor, "transcoder: failed to open source");
+                    return;
+                }
+            };
+
+        let stream_index = match reader.best_video_stream_index() {
+            Ok(stream_index) => stream_index,
+            Err(err) => {
+                tracing::error!(%err, %descriptor, "transcoder: no video stream in source");
+                return;
+            }
+        };
+
+        let resize = settings
+            .size
+            .map(|(width, height)| Resize::FitEven(width, height));
+        let mut decoder = match DecoderSplit::new(&reader, stream_index, resize) {
+            Ok(decoder) => decoder,
+            Err(err) => {
+                tracing::error!(%err, %descriptor, "transcoder: failed to initialize decoder");
+                return;
+            }
+        };
+
+        let (width, height) = decoder.size_out();
+        tracing::debug!(
+            %descriptor,
+            width,
+            height,
+            bitrate = settings.bitrate,
+            "transcoder: decoding",
+        );
+
+        let encoder_settings = EncoderSettings::for_h264_custom(
+            width as usize,
+            height as usize,
+            PixelFormat::YUV420P,
+            Self::encoder_options(&settings),
+        );
+        let mut encoder = match Encoder::new_with_format(&target.into(), encoder_settings, "mpegts")
+        {
+            Ok(encoder) => encoder,
+            Err(err) => {
+                tracing::error!(%err, %descriptor, "transcoder: failed to initialize encoder");
+                return;
+            }
+        };
+
+        let started = time::Instant::now();
+        let time_base = encoder.time_base();
+
+        loop {
+            match stop_rx.try_recv() {
+                Ok(()) | Err(mpsc::error::TryRecvError::Disconnected) => {
+                    tracing::trace!("stopping transcoder");
+                    break;
+                }
+                Err(mpsc::error::TryRecvError::Empty) => {}
+            };
+
+            let packet = match reader.read(stream_index) {
+                Ok(packet) => packet,
+                Err(video::Error::ReadExhausted) if is_file => {
+                    tracing::trace!("transcoder: seeking to beginning of file");
+                    if let Err(err) = reader.seek_to_start() {
+                        tracing::error!(%err, "transcoder: failed to seek to beginning of file");
+                        break;
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!(%err, %descriptor, "transcoder: failed to read source");
+                    break;
+                }
+            };
+
+            if is_file {
+                // Pace file-based sources like the stream reader does.
+                thread::sleep(packet.duration().into());
+            }
+
+            let mut frame = match decoder.decode_raw(packet) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue,
+                Err(err) => {
+                    // A corrupt packet should not take the whole source down,
+                    // the decoder will resync on the next key frame.
+                    tracing::warn!(%err, %descriptor, "transcoder: failed to decode packet");
+                    continue;
+                }
+            };
+
+            // Timestamps of the original source may be missing or restart
+            // when files loop, so the output is timed by wall clock instead.
+            let elapsed = started.elapsed().as_secs_f64();
+            let pts = elapsed * time_base.denominator() as f64 / time_base.numerator() as f64;
+            frame.set_pts(Some(pts as i64));
+
+            if let Err(err) = encoder.encode_raw(frame) {
+                tracing::trace!(%err, "transcoder: failed to write encoded frame");
+                break;
+            }
+        }
+
+        if let Err(err) = encoder.finish() {
+            tracing::trace!(%err, "transcoder: failed to finish encoder");
+        }
+    }
+
+    /// Accept the encoder and the reader, then copy the encoded stream from
+    /// one to the other until either side goes away.
+    fn relay(
+        encoder_listener: net::TcpListener,
+        reader_listener: net::TcpListener,
+        stopped: Arc<AtomicBool>,
+    ) {
+        let encoder = match Self::accept(&encoder_listener, &stopped) {
+            Some(encoder) => encoder,
+            None => return,
+        };
+        let reader = match Self::accept(&reader_listener, &stopped) {
+            Some(reader) => reader,
+            None => return,
+        };
+
+        // Stopping shuts both sockets down, which ends the copy below.
+        let shutdown = thread::spawn({
+            let encoder = encoder.try_clone();
+            let reader = reader.try_clone();
+            let stopped = stopped.clone();
+            move || {
+                while !stopped.load(Ordering::Acquire) {
+                    thread::sleep(Self::ACCEPT_POLL_INTERVAL);
+                }
+                for stream in [encoder, reader].into_iter().flatten() {
+                    let _ = stream.shutdown(net::Shutdown::Both);
+                }
+            }
+        });
+
+        let (mut encoder, mut reader) = (encoder, reader);
+        if let Err(err) = io::copy(&mut encoder, &mut reader) {
+            tracing::trace!(%err, "transcoder: relay stopped");
+        }
+        let _ = reader.shutdown(net::Shutdown::Both);
+        let _ = encoder.shutdown(net::Shutdown::Both);
+        stopped.store(true, Ordering::Release);
+        let _ = shutdown.join();
+    }
+
+    /// Wait for a connection on `listener`. Returns `None` once stopped.
+    fn accept(listener: &net::TcpListener, stopped: &AtomicBool) -> Option<net::TcpStream> {
+        loop {
+            if stopped.load(Ordering::Acquire) {
+                return None;
+            }
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    // Accepted sockets may inherit non-blocking mode.
+                    if let Err(err) = stream.set_nonblocking(false) {
+                        tracing::error!(%err, "transcoder: failed to set up relay connection");
+                        return None;
+                    }
+                    return Some(stream);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Self::ACCEPT_POLL_INTERVAL);
+                }
+                Err(err) => {
+                    tracing::error!(%err, "transcoder: failed to accept relay connection");
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn encoder_options(settings: &TranscodeSettings) -> Options<'static> {
+        let options: HashMap<String, String> = [
+            // Baseline is the only profile every legacy player is guaranteed
+            // to support.
+            ("profile", "baseline".to_string()),
+            ("preset", "veryfast".to_string()),
+            ("tune", "zerolatency".to_string()),
+            ("b", format!("{}k", settings.bitrate)),
+            ("maxrate", format!("{}k", settings.bitrate)),
+            ("bufsize", format!("{}k", settings.bitrate * 2)),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+        Options::new_from_hashmap(&options)
+    }
+
+    /// Bind a loopback listener on a free port. It is polled by the relay, so
+    /// it is switched to non-blocking mode.
+    fn bind_local() -> Result<net::TcpListener> {
+        net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+            .map_err(Self::io_error)
+    }
+
+    fn local_url(listener: &net::TcpListener) -> Result<video::Url> {
+        let port = listener.local_addr().map_err(Self::io_error)?.port();
+        Ok(format!("tcp://127.0.0.1:{port}")
+            .parse()
+            .expect("loopback url is always valid"))
+    }
+
+    fn io_error(err: io::Error) -> video::Error {
+        video::Error::BackendError(ffmpeg_next::Error::Other {
+            errno: err.raw_os_error().unwrap_or_default(),
+        })
+    }
+}
+
+impl Drop for Transcoder {
+    fn drop(&mut self) {
+        if self.handle.is_some() || self.relay.is_some() {
+            panic!("Dropped `Transcoder` whilst running.");
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // from crate oddity-rtsp, commit 27480007
 
 pub mod app;
+mod hls;
 pub(crate) mod media;
 mod net;
 mod runtime;
@@ -1,3 +1,4 @@
+pub mod recorder;
 pub mod source_manager;
 
 use std::time;
@@ -5,18 +6,24 @@ use std::time;
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use tokio::time::timeout;
 
 use video_rs as video;
 
 use crate::oddity_rtsp_server as thiz_root;
 use thiz_root::media::video::reader::StreamReader;
+use thiz_root::media::video::transcoder::TranscodeSettings;
 use thiz_root::media::{self, MediaDescriptor};
 use thiz_root::runtime::task_manager::{Task, TaskContext};
 use thiz_root::runtime::Runtime;
+use thiz_root::source::recorder::{RecordSettings, Recorder};
 
 pub enum SourceState {
     Stopped(SourcePath),
+    /// Stop and forget the source at this path, answered once it has
+    /// stopped. Sent before the recorded segment it serves is removed.
+    Unregister(SourcePath, oneshot::Sender<()>),
 }
 
 pub type SourceStateTx = mpsc::UnboundedSender<SourceState>;
@@ -42,10 +49,12 @@ pub struct Source {
     pub name: String,
     pub path: SourcePath,
     pub descriptor: MediaDescriptor,
+    pub transcode: Option<TranscodeSettings>,
     control_tx: SourceControlTx,
     media_info_tx: SourceMediaInfoTx,
     reset_tx: SourceResetTx,
     packet_tx: SourcePacketTx,
+    recorder: Option<Recorder>,
     worker: Task,
 }
 
@@ -65,6 +74,8 @@ impl Source {
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        transcode: Option<TranscodeSettings>,
+        record: Option<RecordSettings>,
         state_tx: SourceStateTx,
         runtime: &Runtime,
     ) -> Result<Self, video::Error> {
@@ -76,11 +87,13 @@ impl Source {
         let (packet_tx, _) = broadcast::channel(Self::MAX_QUEUED_PACKETS);
 
         tracing::trace!(name, %path, "starting source");
+        let recorder_state_tx = state_tx.clone();
         let worker = runtime
             .task()
             .spawn({
                 let path = path.clone();
                 let descriptor = descriptor.clone();
+                let transcode = transcode.clone();
                 let media_info_tx = media_info_tx.clone();
                 let reset_tx = reset_tx.clone();
                 let packet_tx = packet_tx.clone();
@@ -88,6 +101,7 @@ impl Source {
                     Self::run(
                         path,
                         descriptor,
+                        transcode,
                         control_rx,
                         state_tx,
                         media_info_tx,
@@ -100,19 +114,33 @@ impl Source {
             .await;
         tracing::trace!(name, %path, "started source");
 
-        Ok(Self {
+        let mut source = Self {
             name: name.to_string(),
             path,
             descriptor,
+            transcode,
             control_tx,
             media_info_tx,
             reset_tx,
             packet_tx,
+            recorder: None,
             worker,
-        })
+        };
+
+        if let Some(record) = record {
+            let delegate = source.delegate();
+            source.recorder = Some(
+                Recorder::start(&source.path, record, delegate, recorder_state_tx, runtime).await,
+            );
+        }
+
+        Ok(source)
     }
 
     pub async fn stop(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.stop().await;
+        }
         tracing::trace!("sending stop signal to source");
         self.worker.stop().await;
         tracing::trace!("stopped source");
@@ -131,6 +159,7 @@ impl Source {
     async fn run(
         path: SourcePath,
         descriptor: MediaDescriptor,
+        transcode: Option<TranscodeSettings>,
         mut control_rx: SourceControlRx,
         state_tx: SourceStateTx,
         media_info_tx: SourceMediaInfoTx,
@@ -138,7 +167,7 @@ impl Source {
         packet_tx: SourcePacketTx,
         mut task_context: TaskContext,
     ) {
-        let mut outer_stream_reader = match StreamReader::new(&descriptor).await {
+        let mut outer_stream_reader = match StreamReader::new(&descriptor, transcode.as_ref()).await {
             Ok(stream_reader) => Some(stream_reader),
             Err(err) => {
                 tracing::error!(
@@ -154,7 +183,7 @@ impl Source {
                 Some(stream_reader) => stream_reader,
                 None => {
                     'restart: loop {
-                        match StreamReader::new(&descriptor).await {
+                        match StreamReader::new(&descriptor, transcode.as_ref()).await {
                             Ok(new_stream_reader) => {
                                 // Send reset with new media information to listeners so they can
                                 // reset their muxers and continue playing.
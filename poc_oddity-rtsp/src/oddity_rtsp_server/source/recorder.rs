@@ -0,0 +1,351 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::select;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task;
+
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use video_rs as video;
+
+use crate::oddity_rtsp_server as thiz_root;
+use thiz_root::media::video::segment_muxer;
+use thiz_root::media::MediaInfo;
+use thiz_root::runtime::task_manager::{Task, TaskContext};
+use thiz_root::runtime::Runtime;
+use thiz_root::source::{SourceDelegate, SourcePath, SourcePathRef, SourceState, SourceStateTx};
+
+#[derive(Debug, Clone)]
+pub struct RecordSettings {
+    /// Directory to write segments to.
+    pub dir: PathBuf,
+    /// Minimum duration of a segment. Segments are only cut on key frames
+    /// so they may be a bit longer.
+    pub segment_duration: Duration,
+    /// Segments older than this are removed.
+    pub retention: Duration,
+}
+
+/// Records the packets of a source to rolling MPEG-TS segments. Packets are
+/// remuxed as-is, like `poc_cut_off::cut_off` does, so recording costs no
+/// decoding or encoding.
+///
+/// Segments are named after the UTC time they started at (to the
+/// millisecond, with a numeric suffix should two still clash) and only get
+/// their final extension once finished, so [`list_segments`] never returns
+/// a segment that is still being written.
+pub struct Recorder {
+    worker: Task,
+}
+
+impl Recorder {
+    const SEGMENT_FORMAT: &'static str = "mpegts";
+    const SEGMENT_EXTENSION: &'static str = "ts";
+    const PARTIAL_SEGMENT_EXTENSION: &'static str = "ts.part";
+
+    pub async fn start(
+        path: &SourcePathRef,
+        settings: RecordSettings,
+        source_delegate: SourceDelegate,
+        state_tx: SourceStateTx,
+        runtime: &Runtime,
+    ) -> Self {
+        tracing::trace!(%path, dir=%settings.dir.display(), "starting recorder");
+        let worker = runtime
+            .task()
+            .spawn({
+                let path = path.to_string();
+                move |task_context| {
+                    Self::run(path, settings, source_delegate, state_tx, task_context)
+                }
+            })
+            .await;
+        tracing::trace!(%path, "started recorder");
+
+        Self { worker }
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("sending stop signal to recorder");
+        self.worker.stop().await;
+        tracing::trace!("stopped recorder");
+    }
+
+    async fn run(
+        path: SourcePath,
+        settings: RecordSettings,
+        mut source_delegate: SourceDelegate,
+        state_tx: SourceStateTx,
+        mut task_context: TaskContext,
+    ) {
+        if let Err(err) = fs::create_dir_all(&settings.dir) {
+            tracing::error!(
+              %path, %err, dir=%settings.dir.display(),
+              "failed to create recording directory",
+            );
+            return;
+        }
+
+        let mut media_info = match source_delegate.query_media_info().await {
+            Some(media_info) => media_info,
+            None => {
+                tracing::error!(%path, "failed to query media info for recording");
+                return;
+            }
+        };
+
+        let (mut source_reset_rx, mut source_packet_rx) = source_delegate.into_parts();
+        let mut segment: Option<Segment> = None;
+
+        loop {
+            select! {
+              // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
+              reset = source_reset_rx.recv() => {
+                match reset {
+                  Ok(new_media_info) => {
+                    // Stream parameters may have changed, so the current
+                    // segment can't be continued.
+                    tracing::trace!(%path, "source reset, closing segment");
+                    Self::close(segment.take(), &path, &settings, &state_tx).await;
+                    media_info = new_media_info;
+                  },
+                  Err(_) => {
+                    tracing::error!(%path, "source broken");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
+              packet = source_packet_rx.recv() => {
+                match packet {
+                  Ok(packet) => {
+                    // Segments always start on a key frame so every segment
+                    // can be played back on its own.
+                    let need_new_segment = segment
+                      .as_ref()
+                      .map_or(true, |segment| segment.started.elapsed() >= settings.segment_duration);
+                    if packet.is_key() && need_new_segment {
+                      Self::close(segment.take(), &path, &settings, &state_tx).await;
+                      segment = Segment::create(&settings.dir, media_info.clone())
+                        .await
+                        .map_err(|err| tracing::error!(%path, %err, "failed to create segment"))
+                        .ok();
+                    }
+
+                    if let Some(current) = segment.take() {
+                      let (current, muxed) = current.muxed(packet).await;
+                      match muxed {
+                        Ok(()) => segment = Some(current),
+                        Err(err) => {
+                          tracing::error!(%path, %err, "failed to write segment, closing it");
+                          Self::close(Some(current), &path, &settings, &state_tx).await;
+                        },
+                      }
+                    }
+                  },
+                  Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    // Missing packets would corrupt the segment until the next
+                    // key frame, so start over at the next key frame instead.
+                    tracing::warn!(%path, skipped, "recorder lagged behind source, closing segment");
+                    Self::close(segment.take(), &path, &settings, &state_tx).await;
+                  },
+                  Err(broadcast::error::RecvError::Closed) => {
+                    tracing::error!(%path, "source broken");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+              _ = task_context.wait_for_stop() => {
+                tracing::trace!(%path, "stopping recorder");
+                break;
+              },
+            }
+        }
+
+        Self::close(segment.take(), &path, &settings, &state_tx).await;
+    }
+
+    /// Finish the segment (if any) and remove segments past retention.
+    async fn close(
+        segment: Option<Segment>,
+        path: &SourcePathRef,
+        settings: &RecordSettings,
+        state_tx: &SourceStateTx,
+    ) {
+        if let Some(segment) = segment {
+            match segment.finish().await {
+                Ok(path) => tracing::debug!(path=%path.display(), "finished segment"),
+                Err(err) => tracing::error!(%err, "failed to finish segment"),
+            }
+        }
+
+        let dir = settings.dir.clone();
+        let retention = settings.retention;
+        let expired = task::spawn_blocking(move || expired_segments(&dir, retention))
+            .await
+            .unwrap();
+        let expired = match expired {
+            Ok(expired) => expired,
+            Err(err) => {
+                tracing::error!(%err, dir=%settings.dir.display(), "failed to list old segments");
+                return;
+            }
+        };
+
+        for segment in expired {
+            // A segment that is being served must stop being served before
+            // its file goes away.
+            if let Some(stem) = segment.file_stem().and_then(|stem| stem.to_str()) {
+                let (done_tx, done_rx) = oneshot::channel();
+                let recording = format!("{}/{}", recordings_path(path), stem);
+                if state_tx
+                    .send(SourceState::Unregister(recording, done_tx))
+                    .is_ok()
+                {
+                    let _ = done_rx.await;
+                }
+            }
+
+            tracing::trace!(path=%segment.display(), "removing segment past retention");
+            if let Err(err) = tokio::fs::remove_file(&segment).await {
+                tracing::error!(%err, path=%segment.display(), "failed to remove old segment");
+            }
+        }
+    }
+}
+
+struct Segment {
+    muxer: video::FileMuxer,
+    path: PathBuf,
+    started: Instant,
+}
+
+impl Segment {
+    /// Give up finding a free segment name after this many attempts.
+    const MAX_NAME_ATTEMPTS: usize = 100;
+
+    async fn create(
+        dir: &Path,
+        media_info: MediaInfo,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (path, partial_path) = task::spawn_blocking({
+            let dir = dir.to_path_buf();
+            move || Self::reserve_path(&dir)
+        })
+        .await
+        .unwrap()?;
+
+        let muxer =
+            segment_muxer::make_segment_muxer(partial_path, Recorder::SEGMENT_FORMAT, media_info)
+                .await?;
+        tracing::trace!(path=%path.display(), "created segment");
+
+        Ok(Self {
+            muxer,
+            path,
+            started: Instant::now(),
+        })
+    }
+
+    /// Pick a name that neither a finished nor a partial segment uses yet,
+    /// and claim it by creating the partial file. The muxer then writes over
+    /// the empty file.
+    fn reserve_path(dir: &Path) -> io::Result<(PathBuf, PathBuf)> {
+        let name = OffsetDateTime::now_utc()
+            .format(format_description!(
+                "[year][month][day]T[hour][minute][second].[subsecond digits:3]Z"
+            ))
+            .expect("segment name format is valid");
+
+        for attempt in 0..Self::MAX_NAME_ATTEMPTS {
+            let name = match attempt {
+                0 => name.clone(),
+                n => format!("{}-{}", name, n),
+            };
+            let path = dir.join(format!("{}.{}", name, Recorder::SEGMENT_EXTENSION));
+            let partial_path =
+                dir.join(format!("{}.{}", name, Recorder::PARTIAL_SEGMENT_EXTENSION));
+            if path.exists() {
+                continue;
+            }
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&partial_path)
+            {
+                Ok(_) => return Ok((path, partial_path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "no free segment name",
+        ))
+    }
+
+    async fn muxed(self, packet: video::Packet) -> (Self, Result<(), video::Error>) {
+        let (muxer, muxed) = segment_muxer::muxed(self.muxer, packet).await;
+        (Self { muxer, ..self }, muxed)
+    }
+
+    async fn finish(self) -> io::Result<PathBuf> {
+        if let Err(err) = segment_muxer::finish(self.muxer).await {
+            // The packets that made it to disk are still playable.
+            tracing::warn!(%err, path=%self.path.display(), "failed to write segment trailer");
+        }
+        let partial_path = self.path.with_extension(Recorder::PARTIAL_SEGMENT_EXTENSION);
+        tokio::fs::rename(&partial_path, &self.path).await?;
+        Ok(self.path)
+    }
+}
+
+/// List the finished segments in `dir`, oldest first.
+pub fn list_segments(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some(Recorder::SEGMENT_EXTENSION) {
+            segments.push(path);
+        }
+    }
+    // Names are UTC timestamps, so sorting by name sorts by time.
+    segments.sort();
+    Ok(segments)
+}
+
+/// Path under which the recordings of the source at `path` are served. Each
+/// finished segment is served at `<recordings path>/<segment name>`.
+pub fn recordings_path(path: &SourcePathRef) -> SourcePath {
+    format!("{}/recordings", path.trim_end_matches('/'))
+}
+
+/// The finished segment called `name` in `dir`, if there is one.
+pub fn find_segment(dir: &Path, name: &str) -> Option<PathBuf> {
+    // The name comes from a request path, it must not leave `dir`.
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return None;
+    }
+    let path = dir.join(format!("{}.{}", name, Recorder::SEGMENT_EXTENSION));
+    path.is_file().then_some(path)
+}
+
+fn expired_segments(dir: &Path, retention: Duration) -> io::Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut expired = Vec::new();
+    for path in list_segments(dir)? {
+        let modified = fs::metadata(&path)?.modified()?;
+        let is_expired = now
+            .duration_since(modified)
+            .map_or(false, |age| age > retention);
+        if is_expired {
+            expired.push(path);
+        }
+    }
+    Ok(expired)
+}
@@ -1,19 +1,24 @@
 use std::collections::{hash_map::Entry, HashMap};
 use std::error;
 use std::fmt;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time;
 
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::{Mutex, RwLock};
+use tokio::time::timeout;
 
 use video_rs::Error as MediaError;
 
 use crate::oddity_rtsp_server as thiz_root;
 use thiz_root::media::sdp::{self, Sdp, SdpError};
+use thiz_root::media::video::transcoder::TranscodeSettings;
 use thiz_root::media::MediaDescriptor;
 use thiz_root::runtime::task_manager::{Task, TaskContext};
 use thiz_root::runtime::Runtime;
+use thiz_root::source::recorder::{self, RecordSettings};
 use thiz_root::source::{
     self, Source, SourceDelegate, SourcePath, SourcePathRef, SourceState, SourceStateRx,
     SourceStateTx,
@@ -24,15 +29,29 @@ type SourceMap = Arc<RwLock<HashMap<SourcePath, SourceShared>>>;
 
 type SourceDescriptionsCache = Arc<RwLock<HashMap<SourcePath, Sdp>>>;
 
+/// Recordings path of a source and where its segments are.
+type RecordingDirs = Arc<RwLock<HashMap<SourcePath, RecordingDir>>>;
+
+struct RecordingDir {
+    name: String,
+    dir: PathBuf,
+}
+
 pub struct SourceManager {
     sources: SourceMap,
     source_descriptions_cache: SourceDescriptionsCache,
+    recording_dirs: RecordingDirs,
     source_state_tx: SourceStateTx,
     worker: Task,
     runtime: Arc<Runtime>,
 }
 
 impl SourceManager {
+    /// Transcoded sources are described from their running stream, which
+    /// may take a while to start since the source needs to be decoded and
+    /// encoded first.
+    const MEDIA_INFO_TIMEOUT_SECS: u64 = 30;
+
     pub async fn start(runtime: Arc<Runtime>) -> Self {
         let sources = Arc::new(RwLock::new(HashMap::new()));
         let (source_state_tx, source_state_rx) = mpsc::unbounded_channel();
@@ -44,7 +63,15 @@ impl SourceManager {
             .task()
             .spawn({
                 let sources = sources.clone();
-                move |task_context| Self::run(sources.clone(), source_state_rx, task_context)
+                let source_descriptions_cache = source_descriptions_cache.clone();
+                move |task_context| {
+                    Self::run(
+                        sources,
+                        source_descriptions_cache,
+                        source_state_rx,
+                        task_context,
+                    )
+                }
             })
             .await;
         tracing::trace!("started source manager");
@@ -52,6 +79,7 @@ impl SourceManager {
         Self {
             sources,
             source_descriptions_cache,
+            recording_dirs: Arc::new(RwLock::new(HashMap::new())),
             source_state_tx,
             worker,
             runtime,
@@ -72,32 +100,102 @@ impl SourceManager {
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        transcode: Option<TranscodeSettings>,
+        record: Option<RecordSettings>,
     ) -> Result<(), RegisterSourceError> {
         let path = source::normalize_path(path);
-        let source = Source::start(
+        self.start_source(name, &path, descriptor, transcode, record)
+            .await?;
+
+        tracing::trace!("requesting SDP for source to prime cache");
+        self.describe(&path)
+            .await
+            .unwrap()
+            .map_err(RegisterSourceError::Sdp)?;
+        Ok(())
+    }
+
+    /// Serve the finished recording segments in `dir` under the recordings
+    /// path of the source at `path`. Segments are only opened once they are
+    /// requested, so recordings made while running are served too.
+    pub async fn register_recordings(&self, name: &str, path: SourcePath, dir: PathBuf) {
+        let recordings_path = recorder::recordings_path(&source::normalize_path(path));
+        tracing::trace!(name, %recordings_path, dir=%dir.display(), "registered recordings");
+        self.recording_dirs.write().await.insert(
+            recordings_path,
+            RecordingDir {
+                name: name.to_string(),
+                dir,
+            },
+        );
+    }
+
+    async fn start_source(
+        &self,
+        name: &str,
+        path: &SourcePathRef,
+        descriptor: MediaDescriptor,
+        transcode: Option<TranscodeSettings>,
+        record: Option<RecordSettings>,
+    ) -> Result<(), RegisterSourceError> {
+        let mut source = Source::start(
             name,
-            path.clone(),
+            path.to_string(),
             descriptor,
+            transcode,
+            record,
             self.source_state_tx.clone(),
             self.runtime.as_ref(),
         )
         .await
         .map_err(RegisterSourceError::Media)?;
 
-        if let Entry::Vacant(entry) = self.sources.write().await.entry(path.clone()) {
-            let _ = entry.insert(Arc::new(Mutex::new(source)));
-            tracing::trace!(name, %path, "registered and started source");
-            tracing::trace!("requesting SDP for source to prime cache");
-        } else {
-            tracing::error!(name, %path, "source with given path already registered");
-            return Err(RegisterSourceError::AlreadyRegistered);
+        match self.sources.write().await.entry(path.to_string()) {
+            Entry::Vacant(entry) => {
+                let _ = entry.insert(Arc::new(Mutex::new(source)));
+                tracing::trace!(name, %path, "registered and started source");
+                return Ok(());
+            }
+            Entry::Occupied(_) => {}
         }
 
-        self.describe(&path)
+        tracing::error!(name, %path, "source with given path already registered");
+        source.stop().await;
+        Err(RegisterSourceError::AlreadyRegistered)
+    }
+
+    /// Look up the source at `path`, opening a recorded segment the first
+    /// time it is requested.
+    async fn source(&self, path: &SourcePathRef) -> Option<SourceShared> {
+        let source = self.sources.read().await.get(path).cloned();
+        if source.is_some() {
+            return source;
+        }
+
+        let (recordings_path, segment_name) = path.rsplit_once('/')?;
+        let (name, segment) = {
+            let recording_dirs = self.recording_dirs.read().await;
+            let recording_dir = recording_dirs.get(recordings_path)?;
+            let segment = recorder::find_segment(&recording_dir.dir, segment_name)?;
+            (
+                format!("{} ({})", recording_dir.name, segment_name),
+                segment,
+            )
+        };
+
+        tracing::trace!(%path, segment=%segment.display(), "opening recording");
+        match self
+            .start_source(&name, path, MediaDescriptor::File(segment), None, None)
             .await
-            .unwrap()
-            .map_err(RegisterSourceError::Sdp)?;
-        Ok(())
+        {
+            // Someone else opened it in the meantime.
+            Ok(()) | Err(RegisterSourceError::AlreadyRegistered) => {}
+            Err(err) => {
+                tracing::error!(%path, %err, "failed to open recording");
+                return None;
+            }
+        }
+        self.sources.read().await.get(path).cloned()
     }
 
     pub async fn describe(&self, path: &SourcePathRef) -> Option<Result<Sdp, SdpError>> {
@@ -111,11 +209,29 @@ impl SourceManager {
             tracing::trace!(%path, "pulled SDP from cache");
             Some(Ok(description))
         } else {
-            let source = self.sources.read().await.get(path).cloned();
+            let source = self.source(path).await;
             if let Some(source) = source {
                 let source_name = source.lock().await.name.clone();
-                let source_descriptor = source.lock().await.descriptor.clone();
-                let description = sdp::create(&source_name, &source_descriptor).await;
+                let description = if source.lock().await.transcode.is_some() {
+                    // The transcoded stream only exists inside the running
+                    // source, so ask the source instead of opening the
+                    // descriptor again.
+                    let mut delegate = source.lock().await.delegate();
+                    match timeout(
+                        time::Duration::from_secs(Self::MEDIA_INFO_TIMEOUT_SECS),
+                        delegate.query_media_info(),
+                    )
+                    .await
+                    {
+                        Ok(Some(media_info)) => {
+                            sdp::create_from_media_info(&source_name, media_info).await
+                        }
+                        Ok(None) | Err(_) => Err(SdpError::MediaInfoUnavailable),
+                    }
+                } else {
+                    let source_descriptor = source.lock().await.descriptor.clone();
+                    sdp::create(&source_name, &source_descriptor).await
+                };
                 if let Ok(description) = description.as_ref() {
                     self.source_descriptions_cache
                         .write()
@@ -132,7 +248,7 @@ impl SourceManager {
     }
 
     pub async fn subscribe(&self, path: &SourcePathRef) -> Option<SourceDelegate> {
-        let source = self.sources.read().await.get(path).cloned();
+        let source = self.source(path).await;
         if let Some(source) = source {
             tracing::trace!(path, "creating source delegate for caller");
             Some(source.lock().await.delegate())
@@ -144,6 +260,7 @@ impl SourceManager {
 
     async fn run(
         sources: SourceMap,
+        source_descriptions_cache: SourceDescriptionsCache,
         mut source_state_rx: SourceStateRx,
         mut task_context: TaskContext,
     ) {
@@ -156,6 +273,15 @@ impl SourceManager {
                     tracing::trace!(%source_id, "source manager: received stopped");
                     let _ = sources.write().await.remove(&source_id);
                   },
+                  Some(SourceState::Unregister(source_id, done_tx)) => {
+                    tracing::trace!(%source_id, "source manager: unregistering source");
+                    let source = sources.write().await.remove(&source_id);
+                    source_descriptions_cache.write().await.remove(&source_id);
+                    if let Some(source) = source {
+                      source.lock().await.stop().await;
+                    }
+                    let _ = done_tx.send(());
+                  },
                   None => {
                     tracing::error!("source state channel broke unexpectedly");
                     break;
@@ -0,0 +1,142 @@
+//! Scripted RTSP client used by the conformance tests of both servers.
+//!
+//! Requests are written as raw text so that tests can also send requests
+//! the protocol crate would refuse to build, like ones without a `CSeq`.
+
+use std::net::SocketAddr;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+pub struct RtspTestClient {
+    addr: SocketAddr,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    cseq: u32,
+}
+
+impl RtspTestClient {
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await
+        .with_context(||format!("connect failed, [{addr}]"))?;
+        Ok(Self {
+            addr,
+            stream,
+            buf: Vec::new(),
+            cseq: 0,
+        })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("rtsp://{}{}", self.addr, path)
+    }
+
+    /// Send a request with the next `CSeq` and the given extra headers.
+    pub async fn request(&mut self, method: &str, path: &str, headers: &[(&str, &str)]) -> Result<RtspReply> {
+        self.cseq += 1;
+        let cseq = self.cseq.to_string();
+        let mut all_headers = vec![("CSeq", cseq.as_str())];
+        all_headers.extend_from_slice(headers);
+        let rsp = self.request_raw(method, path, &all_headers).await?;
+        // RFC 2326 12.17: every response echoes the CSeq of its request.
+        rsp.expect_header("CSeq", &cseq)?;
+        Ok(rsp)
+    }
+
+    /// Send a request with exactly the given headers.
+    pub async fn request_raw(&mut self, method: &str, path: &str, headers: &[(&str, &str)]) -> Result<RtspReply> {
+        let mut text = format!("{method} {} RTSP/1.0\r\n", self.url(path));
+        for (name, value) in headers {
+            text.push_str(&format!("{name}: {value}\r\n"));
+        }
+        text.push_str("\r\n");
+        self.stream.write_all(text.as_bytes()).await?;
+        self.read_reply().await
+    }
+
+    async fn read_reply(&mut self) -> Result<RtspReply> {
+        let head_end = loop {
+            if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            self.fill_buf().await?;
+        };
+
+        let head = String::from_utf8(self.buf[..head_end].to_vec())?;
+        let mut lines = head.split("\r\n").filter(|line| !line.is_empty());
+        let status_line = lines.next().with_context(||"empty response")?;
+        let mut parts = status_line.splitn(3, ' ');
+        let status = match (parts.next(), parts.next()) {
+            (Some("RTSP/1.0"), Some(code)) => code.parse::<u16>()
+                .with_context(||format!("invalid status line [{status_line}]"))?,
+            _ => bail!("invalid status line [{status_line}]"),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = line.split_once(':')
+            .with_context(||format!("invalid header line [{line}]"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let content_length = headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, value)| value.parse::<usize>())
+            .transpose()?
+            .unwrap_or(0);
+        while self.buf.len() < head_end + content_length {
+            self.fill_buf().await?;
+        }
+        let body = self.buf[head_end..head_end + content_length].to_vec();
+        self.buf.drain(..head_end + content_length);
+
+        Ok(RtspReply { status, headers, body })
+    }
+
+    async fn fill_buf(&mut self) -> Result<()> {
+        let mut chunk = [0u8; 4096];
+        let n = self.stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("connection closed by server");
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RtspReply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RtspReply {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn expect_status(&self, status: u16) -> Result<&Self> {
+        if self.status != status {
+            bail!("expect status [{status}], but [{}], {self:?}", self.status);
+        }
+        Ok(self)
+    }
+
+    pub fn expect_header(&self, name: &str, value: &str) -> Result<&Self> {
+        match self.header(name) {
+            Some(v) if v == value => Ok(self),
+            v => bail!("expect header [{name}: {value}], but [{v:?}]"),
+        }
+    }
+
+    /// The session id without the optional `;timeout=` parameter.
+    pub fn session(&self) -> Option<&str> {
+        self.header("Session")
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim())
+    }
+}
@@ -0,0 +1,266 @@
+
+use std::net::SocketAddr;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use futures::Future;
+use tokio::net::TcpListener;
+
+use crate::rtsp_test_client::RtspTestClient;
+use super::{run_simple_rtsp_server, RtpChPacket, RtspMediaSource, RtspPlayDesc, RtspServerCallback};
+
+const SDP: &str = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=test\r\nt=0 0\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n";
+const TCP_TRANSPORT: &str = "RTP/AVP/TCP;unicast;interleaved=0-1";
+
+struct Fake;
+
+impl RtspServerCallback for Fake {
+    type MediaSource = FakeSource;
+
+    fn on_request_play(&self, path: &str) -> impl Future<Output = Result<Option<RtspPlayDesc<Self::MediaSource>>>> + Send + Sync + 'static {
+        let result = if path != "/example" {
+            Ok(None)
+        } else {
+            Ok(Some(RtspPlayDesc {
+                sdp: Bytes::from_static(SDP.as_bytes()),
+                num_tracks: 1,
+                source: FakeSource,
+            }))
+        };
+
+        async move {
+            result
+        }
+    }
+}
+
+struct FakeSource;
+
+impl RtspMediaSource for FakeSource {
+    fn on_setup_track(&mut self, control: &str) -> Option<usize> {
+        (control == "streamid=0").then_some(0)
+    }
+
+    fn on_start_play(&mut self) -> impl Future<Output = Result<()>> + Send + Sync {
+        async {
+            Ok(())
+        }
+    }
+
+    fn read_outbound_rtp(&mut self) -> impl Future<Output = Result<Option<RtpChPacket>> > + Send + Sync {
+        // never yields so that the session stays in playing
+        futures::future::pending()
+    }
+
+    fn on_inbound_rtp(&mut self, _packet: RtpChPacket) -> impl Future<Output = Result<()>> + Send + Sync {
+        async {
+            Ok(())
+        }
+    }
+}
+
+/// Run `script` against a fresh server listening on a loopback port
+async fn with_server<F, T>(script: F) -> Result<()>
+where
+    F: FnOnce(SocketAddr) -> T,
+    T: Future<Output = Result<()>>,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    tokio::select! {
+        r = run_simple_rtsp_server(listener, Fake) => {
+            bail!("server exited unexpectedly, [{r:?}]")
+        }
+        r = script(addr) => r,
+    }
+}
+
+/// Walk the client through DESCRIBE and SETUP, returns the session id
+async fn setup(client: &mut RtspTestClient) -> Result<String> {
+    client.request("DESCRIBE", "/example", &[("Accept", "application/sdp")]).await?
+        .expect_status(200)?;
+    let rsp = client.request("SETUP", "/example/streamid=0", &[("Transport", TCP_TRANSPORT)]).await?;
+    rsp.expect_status(200)?;
+    match rsp.session() {
+        Some(session) => Ok(session.to_string()),
+        None => bail!("SETUP response without Session header, {rsp:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_options() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let rsp = client.request("OPTIONS", "/example", &[]).await?;
+        rsp.expect_status(200)?
+            .expect_header("Public", "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN")?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_describe() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+
+        let rsp = client.request("DESCRIBE", "/example", &[("Accept", "application/sdp")]).await?;
+        rsp.expect_status(200)?
+            .expect_header("Content-Type", "application/sdp")?;
+        assert_eq!(rsp.body, SDP.as_bytes());
+
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_describe_not_found() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client.request("DESCRIBE", "/missing", &[("Accept", "application/sdp")]).await?
+            .expect_status(404)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_describe_not_acceptable() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client.request("DESCRIBE", "/example", &[("Accept", "text/plain")]).await?
+            .expect_status(406)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_missing_cseq() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let rsp = client.request_raw("OPTIONS", "/example", &[]).await?;
+        rsp.expect_status(400)?;
+        assert_eq!(rsp.header("CSeq"), None);
+
+        // connection is still usable afterwards
+        client.request("OPTIONS", "/example", &[]).await?
+            .expect_status(200)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_unsupported_require() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client.request("OPTIONS", "/example", &[("Require", "funky-feature")]).await?
+            .expect_status(551)?
+            .expect_header("Unsupported", "funky-feature")?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_unsupported_method() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client.request("ANNOUNCE", "/example", &[]).await?
+            .expect_status(405)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_method_not_valid_in_state() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+
+        // SETUP and PLAY before DESCRIBE
+        client.request("SETUP", "/example/streamid=0", &[("Transport", TCP_TRANSPORT)]).await?
+            .expect_status(455)?;
+        client.request("PLAY", "/example", &[]).await?
+            .expect_status(455)?;
+
+        // PLAY before SETUP
+        client.request("DESCRIBE", "/example", &[("Accept", "application/sdp")]).await?
+            .expect_status(200)?;
+        client.request("PLAY", "/example", &[]).await?
+            .expect_status(455)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_setup_bad_transport() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        client.request("DESCRIBE", "/example", &[("Accept", "application/sdp")]).await?
+            .expect_status(200)?;
+
+        // only interleaved TCP is supported
+        client.request("SETUP", "/example/streamid=0", &[("Transport", "RTP/AVP;unicast;client_port=5000-5001")]).await?
+            .expect_status(461)?;
+        client.request("SETUP", "/example/streamid=0", &[("Transport", "bogus")]).await?
+            .expect_status(461)?;
+        client.request("SETUP", "/example/streamid=0", &[]).await?
+            .expect_status(461)?;
+
+        // unknown track
+        client.request("SETUP", "/example/streamid=9", &[("Transport", TCP_TRANSPORT)]).await?
+            .expect_status(400)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_setup_play_teardown() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let session = setup(&mut client).await?;
+
+        let rsp = client.request("PLAY", "/example", &[("Session", &session)]).await?;
+        rsp.expect_status(200)?;
+        assert!(rsp.header("Range").is_some(), "PLAY response without Range, {rsp:?}");
+
+        client.request("TEARDOWN", "/example", &[("Session", &session)]).await?
+            .expect_status(200)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_wrong_session() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let session = setup(&mut client).await?;
+        let wrong = if session == "12345678" { "87654321" } else { "12345678" };
+
+        client.request("PLAY", "/example", &[]).await?
+            .expect_status(454)?;
+        client.request("PLAY", "/example", &[("Session", wrong)]).await?
+            .expect_status(454)?;
+
+        client.request("PLAY", "/example", &[("Session", &session)]).await?
+            .expect_status(200)?;
+        client.request("TEARDOWN", "/example", &[("Session", wrong)]).await?
+            .expect_status(454)?;
+        client.request("TEARDOWN", "/example", &[("Session", &session)]).await?
+            .expect_status(200)?;
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn test_method_not_valid_in_playing() -> Result<()> {
+    with_server(|addr| async move {
+        let mut client = RtspTestClient::connect(addr).await?;
+        let session = setup(&mut client).await?;
+        client.request("PLAY", "/example", &[("Session", &session)]).await?
+            .expect_status(200)?;
+
+        client.request("SETUP", "/example/streamid=0", &[("Transport", TCP_TRANSPORT), ("Session", &session)]).await?
+            .expect_status(455)?;
+        client.request("OPTIONS", "/example", &[]).await?
+            .expect_status(200)?;
+        Ok(())
+    }).await
+}
@@ -0,0 +1,616 @@
+/*
+    Per-connection output queue.
+
+    Responses and interleaved rtp packets are queued here and written to the
+    socket by a dedicated writer task, so a slow client never blocks reading
+    from its media source. Under congestion video is dropped in steps:
+    - queued bytes above `drop_non_ref_bytes`: drop non-reference frames (nal_ref_idc == 0),
+      a frame is dropped or queued as a whole, never split between its packets
+    - queued bytes would exceed `max_queued_bytes`: drop the queued video and
+      everything after it until the next key frame, i.e. the rest of the GOP
+    Responses are never dropped.
+*/
+
+use std::{collections::VecDeque, fmt, sync::Arc, time::{Duration, Instant}};
+
+use anyhow::{bail, Result};
+use futures::{Sink, SinkExt};
+use oddity_rtsp_protocol::{MaybeInterleaved, Response};
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tracing::debug;
+
+use super::RtpChPacket;
+
+#[derive(Debug, Clone)]
+pub struct OutQueueConfig {
+    /// queued bytes that trigger dropping the rest of the GOP
+    pub max_queued_bytes: usize,
+
+    /// queued bytes that trigger dropping non-reference frames
+    pub drop_non_ref_bytes: usize,
+
+    /// cap of bytes written per second, none for unlimited
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for OutQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_queued_bytes: 2 * 1024 * 1024,
+            drop_non_ref_bytes: 512 * 1024,
+            max_bytes_per_sec: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub dropped_packets: u64,
+    pub dropped_bytes: u64,
+    pub dropped_non_ref_frames: u64,
+    pub dropped_gops: u64,
+    pub queued_bytes: usize,
+
+    /// bytes per second written to the socket, measured over the last interval
+    pub throughput: u64,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} pkts/{} bytes, dropped {} pkts/{} bytes ({} non-ref frames, {} gops), queued {} bytes, {} bytes/s",
+            self.sent_packets, self.sent_bytes,
+            self.dropped_packets, self.dropped_bytes,
+            self.dropped_non_ref_frames, self.dropped_gops,
+            self.queued_bytes, self.throughput,
+        )
+    }
+}
+
+pub enum OutItem {
+    Response(Response),
+    Media(RtpChPacket, MediaKind),
+}
+
+impl OutItem {
+    fn media_len(&self) -> usize {
+        match self {
+            OutItem::Response(_) => 0,
+            OutItem::Media(packet, _) => packet.data.len(),
+        }
+    }
+}
+
+pub struct OutQueue {
+    config: OutQueueConfig,
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+struct Inner {
+    items: VecDeque<OutItem>,
+    queued_bytes: usize,
+    closed: bool,
+
+    // dropping video until the next key frame
+    skip_to_key: bool,
+
+    // channels whose current non-reference frame is being dropped
+    non_ref_dropping: Vec<u8>,
+
+    stats: SessionStats,
+    meter_start: Instant,
+    meter_bytes: u64,
+}
+
+impl OutQueue {
+    pub fn new(config: OutQueueConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner {
+                items: VecDeque::new(),
+                queued_bytes: 0,
+                closed: false,
+                skip_to_key: false,
+                non_ref_dropping: Vec::new(),
+                stats: SessionStats::default(),
+                meter_start: Instant::now(),
+                meter_bytes: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn push_response(&self, rsp: Response) -> Result<()> {
+        let mut inner = self.inner.lock();
+        if inner.closed {
+            bail!("connection writer closed");
+        }
+        inner.items.push_back(OutItem::Response(rsp));
+        drop(inner);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Queue a packet, or drop it if the client can't keep up.
+    /// `video` tells whether the packet carries H.264 rtp.
+    pub fn push_media(&self, packet: RtpChPacket, video: bool) -> Result<()> {
+        let kind = if video { classify_h264_rtp(&packet.data) } else { MediaKind::OTHER };
+        let len = packet.data.len();
+
+        let mut inner = self.inner.lock();
+        if inner.closed {
+            bail!("connection writer closed");
+        }
+        // refresh here too, nothing may be sent while congested
+        inner.update_meter();
+
+        if video && inner.skip_to_key {
+            if kind.key_start {
+                debug!("resume video at key frame");
+                inner.skip_to_key = false;
+            } else {
+                inner.drop_packet(len);
+                return Ok(())
+            }
+        }
+
+        if video {
+            // decided at the first packet, the rest of the frame follows it
+            if kind.frame_start {
+                inner.non_ref_dropping.retain(|ch_id| *ch_id != packet.ch_id);
+                if !kind.reference && inner.queued_bytes > self.config.drop_non_ref_bytes {
+                    inner.stats.dropped_non_ref_frames += 1;
+                    inner.non_ref_dropping.push(packet.ch_id);
+                }
+            }
+            if inner.non_ref_dropping.contains(&packet.ch_id) {
+                inner.drop_packet(len);
+                return Ok(())
+            }
+        }
+
+        if inner.queued_bytes + len > self.config.max_queued_bytes {
+            // a dropped video packet breaks its frame, skip to the next key frame as well
+            if !inner.skip_to_key && (inner.purge_video() || video) {
+                debug!("client congested, dropping rest of gop");
+                inner.stats.dropped_gops += 1;
+                inner.skip_to_key = true;
+            }
+
+            // the packet itself may start the next gop
+            let keep = video && kind.key_start && inner.queued_bytes + len <= self.config.max_queued_bytes;
+            if !keep {
+                inner.drop_packet(len);
+                return Ok(())
+            }
+            inner.skip_to_key = false;
+        }
+
+        inner.queued_bytes += len;
+        inner.items.push_back(OutItem::Media(packet, kind));
+        drop(inner);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Wait for the next item, none once closed and drained.
+    pub async fn pop(&self) -> Option<OutItem> {
+        loop {
+            {
+                let mut inner = self.inner.lock();
+                if let Some(item) = inner.items.pop_front() {
+                    inner.queued_bytes -= item.media_len();
+                    return Some(item)
+                }
+                if inner.closed {
+                    return None
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stop accepting items, the writer still drains what is queued.
+    pub fn close(&self) {
+        self.inner.lock().closed = true;
+        self.notify.notify_one();
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        let mut inner = self.inner.lock();
+        inner.update_meter();
+        let mut stats = inner.stats.clone();
+        stats.queued_bytes = inner.queued_bytes;
+        stats
+    }
+
+    fn on_sent(&self, len: usize) {
+        let mut inner = self.inner.lock();
+        inner.stats.sent_packets += 1;
+        inner.stats.sent_bytes += len as u64;
+        inner.meter_bytes += len as u64;
+        inner.update_meter();
+    }
+}
+
+impl Inner {
+    const METER_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn update_meter(&mut self) {
+        let elapsed = self.meter_start.elapsed();
+        if elapsed >= Self::METER_INTERVAL {
+            self.stats.throughput = (self.meter_bytes as f64 / elapsed.as_secs_f64()) as u64;
+            self.meter_bytes = 0;
+            self.meter_start = Instant::now();
+        }
+    }
+
+    fn drop_packet(&mut self, len: usize) {
+        self.stats.dropped_packets += 1;
+        self.stats.dropped_bytes += len as u64;
+    }
+
+    // drop all queued video packets, returns whether any was dropped
+    fn purge_video(&mut self) -> bool {
+        let mut purged = Vec::new();
+        self.items.retain(|item| match item {
+            OutItem::Media(packet, kind) if kind.video => {
+                purged.push(packet.data.len());
+                false
+            },
+            _ => true,
+        });
+        for len in purged.iter() {
+            self.queued_bytes -= len;
+            self.drop_packet(*len);
+        }
+        !purged.is_empty()
+    }
+}
+
+/// Write queued items to `outbound` until the queue is closed and drained
+/// or writing fails.
+pub async fn run_writer<W, E>(queue: Arc<OutQueue>, mut outbound: W) -> Result<()>
+where
+    W: Sink<MaybeInterleaved<Response>, Error = E> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut pacer = queue.config.max_bytes_per_sec.map(Pacer::new);
+
+    let r = loop {
+        let item = match queue.pop().await {
+            Some(item) => item,
+            None => break Ok(()),
+        };
+
+        let msg = match item {
+            OutItem::Response(rsp) => {
+                debug!("S -> C: Response {rsp}");
+                MaybeInterleaved::Message(rsp)
+            },
+            OutItem::Media(packet, _kind) => {
+                if let Some(pacer) = pacer.as_mut() {
+                    pacer.wait(packet.data.len()).await;
+                }
+                MaybeInterleaved::Interleaved { channel: packet.ch_id, payload: packet.data }
+            },
+        };
+
+        let len = match &msg {
+            MaybeInterleaved::Interleaved { payload, .. } => payload.len(),
+            MaybeInterleaved::Message(_) => 0,
+        };
+
+        if let Err(e) = outbound.send(msg).await {
+            break Err(e.into())
+        }
+
+        if len > 0 {
+            queue.on_sent(len);
+        }
+    };
+
+    queue.close();
+    r
+}
+
+/// Token bucket that limits the bytes written per second
+struct Pacer {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Pacer {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            rate: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            last: Instant::now(),
+        }
+    }
+
+    async fn wait(&mut self, len: usize) {
+        let now = Instant::now();
+        // at most one second of burst
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+
+        self.tokens -= len as f64;
+        if self.tokens < 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(-self.tokens / self.rate)).await;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaKind {
+    pub video: bool,
+
+    /// other frames may depend on this one
+    pub reference: bool,
+
+    /// first packet of a frame
+    pub frame_start: bool,
+
+    /// first packet of a key frame or the parameter sets before it
+    pub key_start: bool,
+}
+
+impl MediaKind {
+    pub const OTHER: MediaKind = MediaKind {
+        video: false,
+        reference: true,
+        frame_start: true,
+        key_start: false,
+    };
+}
+
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_STAP_A: u8 = 24;
+const NAL_FU_A: u8 = 28;
+
+/// Classify an H.264 rtp packet (RFC 6184) by its NAL header.
+/// Packets that can't be parsed are treated as reference data.
+pub fn classify_h264_rtp(data: &[u8]) -> MediaKind {
+    let mut kind = MediaKind {
+        video: true,
+        reference: true,
+        frame_start: false,
+        key_start: false,
+    };
+
+    let payload = match rtp_payload(data) {
+        Some(v) if !v.is_empty() => v,
+        _ => return kind,
+    };
+
+    let nal = payload[0];
+    kind.reference = (nal >> 5) & 0x3 != 0;
+
+    match nal & 0x1f {
+        NAL_STAP_A => {
+            // first aggregated NAL unit follows a 16 bits size
+            kind.frame_start = true;
+            if let Some(first) = payload.get(3) {
+                kind.key_start = matches!(first & 0x1f, NAL_SPS | NAL_IDR);
+            }
+        },
+        NAL_FU_A => {
+            if let Some(fu_header) = payload.get(1) {
+                kind.frame_start = fu_header & 0x80 != 0;
+                kind.key_start = kind.frame_start && fu_header & 0x1f == NAL_IDR;
+            }
+        },
+        nal_type => {
+            kind.frame_start = true;
+            kind.key_start = matches!(nal_type, NAL_SPS | NAL_IDR);
+        },
+    }
+    kind
+}
+
+fn rtp_payload(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 12 || data[0] >> 6 != 2 {
+        return None
+    }
+
+    let csrc_count = (data[0] & 0x0f) as usize;
+    let mut offset = 12 + csrc_count * 4;
+
+    if data[0] & 0x10 != 0 {
+        let ext = data.get(offset..offset + 4)?;
+        let ext_words = u16::from_be_bytes([ext[2], ext[3]]) as usize;
+        offset += 4 + ext_words * 4;
+    }
+
+    data.get(offset..)
+}
+
+/// Channels of the H.264 video tracks described by `sdp`, in the numbering
+/// of `RtpChPacket::ch_id` (track index << 1).
+pub fn h264_channels_of_sdp(sdp: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(sdp);
+    let mut track: Option<usize> = None;
+    let mut is_video = false;
+    let mut channels = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(media) = line.strip_prefix("m=") {
+            track = Some(track.map_or(0, |t| t + 1));
+            is_video = media.starts_with("video");
+        } else if is_video && line.starts_with("a=rtpmap:") && line.contains(" H264/") {
+            if let Some(track) = track {
+                let ch_id = (track << 1) as u8;
+                if !channels.contains(&ch_id) {
+                    channels.push(ch_id);
+                }
+            }
+        }
+    }
+    channels
+}
+
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn rtp(nal: &[u8]) -> RtpChPacket {
+        let mut data = vec![0x80, 96, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(nal);
+        data.resize(data.len() + 100, 0);
+        RtpChPacket { ch_id: 0, data: Bytes::from(data) }
+    }
+
+    fn idr() -> RtpChPacket { rtp(&[0x65]) }
+    fn p_frame() -> RtpChPacket { rtp(&[0x41]) }
+    fn non_ref() -> RtpChPacket { rtp(&[0x01]) }
+
+    fn queued(queue: &OutQueue) -> usize {
+        queue.inner.lock().items.len()
+    }
+
+    #[test]
+    fn test_classify() {
+        assert!(classify_h264_rtp(&idr().data).key_start);
+        assert!(classify_h264_rtp(&rtp(&[0x67]).data).key_start);
+        assert!(!classify_h264_rtp(&non_ref().data).reference);
+
+        let kind = classify_h264_rtp(&p_frame().data);
+        assert!(kind.reference && kind.frame_start && !kind.key_start);
+
+        // FU-A of an IDR, start and continuation
+        assert!(classify_h264_rtp(&rtp(&[0x7c, 0x85]).data).key_start);
+        let kind = classify_h264_rtp(&rtp(&[0x7c, 0x05]).data);
+        assert!(!kind.frame_start && !kind.key_start);
+
+        // STAP-A with SPS first
+        assert!(classify_h264_rtp(&rtp(&[0x78, 0, 10, 0x67]).data).key_start);
+    }
+
+    #[test]
+    fn test_sdp_channels() {
+        let sdp = "v=0\r\nm=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/48000/2\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n";
+        assert_eq!(h264_channels_of_sdp(sdp.as_bytes()), vec![2]);
+    }
+
+    #[test]
+    fn test_drop_non_ref() {
+        let len = idr().data.len();
+        let queue = OutQueue::new(OutQueueConfig {
+            max_queued_bytes: len * 10,
+            drop_non_ref_bytes: len * 2,
+            max_bytes_per_sec: None,
+        });
+
+        for _ in 0..3 {
+            queue.push_media(p_frame(), true).unwrap();
+        }
+        queue.push_media(non_ref(), true).unwrap();
+        // audio is never classified as non-ref
+        queue.push_media(non_ref(), false).unwrap();
+
+        assert_eq!(queued(&queue), 4);
+        let stats = queue.stats();
+        assert_eq!(stats.dropped_non_ref_frames, 1);
+        assert_eq!(stats.dropped_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_non_ref_whole_frame() {
+        let len = idr().data.len();
+        let queue = OutQueue::new(OutQueueConfig {
+            max_queued_bytes: len * 10,
+            drop_non_ref_bytes: len * 2,
+            max_bytes_per_sec: None,
+        });
+
+        // FU-A of a non-ref frame, started below the threshold: kept whole
+        queue.push_media(p_frame(), true).unwrap();
+        queue.push_media(p_frame(), true).unwrap();
+        queue.push_media(rtp(&[0x1c, 0x81]), true).unwrap();
+        queue.push_media(rtp(&[0x1c, 0x01]), true).unwrap();
+        queue.push_media(rtp(&[0x1c, 0x41]), true).unwrap();
+        assert_eq!(queued(&queue), 5);
+
+        // started above the threshold: dropped whole, even once drained
+        queue.push_media(rtp(&[0x1c, 0x81]), true).unwrap();
+        while queued(&queue) > 0 {
+            queue.pop().await.unwrap();
+        }
+        queue.push_media(rtp(&[0x1c, 0x41]), true).unwrap();
+        assert_eq!(queued(&queue), 0);
+
+        queue.push_media(p_frame(), true).unwrap();
+        assert_eq!(queued(&queue), 1);
+
+        let stats = queue.stats();
+        assert_eq!(stats.dropped_non_ref_frames, 1);
+        assert_eq!(stats.dropped_packets, 2);
+    }
+
+    #[test]
+    fn test_throughput_decays() {
+        let queue = OutQueue::new(OutQueueConfig::default());
+        {
+            let mut inner = queue.inner.lock();
+            inner.meter_start = Instant::now() - Duration::from_secs(2);
+            inner.meter_bytes = 2000;
+        }
+        let throughput = queue.stats().throughput;
+        assert!((900..=1000).contains(&throughput), "{throughput}");
+
+        // nothing sent in the next interval
+        queue.inner.lock().meter_start = Instant::now() - Duration::from_secs(2);
+        assert_eq!(queue.stats().throughput, 0);
+    }
+
+    #[test]
+    fn test_drop_gop() {
+        let len = idr().data.len();
+        let queue = OutQueue::new(OutQueueConfig {
+            max_queued_bytes: len * 4,
+            drop_non_ref_bytes: len * 4,
+            max_bytes_per_sec: None,
+        });
+
+        queue.push_media(idr(), true).unwrap();
+        for _ in 0..3 {
+            queue.push_media(p_frame(), true).unwrap();
+        }
+        queue.push_response(Response::ok().build()).unwrap();
+
+        // over budget: queued video is purged and P-frames are skipped
+        queue.push_media(p_frame(), true).unwrap();
+        queue.push_media(p_frame(), true).unwrap();
+        assert_eq!(queued(&queue), 1);
+        assert_eq!(queue.stats().dropped_gops, 1);
+        assert_eq!(queue.stats().dropped_packets, 6);
+
+        // resumes at the next key frame
+        queue.push_media(idr(), true).unwrap();
+        queue.push_media(p_frame(), true).unwrap();
+        assert_eq!(queued(&queue), 3);
+        assert_eq!(queue.stats().queued_bytes, len * 2);
+    }
+
+    #[tokio::test]
+    async fn test_close_drains() {
+        let queue = OutQueue::new(OutQueueConfig::default());
+        queue.push_media(idr(), true).unwrap();
+        queue.close();
+        assert!(queue.push_media(idr(), true).is_err());
+        assert!(queue.pop().await.is_some());
+        assert!(queue.pop().await.is_none());
+    }
+}
@@ -4,16 +4,22 @@ use std::{fmt, sync::Arc};
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
-use futures::{Future, SinkExt};
+use futures::Future;
 use oddity_rtsp_protocol::{AsServer, Channel, Codec, Lower, MaybeInterleaved, Method, Parameter, Range, Request, Response, Status, Transport};
 use rand::Rng;
-use tokio::net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpListener, TcpStream};
+use tokio::{net::{tcp::OwnedReadHalf, TcpListener, TcpStream}, task::JoinHandle, time::{interval, Duration, MissedTickBehavior}};
 use tokio_stream::StreamExt;
-use tokio_util::codec::{self, FramedRead, FramedWrite};
+use tokio_util::codec::{self, FramedRead};
 use tracing::{debug, warn};
 
+use super::out_queue::{h264_channels_of_sdp, run_writer, OutQueue, OutQueueConfig, SessionStats};
+
 
 pub async fn run_simple_rtsp_server<C: RtspServerCallback>(listener: TcpListener, callback: C) -> Result<()>  {
+    run_simple_rtsp_server_with_config(listener, callback, OutQueueConfig::default()).await
+}
+
+pub async fn run_simple_rtsp_server_with_config<C: RtspServerCallback>(listener: TcpListener, callback: C, out_config: OutQueueConfig) -> Result<()>  {
 
     let supported_methods = SupportedMethods::new(vec![
         Method::Options,
@@ -27,6 +33,7 @@ pub async fn run_simple_rtsp_server<C: RtspServerCallback>(listener: TcpListener
         callback,
         supported_methods_str: supported_methods.to_header_value(),
         supported_methods,
+        out_config,
     });
 
     loop {
@@ -38,6 +45,7 @@ pub async fn run_simple_rtsp_server<C: RtspServerCallback>(listener: TcpListener
 
         spawn_with_name(addr.to_string(), async move {
             let r = conn_task(&mut conn).await;
+            conn.shutdown().await;
             debug!("finished with [{r:?}]");
         });
     }
@@ -48,6 +56,7 @@ struct ServerShared<C> {
     callback: C,
     supported_methods: SupportedMethods,
     supported_methods_str: String,
+    out_config: OutQueueConfig,
 }
 
 pub struct RtspPlayDesc<S> {
@@ -62,6 +71,9 @@ pub trait RtspServerCallback: Send + Sync + 'static {
     type MediaSource: RtspMediaSource + Send + Sync + 'static;
 
     fn on_request_play(&self, path: &str) -> impl Future<Output = OnRequestPlayResult<Self::MediaSource>> + Send + Sync + 'static;
+
+    // called every STATS_INTERVAL while playing and once when playing stops
+    fn on_session_stats(&self, _session_id: &str, _stats: &SessionStats) {}
 }
 
 
@@ -112,12 +124,12 @@ where
 }
 
 type Inbound = FramedRead<OwnedReadHalf, Codec<AsServer>>;
-type Outbound = FramedWrite<OwnedWriteHalf, Codec<AsServer>>;
 
 struct Connection<C> {
     sid: SessionId,
     inbound: Inbound,
-    outbound: Outbound,
+    out: Arc<OutQueue>,
+    writer: Option<JoinHandle<Result<()>>>,
     packet: Option<MaybeInterleaved<Request>>,
     is_teardown: bool,
     shared: Arc<ServerShared<C>>,
@@ -126,10 +138,13 @@ struct Connection<C> {
 impl<C> Connection<C> {
     pub fn new(socket: TcpStream, shared: Arc<ServerShared<C>>) -> Self {
         let (read, write) = socket.into_split();
+        let out = Arc::new(OutQueue::new(shared.out_config.clone()));
+        let writer = tokio::spawn(run_writer(out.clone(), codec::FramedWrite::new(write, Codec::<AsServer>::new())));
         Self { 
             sid: SessionId::generate(),
             inbound: codec::FramedRead::new(read, Codec::<AsServer>::new()),
-            outbound: codec::FramedWrite::new(write, Codec::<AsServer>::new()),
+            out,
+            writer: Some(writer),
             packet: None,
             is_teardown: false,
             shared,
@@ -137,22 +152,29 @@ impl<C> Connection<C> {
     }
 
     pub async fn send_response(&mut self,  msg: Response) -> Result<()> {
-        debug!("S -> C: Response {msg}");
-        self.outbound.send(MaybeInterleaved::Message(msg)).await
-        .with_context(||"send response failed")?;
-        Ok(())
+        self.out.push_response(msg)
+        .with_context(||"send response failed")
+    }
 
-        // send_msg(&mut self.outbound, msg).await
+    // flush what is queued and wait for the writer to finish
+    pub async fn shutdown(&mut self) {
+        self.out.close();
+        if let Some(writer) = self.writer.take() {
+            match writer.await {
+                Ok(Err(e)) => debug!("writer finished with [{e:?}]"),
+                Err(e) => warn!("writer panicked [{e:?}]"),
+                Ok(Ok(())) => {},
+            }
+        }
     }
 
     pub fn is_teardown(&self) -> bool {
         self.is_teardown
     }
 
-    pub async fn send_interleave(&mut self, packet: RtpChPacket) -> Result<()> {
-        // debug!("S -> C: Interleaved ch {}, len {}", packet.ch_id, packet.data.len());
-        self.outbound.send(MaybeInterleaved::Interleaved{channel: packet.ch_id, payload: packet.data}).await?;
-        Ok(())
+    // never blocks, the queue drops the packet if the client is congested
+    pub fn send_interleave(&self, packet: RtpChPacket, video: bool) -> Result<()> {
+        self.out.push_media(packet, video)
     }
 
     pub async fn read_request(&mut self) -> Result<Option<Request>> {
@@ -182,6 +204,12 @@ impl<C> Connection<C> {
         if let Some(packet) = self.packet.take() {
             match &packet {
                 MaybeInterleaved::Message(req) => {
+                    if !is_request_cseq_present(&req) {
+                        let rsp = reply_bad_request(&req);
+                        self.send_response(rsp).await?;
+                        return Ok(None)
+                    }
+
                     if !is_request_require_supported(&req) {
                         let rsp = reply_option_not_supported(&req);
                         self.send_response(rsp).await?;
@@ -194,6 +222,12 @@ impl<C> Connection<C> {
                         return Ok(None)
                     }
 
+                    if !self.is_request_session_valid(req) {
+                        let rsp = reply_session_not_found(req);
+                        self.send_response(rsp).await?;
+                        return Ok(None)
+                    }
+
                     match req.method {
                         Method::Options => {
                             let rsp = reply_to_options_with_supported_methods_value(req, &self.shared.supported_methods_str);
@@ -236,6 +270,14 @@ impl<C> Connection<C> {
         Ok(None)
     }
     
+    // A request may only carry the session id this connection handed out
+    fn is_request_session_valid(&self, request: &Request) -> bool {
+        match request.session() {
+            Some(session) => session.split(';').next().map(|v| v.trim()) == Some(self.sid.as_str()),
+            None => true,
+        }
+    }
+
     async fn reply_teardown(&mut self, request: &Request) -> Result<()> {
         self.is_teardown = true;
         let rsp = reply_to_teardown(request);
@@ -308,6 +350,7 @@ impl Probe {
                     return Ok(State::PrePlaying(PrePlaying {
                         base_path: request.path().to_string(),
                         channels: vec![Xtrans::Empty; desc.num_tracks << 1],
+                        video_channels: h264_channels_of_sdp(&desc.sdp),
                         session: desc.source,
                         num_setup_tracks: 0,
                     }));    
@@ -332,6 +375,7 @@ enum Xtrans {
 struct PrePlaying<S> {
     base_path: String,
     channels: Vec<Xtrans>,
+    video_channels: Vec<u8>,
     num_setup_tracks: usize,
     session: S,
 }
@@ -353,6 +397,12 @@ where
                 Method::Setup => {
                     self.handle_setup(conn, request)?
                 },
+                Method::Play if self.num_setup_tracks == 0 => {
+                    reply_method_not_valid(request)
+                },
+                Method::Play if request.session().is_none() => {
+                    reply_session_not_found(request)
+                },
                 Method::Play => {
                     match request.range() {
                         Some(Ok(_)) | None => {
@@ -383,6 +433,7 @@ where
             if got_playing {
                 return Ok(State::InPlaying(InPlaying {
                     channels: self.channels,
+                    video_channels: self.video_channels,
                     session: self.session,
                 }))
             }
@@ -489,6 +540,7 @@ fn strip_base_path<'a>(path: &'a str, base: &str) -> Option<&'a str> {
 
 struct InPlaying<S> {
     channels: Vec<Xtrans>,
+    video_channels: Vec<u8>,
     session: S,
 }
 
@@ -500,12 +552,28 @@ where
     where
         C: RtspServerCallback<MediaSource = S>,
     {
+        let r = self.serve_playing(conn).await;
+        let stats = conn.out.stats();
+        debug!("session stats: {stats}");
+        conn.shared.callback.on_session_stats(conn.sid.as_str(), &stats);
+        r
+    }
+
+    async fn serve_playing<C>(&mut self, conn: &mut Connection<C>) ->Result<State<C>> 
+    where
+        C: RtspServerCallback<MediaSource = S>,
+    {
+        let mut stats_interval = interval(STATS_INTERVAL);
+        stats_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         loop {
             if conn.is_teardown() {
                 return Ok(State::TearDown)
             }
             tokio::select! {
+                _ = stats_interval.tick() => {
+                    conn.shared.callback.on_session_stats(conn.sid.as_str(), &conn.out.stats());
+                }
                 r = conn.wait_packet() => {
                     r?;
                     if let Some(packet) = conn.next_packet().await? {
@@ -535,8 +603,9 @@ where
             match ch {
                 Xtrans::Empty => {},
                 Xtrans::Interleaved(ch_id) => {
+                    let video = self.video_channels.contains(&packet.ch_id);
                     packet.ch_id = *ch_id;
-                    conn.send_interleave(packet).await?;
+                    conn.send_interleave(packet, video)?;
                 },
             }
         }
@@ -634,8 +703,16 @@ impl fmt::Display for SupportedMethods {
     }
 }
 
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
 static SERVER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
+#[inline]
+fn is_request_cseq_present(request: &Request) -> bool {
+    // RFC 2326 12.17: CSeq must be present in every request
+    request.headers.contains_key("CSeq")
+}
+
 #[inline]
 fn is_request_require_supported(request: &Request) -> bool {
     // We don't support any features at this point
@@ -727,6 +804,7 @@ fn reply_option_not_supported(request: &Request) -> Response {
     Response::error(Status::OptionNotSupported)
         .with_cseq_of(request)
         .with_header("Server", SERVER)
+        .with_header("Unsupported", request.require().unwrap_or_default())
         .build()
 }
 
@@ -802,16 +880,16 @@ fn reply_unsupported_transport(request: &Request) -> Response {
         .build()
 }
 
-// #[inline]
-// fn reply_session_not_found(request: &Request) -> Response {
-//     tracing::debug!(
-//     %request,
-//     "session not found");
-//     Response::error(Status::SessionNotFound)
-//         .with_cseq_of(request)
-//         .with_header("Server", SERVER)
-//         .build()
-// }
+#[inline]
+fn reply_session_not_found(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    "session not found");
+    Response::error(Status::SessionNotFound)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
 
 #[inline]
 fn reply_not_implemented(request: &Request) -> Response {
@@ -859,6 +937,10 @@ impl SessionId {
                 .to_string(),
         )
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for SessionId {